@@ -0,0 +1,181 @@
+/// Math and vector functions flare programs call by name, defined once for
+/// every backend; each backend spells them through `IntrinsicLowering`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Intrinsic {
+    Abs,
+    Fabs,
+    Min,
+    Max,
+    Clamp,
+    Saturate,
+    Sign,
+    Floor,
+    Ceil,
+    Round,
+    Trunc,
+    Fract,
+    Fmod,
+    Fma,
+    Mix,
+    Step,
+    Smoothstep,
+    Sqrt,
+    Rsqrt,
+    Exp,
+    Exp2,
+    Log,
+    Log2,
+    Pow,
+    Powr,
+    Sin,
+    Cos,
+    Tan,
+    Asin,
+    Acos,
+    Atan,
+    Atan2,
+    Sinh,
+    Cosh,
+    Tanh,
+    Length,
+    Distance,
+    Normalize,
+    Select,
+    IsNan,
+    IsInf,
+    Dot,
+    Cross,
+    Transpose,
+}
+
+impl Intrinsic {
+    pub const ALL: &'static [Intrinsic] = &[
+        Intrinsic::Abs,
+        Intrinsic::Fabs,
+        Intrinsic::Min,
+        Intrinsic::Max,
+        Intrinsic::Clamp,
+        Intrinsic::Saturate,
+        Intrinsic::Sign,
+        Intrinsic::Floor,
+        Intrinsic::Ceil,
+        Intrinsic::Round,
+        Intrinsic::Trunc,
+        Intrinsic::Fract,
+        Intrinsic::Fmod,
+        Intrinsic::Fma,
+        Intrinsic::Mix,
+        Intrinsic::Step,
+        Intrinsic::Smoothstep,
+        Intrinsic::Sqrt,
+        Intrinsic::Rsqrt,
+        Intrinsic::Exp,
+        Intrinsic::Exp2,
+        Intrinsic::Log,
+        Intrinsic::Log2,
+        Intrinsic::Pow,
+        Intrinsic::Powr,
+        Intrinsic::Sin,
+        Intrinsic::Cos,
+        Intrinsic::Tan,
+        Intrinsic::Asin,
+        Intrinsic::Acos,
+        Intrinsic::Atan,
+        Intrinsic::Atan2,
+        Intrinsic::Sinh,
+        Intrinsic::Cosh,
+        Intrinsic::Tanh,
+        Intrinsic::Length,
+        Intrinsic::Distance,
+        Intrinsic::Normalize,
+        Intrinsic::Select,
+        Intrinsic::IsNan,
+        Intrinsic::IsInf,
+        Intrinsic::Dot,
+        Intrinsic::Cross,
+        Intrinsic::Transpose,
+    ];
+
+    /// The intrinsic a flare call to `name` refers to.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|intrinsic| intrinsic.name() == name)
+    }
+
+    /// Name flare source uses for the intrinsic.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Intrinsic::Abs => "abs",
+            Intrinsic::Fabs => "fabs",
+            Intrinsic::Min => "min",
+            Intrinsic::Max => "max",
+            Intrinsic::Clamp => "clamp",
+            Intrinsic::Saturate => "saturate",
+            Intrinsic::Sign => "sign",
+            Intrinsic::Floor => "floor",
+            Intrinsic::Ceil => "ceil",
+            Intrinsic::Round => "round",
+            Intrinsic::Trunc => "trunc",
+            Intrinsic::Fract => "fract",
+            Intrinsic::Fmod => "fmod",
+            Intrinsic::Fma => "fma",
+            Intrinsic::Mix => "mix",
+            Intrinsic::Step => "step",
+            Intrinsic::Smoothstep => "smoothstep",
+            Intrinsic::Sqrt => "sqrt",
+            Intrinsic::Rsqrt => "rsqrt",
+            Intrinsic::Exp => "exp",
+            Intrinsic::Exp2 => "exp2",
+            Intrinsic::Log => "log",
+            Intrinsic::Log2 => "log2",
+            Intrinsic::Pow => "pow",
+            Intrinsic::Powr => "powr",
+            Intrinsic::Sin => "sin",
+            Intrinsic::Cos => "cos",
+            Intrinsic::Tan => "tan",
+            Intrinsic::Asin => "asin",
+            Intrinsic::Acos => "acos",
+            Intrinsic::Atan => "atan",
+            Intrinsic::Atan2 => "atan2",
+            Intrinsic::Sinh => "sinh",
+            Intrinsic::Cosh => "cosh",
+            Intrinsic::Tanh => "tanh",
+            Intrinsic::Length => "length",
+            Intrinsic::Distance => "distance",
+            Intrinsic::Normalize => "normalize",
+            Intrinsic::Select => "select",
+            Intrinsic::IsNan => "isnan",
+            Intrinsic::IsInf => "isinf",
+            Intrinsic::Dot => "dot",
+            Intrinsic::Cross => "cross",
+            Intrinsic::Transpose => "transpose",
+        }
+    }
+}
+
+/// Backend spelling of the shared intrinsics.
+pub trait IntrinsicLowering {
+    /// Function name the backend emits for `intrinsic`.
+    fn lower_intrinsic(&self, intrinsic: &Intrinsic) -> String;
+
+    /// Backend spelling of the flare function `name`, or `None` when it is
+    /// not an intrinsic.
+    fn resolve(&self, name: &str) -> Option<String> {
+        Intrinsic::from_name(name).map(|intrinsic| self.lower_intrinsic(&intrinsic))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names_round_trip() {
+        for intrinsic in Intrinsic::ALL {
+            assert_eq!(Intrinsic::from_name(intrinsic.name()), Some(*intrinsic));
+        }
+        assert_eq!(Intrinsic::from_name("gather"), None);
+    }
+}
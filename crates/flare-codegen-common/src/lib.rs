@@ -0,0 +1,3 @@
+pub mod intrinsic;
+
+pub use intrinsic::{Intrinsic, IntrinsicLowering};
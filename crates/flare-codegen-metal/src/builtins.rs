@@ -0,0 +1,549 @@
+use crate::error::{CodegenError, Result};
+use crate::expr::ExprGenerator;
+use crate::typeck::{ScalarType, ValueType};
+use flare::ast::Expr;
+use flare::validate::RESERVED_PREFIX;
+use flare_codegen_common::{Intrinsic, IntrinsicLowering};
+use std::borrow::Cow;
+use std::ops::Range;
+
+/// Flare builtins lowered by the backend.
+pub const FLARE_BUILTINS: &[&str] = &[
+    "gather",
+    "scatter",
+    "transpose",
+    "dot",
+    "cross",
+    "true_div",
+    "async_copy",
+    "simd_ballot",
+    "simd_any",
+    "simd_all",
+];
+
+/// Simdgroup vote builtins; kernels calling them receive their lane index
+/// through `[[thread_index_in_simdgroup]]`.
+pub const SIMD_VOTE_FUNCTIONS: &[&str] = &["simd_ballot", "simd_any", "simd_all"];
+
+/// Spells the shared intrinsics in MSL, whose standard library names match
+/// flare's.
+pub struct MetalIntrinsics;
+
+impl IntrinsicLowering for MetalIntrinsics {
+    fn lower_intrinsic(&self, intrinsic: &Intrinsic) -> String {
+        intrinsic.name().to_string()
+    }
+}
+
+/// MSL scalar and vector constructors usable as conversion calls.
+pub const MSL_CONSTRUCTORS: &[&str] = &[
+    "int", "uint", "long", "ulong", "float", "half", "double", "bool", "int2", "int3", "int4",
+    "uint2", "uint3", "uint4", "float2", "float3", "float4", "half2", "half3", "half4",
+];
+
+/// MSL and C++ keywords a flare name can spell through a raw identifier
+/// such as `r#kernel`.
+pub const MSL_RESERVED_WORDS: &[&str] = &[
+    "auto",
+    "bool",
+    "break",
+    "case",
+    "char",
+    "class",
+    "const",
+    "constant",
+    "continue",
+    "default",
+    "delete",
+    "device",
+    "do",
+    "double",
+    "else",
+    "enum",
+    "explicit",
+    "extern",
+    "false",
+    "float",
+    "for",
+    "fragment",
+    "friend",
+    "goto",
+    "half",
+    "if",
+    "inline",
+    "int",
+    "kernel",
+    "long",
+    "mutable",
+    "namespace",
+    "new",
+    "operator",
+    "private",
+    "protected",
+    "public",
+    "register",
+    "return",
+    "short",
+    "signed",
+    "sizeof",
+    "static",
+    "struct",
+    "switch",
+    "template",
+    "this",
+    "thread",
+    "threadgroup",
+    "throw",
+    "true",
+    "typedef",
+    "typename",
+    "uint",
+    "union",
+    "unsigned",
+    "using",
+    "vertex",
+    "virtual",
+    "void",
+    "volatile",
+    "while",
+];
+
+/// Name emitted for the flare variable, parameter or field `name`: reserved
+/// words become `flare_kw_<word>`, under the prefix user names cannot start
+/// with.
+pub fn msl_identifier(name: &str) -> Cow<'_, str> {
+    if MSL_RESERVED_WORDS.contains(&name) {
+        Cow::Owned(format!("{}kw_{}", RESERVED_PREFIX, name))
+    } else {
+        Cow::Borrowed(name)
+    }
+}
+
+pub fn is_builtin(name: &str) -> bool {
+    FLARE_BUILTINS.contains(&name)
+        || Intrinsic::from_name(name).is_some()
+        || MSL_CONSTRUCTORS.contains(&name)
+}
+
+impl ExprGenerator {
+    /// Lowers calls to flare builtins. Returns `None` when `name` is not a
+    /// builtin so the call is emitted as a regular function call.
+    pub(crate) fn generate_builtin(
+        &mut self,
+        name: &str,
+        args: &[Expr],
+        span: Range<usize>,
+    ) -> Result<Option<String>> {
+        match name {
+            "gather" => {
+                Self::expect_arity(name, args, 2, &span)?;
+                self.expect_buffer(name, &args[0])?;
+                self.expect_integer_index(name, &args[1])?;
+                let buf_code = self.generate(&args[0])?;
+                let idx_code = self.generate(&args[1])?;
+                Ok(Some(format!("{}[{}]", buf_code, idx_code)))
+            }
+            "transpose" => {
+                Self::expect_arity(name, args, 1, &span)?;
+                self.expect_matrix(name, &args[0])?;
+                let matrix_code = self.generate(&args[0])?;
+                let func = MetalIntrinsics.lower_intrinsic(&Intrinsic::Transpose);
+                Ok(Some(format!("{}({})", func, matrix_code)))
+            }
+            "dot" | "cross" => {
+                Self::expect_arity(name, args, 2, &span)?;
+                let (intrinsic, required_len) = if name == "cross" {
+                    (Intrinsic::Cross, Some(3))
+                } else {
+                    (Intrinsic::Dot, None)
+                };
+                self.expect_vector_pair(name, &args[0], &args[1], required_len, &span)?;
+                let left_code = self.generate(&args[0])?;
+                let right_code = self.generate(&args[1])?;
+                let func = MetalIntrinsics.lower_intrinsic(&intrinsic);
+                Ok(Some(format!("{}({}, {})", func, left_code, right_code)))
+            }
+            "true_div" => {
+                Self::expect_arity(name, args, 2, &span)?;
+                let left_code = self.generate(&args[0])?;
+                let right_code = self.generate(&args[1])?;
+                Ok(Some(format!(
+                    "(float({}) / float({}))",
+                    left_code, right_code
+                )))
+            }
+            "simd_ballot" | "simd_any" | "simd_all" => {
+                Self::expect_arity(name, args, 1, &span)?;
+                self.expect_condition(name, &args[0])?;
+                let cond_code = self.generate(&args[0])?;
+                // `simd_vote` converts to its bit mask only explicitly
+                if name == "simd_ballot" {
+                    Ok(Some(format!("ulong(simd_ballot({}))", cond_code)))
+                } else {
+                    Ok(Some(format!("{}({})", name, cond_code)))
+                }
+            }
+            "scatter" => Err(CodegenError::expression_error(
+                "scatter(buf, idx, val) writes memory and must be used as a statement",
+                span,
+            )),
+            "async_copy" => Err(CodegenError::expression_error(
+                "async_copy(dest, src, count) writes memory and must be used as a statement",
+                span,
+            )),
+            _ => Ok(None),
+        }
+    }
+
+    /// Emits a call to an MSL math function. Calls on `half` operands are
+    /// qualified as `metal::name` and take `half` literals so overload
+    /// resolution stays in half precision instead of promoting to `float`.
+    pub(crate) fn generate_intrinsic(
+        &mut self,
+        intrinsic: Intrinsic,
+        args: &[Expr],
+    ) -> Result<String> {
+        let half_args = args
+            .iter()
+            .any(|arg| self.infer_elem_type(arg) == Some(ScalarType::Half));
+        let literal_ty = half_args.then_some(ScalarType::Half);
+
+        let mut args_code = Vec::new();
+        for arg in args {
+            args_code.push(self.generate_as(arg, literal_ty)?);
+        }
+
+        let qualifier = if half_args { "metal::" } else { "" };
+        let name = MetalIntrinsics.lower_intrinsic(&intrinsic);
+        Ok(format!("{}{}({})", qualifier, name, args_code.join(", ")))
+    }
+
+    /// Checks that a vector constructor such as `float4(...)` receives either
+    /// a single value to splat or convert, or components that add up to the
+    /// vector length, counting vector arguments by their own length.
+    pub(crate) fn check_constructor_arity(
+        &self,
+        name: &str,
+        args: &[Expr],
+        span: &Range<usize>,
+    ) -> Result<()> {
+        let len = match ValueType::from_msl_name(name) {
+            ValueType::Vector { len, .. } => len,
+            _ => return Ok(()),
+        };
+        if args.len() == 1 {
+            return Ok(());
+        }
+
+        let components: usize = args
+            .iter()
+            .map(|arg| match self.infer_type(arg) {
+                Some(ValueType::Vector { len, .. }) => len,
+                _ => 1,
+            })
+            .sum();
+        if components != len {
+            return Err(CodegenError::expression_error(
+                format!("{} expects {} components, got {}", name, len, components),
+                span.clone(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn infer_builtin_type(&self, name: &str, args: &[Expr]) -> Option<ValueType> {
+        if MSL_CONSTRUCTORS.contains(&name) {
+            return Some(ValueType::from_msl_name(name));
+        }
+        match name {
+            "gather" => self.infer_type(args.first()?)?.element_type(),
+            "true_div" => Some(ValueType::Scalar(ScalarType::Float)),
+            "simd_ballot" => Some(ValueType::Scalar(ScalarType::ULong)),
+            "simd_any" | "simd_all" => Some(ValueType::Scalar(ScalarType::Bool)),
+            "transpose" => match self.infer_type(args.first()?)? {
+                ValueType::Matrix { elem, cols, rows } => Some(ValueType::Matrix {
+                    elem,
+                    cols: rows,
+                    rows: cols,
+                }),
+                _ => None,
+            },
+            "dot" => match self.infer_type(args.first()?)? {
+                ValueType::Vector { elem, .. } => Some(ValueType::Scalar(elem)),
+                _ => None,
+            },
+            "cross" => match self.infer_type(args.first()?)? {
+                vector @ ValueType::Vector { .. } => Some(vector),
+                _ => None,
+            },
+            _ => match Intrinsic::from_name(name)? {
+                Intrinsic::IsNan | Intrinsic::IsInf => Some(ValueType::Scalar(ScalarType::Bool)),
+                Intrinsic::Length | Intrinsic::Distance => {
+                    Some(ValueType::Scalar(self.infer_elem_type(args.first()?)?))
+                }
+                _ => self.infer_operand_type(&args.iter().collect::<Vec<_>>()),
+            },
+        }
+    }
+
+    /// Emits `scatter(buf, idx, val)` as a store guarded by the buffer extent
+    /// when it is known from the declared shape. The guarded form binds the
+    /// index to a temporary first so it is evaluated once, and also rejects
+    /// negative signed indices.
+    pub(crate) fn generate_scatter(
+        &mut self,
+        args: &[Expr],
+        span: Range<usize>,
+    ) -> Result<Vec<String>> {
+        Self::expect_arity("scatter", args, 3, &span)?;
+        let buf_ty = self.expect_buffer("scatter", &args[0])?;
+        self.expect_integer_index("scatter", &args[1])?;
+
+        let buf_code = self.generate(&args[0])?;
+        let idx_code = self.generate(&args[1])?;
+        let val_code = self.generate(&args[2])?;
+
+        let extent = match &buf_ty {
+            Some(ValueType::Tensor { shape, .. }) if !shape.is_empty() => Some(shape.join(" * ")),
+            Some(ValueType::Array { size: Some(n), .. }) => Some(n.to_string()),
+            _ => None,
+        };
+
+        let Some(extent) = extent else {
+            return Ok(vec![format!("{}[{}] = {};", buf_code, idx_code, val_code)]);
+        };
+
+        let unsigned = matches!(
+            self.infer_type(&args[1]),
+            Some(ValueType::Scalar(ScalarType::UInt | ScalarType::ULong))
+        );
+        let idx = SCATTER_INDEX;
+        let guard = if unsigned {
+            format!("{} < {}", idx, extent)
+        } else {
+            format!("{} >= 0 && {} < {}", idx, idx, extent)
+        };
+        Ok(vec![
+            format!("const auto {} = {};", idx, idx_code),
+            format!("if ({}) {}[{}] = {};", guard, buf_code, idx, val_code),
+        ])
+    }
+
+    /// Emits `async_copy(dest, src, count)` as a simdgroup async copy of
+    /// `count` elements from a device buffer into a threadgroup buffer,
+    /// followed by the wait on its event. Either buffer may be offset by
+    /// indexing it, e.g. `async_copy(tile, A[row * N], N)`.
+    pub(crate) fn generate_async_copy(
+        &mut self,
+        args: &[Expr],
+        span: Range<usize>,
+    ) -> Result<Vec<String>> {
+        Self::expect_arity("async_copy", args, 3, &span)?;
+        let dest = &args[0];
+        let src = &args[1];
+
+        match buffer_root(dest) {
+            Some(name) if self.is_threadgroup_buffer(name) => {}
+            _ => {
+                return Err(CodegenError::invalid_memory_config(
+                    "async_copy destination must be a shared_memory buffer",
+                    dest.span(),
+                ))
+            }
+        }
+        let is_device = match buffer_root(src) {
+            Some(name) if !self.is_threadgroup_buffer(name) => {
+                let root = Expr::Ident(name, src.span());
+                self.infer_type(&root).is_some_and(|ty| ty.is_buffer())
+            }
+            _ => false,
+        };
+        if !is_device {
+            return Err(CodegenError::invalid_memory_config(
+                "async_copy source must be a device buffer parameter",
+                src.span(),
+            ));
+        }
+        self.expect_integer_index("async_copy", &args[2])?;
+
+        let dest_code = self.generate_buffer_address(dest)?;
+        let src_code = self.generate_buffer_address(src)?;
+        let count_code = self.generate(&args[2])?;
+        Ok(vec![
+            "simdgroup_event copy_event;".to_string(),
+            format!(
+                "copy_event.async_copy({}, {}, {});",
+                dest_code, src_code, count_code
+            ),
+            "simdgroup_event::wait(1, &copy_event);".to_string(),
+        ])
+    }
+
+    /// A buffer by name, or the address of the element an index selects.
+    fn generate_buffer_address(&mut self, buf: &Expr) -> Result<String> {
+        let code = self.generate(buf)?;
+        match buf {
+            Expr::Index { .. } => Ok(format!("&{}", code)),
+            _ => Ok(code),
+        }
+    }
+
+    pub(crate) fn is_async_copy_call(expr: &Expr) -> bool {
+        matches!(expr, Expr::Call { func, .. } if matches!(func.as_ref(), Expr::Ident("async_copy", _)))
+    }
+
+    pub(crate) fn is_scatter_call(expr: &Expr) -> bool {
+        matches!(expr, Expr::Call { func, .. } if matches!(func.as_ref(), Expr::Ident("scatter", _)))
+    }
+
+    fn expect_arity(name: &str, args: &[Expr], arity: usize, span: &Range<usize>) -> Result<()> {
+        if args.len() != arity {
+            return Err(CodegenError::expression_error(
+                format!("{} expects {} arguments, got {}", name, arity, args.len()),
+                span.clone(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn expect_buffer(&self, name: &str, buf: &Expr) -> Result<Option<ValueType>> {
+        match self.infer_type(buf) {
+            Some(ty) if ty.is_buffer() => Ok(Some(ty)),
+            Some(ty) => Err(CodegenError::expression_error(
+                format!(
+                    "{} expects a tensor or array buffer, found '{}'",
+                    name,
+                    ty.msl_name()
+                ),
+                buf.span(),
+            )),
+            None => match buf {
+                Expr::Ident(..) | Expr::Member { .. } => Ok(None),
+                _ => Err(CodegenError::expression_error(
+                    format!("{} expects a tensor or array buffer", name),
+                    buf.span(),
+                )),
+            },
+        }
+    }
+
+    fn expect_matrix(&self, name: &str, arg: &Expr) -> Result<()> {
+        match self.infer_type(arg) {
+            Some(ValueType::Matrix { .. }) => Ok(()),
+            Some(ty) if ty.is_buffer() => Err(CodegenError::unsupported_feature(
+                format!("{} of a buffer", name),
+                arg.span(),
+                Some("transpose tensors on the host before dispatch".to_string()),
+            )),
+            Some(ty) => Err(CodegenError::expression_error(
+                format!("{} expects a matrix, found '{}'", name, ty.msl_name()),
+                arg.span(),
+            )),
+            None => Err(CodegenError::expression_error(
+                format!(
+                    "{} expects a matrix, found an operand of unknown type",
+                    name
+                ),
+                arg.span(),
+            )),
+        }
+    }
+
+    fn expect_vector_pair(
+        &self,
+        name: &str,
+        left: &Expr,
+        right: &Expr,
+        required_len: Option<usize>,
+        span: &Range<usize>,
+    ) -> Result<()> {
+        let mut lens = Vec::new();
+        for arg in [left, right] {
+            match self.infer_type(arg) {
+                Some(ValueType::Vector { len, .. }) => lens.push(len),
+                Some(ty) => {
+                    return Err(CodegenError::expression_error(
+                        format!(
+                            "{} expects vector arguments, found '{}'",
+                            name,
+                            ty.msl_name()
+                        ),
+                        arg.span(),
+                    ))
+                }
+                None => {}
+            }
+        }
+
+        if let [left_len, right_len] = lens[..] {
+            if left_len != right_len {
+                return Err(CodegenError::expression_error(
+                    format!(
+                        "{} expects vectors of the same length, got {} and {}",
+                        name, left_len, right_len
+                    ),
+                    span.clone(),
+                ));
+            }
+        }
+
+        if let Some(required) = required_len {
+            if let Some(len) = lens.iter().find(|len| **len != required) {
+                return Err(CodegenError::expression_error(
+                    format!(
+                        "{} expects {}-component vectors, got {}",
+                        name, required, len
+                    ),
+                    span.clone(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn expect_condition(&self, name: &str, cond: &Expr) -> Result<()> {
+        match self.infer_type(cond) {
+            Some(ty) if ty != ValueType::Scalar(ScalarType::Bool) => {
+                Err(CodegenError::expression_error(
+                    format!(
+                        "{} expects a bool condition, found '{}'",
+                        name,
+                        ty.msl_name()
+                    ),
+                    cond.span(),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn expect_integer_index(&self, name: &str, idx: &Expr) -> Result<()> {
+        match self.infer_type(idx) {
+            Some(ty) if !ty.is_integer() => Err(CodegenError::expression_error(
+                format!(
+                    "{} index must be an integer, found '{}'",
+                    name,
+                    ty.msl_name()
+                ),
+                idx.span(),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Temporary holding a `scatter` index while it is bounds-checked.
+const SCATTER_INDEX: &str = "flare_scatter_idx";
+
+/// Name of the buffer `expr` refers to, either directly or through an index.
+fn buffer_root<'src>(expr: &Expr<'src>) -> Option<&'src str> {
+    match expr {
+        Expr::Ident(name, _) => Some(name),
+        Expr::Index { object, .. } => match object.as_ref() {
+            Expr::Ident(name, _) => Some(name),
+            _ => None,
+        },
+        _ => None,
+    }
+}
@@ -1,4 +1,5 @@
 use core::{fmt, panic::PanicMessage};
+use flare_ir::mir::error::LoweringError;
 use std::ops::Range;
 use thiserror::Error;
 
@@ -21,6 +22,9 @@ pub enum CodegenError {
     #[error("invalid kernel configuration at {span:?}: {message}")]
     InvalidKernelConfig { message: String, span: Range<usize> },
 
+    #[error("invalid kernel at {span:?}: {message}")]
+    InvalidKernel { message: String, span: Range<usize> },
+
     #[error("invalid schedule directive at {span:?}: {message}")]
     InvalidScheduleDirective { message: String, span: Range<usize> },
 
@@ -57,6 +61,7 @@ impl CodegenError {
             CodegenError::UnsupportedType { span, .. }
             | CodegenError::UnsupportedFeature { span, .. }
             | CodegenError::InvalidKernelConfig { span, .. }
+            | CodegenError::InvalidKernel { span, .. }
             | CodegenError::InvalidScheduleDirective { span, .. }
             | CodegenError::InvalidMemoryConfig { span, .. }
             | CodegenError::ExpressionError { span, .. }
@@ -94,6 +99,13 @@ impl CodegenError {
         }
     }
 
+    pub fn invalid_kernel(message: impl Into<String>, span: Range<usize>) -> Self {
+        CodegenError::InvalidKernel {
+            message: message.into(),
+            span,
+        }
+    }
+
     pub fn invalid_schedule_directive(message: impl Into<String>, span: Range<usize>) -> Self {
         CodegenError::InvalidScheduleDirective {
             message: message.into(),
@@ -160,3 +172,14 @@ impl From<fmt::Error> for CodegenError {
         CodegenError::fmt_error(err.to_string())
     }
 }
+
+impl From<LoweringError> for CodegenError {
+    fn from(err: LoweringError) -> Self {
+        match err {
+            LoweringError::InvalidKernel { message, span } => {
+                CodegenError::invalid_kernel(message, span)
+            }
+            LoweringError::FormatError { message } => CodegenError::fmt_error(message),
+        }
+    }
+}
@@ -1,31 +1,472 @@
+use crate::builtins::{msl_identifier, MSL_CONSTRUCTORS};
 use crate::error::{CodegenError, Result};
-use crate::types::TypeConverter;
-use flare::ast::{BinOp, Expr, UnOp};
+use crate::link::{method_name, MethodTable};
+use crate::typeck::{is_narrowing, promote, ScalarType, SymbolTable, ValueType};
+use crate::types::{MetalType, TypeConverter};
+use flare::ast::{BinOp, Expr, Stmt, Type, UnOp};
+use flare::Diagnostic;
+use flare_codegen_common::Intrinsic;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
 
 pub struct ExprGenerator {
     indent_level: usize,
+
+    symbols: SymbolTable,
+
+    /// Identifiers emitted under another name, e.g. packed uniforms read
+    /// through `u.name`.
+    renames: HashMap<String, String>,
+
+    /// Threadgroup size known at compile time, substituted for `block_dim`.
+    block_dims: Option<(u32, u32, u32)>,
+
+    /// Names declared with `type`, emitted as MSL typedefs.
+    type_aliases: HashSet<String>,
+
+    /// `shared_memory` buffers of the kernel being generated, which live in
+    /// the threadgroup address space, with the code of their dimensions.
+    threadgroup_buffers: HashMap<String, Vec<String>>,
+
+    /// `impl` methods, resolved when a call's receiver has their type.
+    methods: MethodTable,
+
+    /// Module-prefixed symbols of user functions, from
+    /// `link::function_symbols`; empty when functions keep their own names.
+    function_symbols: HashMap<String, String>,
+
+    diagnostics: Vec<Diagnostic>,
 }
 
 impl ExprGenerator {
     pub fn new() -> Self {
-        Self { indent_level: 0 }
+        Self {
+            indent_level: 0,
+            symbols: SymbolTable::new(),
+            renames: HashMap::new(),
+            block_dims: None,
+            type_aliases: HashSet::new(),
+            threadgroup_buffers: HashMap::new(),
+            methods: MethodTable::new(),
+            function_symbols: HashMap::new(),
+            diagnostics: Vec::new(),
+        }
     }
 
     pub fn with_indent(indent_level: usize) -> Self {
-        Self { indent_level }
+        Self {
+            indent_level,
+            symbols: SymbolTable::new(),
+            renames: HashMap::new(),
+            block_dims: None,
+            type_aliases: HashSet::new(),
+            threadgroup_buffers: HashMap::new(),
+            methods: MethodTable::new(),
+            function_symbols: HashMap::new(),
+            diagnostics: Vec::new(),
+        }
     }
 
-    pub fn generate(&mut self, expr: &Expr) -> Result<String> {
+    pub fn set_indent(&mut self, level: usize) {
+        self.indent_level = level;
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    pub fn symbols_mut(&mut self) -> &mut SymbolTable {
+        &mut self.symbols
+    }
+
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.diagnostics)
+    }
+
+    pub fn set_renames(&mut self, renames: HashMap<String, String>) {
+        self.renames = renames;
+    }
+
+    pub fn set_block_dims(&mut self, dims: Option<(u32, u32, u32)>) {
+        self.block_dims = dims;
+    }
+
+    pub fn set_type_aliases(&mut self, aliases: HashSet<String>) {
+        self.type_aliases = aliases;
+    }
+
+    pub fn set_threadgroup_buffers(&mut self, buffers: HashMap<String, Vec<String>>) {
+        self.threadgroup_buffers = buffers;
+    }
+
+    pub fn set_methods(&mut self, methods: MethodTable) {
+        self.methods = methods;
+    }
+
+    pub fn set_function_symbols(&mut self, symbols: HashMap<String, String>) {
+        self.function_symbols = symbols;
+    }
+
+    /// Name user function `name` is emitted and called as, and whether it
+    /// was module-prefixed.
+    pub(crate) fn function_symbol(&self, name: &str) -> (String, bool) {
+        match self.function_symbols.get(name) {
+            Some(symbol) => (symbol.clone(), true),
+            None => (name.to_string(), false),
+        }
+    }
+
+    /// Type named by `object` when it has an `impl` method called `method`.
+    fn method_receiver(&self, object: &Expr, method: &str) -> Option<String> {
+        match self.infer_type(object)? {
+            ValueType::Named(ty)
+                if self.methods.contains_key(&(ty.clone(), method.to_string())) =>
+            {
+                Some(ty)
+            }
+            _ => None,
+        }
+    }
+
+    pub(crate) fn is_threadgroup_buffer(&self, name: &str) -> bool {
+        self.threadgroup_buffers.contains_key(name)
+    }
+
+    /// Row-major offset of `indices` into the threadgroup buffer `name`,
+    /// which is declared flat.
+    pub(crate) fn shared_offset(
+        &mut self,
+        name: &str,
+        indices: &[Expr],
+        span: Range<usize>,
+    ) -> Result<String> {
+        let dims = self
+            .threadgroup_buffers
+            .get(name)
+            .cloned()
+            .unwrap_or_default();
+        if dims.len() != indices.len() {
+            return Err(CodegenError::invalid_memory_config(
+                format!(
+                    "load_shared into '{}' needs {} indices, got {}",
+                    name,
+                    dims.len(),
+                    indices.len()
+                ),
+                span,
+            ));
+        }
+        self.row_major_offset(indices, &dims)
+    }
+
+    /// `A[row, col]` on a row-major tensor as one flattened load; other
+    /// expressions generate as usual.
+    pub(crate) fn generate_flat_load(&mut self, expr: &Expr) -> Result<String> {
+        if let Expr::Index {
+            object, indices, ..
+        } = expr
+        {
+            if let Some(ValueType::Tensor {
+                shape,
+                strides: None,
+                ..
+            }) = self.infer_type(object)
+            {
+                if indices.len() > 1 && shape.len() == indices.len() {
+                    let object_code = self.generate(object)?;
+                    let offset = self.row_major_offset(indices, &shape)?;
+                    return Ok(format!("{}[{}]", object_code, offset));
+                }
+            }
+        }
+        self.generate(expr)
+    }
+
+    /// `((i0 * d1) + i1) * d2 + i2 ...`, leaving the outermost dimension out.
+    fn row_major_offset(&mut self, indices: &[Expr], dims: &[String]) -> Result<String> {
+        let mut offset = String::new();
+        for (i, (index, dim)) in indices.iter().zip(dims).enumerate() {
+            let index_code = self.generate(index)?;
+            offset = if i == 0 {
+                index_code
+            } else {
+                format!("({} * {}) + {}", offset, dim, index_code)
+            };
+        }
+        Ok(offset)
+    }
+
+    pub fn convert_type(&self, ty: &Type, span: Range<usize>) -> Result<MetalType> {
+        TypeConverter::convert_with(ty, span, &self.type_aliases)
+    }
+
+    /// Rejects storing `value` into a slot of type `target` when that would
+    /// implicitly narrow a float to an integer.
+    pub fn check_assignment(&self, target: &ValueType, value: &Expr) -> Result<()> {
+        match self.infer_type(value) {
+            Some(value_ty) if is_narrowing(&value_ty, target) => {
+                Err(CodegenError::expression_error(
+                    format!(
+                        "cannot implicitly convert '{}' to '{}'; use an explicit `as` cast",
+                        value_ty.msl_name(),
+                        target.msl_name()
+                    ),
+                    value.span(),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn infer_type(&self, expr: &Expr) -> Option<ValueType> {
         match expr {
-            Expr::IntLiteral(val, _) => Ok(val.to_string()),
+            Expr::IntLiteral(..) => Some(ValueType::Scalar(ScalarType::Int)),
+            Expr::UIntLiteral(val, _) => Some(ValueType::Scalar(Self::uint_literal_type(*val))),
+            Expr::FloatLiteral(..) => Some(ValueType::Scalar(ScalarType::Float)),
+            Expr::BoolLiteral(..) => Some(ValueType::Scalar(ScalarType::Bool)),
+            Expr::Ident(name, _) => self.symbols.lookup(name).cloned(),
+            Expr::Binary {
+                left, op, right, ..
+            } => match op {
+                BinOp::Equal
+                | BinOp::NotEqual
+                | BinOp::Less
+                | BinOp::Greater
+                | BinOp::LessEqual
+                | BinOp::GreaterEqual
+                | BinOp::And
+                | BinOp::Or => Some(ValueType::Scalar(ScalarType::Bool)),
+                _ => self.infer_operand_type(&[left, right]),
+            },
+            Expr::Unary { op, expr, .. } => match op {
+                UnOp::Neg => self.infer_type(expr),
+                UnOp::Not => Some(ValueType::Scalar(ScalarType::Bool)),
+                UnOp::AddrOf => Some(ValueType::Pointer(Box::new(self.infer_type(expr)?))),
+                UnOp::Deref => match self.infer_type(expr)? {
+                    ValueType::Pointer(inner) => Some(*inner),
+                    _ => None,
+                },
+            },
+            Expr::Call { func, args, .. } => match func.as_ref() {
+                Expr::Ident(name, _) => self.infer_builtin_type(name, args),
+                Expr::Member { object, field, .. } => {
+                    let ty = self.method_receiver(object, field)?;
+                    self.methods.get(&(ty, field.to_string()))?.clone()
+                }
+                _ => None,
+            },
+            Expr::Index { object, .. } => self.infer_type(object)?.element_type(),
+            Expr::If {
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                let then_branch = Self::branch_value(then_branch);
+                else_branch
+                    .as_deref()
+                    .and_then(|else_expr| {
+                        self.infer_operand_type(&[then_branch, Self::branch_value(else_expr)])
+                    })
+                    .or_else(|| self.infer_type(then_branch))
+            }
+            Expr::Assign { value, .. } => self.infer_type(value),
+            Expr::CompoundAssign { target, .. } => self.infer_type(target),
+            Expr::Cast { target_type, .. } | Expr::Bitcast { target_type, .. } => {
+                ValueType::from_ast(target_type)
+            }
+            Expr::SizeOf { .. } => Some(ValueType::Scalar(ScalarType::Int)),
+            Expr::ThreadIdx { dim, .. }
+            | Expr::BlockIdx { dim, .. }
+            | Expr::BlockDim { dim, .. } => match dim {
+                Some(_) => Some(ValueType::Scalar(ScalarType::UInt)),
+                None => Some(ValueType::Vector {
+                    elem: ScalarType::UInt,
+                    len: 3,
+                }),
+            },
+            _ => None,
+        }
+    }
+
+    pub(crate) fn infer_elem_type(&self, expr: &Expr) -> Option<ScalarType> {
+        match self.infer_type(expr)? {
+            ValueType::Scalar(elem) | ValueType::Vector { elem, .. } => Some(elem),
+            _ => None,
+        }
+    }
+
+    /// Promoted type of a group of operands. Float literals adopt the
+    /// precision of the other operands, so `h * 2.0` stays `half`.
+    pub(crate) fn infer_operand_type(&self, operands: &[&Expr]) -> Option<ValueType> {
+        let mut result: Option<ValueType> = None;
+        let mut has_float_literal = false;
+
+        for operand in operands {
+            if Self::is_float_literal(operand) {
+                has_float_literal = true;
+                continue;
+            }
+            let ty = self.infer_type(operand)?;
+            result = Some(match result {
+                Some(acc) => promote(&acc, &ty)?,
+                None => ty,
+            });
+        }
+
+        let float = ValueType::Scalar(ScalarType::Float);
+        match result {
+            Some(ty) if has_float_literal && ty.is_integer() => promote(&ty, &float),
+            Some(ty) => Some(ty),
+            None if has_float_literal => Some(float),
+            None => None,
+        }
+    }
+
+    fn is_float_literal(expr: &Expr) -> bool {
+        match expr {
+            Expr::FloatLiteral(..) => true,
+            Expr::Unary {
+                op: UnOp::Neg,
+                expr,
+                ..
+            } => Self::is_float_literal(expr),
+            _ => false,
+        }
+    }
+
+    /// Generates `expr` for a slot of type `target`, so float literals
+    /// stored into `half` values are spelled as `half` literals.
+    pub fn generate_for_type(&mut self, expr: &Expr, target: Option<&ValueType>) -> Result<String> {
+        if let Some(ValueType::Scalar(float_ty)) =
+            target.filter(|ty| matches!(ty, ValueType::Scalar(s) if s.is_float()))
+        {
+            self.note_integer_division(expr, *float_ty);
+        }
+
+        let literal_ty = match target {
+            Some(ValueType::Scalar(elem)) | Some(ValueType::Vector { elem, .. }) => Some(*elem),
+            _ => None,
+        };
+        self.generate_as(expr, literal_ty)
+    }
 
-            Expr::FloatLiteral(val, _) => {
-                if val.fract() == 0.0 && !val.is_infinite() && !val.is_nan() {
-                    Ok(format!("{}.0f", val))
-                } else {
-                    Ok(format!("{}f", val))
+    /// Generates `expr`, emitting float literals with the suffix of
+    /// `literal_ty` when it is `half`, and integer literals range-checked
+    /// against `literal_ty` when it is an integer type.
+    pub(crate) fn generate_as(
+        &mut self,
+        expr: &Expr,
+        literal_ty: Option<ScalarType>,
+    ) -> Result<String> {
+        if let Some(int_ty) = literal_ty.filter(|ty| ty.is_integer()) {
+            return match expr {
+                Expr::IntLiteral(val, span) => Self::int_literal(*val, int_ty, span),
+                Expr::UIntLiteral(val, _) if int_ty == ScalarType::ULong => {
+                    Ok(format!("{}ul", val))
                 }
+                Expr::Unary {
+                    op: UnOp::Neg,
+                    expr: inner,
+                    span,
+                } => match inner.as_ref() {
+                    Expr::IntLiteral(val, _) => {
+                        Self::check_int_range(-val, int_ty, span)?;
+                        Ok(format!("(-{}{})", val, Self::int_suffix(int_ty)))
+                    }
+                    _ => self.generate(expr),
+                },
+                _ => self.generate(expr),
+            };
+        }
+        if literal_ty != Some(ScalarType::Half) {
+            return self.generate(expr);
+        }
+
+        match expr {
+            Expr::FloatLiteral(val, _) => Ok(Self::float_literal(*val, "h")),
+            Expr::Unary {
+                op: UnOp::Neg,
+                expr: inner,
+                ..
+            } if Self::is_float_literal(inner) => {
+                Ok(format!("(-{})", self.generate_as(inner, literal_ty)?))
             }
+            _ => self.generate(expr),
+        }
+    }
+
+    /// Emits an integer literal for a value of type `ty`, with an `L` suffix
+    /// in `long` contexts so it is not narrowed through `int`.
+    fn int_literal(val: i64, ty: ScalarType, span: &Range<usize>) -> Result<String> {
+        Self::check_int_range(val, ty, span)?;
+        Ok(format!("{}{}", val, Self::int_suffix(ty)))
+    }
+
+    /// `uint` for unsigned literals that fit in 32 bits, `ulong` otherwise.
+    fn uint_literal_type(val: u64) -> ScalarType {
+        if u32::try_from(val).is_ok() {
+            ScalarType::UInt
+        } else {
+            ScalarType::ULong
+        }
+    }
+
+    fn int_suffix(ty: ScalarType) -> &'static str {
+        if ty == ScalarType::Long {
+            "L"
+        } else {
+            ""
+        }
+    }
+
+    fn check_int_range(value: i64, ty: ScalarType, span: &Range<usize>) -> Result<()> {
+        let fits = match ty {
+            ScalarType::Int => i32::try_from(value).is_ok(),
+            ScalarType::UInt => u32::try_from(value).is_ok(),
+            ScalarType::ULong => value >= 0,
+            _ => true,
+        };
+        if value < 0 && matches!(ty, ScalarType::UInt | ScalarType::ULong) {
+            return Err(CodegenError::expression_error(
+                format!(
+                    "negative literal {} cannot be stored in unsigned '{}'",
+                    value,
+                    ty.msl_name()
+                ),
+                span.clone(),
+            ));
+        }
+        if !fits {
+            return Err(CodegenError::expression_error(
+                format!(
+                    "integer literal {} does not fit in '{}'",
+                    value,
+                    ty.msl_name()
+                ),
+                span.clone(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn float_literal(val: f64, suffix: &str) -> String {
+        if val.fract() == 0.0 && !val.is_infinite() && !val.is_nan() {
+            format!("{}.0{}", val, suffix)
+        } else {
+            format!("{}{}", val, suffix)
+        }
+    }
+
+    pub fn generate(&mut self, expr: &Expr) -> Result<String> {
+        match expr {
+            Expr::IntLiteral(val, _) => Ok(val.to_string()),
+
+            Expr::UIntLiteral(val, _) => match Self::uint_literal_type(*val) {
+                ScalarType::UInt => Ok(format!("{}u", val)),
+                _ => Ok(format!("{}ul", val)),
+            },
+
+            Expr::FloatLiteral(val, _) => Ok(Self::float_literal(*val, "f")),
 
             Expr::StringLiteral(_val, span) => Err(CodegenError::unsupported_feature(
                 "string literals",
@@ -35,7 +476,17 @@ impl ExprGenerator {
 
             Expr::BoolLiteral(val, _) => Ok(val.to_string()),
 
-            Expr::Ident(name, _) => Ok((*name).to_string()),
+            Expr::Ident("_", span) => Err(CodegenError::invalid_identifier(
+                "_",
+                "`_` discards a value and cannot be read",
+                span.clone(),
+            )),
+
+            Expr::Ident(name, _) => Ok(self
+                .renames
+                .get(*name)
+                .cloned()
+                .unwrap_or_else(|| msl_identifier(name).into_owned())),
 
             Expr::Binary {
                 left,
@@ -60,11 +511,7 @@ impl ExprGenerator {
                 span,
             } => self.generate_index(object, indices, span.clone()),
 
-            Expr::Range {
-                start: _,
-                end: _,
-                span,
-            } => Err(CodegenError::unsupported_feature(
+            Expr::Range { span, .. } => Err(CodegenError::unsupported_feature(
                 "range expressions",
                 span.clone(),
                 Some("metal does not support range syntax".to_string()),
@@ -99,14 +546,21 @@ impl ExprGenerator {
             )),
 
             Expr::Assign { target, value, .. } => {
+                let target_ty = self.infer_type(target);
+                if let Some(target_ty) = &target_ty {
+                    self.check_assignment(target_ty, value)?;
+                }
                 let target_code = self.generate(target)?;
-                let value_code = self.generate(value)?;
+                let value_code = self.generate_for_type(value, target_ty.as_ref())?;
                 Ok(format!("{} = {}", target_code, value_code))
             }
 
             Expr::CompoundAssign {
                 target, op, value, ..
             } => {
+                if let Some(target_ty) = self.infer_type(target) {
+                    self.check_assignment(&target_ty, value)?;
+                }
                 let target_code = self.generate(target)?;
                 let value_code = self.generate(value)?;
                 let op_str = Self::binop_to_string(*op);
@@ -119,10 +573,65 @@ impl ExprGenerator {
                 span,
             } => {
                 let expr_code = self.generate(expr)?;
-                let type_code = TypeConverter::convert(target_type, span.clone())?;
+                let type_code = self.convert_type(target_type, span.clone())?;
+                // `device float4*(p)` does not parse as a functional cast
+                if matches!(target_type, Type::Ptr(_)) {
+                    return Ok(format!(
+                        "reinterpret_cast<{}>({})",
+                        type_code.as_str(),
+                        expr_code
+                    ));
+                }
                 Ok(format!("{}({})", type_code.as_str(), expr_code))
             }
 
+            Expr::Bitcast {
+                expr,
+                target_type,
+                span,
+            } => {
+                let type_code = self.convert_type(target_type, span.clone())?;
+                let from = self.infer_type(expr);
+                let to = ValueType::from_ast(target_type);
+                let from_size = from.as_ref().and_then(TypeConverter::value_size);
+                let to_size = to.as_ref().and_then(TypeConverter::value_size);
+                match (from_size, to_size) {
+                    (Some(a), Some(b)) if a == b => {}
+                    (Some(a), Some(b)) => {
+                        return Err(CodegenError::expression_error(
+                            format!(
+                                "as_type cannot reinterpret '{}' ({} bytes) as '{}' ({} bytes); \
+                                 the sizes must match",
+                                from.map(|ty| ty.msl_name()).unwrap_or_default(),
+                                a,
+                                type_code.as_str(),
+                                b
+                            ),
+                            span.clone(),
+                        ));
+                    }
+                    _ => {
+                        return Err(CodegenError::expression_error(
+                            "as_type needs a scalar, vector or matrix operand and target",
+                            span.clone(),
+                        ));
+                    }
+                }
+                let expr_code = self.generate(expr)?;
+                Ok(format!("as_type<{}>({})", type_code.as_str(), expr_code))
+            }
+
+            Expr::SizeOf { ty, span } => {
+                let size = self.convert_type(ty, span.clone())?.size_bytes;
+                match size {
+                    Some(size) => Ok(size.to_string()),
+                    None => Err(CodegenError::expression_error(
+                        "sizeof needs a type whose size is known at compile time",
+                        span.clone(),
+                    )),
+                }
+            }
+
             Expr::ThreadIdx { dim, span } => self.generate_thread_idx(dim, span.clone()),
 
             Expr::BlockIdx { dim, span } => self.generate_block_idx(dim, span.clone()),
@@ -136,37 +645,201 @@ impl ExprGenerator {
         left: &Expr,
         op: BinOp,
         right: &Expr,
-        _span: std::ops::Range<usize>,
+        span: std::ops::Range<usize>,
     ) -> Result<String> {
-        let left_code = self.generate(left)?;
-        let right_code = self.generate(right)?;
+        let arithmetic = matches!(
+            op,
+            BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Mod
+        );
+        if arithmetic {
+            // statements assigning whole-tensor arithmetic lower it to one
+            // element per thread before it gets here; anything else would be
+            // pointer arithmetic
+            if let Some(ValueType::Tensor { .. }) = self.infer_operand_type(&[left, right]) {
+                return Err(CodegenError::expression_error(
+                    "arithmetic on whole tensors is only supported as the value of an assignment to a tensor, as in `B = A * alpha`",
+                    span,
+                ));
+            }
+            self.note_promotion(left, right, span.clone());
+        }
+
+        let half_operands = [left, right].iter().any(|operand| {
+            !Self::is_float_literal(operand)
+                && self.infer_elem_type(operand) == Some(ScalarType::Half)
+        });
+        let literal_ty = half_operands.then_some(ScalarType::Half);
+
+        let mut left_code = self.generate_as(left, literal_ty)?;
+        let mut right_code = self.generate_as(right, literal_ty)?;
+        if arithmetic {
+            left_code = self.bool_as_number(left, right, left_code, literal_ty, &span)?;
+            right_code = self.bool_as_number(right, left, right_code, literal_ty, &span)?;
+        }
         let op_str = Self::binop_to_string(op);
 
         Ok(format!("({} {} {})", left_code, op_str, right_code))
     }
 
+    /// Converts a `bool` arithmetic operand to the type of the other operand
+    /// rather than leaving it to MSL's implicit conversion: `float(mask)`
+    /// against a float, `(mask ? 1 : 0)` against an integer. Two `bool`
+    /// operands have no numeric type to convert to and are rejected.
+    fn bool_as_number(
+        &self,
+        operand: &Expr,
+        other: &Expr,
+        code: String,
+        literal_ty: Option<ScalarType>,
+        span: &std::ops::Range<usize>,
+    ) -> Result<String> {
+        let bool_ty = ValueType::Scalar(ScalarType::Bool);
+        if self.infer_type(operand).as_ref() != Some(&bool_ty) {
+            return Ok(code);
+        }
+        let other_elem = if Self::is_float_literal(other) {
+            Some(literal_ty.unwrap_or(ScalarType::Float))
+        } else {
+            self.infer_elem_type(other)
+        };
+        match other_elem {
+            Some(elem) if elem.is_float() => Ok(format!("{}({})", elem.msl_name(), code)),
+            Some(elem) if elem.is_integer() => Ok(format!("({} ? 1 : 0)", code)),
+            Some(ScalarType::Bool) => Err(CodegenError::expression_error(
+                "arithmetic on two 'bool' operands is ambiguous; convert one with `as i32` or `as f32`",
+                span.clone(),
+            )),
+            _ => Ok(code),
+        }
+    }
+
+    /// Records a note when mixing an integer and a float operand promotes
+    /// the integer side, mirroring the conversion MSL performs implicitly.
+    fn note_promotion(&mut self, left: &Expr, right: &Expr, span: std::ops::Range<usize>) {
+        let (Some(ValueType::Scalar(l)), Some(ValueType::Scalar(r))) =
+            (self.infer_type(left), self.infer_type(right))
+        else {
+            return;
+        };
+
+        let (int_ty, float_ty) = match (l.is_integer(), r.is_integer()) {
+            (true, false) if r.is_float() => (l, r),
+            (false, true) if l.is_float() => (r, l),
+            _ => return,
+        };
+
+        self.diagnostics.push(Diagnostic::note(
+            format!(
+                "'{}' operand promoted to '{}'",
+                int_ty.msl_name(),
+                float_ty.msl_name()
+            ),
+            span,
+        ));
+    }
+
+    /// Records a note when an integer division feeds a float destination,
+    /// since the quotient is truncated before it is converted.
+    fn note_integer_division(&mut self, expr: &Expr, float_ty: ScalarType) {
+        let Expr::Binary {
+            left,
+            op: BinOp::Div,
+            right,
+            span,
+        } = expr
+        else {
+            return;
+        };
+        let integer = |operand: &Expr| self.infer_type(operand).is_some_and(|ty| ty.is_integer());
+        if integer(left) && integer(right) {
+            self.diagnostics.push(Diagnostic::note(
+                format!(
+                    "integer division truncates before conversion to '{}'; use true_div(a, b) for float division",
+                    float_ty.msl_name()
+                ),
+                span.clone(),
+            ));
+        }
+    }
+
     fn generate_unary(
         &mut self,
         op: UnOp,
         expr: &Expr,
-        _span: std::ops::Range<usize>,
+        span: std::ops::Range<usize>,
     ) -> Result<String> {
-        let expr_code = self.generate(expr)?;
-        let op_str = match op {
-            UnOp::Neg => "-",
-            UnOp::Not => "!",
-        };
+        if op == UnOp::AddrOf
+            && !matches!(
+                expr,
+                Expr::Ident(..) | Expr::Index { .. } | Expr::Member { .. }
+            )
+        {
+            return Err(CodegenError::expression_error(
+                "cannot take the address of a temporary; `&` needs an identifier, index, or member access",
+                span,
+            ));
+        }
 
-        Ok(format!("({}{})", op_str, expr_code))
+        if op == UnOp::Deref {
+            match self.infer_type(expr) {
+                Some(ValueType::Pointer(_)) | None => {}
+                Some(ty) => {
+                    return Err(CodegenError::expression_error(
+                        format!("cannot dereference a value of type '{}'", ty.msl_name()),
+                        span,
+                    ))
+                }
+            }
+        }
+
+        let expr_code = self.generate(expr)?;
+        match op {
+            UnOp::Neg => Ok(format!("(-{})", expr_code)),
+            UnOp::Not => Ok(format!("(!{})", expr_code)),
+            UnOp::AddrOf => Ok(format!("&({})", expr_code)),
+            UnOp::Deref => Ok(format!("(*{})", expr_code)),
+        }
     }
 
     fn generate_call(
         &mut self,
         func: &Expr,
         args: &[Expr],
-        _span: std::ops::Range<usize>,
+        span: std::ops::Range<usize>,
     ) -> Result<String> {
-        let func_code = self.generate(func)?;
+        if let Expr::Ident(name, _) = func {
+            if MSL_CONSTRUCTORS.contains(name) {
+                self.check_constructor_arity(name, args, &span)?;
+            }
+            if let Some(code) = self.generate_builtin(name, args, span)? {
+                return Ok(code);
+            }
+            if let Some(intrinsic) = Intrinsic::from_name(name) {
+                return self.generate_intrinsic(intrinsic, args);
+            }
+        }
+
+        // `p.distance(q)` on an `impl Point` method calls the free function
+        // `Point_distance(p, q)`
+        if let Expr::Member { object, field, .. } = func {
+            if let Some(ty) = self.method_receiver(object, field) {
+                let mut args_code = vec![self.generate(object)?];
+                for arg in args {
+                    args_code.push(self.generate(arg)?);
+                }
+                let (symbol, _) = self.function_symbol(&method_name(&ty, field));
+                return Ok(format!("{}({})", symbol, args_code.join(", ")));
+            }
+        }
+
+        // constructors such as `float(i)` and user helpers keep their own
+        // (or module-prefixed) names; a local of the same name shadows them
+        let func_code = match func {
+            Expr::Ident(name, _) if self.symbols.lookup(name).is_none() => {
+                self.function_symbol(name).0
+            }
+            _ => self.generate(func)?,
+        };
 
         let mut args_code = Vec::new();
         for arg in args {
@@ -198,6 +871,26 @@ impl ExprGenerator {
             return Ok(object_code);
         }
 
+        // tensors flatten to one offset, row-major unless a layout says otherwise
+        if let Some(strides) = self
+            .infer_type(object)
+            .as_ref()
+            .and_then(ValueType::tensor_strides)
+        {
+            if strides.len() == indices.len() {
+                let mut terms = Vec::new();
+                for (index, stride) in indices.iter().zip(&strides) {
+                    let index_code = self.generate(index)?;
+                    if stride == "1" {
+                        terms.push(index_code);
+                    } else {
+                        terms.push(format!("{} * {}", index_code, stride));
+                    }
+                }
+                return Ok(format!("{}[{}]", object_code, terms.join(" + ")));
+            }
+        }
+
         if indices.len() == 1 {
             let index_code = self.generate(&indices[0])?;
             Ok(format!("{}[{}]", object_code, index_code))
@@ -231,6 +924,21 @@ impl ExprGenerator {
         else_branch: Option<&Box<Expr>>,
         span: std::ops::Range<usize>,
     ) -> Result<String> {
+        let then_branch = Self::branch_value(then_branch);
+        let else_branch = else_branch.map(|e| Self::branch_value(e));
+
+        if let Some(else_expr) = else_branch {
+            if let Some((intrinsic, left, right)) =
+                Self::min_max_pattern(condition, then_branch, else_expr)
+            {
+                return self.generate_intrinsic(intrinsic, &[left.clone(), right.clone()]);
+            }
+        }
+
+        if let Some(else_expr) = else_branch {
+            self.check_branch_types(then_branch, else_expr, &span)?;
+        }
+
         let cond_code = self.generate(condition)?;
         let then_code = self.generate(then_branch)?;
 
@@ -246,6 +954,112 @@ impl ExprGenerator {
         }
     }
 
+    /// Requires the two values an `if` expression can produce to share a
+    /// type once promoted, recording a note when an integer branch is
+    /// promoted to the float type of the other.
+    fn check_branch_types(
+        &mut self,
+        then_branch: &Expr,
+        else_branch: &Expr,
+        span: &Range<usize>,
+    ) -> Result<()> {
+        let (Some(then_ty), Some(else_ty)) =
+            (self.infer_type(then_branch), self.infer_type(else_branch))
+        else {
+            return Ok(());
+        };
+        if then_ty == else_ty {
+            return Ok(());
+        }
+
+        let is_bool = |ty: &ValueType| ty.as_scalar() == Some(ScalarType::Bool);
+        let promoted = promote(&then_ty, &else_ty);
+        if is_bool(&then_ty) || is_bool(&else_ty) || promoted.is_none() {
+            return Err(CodegenError::expression_error(
+                format!(
+                    "if expression branches have incompatible types '{}' and '{}'",
+                    then_ty.msl_name(),
+                    else_ty.msl_name()
+                ),
+                span.clone(),
+            ));
+        }
+
+        let int_branch = match (then_ty.as_scalar(), else_ty.as_scalar()) {
+            (Some(l), Some(r)) if l.is_integer() && r.is_float() => Some((l, r)),
+            (Some(l), Some(r)) if l.is_float() && r.is_integer() => Some((r, l)),
+            _ => None,
+        };
+        if let Some((int_ty, float_ty)) = int_branch {
+            self.diagnostics.push(Diagnostic::note(
+                format!(
+                    "if expression branches mix '{}' and '{}'; the '{}' branch is promoted",
+                    int_ty.msl_name(),
+                    float_ty.msl_name(),
+                    int_ty.msl_name()
+                ),
+                span.clone(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// The value of an `if` branch: `{ x }` is just `x`.
+    fn branch_value<'e, 'src>(expr: &'e Expr<'src>) -> &'e Expr<'src> {
+        match expr {
+            Expr::Block { statements, .. } => match statements.as_slice() {
+                [Stmt::Expr(value)] => Self::branch_value(value),
+                _ => expr,
+            },
+            _ => expr,
+        }
+    }
+
+    /// Recognizes `if a > b { a } else { b }` and its `<`, `>=`, `<=` and
+    /// swapped-branch variants, returning the intrinsic and its operands.
+    fn min_max_pattern<'e, 'src>(
+        condition: &'e Expr<'src>,
+        then_branch: &Expr<'src>,
+        else_branch: &Expr<'src>,
+    ) -> Option<(Intrinsic, &'e Expr<'src>, &'e Expr<'src>)> {
+        let Expr::Binary {
+            left, op, right, ..
+        } = condition
+        else {
+            return None;
+        };
+
+        let picks_left = if Self::same_operand(then_branch, left)
+            && Self::same_operand(else_branch, right)
+        {
+            true
+        } else if Self::same_operand(then_branch, right) && Self::same_operand(else_branch, left) {
+            false
+        } else {
+            return None;
+        };
+
+        let intrinsic = match (op, picks_left) {
+            (BinOp::Greater | BinOp::GreaterEqual, true)
+            | (BinOp::Less | BinOp::LessEqual, false) => Intrinsic::Max,
+            (BinOp::Greater | BinOp::GreaterEqual, false)
+            | (BinOp::Less | BinOp::LessEqual, true) => Intrinsic::Min,
+            _ => return None,
+        };
+        Some((intrinsic, left, right))
+    }
+
+    /// Whether two side-effect-free operands are the same, ignoring spans.
+    fn same_operand(a: &Expr, b: &Expr) -> bool {
+        match (a, b) {
+            (Expr::Ident(a, _), Expr::Ident(b, _)) => a == b,
+            (Expr::IntLiteral(a, _), Expr::IntLiteral(b, _)) => a == b,
+            (Expr::UIntLiteral(a, _), Expr::UIntLiteral(b, _)) => a == b,
+            (Expr::FloatLiteral(a, _), Expr::FloatLiteral(b, _)) => a == b,
+            _ => false,
+        }
+    }
+
     fn generate_thread_idx(
         &mut self,
         dim: &Option<&str>,
@@ -285,6 +1099,19 @@ impl ExprGenerator {
         dim: &Option<&str>,
         span: std::ops::Range<usize>,
     ) -> Result<String> {
+        if let Some((x, y, z)) = self.block_dims {
+            return match dim {
+                Some("x") | Some("0") => Ok(format!("{}u", x)),
+                Some("y") | Some("1") => Ok(format!("{}u", y)),
+                Some("z") | Some("2") => Ok(format!("{}u", z)),
+                None => Ok(format!("uint3({}, {}, {})", x, y, z)),
+                Some(other) => Err(CodegenError::expression_error(
+                    format!("invalid block_dim dimension: {}", other),
+                    span,
+                )),
+            };
+        }
+
         match dim {
             Some("x") | Some("0") => Ok("threads_per_threadgroup.x".to_string()),
             Some("y") | Some("1") => Ok("threads_per_threadgroup.y".to_string()),
@@ -304,6 +1131,9 @@ impl ExprGenerator {
             BinOp::Mul => "*",
             BinOp::Div => "/",
             BinOp::Mod => "%",
+            BinOp::Shl => "<<",
+            BinOp::Shr => ">>",
+            BinOp::BitAnd => "&",
             BinOp::Equal => "==",
             BinOp::NotEqual => "!=",
             BinOp::Less => "<",
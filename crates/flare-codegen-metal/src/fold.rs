@@ -0,0 +1,124 @@
+use crate::types::TypeConverter;
+use flare::ast::{walk_expr_mut, walk_stmt_mut, Expr, Program, Stmt, Type, VisitorMut};
+use flare::fold::{self, ConstEnv};
+use std::collections::HashMap;
+
+/// Value of an integer constant expression over the names in `env`, with
+/// `sizeof` taken from Metal type layouts.
+pub fn fold_int(expr: &Expr, env: &HashMap<String, i64>) -> Option<i64> {
+    fold::fold_int(expr, &MetalConsts(env))
+}
+
+struct MetalConsts<'e>(&'e HashMap<String, i64>);
+
+impl ConstEnv for MetalConsts<'_> {
+    fn value(&self, name: &str) -> Option<i64> {
+        self.0.value(name)
+    }
+
+    fn size_of(&self, ty: &Type) -> Option<usize> {
+        TypeConverter::convert(ty, ty.span()).ok()?.size_bytes
+    }
+}
+
+/// Program-level `const` items whose values fold to integers; later consts
+/// may refer to earlier ones.
+pub fn program_int_consts(program: &Program) -> HashMap<String, i64> {
+    let mut consts = HashMap::new();
+    for item in &program.items {
+        if let Stmt::Const { name, value, .. } = item {
+            if let Some(folded) = fold_int(value, &consts) {
+                consts.insert(name.to_string(), folded);
+            }
+        }
+    }
+    consts
+}
+
+/// Sets the size of arrays declared with a const expression, such as
+/// `f32[TILE]`, wherever a type is written in `program`. Sizes that do not
+/// fold to a non-negative integer stay unresolved for codegen to reject.
+pub fn resolve_array_sizes(program: &mut Program, consts: &HashMap<String, i64>) {
+    let mut resolver = SizeResolver { consts };
+    for item in &mut program.items {
+        resolver.visit_stmt_mut(item);
+    }
+}
+
+struct SizeResolver<'c> {
+    consts: &'c HashMap<String, i64>,
+}
+
+impl SizeResolver<'_> {
+    fn resolve(&self, ty: &mut Type) {
+        match ty {
+            Type::Array {
+                dtype,
+                size,
+                size_expr,
+            } => {
+                self.resolve(dtype);
+                let folded = size_expr
+                    .as_ref()
+                    .and_then(|expr| fold_int(expr, self.consts))
+                    .and_then(|n| usize::try_from(n).ok());
+                if let Some(n) = folded {
+                    *size = Some(n);
+                    *size_expr = None;
+                }
+            }
+            Type::Tensor { dtype, .. }
+            | Type::Matrix { dtype, .. }
+            | Type::Vector { dtype, .. }
+            | Type::Ptr(dtype) => self.resolve(dtype),
+            _ => {}
+        }
+    }
+}
+
+impl<'src> VisitorMut<'src> for SizeResolver<'_> {
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt<'src>) {
+        match stmt {
+            Stmt::Let { ty: Some(ty), .. }
+            | Stmt::Var { ty: Some(ty), .. }
+            | Stmt::Const { ty: Some(ty), .. }
+            | Stmt::TypeDef { ty, .. } => self.resolve(ty),
+            Stmt::Function {
+                params,
+                return_type,
+                ..
+            } => {
+                for param in params {
+                    self.resolve(&mut param.ty);
+                }
+                if let Some(ty) = return_type {
+                    self.resolve(ty);
+                }
+            }
+            Stmt::Kernel(kernel) => {
+                for param in &mut kernel.params {
+                    self.resolve(&mut param.ty);
+                }
+                if let Some(ty) = &mut kernel.return_type {
+                    self.resolve(ty);
+                }
+            }
+            _ => {}
+        }
+        walk_stmt_mut(self, stmt);
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr<'src>) {
+        if let Expr::Cast {
+            target_type: ty, ..
+        }
+        | Expr::Bitcast {
+            target_type: ty, ..
+        }
+        | Expr::SizeOf { ty, .. } = expr
+        {
+            self.resolve(ty);
+        }
+        walk_expr_mut(self, expr);
+    }
+}
@@ -1,8 +1,26 @@
+use crate::builtins::{msl_identifier, SIMD_VOTE_FUNCTIONS};
 use crate::error::{CodegenError, Result};
+use crate::fold::fold_int;
+use crate::link::{kernel_calls, MethodTable};
 use crate::stmt::StmtGenerator;
-use crate::types::TypeConverter;
-use flare::ast::{KernelDef, Param, ScheduleBlock, ScheduleDirective, SharedMemoryDecl};
+use crate::typeck::ValueType;
+use crate::types::MetalType;
+use flare::ast::{
+    walk_expr, walk_stmt, AttributeArg, ConstParam, Expr, KernelDef, Param, ScheduleBlock,
+    ScheduleDirective, SharedMemoryDecl, Stmt, Type, Visitor,
+};
+use flare::{Diagnostic, LineMap};
+use std::collections::{HashMap, HashSet};
 use std::fmt::Write;
+use std::ops::Range;
+
+/// Constant `grid` of a kernel and the threadgroups it takes to cover it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DispatchSize {
+    pub grid: [u32; 3],
+    /// `None` when the threadgroup size is only known at dispatch.
+    pub threadgroups: Option<[u32; 3]>,
+}
 
 #[derive(Debug, Clone)]
 pub struct KernelConfig {
@@ -10,7 +28,26 @@ pub struct KernelConfig {
 
     pub max_threads_per_threadgroup: u32,
 
+    /// Threadgroup memory available to one threadgroup, in bytes.
+    pub max_threadgroup_memory: usize,
+
+    /// Buffer argument slots a kernel may bind; indices run below this.
+    pub max_buffer_arguments: usize,
+
+    /// First `[[buffer(n)]]` index given to kernel parameters.
+    pub buffer_index_base: usize,
+
+    /// Buffer indices the host reserves, skipped when numbering parameters.
+    pub reserved_buffers: Vec<usize>,
+
     pub emit_debug: bool,
+
+    /// Precede each kernel with `#line N "source_name"` so GPU debuggers
+    /// map it back to the flare source.
+    pub emit_line_directives: bool,
+
+    /// File name reported by `#line` directives.
+    pub source_name: String,
 }
 
 impl Default for KernelConfig {
@@ -18,15 +55,38 @@ impl Default for KernelConfig {
         Self {
             default_threadgroup_size: (256, 1, 1),
             max_threads_per_threadgroup: 1024,
+            max_threadgroup_memory: 32 * 1024,
+            max_buffer_arguments: 31,
+            buffer_index_base: 0,
+            reserved_buffers: Vec::new(),
             emit_debug: false,
+            emit_line_directives: false,
+            source_name: "source.flare".to_string(),
         }
     }
 }
 
+impl KernelConfig {
+    /// Buffer indices handed to kernel parameters, in order.
+    pub fn buffer_indices(&self) -> impl Iterator<Item = usize> + '_ {
+        (self.buffer_index_base..).filter(|index| !self.reserved_buffers.contains(index))
+    }
+}
+
 pub struct KernelGenerator {
     config: KernelConfig,
 
     stmt_gen: StmtGenerator,
+
+    line_map: Option<LineMap>,
+
+    /// Program-level integer consts usable as compile-time sizes.
+    consts: HashMap<String, i64>,
+
+    /// Precede each kernel with a comment giving its threadgroup size.
+    emit_comments: bool,
+
+    diagnostics: Vec<Diagnostic>,
 }
 
 impl KernelGenerator {
@@ -34,6 +94,10 @@ impl KernelGenerator {
         Self {
             config: KernelConfig::default(),
             stmt_gen: StmtGenerator::new(),
+            line_map: None,
+            consts: HashMap::new(),
+            emit_comments: false,
+            diagnostics: Vec::new(),
         }
     }
 
@@ -41,33 +105,218 @@ impl KernelGenerator {
         Self {
             config,
             stmt_gen: StmtGenerator::new(),
+            line_map: None,
+            consts: HashMap::new(),
+            emit_comments: false,
+            diagnostics: Vec::new(),
         }
     }
 
+    /// Source line map used to annotate statements when `emit_debug` is set
+    /// and to number `#line` directives when `emit_line_directives` is set.
+    pub fn set_line_map(&mut self, line_map: Option<LineMap>) {
+        if self.config.emit_debug {
+            self.stmt_gen.set_line_map(line_map.clone());
+        }
+        if self.config.emit_line_directives {
+            self.line_map = line_map;
+        }
+    }
+
+    pub fn config(&self) -> &KernelConfig {
+        &self.config
+    }
+
+    pub fn set_type_aliases(&mut self, aliases: HashSet<String>) {
+        self.stmt_gen.set_type_aliases(aliases);
+    }
+
+    pub fn set_methods(&mut self, methods: MethodTable) {
+        self.stmt_gen.set_methods(methods);
+    }
+
+    pub fn set_function_symbols(&mut self, symbols: HashMap<String, String>) {
+        self.stmt_gen.set_function_symbols(symbols);
+    }
+
+    pub fn set_consts(&mut self, consts: HashMap<String, i64>) {
+        self.consts = consts;
+    }
+
+    pub fn set_emit_comments(&mut self, emit_comments: bool) {
+        self.emit_comments = emit_comments;
+    }
+
+    pub(crate) fn convert_type(&self, ty: &Type, span: Range<usize>) -> Result<MetalType> {
+        self.stmt_gen.convert_type(ty, span)
+    }
+
+    /// Notes and warnings gathered since the last call.
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        let mut diagnostics = std::mem::take(&mut self.diagnostics);
+        diagnostics.extend(self.stmt_gen.take_diagnostics());
+        diagnostics
+    }
+
+    /// Generates `kernel`. Kernels with const parameters are emitted once per
+    /// `@specialize` value combination, named like `matmul_16`.
     pub fn generate(
         &mut self,
         kernel: &KernelDef,
         schedule: Option<&ScheduleBlock>,
+    ) -> Result<String> {
+        self.validate_kernel(kernel)?;
+
+        if kernel.const_params.is_empty() {
+            return self.generate_instance(kernel, schedule, kernel.name, &[]);
+        }
+
+        let mut instances = Vec::new();
+        for bindings in Self::specializations(kernel)? {
+            let suffix: Vec<String> = bindings.iter().map(|(_, v)| v.to_string()).collect();
+            let name = format!("{}_{}", kernel.name, suffix.join("_"));
+            instances.push(self.generate_instance(kernel, schedule, &name, &bindings)?);
+        }
+
+        Ok(instances.join("\n"))
+    }
+
+    fn generate_instance(
+        &mut self,
+        kernel: &KernelDef,
+        schedule: Option<&ScheduleBlock>,
+        name: &str,
+        bindings: &[(&ConstParam, i64)],
     ) -> Result<String> {
         let mut output = String::new();
 
-        self.validate_kernel(kernel)?;
+        self.warn_unresolved_threadgroup_size(kernel, schedule);
+        if self.emit_comments {
+            let (tg_x, tg_y, tg_z) = self.get_threadgroup_size(kernel, schedule);
+            writeln!(
+                &mut output,
+                "// threadgroup size: ({}, {}, {})",
+                tg_x, tg_y, tg_z
+            )?;
+        }
+
+        let mut sizes = self.consts.clone();
+        for (param, value) in bindings {
+            sizes.insert(param.name.to_string(), *value);
+        }
+        self.check_threadgroup_memory(kernel, &sizes)?;
+        self.check_constraints(kernel, &sizes)?;
+        Self::dispatch_size_with(kernel, schedule, &sizes)?;
 
-        let signature = self.generate_signature(kernel)?;
+        let uniforms = packed_uniforms(kernel);
+        let elementwise = elementwise_output(kernel)?;
+        if !uniforms.is_empty() {
+            writeln!(&mut output, "struct {} {{", uniforms_struct_name(name))?;
+            let mut offset = 0;
+            for param in &uniforms {
+                let ty = self.stmt_gen.convert_type(&param.ty, param.span.clone())?;
+                let field = format!("{} {};", ty.as_str(), msl_identifier(param.name));
+                match uniform_alignment(param, &ty, offset)? {
+                    Some(align) => writeln!(&mut output, "    alignas({}) {}", align, field)?,
+                    None => writeln!(&mut output, "    {}", field)?,
+                }
+                if let (Some(size), Some(align)) = (ty.size_bytes, ty.alignment) {
+                    offset = offset.next_multiple_of(align) + size;
+                }
+            }
+            writeln!(&mut output, "}};")?;
+            writeln!(&mut output)?;
+        }
+
+        if let Some(line_map) = &self.line_map {
+            writeln!(
+                &mut output,
+                "#line {} \"{}\"",
+                line_map.line(kernel.span.start),
+                self.config.source_name
+            )?;
+        }
+
+        let signature = self.generate_signature(kernel, name)?;
         writeln!(&mut output, "{}", signature)?;
         writeln!(&mut output, "{{")?;
 
+        for (param, value) in bindings {
+            let ty = self.stmt_gen.convert_type(&param.ty, param.span.clone())?;
+            writeln!(
+                &mut output,
+                "    const {} {} = {};",
+                ty.as_str(),
+                param.name,
+                value
+            )?;
+        }
+
         if let Some(shared_mem) = &kernel.shared_memory {
             for decl in shared_mem {
-                let shared_code = self.generate_shared_memory(decl)?;
+                let shared_code = self.generate_shared_memory(decl, &sizes)?;
                 writeln!(&mut output, "    {}", shared_code)?;
             }
-            if !shared_mem.is_empty() {
+            let has_body =
+                kernel.compute.as_ref().is_some_and(|c| !c.is_empty()) || !kernel.body.is_empty();
+            if !shared_mem.is_empty() && has_body {
                 writeln!(&mut output)?;
             }
         }
 
         self.stmt_gen.set_indent(1);
+        self.stmt_gen.reset_symbols();
+        self.stmt_gen.set_memory_placements(schedule);
+        let mut threadgroup_buffers = HashMap::new();
+        for decl in kernel.shared_memory.iter().flatten() {
+            let dims = decl
+                .shape
+                .iter()
+                .map(|dim| self.stmt_gen.generate_expr(dim))
+                .collect::<Result<Vec<_>>>()?;
+            threadgroup_buffers.insert(decl.name.to_string(), dims);
+        }
+        self.stmt_gen.set_threadgroup_buffers(threadgroup_buffers);
+        self.stmt_gen
+            .set_block_dims(Self::known_threadgroup_size(kernel, schedule));
+        let mut renames: HashMap<String, String> = uniforms
+            .iter()
+            .map(|param| {
+                let field = format!("{}.{}", UNIFORMS_PARAM, msl_identifier(param.name));
+                (param.name.to_string(), field)
+            })
+            .collect();
+        for param in &kernel.params {
+            self.stmt_gen.declare(param.name, Some(&param.ty));
+        }
+        for (param, _) in bindings {
+            self.stmt_gen.declare(param.name, Some(&param.ty));
+        }
+
+        // the body of an @elementwise kernel computes one element; `output`
+        // names that element of the output buffer
+        if let Some((output_param, len)) = &elementwise {
+            writeln!(
+                &mut output,
+                "    const uint {} = thread_position_in_grid.x;",
+                ELEMENTWISE_INDEX
+            )?;
+            writeln!(&mut output, "    if ({} >= {}) {{", ELEMENTWISE_INDEX, len)?;
+            writeln!(&mut output, "        return;")?;
+            writeln!(&mut output, "    }}")?;
+            self.stmt_gen
+                .declare(ELEMENTWISE_INDEX, Some(&Type::U32(0..0)));
+            if let Type::Tensor { dtype, .. } = &output_param.ty {
+                self.stmt_gen.declare(OUTPUT_PARAM, Some(dtype));
+            }
+            renames.insert(
+                OUTPUT_PARAM.to_string(),
+                format!("{}[{}]", OUTPUT_PARAM, ELEMENTWISE_INDEX),
+            );
+        }
+        self.stmt_gen.set_renames(renames);
+        self.stmt_gen
+            .set_elementwise_len(elementwise.as_ref().map(|(_, len)| *len));
 
         if let Some(compute_stmts) = &kernel.compute {
             for stmt in compute_stmts {
@@ -90,10 +339,10 @@ impl KernelGenerator {
         Ok(output)
     }
 
-    fn generate_signature(&self, kernel: &KernelDef) -> Result<String> {
+    fn generate_signature(&self, kernel: &KernelDef, name: &str) -> Result<String> {
         let mut output = String::new();
 
-        write!(&mut output, "kernel void {}", kernel.name)?;
+        write!(&mut output, "kernel void {}", name)?;
 
         if !kernel.generic_params.is_empty() {
             return Err(CodegenError::unsupported_feature(
@@ -108,15 +357,59 @@ impl KernelGenerator {
 
         write!(&mut output, "(")?;
 
-        let mut param_index = 0;
+        let mut buffer_indices = self.config.buffer_indices();
         let mut params_code = Vec::new();
+        let mut threadgroup_params = Vec::new();
 
+        let uniforms = packed_uniforms(kernel);
         for param in &kernel.params {
+            if uniforms.contains(&param) {
+                continue;
+            }
+            // threadgroup arguments are numbered apart from buffers
+            if is_threadgroup_parameter(param)? {
+                let index = threadgroup_params.len();
+                threadgroup_params.push(self.generate_threadgroup_parameter(param, index)?);
+                continue;
+            }
+            let param_index = buffer_indices.next().unwrap_or_default();
             let param_str = self.generate_parameter(param, param_index)?;
             params_code.push(param_str);
-            param_index += 1;
         }
 
+        let elementwise = elementwise_output(kernel)?;
+        if let Some((output_param, _)) = &elementwise {
+            let param_index = buffer_indices.next().unwrap_or_default();
+            params_code.push(self.generate_parameter(output_param, param_index)?);
+        }
+
+        if !uniforms.is_empty() {
+            params_code.push(format!(
+                "constant {}& {} [[buffer({})]]",
+                uniforms_struct_name(name),
+                UNIFORMS_PARAM,
+                buffer_indices.next().unwrap_or_default()
+            ));
+        }
+
+        // every entry so far is a buffer argument
+        let last_index = params_code
+            .len()
+            .checked_sub(1)
+            .and_then(|n| self.config.buffer_indices().nth(n));
+        if let Some(last) = last_index {
+            if last >= self.config.max_buffer_arguments {
+                return Err(CodegenError::resource_limit_exceeded(
+                    format!(
+                        "kernel '{}' binds buffer index {} but Metal provides {} buffer slots",
+                        name, last, self.config.max_buffer_arguments
+                    ),
+                    kernel.span.clone(),
+                ));
+            }
+        }
+
+        params_code.extend(threadgroup_params);
         params_code.push(
             "uint3 thread_position_in_threadgroup [[thread_position_in_threadgroup]]".to_string(),
         );
@@ -124,6 +417,14 @@ impl KernelGenerator {
             "uint3 threadgroup_position_in_grid [[threadgroup_position_in_grid]]".to_string(),
         );
         params_code.push("uint3 threads_per_threadgroup [[threads_per_threadgroup]]".to_string());
+        if elementwise.is_some() {
+            params_code
+                .push("uint3 thread_position_in_grid [[thread_position_in_grid]]".to_string());
+        }
+        if kernel_calls(kernel, SIMD_VOTE_FUNCTIONS) {
+            params_code
+                .push("uint thread_index_in_simdgroup [[thread_index_in_simdgroup]]".to_string());
+        }
 
         write!(
             &mut output,
@@ -135,46 +436,260 @@ impl KernelGenerator {
         Ok(output)
     }
 
-    fn generate_parameter(&self, param: &Param, buffer_index: usize) -> Result<String> {
-        let param_type = TypeConverter::convert(&param.ty, param.span.clone())?;
+    /// Rejects an instance whose `where` constraints fold to false for the
+    /// program's consts and specialization values; constraints on dims
+    /// known only at dispatch are left to the host.
+    fn check_constraints(
+        &mut self,
+        kernel: &KernelDef,
+        sizes: &HashMap<String, i64>,
+    ) -> Result<()> {
+        for constraint in &kernel.constraints {
+            if fold_int(constraint, sizes) == Some(0) {
+                let code = self.stmt_gen.generate_expr(constraint)?;
+                return Err(CodegenError::invalid_kernel_config(
+                    format!(
+                        "kernel '{}' requires {}, which does not hold",
+                        kernel.name, code
+                    ),
+                    constraint.span(),
+                ));
+            }
+        }
+        Ok(())
+    }
 
-        let address_space = if param_type.as_str().contains("*") {
-            "device"
-        } else {
-            ""
-        };
+    /// `where` constraints of `kernel` that do not fold with the program's
+    /// consts, as MSL expressions for the host to check at dispatch.
+    pub fn symbolic_constraints(&mut self, kernel: &KernelDef) -> Result<Vec<String>> {
+        let mut symbolic = Vec::new();
+        for constraint in &kernel.constraints {
+            if fold_int(constraint, &self.consts).is_none() {
+                symbolic.push(self.stmt_gen.generate_expr(constraint)?);
+            }
+        }
+        Ok(symbolic)
+    }
 
-        if address_space.is_empty() {
-            Ok(format!(
-                "{} {} [[buffer({})]]",
-                param_type.as_str(),
-                param.name,
-                buffer_index
-            ))
-        } else {
-            let type_str = param_type.as_str();
-            if type_str.ends_with('*') {
-                let base = &type_str[..type_str.len() - 1];
-                Ok(format!(
-                    "{} {} [[buffer({})]]",
-                    base,
-                    format!("*{}", param.name),
-                    buffer_index
-                ))
-            } else {
+    /// Expands `@specialize(TILE=16,32)` into one binding set per value
+    /// combination of the kernel's const parameters.
+    fn specializations<'k, 'src>(
+        kernel: &'k KernelDef<'src>,
+    ) -> Result<Vec<Vec<(&'k ConstParam<'src>, i64)>>> {
+        let attribute = kernel
+            .attributes
+            .iter()
+            .find(|attr| attr.name == "specialize")
+            .ok_or_else(|| {
+                CodegenError::invalid_kernel_config(
+                    format!(
+                        "kernel '{}' has const parameters but no @specialize(...) values",
+                        kernel.name
+                    ),
+                    kernel.span.clone(),
+                )
+            })?;
+
+        let invalid =
+            |message: String| CodegenError::invalid_kernel_config(message, attribute.span.clone());
+
+        let mut values: HashMap<&str, Vec<i64>> = HashMap::new();
+        let mut current = None;
+        for arg in &attribute.args {
+            match arg {
+                AttributeArg::KeyValue { key, value } => {
+                    current = Some(*key);
+                    let entry = values.entry(key).or_default();
+                    match value.as_ref() {
+                        AttributeArg::IntLiteral(n) => entry.push(*n),
+                        AttributeArg::List(items) => {
+                            for item in items {
+                                match item {
+                                    AttributeArg::IntLiteral(n) => entry.push(*n),
+                                    other => {
+                                        return Err(invalid(format!(
+                                            "@specialize values must be integers, found {:?}",
+                                            other
+                                        )))
+                                    }
+                                }
+                            }
+                        }
+                        other => {
+                            return Err(invalid(format!(
+                                "@specialize values must be integers, found {:?}",
+                                other
+                            )))
+                        }
+                    }
+                }
+                AttributeArg::IntLiteral(n) => match current {
+                    Some(key) => values.entry(key).or_default().push(*n),
+                    None => {
+                        return Err(invalid(
+                            "@specialize values must follow a `NAME=` key".to_string(),
+                        ))
+                    }
+                },
+                other => {
+                    return Err(invalid(format!(
+                        "unexpected @specialize argument {:?}",
+                        other
+                    )))
+                }
+            }
+        }
+
+        if let Some(key) = values
+            .keys()
+            .find(|key| !kernel.const_params.iter().any(|p| p.name == **key))
+        {
+            return Err(invalid(format!(
+                "@specialize names '{}', which is not a const parameter of '{}'",
+                key, kernel.name
+            )));
+        }
+
+        let mut combinations: Vec<Vec<(&ConstParam, i64)>> = vec![Vec::new()];
+        for param in &kernel.const_params {
+            let param_values = values
+                .get(param.name)
+                .filter(|v| !v.is_empty())
+                .ok_or_else(|| {
+                    invalid(format!(
+                        "no @specialize values for const parameter '{}'",
+                        param.name
+                    ))
+                })?;
+
+            combinations = combinations
+                .into_iter()
+                .flat_map(|prefix| {
+                    param_values.iter().map(move |value| {
+                        let mut bindings = prefix.clone();
+                        bindings.push((param, *value));
+                        bindings
+                    })
+                })
+                .collect();
+        }
+
+        Ok(combinations)
+    }
+
+    fn generate_parameter(&self, param: &Param, buffer_index: usize) -> Result<String> {
+        let param_type = self.stmt_gen.convert_type(&param.ty, param.span.clone())?;
+        let name = msl_identifier(param.name);
+        let type_str = param_type.as_str();
+
+        // buffers default to `device` and scalars are passed by value, unless
+        // `@memory` places the parameter explicitly
+        match (type_str.strip_suffix('*'), parameter_address_space(param)?) {
+            (Some(pointee), None) => Ok(format!(
+                "{} *{} [[buffer({})]]",
+                pointee, name, buffer_index
+            )),
+            (Some(pointee), Some(space)) => {
+                let elem = pointee.strip_prefix("device ").unwrap_or(pointee);
                 Ok(format!(
-                    "{} {} [[buffer({})]]",
-                    param_type.as_str(),
-                    param.name,
-                    buffer_index
+                    "{} {} *{} [[buffer({})]]",
+                    space, elem, name, buffer_index
                 ))
             }
+            (None, None) => Ok(format!(
+                "{} {} [[buffer({})]]",
+                type_str, name, buffer_index
+            )),
+            (None, Some(space)) => Ok(format!(
+                "{} {} &{} [[buffer({})]]",
+                space, type_str, name, buffer_index
+            )),
+        }
+    }
+
+    /// `threadgroup T *name [[threadgroup(index)]]` for a buffer parameter
+    /// placed in threadgroup memory.
+    fn generate_threadgroup_parameter(&self, param: &Param, index: usize) -> Result<String> {
+        let param_type = self.stmt_gen.convert_type(&param.ty, param.span.clone())?;
+        let Some(pointee) = param_type.as_str().strip_suffix('*') else {
+            return Err(CodegenError::invalid_memory_config(
+                format!(
+                    "parameter '{}' is not a buffer, so it cannot be placed in threadgroup memory",
+                    param.name
+                ),
+                param.span.clone(),
+            ));
+        };
+        let elem = pointee.strip_prefix("device ").unwrap_or(pointee);
+        Ok(format!(
+            "threadgroup {} *{} [[threadgroup({})]]",
+            elem,
+            msl_identifier(param.name),
+            index
+        ))
+    }
+
+    /// Totals the `shared_memory` declarations whose element type and shape
+    /// are known at compile time and rejects kernels that exceed
+    /// `max_threadgroup_memory`.
+    fn check_threadgroup_memory(
+        &self,
+        kernel: &KernelDef,
+        sizes: &HashMap<String, i64>,
+    ) -> Result<()> {
+        let Some(shared_mem) = &kernel.shared_memory else {
+            return Ok(());
+        };
+
+        let mut total = 0usize;
+        for decl in shared_mem {
+            let Some(elem_size) = decl
+                .ty
+                .as_ref()
+                .and_then(|ty| self.stmt_gen.convert_type(ty, decl.span.clone()).ok())
+                .and_then(|ty| ty.size_bytes)
+            else {
+                continue;
+            };
+
+            let dims: Option<Vec<usize>> = decl
+                .shape
+                .iter()
+                .map(|dim| fold_int(dim, sizes).and_then(|n| usize::try_from(n).ok()))
+                .collect();
+            let Some(dims) = dims else {
+                continue;
+            };
+
+            let size = dims
+                .iter()
+                .try_fold(elem_size, |acc, dim| acc.checked_mul(*dim))
+                .unwrap_or(usize::MAX);
+            total = total.saturating_add(size);
         }
+
+        if total > self.config.max_threadgroup_memory {
+            return Err(CodegenError::resource_limit_exceeded(
+                format!(
+                    "kernel '{}' uses {} bytes of threadgroup memory, more than the {} bytes available",
+                    kernel.name, total, self.config.max_threadgroup_memory
+                ),
+                kernel.span.clone(),
+            ));
+        }
+
+        Ok(())
     }
 
-    fn generate_shared_memory(&self, decl: &SharedMemoryDecl) -> Result<String> {
+    fn generate_shared_memory(
+        &self,
+        decl: &SharedMemoryDecl,
+        sizes: &HashMap<String, i64>,
+    ) -> Result<String> {
         let ty_str = match &decl.ty {
-            Some(ty) => TypeConverter::convert(ty, decl.span.clone())?
+            Some(ty) => self
+                .stmt_gen
+                .convert_type(ty, decl.span.clone())?
                 .as_str()
                 .to_string(),
             None => {
@@ -192,21 +707,31 @@ impl KernelGenerator {
             ));
         }
 
-        let mut expr_gen = crate::expr::ExprGenerator::new();
-        let mut size_exprs = Vec::new();
+        // MSL needs a constant extent, so the dims are folded to one length
+        let mut len: i64 = 1;
         for dim in &decl.shape {
-            size_exprs.push(expr_gen.generate(dim)?);
+            let value = fold_int(dim, sizes).filter(|n| *n > 0).ok_or_else(|| {
+                CodegenError::invalid_memory_config(
+                    format!(
+                        "shared memory '{}' dimensions must be positive compile-time constants",
+                        decl.name
+                    ),
+                    dim.span(),
+                )
+            })?;
+            len = len.checked_mul(value).ok_or_else(|| {
+                CodegenError::invalid_memory_config(
+                    format!("shared memory '{}' is too large", decl.name),
+                    decl.span.clone(),
+                )
+            })?;
         }
 
-        let array_spec = if size_exprs.len() == 1 {
-            format!("[{}]", size_exprs[0])
-        } else {
-            format!("[{}]", size_exprs.join(" * "))
-        };
-
         Ok(format!(
-            "threadgroup {} {}{}",
-            ty_str, decl.name, array_spec
+            "threadgroup {} {}[{}];",
+            ty_str,
+            msl_identifier(decl.name),
+            len
         ))
     }
 
@@ -231,7 +756,74 @@ impl KernelGenerator {
             if block.len() == 3 {}
         }
 
-        Ok(())
+        let compute = kernel.compute.iter().flatten();
+        for stmt in compute.chain(&kernel.body) {
+            Self::validate_kernel_returns(stmt)?;
+        }
+        Self::validate_constant_writes(kernel)
+    }
+
+    /// Rejects assignments to parameters placed in the read-only `constant`
+    /// address space, unless a local binding of the same name shadows them.
+    fn validate_constant_writes(kernel: &KernelDef) -> Result<()> {
+        let mut constants = HashSet::new();
+        for param in &kernel.params {
+            if parameter_address_space(param)? == Some("constant") {
+                constants.insert(param.name);
+            }
+        }
+        if constants.is_empty() {
+            return Ok(());
+        }
+
+        let mut writes = ConstantWrites {
+            constants: &constants,
+            locals: HashSet::new(),
+            write: None,
+        };
+        for stmt in kernel.compute.iter().flatten().chain(&kernel.body) {
+            writes.visit_stmt(stmt);
+        }
+        match writes.write {
+            Some((name, span)) => Err(CodegenError::invalid_memory_config(
+                format!(
+                    "cannot write to parameter '{}', which is in the read-only constant address space",
+                    name
+                ),
+                span,
+            )),
+            None => Ok(()),
+        }
+    }
+
+    fn validate_kernel_returns(stmt: &Stmt) -> Result<()> {
+        match stmt {
+            Stmt::Return {
+                value: Some(_),
+                span,
+            } => Err(CodegenError::statement_error(
+                "kernels cannot return a value; write the result to `output` instead",
+                span.clone(),
+            )),
+            Stmt::Block { statements, .. } => statements
+                .iter()
+                .try_for_each(Self::validate_kernel_returns),
+            Stmt::If {
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                Self::validate_kernel_returns(then_branch)?;
+                match else_branch {
+                    Some(else_stmt) => Self::validate_kernel_returns(else_stmt),
+                    None => Ok(()),
+                }
+            }
+            Stmt::While { body, .. } | Stmt::For { body, .. } | Stmt::Staged { stmt: body, .. } => {
+                Self::validate_kernel_returns(body)
+            }
+            _ => Ok(()),
+        }
     }
 
     fn apply_scheduling_hints(&self, code: String, schedule: &ScheduleBlock) -> Result<String> {
@@ -249,6 +841,9 @@ impl KernelGenerator {
                 ScheduleDirective::Unroll(factor) => {
                     writeln!(&mut hints, "// - unroll factor: {}", factor)?;
                 }
+                ScheduleDirective::UnrollJam(factor) => {
+                    writeln!(&mut hints, "// - unroll-and-jam factor: {}", factor)?;
+                }
                 ScheduleDirective::Threads { x, y } => {
                     writeln!(&mut hints, "// - thread config: ({}, {:?})", x, y)?;
                 }
@@ -268,6 +863,11 @@ impl KernelGenerator {
                 ScheduleDirective::Parallel => {
                     writeln!(&mut hints, "// parallel execution enabled")?;
                 }
+                ScheduleDirective::Hints(attributes) => {
+                    let names: Vec<String> =
+                        attributes.iter().map(|a| format!("@{}", a.name)).collect();
+                    writeln!(&mut hints, "// - hints: {}", names.join(", "))?;
+                }
             }
         }
 
@@ -280,24 +880,128 @@ impl KernelGenerator {
         kernel: &KernelDef,
         schedule: Option<&ScheduleBlock>,
     ) -> (u32, u32, u32) {
+        Self::known_threadgroup_size(kernel, schedule)
+            .unwrap_or(self.config.default_threadgroup_size)
+    }
+
+    /// Warns when the threadgroup size falls back to the default because a
+    /// block dimension is not a positive integer literal.
+    fn warn_unresolved_threadgroup_size(
+        &mut self,
+        kernel: &KernelDef,
+        schedule: Option<&ScheduleBlock>,
+    ) {
+        if Self::known_threadgroup_size(kernel, schedule).is_some() {
+            return;
+        }
+
+        let default = self.config.default_threadgroup_size;
+        let unresolved = kernel
+            .block
+            .iter()
+            .flatten()
+            .enumerate()
+            .find(|(i, dim)| Self::block_dim_literal(*i, dim).is_none());
+        if let Some((i, dim)) = unresolved {
+            self.diagnostics.push(Diagnostic::warning(
+                format!(
+                    "threadgroup size of kernel '{}' is unresolved because block \
+                     dimension {} is not a positive integer literal; using the \
+                     default {:?}",
+                    kernel.name, i, default
+                ),
+                dim.span(),
+            ));
+        }
+    }
+
+    /// Thread grid of `kernel` when every `grid` dimension folds with the
+    /// program's consts, with the number of threadgroups it dispatches when
+    /// the threadgroup size is known too.
+    pub fn dispatch_size(
+        &self,
+        kernel: &KernelDef,
+        schedule: Option<&ScheduleBlock>,
+    ) -> Result<Option<DispatchSize>> {
+        Self::dispatch_size_with(kernel, schedule, &self.consts)
+    }
+
+    /// Like `dispatch_size`, folding with `sizes`; rejects a constant grid
+    /// that is not a whole number of threadgroups.
+    fn dispatch_size_with(
+        kernel: &KernelDef,
+        schedule: Option<&ScheduleBlock>,
+        sizes: &HashMap<String, i64>,
+    ) -> Result<Option<DispatchSize>> {
+        let Some(grid_dims) = kernel.grid.as_ref().filter(|dims| dims.len() <= 3) else {
+            return Ok(None);
+        };
+        let mut grid = [1u32; 3];
+        for (i, dim) in grid_dims.iter().enumerate() {
+            match fold_int(dim, sizes).and_then(|value| u32::try_from(value).ok()) {
+                Some(value) if value > 0 => grid[i] = value,
+                _ => return Ok(None),
+            }
+        }
+
+        let Some((x, y, z)) = Self::known_threadgroup_size(kernel, schedule) else {
+            return Ok(Some(DispatchSize {
+                grid,
+                threadgroups: None,
+            }));
+        };
+        let block = [x, y, z];
+        for i in 0..3 {
+            if grid[i] % block[i] != 0 {
+                let span = grid_dims
+                    .get(i)
+                    .map_or(kernel.span.clone(), |dim| dim.span());
+                return Err(CodegenError::invalid_kernel_config(
+                    format!(
+                        "grid dimension {} of kernel '{}' is {}, which is not a multiple of \
+                         the threadgroup size {}",
+                        i, kernel.name, grid[i], block[i]
+                    ),
+                    span,
+                ));
+            }
+        }
+        let counts = [grid[0] / block[0], grid[1] / block[1], grid[2] / block[2]];
+        Ok(Some(DispatchSize {
+            grid,
+            threadgroups: Some(counts),
+        }))
+    }
+
+    /// Threadgroup size fixed at compile time by a `threads(...)` schedule
+    /// directive or an all-literal `block` config.
+    pub fn known_threadgroup_size(
+        kernel: &KernelDef,
+        schedule: Option<&ScheduleBlock>,
+    ) -> Option<(u32, u32, u32)> {
         if let Some(sched) = schedule {
             for directive in &sched.directives {
                 if let ScheduleDirective::Threads { x, y } = directive {
                     let y_val = y.unwrap_or(1);
-                    return (*x as u32, y_val as u32, 1);
+                    return Some((*x as u32, y_val as u32, 1));
                 }
             }
         }
 
-        if let Some(block) = &kernel.block {
-            match block.len() {
-                1 => (256, 1, 1),
-                2 => (16, 16, 1),
-                3 => (8, 8, 8),
-                _ => self.config.default_threadgroup_size,
+        let block = kernel.block.as_ref()?;
+        let mut dims = [1u32; 3];
+        for (i, dim) in block.iter().enumerate() {
+            dims[i] = Self::block_dim_literal(i, dim)?;
+        }
+        Some((dims[0], dims[1], dims[2]))
+    }
+
+    fn block_dim_literal(i: usize, dim: &Expr) -> Option<u32> {
+        match dim {
+            Expr::IntLiteral(value, _) if i < 3 && *value > 0 => {
+                Some(u32::try_from(*value).unwrap_or(u32::MAX))
             }
-        } else {
-            self.config.default_threadgroup_size
+            _ => None,
         }
     }
 }
@@ -307,3 +1011,161 @@ impl Default for KernelGenerator {
         Self::new()
     }
 }
+
+/// Name of the output buffer of `@elementwise` kernels.
+pub const OUTPUT_PARAM: &str = "output";
+
+/// Global element index injected at the top of `@elementwise` kernels.
+pub const ELEMENTWISE_INDEX: &str = "gid";
+
+/// Output buffer and element count of an `@elementwise` kernel, which must
+/// return a single one-dimensional tensor.
+pub(crate) fn elementwise_output<'src>(
+    kernel: &KernelDef<'src>,
+) -> Result<Option<(Param<'src>, &'src str)>> {
+    if !kernel
+        .attributes
+        .iter()
+        .any(|attr| attr.name == "elementwise")
+    {
+        return Ok(None);
+    }
+
+    match &kernel.return_type {
+        Some(ty @ Type::Tensor { shape, .. }) if shape.len() == 1 => {
+            let output = Param {
+                name: OUTPUT_PARAM,
+                ty: ty.clone(),
+                attributes: Vec::new(),
+                span: kernel.span.clone(),
+            };
+            Ok(Some((output, shape[0])))
+        }
+        _ => Err(CodegenError::invalid_kernel_config(
+            format!(
+                "@elementwise kernel '{}' must return a single `Tensor<T, [N]>`",
+                kernel.name
+            ),
+            kernel.span.clone(),
+        )),
+    }
+}
+
+/// Address space named by a parameter's `@memory(location)` attribute:
+/// `device`, `constant` or `threadgroup`.
+fn parameter_address_space(param: &Param) -> Result<Option<&'static str>> {
+    let Some(attr) = param.attributes.iter().find(|attr| attr.name == "memory") else {
+        return Ok(None);
+    };
+    let location = match attr.args.as_slice() {
+        [AttributeArg::Ident(location)] => *location,
+        _ => {
+            return Err(CodegenError::invalid_memory_config(
+                format!(
+                    "@memory on parameter '{}' takes one location, such as `@memory(constant)`",
+                    param.name
+                ),
+                attr.span.clone(),
+            ))
+        }
+    };
+    match location {
+        "constant" | "const" => Ok(Some("constant")),
+        "device" | "global" => Ok(Some("device")),
+        "shared" | "threadgroup" => Ok(Some("threadgroup")),
+        other => Err(CodegenError::invalid_memory_config(
+            format!(
+                "unknown memory location '{}' for parameter '{}'",
+                other, param.name
+            ),
+            attr.span.clone(),
+        )),
+    }
+}
+
+/// Whether `@memory(shared)` places `param` in threadgroup memory, which
+/// the host allocates per threadgroup instead of binding a buffer.
+pub(crate) fn is_threadgroup_parameter(param: &Param) -> Result<bool> {
+    Ok(parameter_address_space(param)? == Some("threadgroup"))
+}
+
+/// Finds the first assignment to one of `constants` outside the scope of a
+/// local binding that shadows it.
+struct ConstantWrites<'a, 'src> {
+    constants: &'a HashSet<&'src str>,
+    locals: HashSet<&'src str>,
+    write: Option<(&'src str, Range<usize>)>,
+}
+
+impl<'src> Visitor<'src> for ConstantWrites<'_, 'src> {
+    fn visit_stmt(&mut self, stmt: &Stmt<'src>) {
+        match stmt {
+            Stmt::Let { name, .. } | Stmt::Var { name, .. } | Stmt::For { var: name, .. } => {
+                self.locals.insert(name);
+            }
+            _ => {}
+        }
+        walk_stmt(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr<'src>) {
+        if let Expr::Assign { target, span, .. } | Expr::CompoundAssign { target, span, .. } = expr
+        {
+            let mut root = target.as_ref();
+            while let Expr::Index { object, .. } | Expr::Member { object, .. } = root {
+                root = object;
+            }
+            if let Expr::Ident(name, _) = root {
+                if self.constants.contains(name) && !self.locals.contains(name) {
+                    self.write.get_or_insert((name, span.clone()));
+                }
+            }
+        }
+        walk_expr(self, expr);
+    }
+}
+
+/// Checks the alignment of a packed uniforms field placed at `offset` and
+/// returns the `alignas` it needs when it would not start there naturally.
+fn uniform_alignment(param: &Param, ty: &MetalType, offset: usize) -> Result<Option<usize>> {
+    let Some(align) = ty.alignment else {
+        return Ok(None);
+    };
+    if !align.is_power_of_two() || align > 16 {
+        return Err(CodegenError::unsupported_type(
+            format!(
+                "uniform '{}' has unsupported alignment {}; Metal constant buffers align to at most 16 bytes",
+                param.name, align
+            ),
+            param.span.clone(),
+        ));
+    }
+    Ok((!offset.is_multiple_of(align)).then_some(align))
+}
+
+/// Name of the packed uniforms argument of `@pack_uniforms` kernels.
+pub const UNIFORMS_PARAM: &str = "u";
+
+/// Scalar and vector params that `@pack_uniforms` gathers into one
+/// constant struct instead of passing as separate buffers. Params placed
+/// with `@memory` keep their own argument.
+pub(crate) fn packed_uniforms<'k, 'src>(kernel: &'k KernelDef<'src>) -> Vec<&'k Param<'src>> {
+    if !kernel
+        .attributes
+        .iter()
+        .any(|attr| attr.name == "pack_uniforms")
+    {
+        return Vec::new();
+    }
+
+    kernel
+        .params
+        .iter()
+        .filter(|param| ValueType::from_ast(&param.ty).is_some_and(|ty| !ty.is_buffer()))
+        .filter(|param| param.attributes.iter().all(|attr| attr.name != "memory"))
+        .collect()
+}
+
+pub(crate) fn uniforms_struct_name(kernel_name: &str) -> String {
+    format!("{}_Uniforms", kernel_name)
+}
@@ -1,13 +1,27 @@
+pub mod builtins;
 pub mod error;
 pub mod expr;
+pub mod fold;
 pub mod kernel;
+pub mod link;
+pub mod metadata;
 pub mod stmt;
+pub mod typeck;
 pub mod types;
 
 use error::{CodegenError, Result};
-use flare::ast::{Program, Stmt};
+use flare::ast::{Program, ScheduleDirective, Stmt};
+use flare::{Diagnostic, LineMap};
+use flare_ir::mir::pass::PassManager;
+use flare_ir::mir::{barrier, purity, resolve, unroll_jam};
 use kernel::{KernelConfig, KernelGenerator};
+use link::CallGraph;
+use metadata::ProgramMetadata;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Write;
+use stmt::StmtGenerator;
+
+pub use flare_ir::mir::pass::{OptLevel, OptReport};
 
 #[derive(Debug, Clone)]
 pub struct CodegenOptions {
@@ -20,6 +34,13 @@ pub struct CodegenOptions {
     pub metal_version: String,
 
     pub include_metal_stdlib: bool,
+
+    /// Which flare-ir optimizations run before generating kernels.
+    pub opt_level: OptLevel,
+
+    /// Prefixes user `fn` helpers as `<module_id>_name` and emits them
+    /// `static`, so several programs can share one translation unit.
+    pub module_id: Option<String>,
 }
 
 impl Default for CodegenOptions {
@@ -30,6 +51,8 @@ impl Default for CodegenOptions {
             pretty_print: true,
             metal_version: "2.4".to_string(),
             include_metal_stdlib: true,
+            opt_level: OptLevel::O0,
+            module_id: None,
         }
     }
 }
@@ -38,6 +61,12 @@ pub struct MetalCodegen {
     options: CodegenOptions,
 
     kernel_gen: KernelGenerator,
+
+    stmt_gen: StmtGenerator,
+
+    diagnostics: Vec<Diagnostic>,
+
+    opt_report: OptReport,
 }
 
 impl MetalCodegen {
@@ -46,50 +75,168 @@ impl MetalCodegen {
     }
 
     pub fn with_options(options: CodegenOptions) -> Self {
-        let kernel_gen = KernelGenerator::with_config(options.kernel_config.clone());
+        let mut kernel_gen = KernelGenerator::with_config(options.kernel_config.clone());
+        kernel_gen.set_emit_comments(options.emit_comments);
         Self {
             options,
             kernel_gen,
+            stmt_gen: StmtGenerator::new(),
+            diagnostics: Vec::new(),
+            opt_report: OptReport::new(),
         }
     }
 
     pub fn generate(&mut self, program: &Program) -> Result<String> {
         let mut output = String::new();
+        self.diagnostics.clear();
+        if let Some(module) = &self.options.module_id {
+            link::check_module_id(module)?;
+        }
 
         self.generate_header(&mut output)?;
 
-        let mut kernels = Vec::new();
-        let mut schedules = std::collections::HashMap::new();
+        let mut program = program.clone();
+        let consts = fold::program_int_consts(&program);
+        fold::resolve_array_sizes(&mut program, &consts);
+        self.diagnostics
+            .extend(flare::lint::program_warnings(&program));
+        purity::check_pure_functions(&program.items)?;
+        for item in &mut program.items {
+            if let Stmt::Kernel(kernel) = item {
+                barrier::apply_auto_barriers(kernel);
+                barrier::check_divergent_barriers(kernel)?;
+            }
+        }
+        self.opt_report = PassManager::for_opt_level(self.options.opt_level).run(&mut program)?;
+        let program = &program;
+
+        let call_graph = CallGraph::build(program)?;
+        call_graph.check_recursion()?;
+
+        let type_defs = link::type_def_order(program)?;
+        let aliases: HashSet<String> = type_defs.iter().map(|(name, _)| name.to_string()).collect();
+        self.set_type_aliases(&aliases);
+        for (name, ty) in &type_defs {
+            let ty = types::TypeConverter::convert_with(ty, program.span.clone(), &aliases)?;
+            // array extents follow the declared name: `typedef float Row[4];`
+            match ty.as_str().split_once('[') {
+                Some((elem, extent)) => {
+                    writeln!(&mut output, "typedef {} {}[{};", elem, name, extent)?
+                }
+                None => writeln!(&mut output, "typedef {} {};", ty.as_str(), name)?,
+            }
+        }
+        if !type_defs.is_empty() {
+            writeln!(&mut output)?;
+        }
+
+        let mut globals = String::new();
 
         for stmt in &program.items {
             match stmt {
-                Stmt::Kernel(kernel) => {
-                    kernels.push(kernel);
+                Stmt::Const { .. } => {
+                    globals.push_str(&self.stmt_gen.generate(stmt)?);
                 }
-                Stmt::Schedule(schedule) => {
-                    if let Some(target) = schedule.target {
-                        schedules.insert(target, schedule);
-                    }
-                }
-                Stmt::Fusion(_) => {}
+                Stmt::Kernel(_)
+                | Stmt::Schedule(_)
+                | Stmt::Function { .. }
+                | Stmt::Fusion(_)
+                | Stmt::TypeDef { .. } => {}
                 _ => {
                     return Err(CodegenError::statement_error(
-                        "only kernel, schedule, and fusion statements allowed at top level",
+                        "only kernel, fn, type, const, schedule, and fusion statements allowed at top level",
                         stmt.span(),
                     ));
                 }
             }
         }
 
-        for kernel in kernels {
-            let schedule = schedules.get(kernel.name).copied();
-            let kernel_code = self.kernel_gen.generate(kernel, schedule)?;
+        if !globals.is_empty() {
+            writeln!(&mut output, "{}", globals)?;
+        }
+
+        self.kernel_gen.set_consts(consts);
+        let methods = link::method_table(program);
+        self.stmt_gen.set_methods(methods.clone());
+        self.kernel_gen.set_methods(methods);
+        let symbols = match &self.options.module_id {
+            Some(module) => link::function_symbols(program, module),
+            None => HashMap::new(),
+        };
+        self.stmt_gen.set_function_symbols(symbols.clone());
+        self.kernel_gen.set_function_symbols(symbols);
+
+        for function in call_graph.emission_order() {
+            let function_code = self.stmt_gen.generate(function)?;
+            writeln!(&mut output, "{}", function_code)?;
+        }
+
+        for (kernel, schedule) in resolve::resolve_targets(program)? {
+            let mut kernel = kernel.clone();
+            let jam_factor = schedule
+                .into_iter()
+                .flat_map(|schedule| &schedule.directives)
+                .find_map(|directive| match directive {
+                    ScheduleDirective::UnrollJam(factor) => Some(*factor),
+                    _ => None,
+                });
+            if let Some(factor) = jam_factor {
+                unroll_jam::unroll_and_jam(&mut kernel, factor)?;
+            }
+            let kernel_code = self.kernel_gen.generate(&kernel, schedule)?;
             writeln!(&mut output, "{}", kernel_code)?;
         }
 
+        self.diagnostics.extend(self.stmt_gen.take_diagnostics());
+        self.diagnostics.extend(self.kernel_gen.take_diagnostics());
+
         Ok(output)
     }
 
+    /// Provides the flare source the program was parsed from, so debug
+    /// builds and `#line` directives can map generated code back to source
+    /// lines.
+    pub fn set_source(&mut self, source: &str) {
+        let config = &self.options.kernel_config;
+        let line_map =
+            (config.emit_debug || config.emit_line_directives).then(|| LineMap::new(source));
+        self.kernel_gen.set_line_map(line_map.clone());
+        if config.emit_debug {
+            self.stmt_gen.set_line_map(line_map);
+        }
+    }
+
+    /// Builds the host-dispatch metadata for `program`; serialize it with
+    /// `ProgramMetadata::to_json`.
+    pub fn generate_metadata(&mut self, program: &Program) -> Result<ProgramMetadata> {
+        let mut program = program.clone();
+        let consts = fold::program_int_consts(&program);
+        fold::resolve_array_sizes(&mut program, &consts);
+
+        let aliases = link::type_def_order(&program)?
+            .into_iter()
+            .map(|(name, _)| name.to_string())
+            .collect();
+        self.set_type_aliases(&aliases);
+        self.kernel_gen.set_consts(consts);
+        ProgramMetadata::build(&program, &mut self.kernel_gen)
+    }
+
+    fn set_type_aliases(&mut self, aliases: &HashSet<String>) {
+        self.stmt_gen.set_type_aliases(aliases.clone());
+        self.kernel_gen.set_type_aliases(aliases.clone());
+    }
+
+    /// Notes and warnings produced by the last call to `generate`.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// What the optimization passes changed in the last call to `generate`.
+    pub fn opt_report(&self) -> &OptReport {
+        &self.opt_report
+    }
+
     fn generate_header(&self, output: &mut String) -> Result<()> {
         if self.options.emit_comments {
             writeln!(output, "// generated by Flare")?;
@@ -130,6 +277,24 @@ pub fn compile_with_options(program: &Program, options: CodegenOptions) -> Resul
     codegen.generate(program)
 }
 
+/// Like `compile_with_options`, also returning what the optimization passes
+/// changed.
+pub fn compile_with_report(
+    program: &Program,
+    options: CodegenOptions,
+) -> Result<(String, OptReport)> {
+    let mut codegen = MetalCodegen::with_options(options);
+    let output = codegen.generate(program)?;
+    Ok((output, codegen.opt_report))
+}
+
+/// C header describing how to launch each kernel of `program`; see
+/// `ProgramMetadata::to_c_header`.
+pub fn generate_c_header(program: &Program) -> Result<String> {
+    let mut codegen = MetalCodegen::new();
+    Ok(codegen.generate_metadata(program)?.to_c_header())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -159,4 +324,2501 @@ mod tests {
         let program = Flare::compile_from_string(source).expect("failed to parse kernel");
         let metal_code = compile(&program).expect("failed to generate Metal code");
     }
+
+    #[test]
+    fn test_labeled_break_codegen() {
+        let source = r#"
+            kernel find(A: Tensor<f32, [N, N]>) {
+                'rows: for i in 0..N {
+                    for j in 0..N {
+                        if A[i, j] > 0.0 {
+                            break 'rows;
+                        }
+                    }
+                }
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+
+        assert!(metal_code.contains("bool flare_break_rows = false;"));
+        assert!(metal_code.contains("flare_break_rows = true;"));
+        assert!(metal_code.contains("if (flare_break_rows) break;"));
+        assert!(!metal_code.contains("goto"));
+    }
+
+    #[test]
+    fn test_gather_codegen() {
+        let source = r#"
+            kernel sparse_read(values: Tensor<f32, [N]>, indices: Tensor<i32, [M]>, out: Tensor<f32, [M]>) {
+                let i = thread_idx.x
+                let j = gather(indices, i)
+                out[i] = gather(values, j)
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+
+        assert!(metal_code.contains("const auto j = indices[i];"));
+        assert!(metal_code.contains("out[i] = values[j];"));
+    }
+
+    #[test]
+    fn test_scatter_codegen() {
+        let source = r#"
+            kernel sparse_write(values: Tensor<f32, [N]>, indices: Tensor<i32, [M]>) {
+                let i = thread_idx.x
+                scatter(values, gather(indices, i), 1.0)
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+        assert!(metal_code.contains(
+            "    {\n        const auto flare_scatter_idx = indices[i];\n        \
+             if (flare_scatter_idx >= 0 && flare_scatter_idx < N) values[flare_scatter_idx] = 1.0f;\n    }\n"
+        ));
+
+        let unsigned_index = r#"
+            kernel sparse_write(values: Tensor<f32, [N]>, j: u32) {
+                scatter(values, j, 1.0)
+            }
+        "#;
+        let program = Flare::compile_from_string(unsigned_index).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+        assert!(metal_code.contains("if (flare_scatter_idx < N) values[flare_scatter_idx] = 1.0f;"));
+
+        let bad_index = r#"
+            kernel sparse_write(values: Tensor<f32, [N]>) {
+                scatter(values, 0.5, 1.0)
+            }
+        "#;
+        let program = Flare::compile_from_string(bad_index).expect("failed to parse kernel");
+        assert!(matches!(
+            compile(&program),
+            Err(CodegenError::ExpressionError { .. })
+        ));
+    }
+
+    #[test]
+    fn test_transpose_codegen() {
+        let source = r#"
+            kernel rotate(m: Matrix<f32, 3, 3>) {
+                let mt = transpose(m)
+                let back = transpose(mt)
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+        assert!(metal_code.contains("const auto mt = transpose(m);"));
+        assert!(metal_code.contains("const auto back = transpose(mt);"));
+
+        let tensor_source = r#"
+            kernel rotate(A: Tensor<f32, [N, N]>) {
+                let at = transpose(A)
+            }
+        "#;
+        let program = Flare::compile_from_string(tensor_source).expect("failed to parse kernel");
+        match compile(&program) {
+            Err(CodegenError::UnsupportedFeature { suggestion, .. }) => {
+                assert!(suggestion.unwrap().contains("host"));
+            }
+            other => panic!("expected unsupported feature error, got {:?}", other),
+        }
+
+        let unknown_source = r#"
+            kernel rotate(A: Tensor<f32, [N]>) {
+                let at = transpose(unknown)
+            }
+        "#;
+        let program = Flare::compile_from_string(unknown_source).expect("failed to parse kernel");
+        let err = compile(&program).expect_err("expected a matrix operand error");
+        assert!(err.to_string().contains("transpose expects a matrix"));
+    }
+
+    #[test]
+    fn test_dot_cross_codegen() {
+        let source = r#"
+            kernel shade(n: Vector<f32, 3>, l: Vector<f32, 3>, out: Tensor<f32, [N]>) {
+                let i = thread_idx.x
+                let d = dot(n, l)
+                let c = cross(n, l)
+                out[i] = d + dot(c, n)
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+        assert!(metal_code.contains("const auto d = dot(n, l);"));
+        assert!(metal_code.contains("const auto c = cross(n, l);"));
+        assert!(metal_code.contains("out[i] = (d + dot(c, n));"));
+    }
+
+    #[test]
+    fn test_dot_cross_length_mismatch() {
+        let mismatched = r#"
+            kernel shade(a: Vector<f32, 3>, b: Vector<f32, 4>) {
+                let d = dot(a, b)
+            }
+        "#;
+        let program = Flare::compile_from_string(mismatched).expect("failed to parse kernel");
+        match compile(&program) {
+            Err(CodegenError::ExpressionError { message, .. }) => {
+                assert!(message.contains("same length"), "{}", message);
+            }
+            other => panic!("expected length mismatch error, got {:?}", other),
+        }
+
+        let not_3d = r#"
+            kernel shade(a: Vector<f32, 4>, b: Vector<f32, 4>) {
+                let c = cross(a, b)
+            }
+        "#;
+        let program = Flare::compile_from_string(not_3d).expect("failed to parse kernel");
+        assert!(matches!(
+            compile(&program),
+            Err(CodegenError::ExpressionError { .. })
+        ));
+    }
+
+    #[test]
+    fn test_program_scope_const() {
+        let source = r#"
+            const TILE: i32 = 16;
+
+            kernel first(A: Tensor<f32, [N]>) {
+                let i = thread_idx.x * TILE
+            }
+
+            kernel second(A: Tensor<f32, [N]>) {
+                let j = thread_idx.x + TILE
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+
+        assert_eq!(metal_code.matches("constant int TILE = 16;").count(), 1);
+        let const_pos = metal_code.find("constant int TILE").unwrap();
+        let kernel_pos = metal_code.find("kernel void first").unwrap();
+        assert!(const_pos < kernel_pos);
+    }
+
+    #[test]
+    fn test_kernel_return_value_rejected() {
+        let source = r#"
+            kernel reduce(A: Tensor<f32, [N]>) {
+                var sum: f32 = 0.0
+                for i in 0..N {
+                    if A[i] < 0.0 {
+                        return;
+                    }
+                    sum = sum + A[i]
+                }
+                return sum;
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        match compile(&program) {
+            Err(CodegenError::StatementError { message, span }) => {
+                assert!(message.contains("output"), "{}", message);
+                assert_eq!(&source[span], "return sum;");
+            }
+            other => panic!("expected return value error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_function_return_value_allowed() {
+        let source = r#"
+            fn clamp01(x: f32) -> f32 {
+                if x < 0.0 {
+                    return 0.0;
+                }
+                return x;
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse function");
+        let mut stmt_gen = StmtGenerator::new();
+        let function_code = stmt_gen
+            .generate(&program.items[0])
+            .expect("failed to generate function");
+
+        assert!(function_code.starts_with("float clamp01(float x) {"));
+        assert!(function_code.contains("return 0.0f;"));
+        assert!(function_code.contains("return x;"));
+    }
+
+    #[test]
+    fn test_kernel_calls_user_function() {
+        let source = r#"
+            fn scale(x: f32) -> f32 {
+                return square(x) * 2.0;
+            }
+
+            fn square(x: f32) -> f32 {
+                return x * x;
+            }
+
+            kernel apply(A: Tensor<f32, [N]>) {
+                let i = thread_id.x
+                A[i] = scale(A[i])
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse program");
+        let mut codegen = MetalCodegen::new();
+        let msl = codegen.generate(&program).expect("failed to generate code");
+
+        let square_at = msl
+            .find("float square(float x)")
+            .expect("square not emitted");
+        let scale_at = msl.find("float scale(float x)").expect("scale not emitted");
+        let kernel_at = msl.find("kernel void apply").expect("kernel not emitted");
+        assert!(square_at < scale_at);
+        assert!(scale_at < kernel_at);
+        assert!(msl.contains("scale(A[i])"));
+    }
+
+    #[test]
+    fn test_unknown_function_call_rejected() {
+        let source = r#"
+            kernel apply(A: Tensor<f32, [N]>) {
+                let i = thread_id.x
+                A[i] = missing(A[i])
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse program");
+        let mut codegen = MetalCodegen::new();
+        let err = codegen.generate(&program).unwrap_err();
+        assert!(err.to_string().contains("missing"));
+    }
+
+    #[test]
+    fn test_recursive_functions_rejected() {
+        let self_recursive = r#"
+            fn fact(n: i32) -> i32 {
+                return n * fact(n - 1);
+            }
+        "#;
+        let program = Flare::compile_from_string(self_recursive).expect("failed to parse program");
+        let err = compile(&program).unwrap_err();
+        assert!(err.to_string().contains("fact -> fact"));
+
+        let mutually_recursive = r#"
+            fn is_even(n: i32) -> bool {
+                return is_odd(n - 1);
+            }
+
+            fn is_odd(n: i32) -> bool {
+                return is_even(n - 1);
+            }
+        "#;
+        let program =
+            Flare::compile_from_string(mutually_recursive).expect("failed to parse program");
+        let err = compile(&program).unwrap_err();
+        assert!(err.to_string().contains("is_even -> is_odd -> is_even"));
+    }
+
+    #[test]
+    fn test_ascending_and_descending_loops() {
+        let source = r#"
+            kernel scan(A: Tensor<f32, [N]>) {
+                for i in 0..N {
+                    A[i] = 0.0
+                }
+                for i in 0..N step 2 {
+                    A[i] = 1.0
+                }
+                for i in (0..N).rev() {
+                    A[i] = 2.0
+                }
+                for i in N..0 step -1 {
+                    A[i] = 3.0
+                }
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+
+        assert!(metal_code.contains("for (int i = 0; i < N; i++) {"));
+        assert!(metal_code.contains("for (int i = 0; i < N; i += 2) {"));
+        assert!(metal_code.contains("for (int i = N - 1; i >= 0; i--) {"));
+        assert!(metal_code.contains("for (int i = N; i > 0; i--) {"));
+    }
+
+    #[test]
+    fn test_loop_step_direction_mismatch() {
+        let source = r#"
+            kernel scan(A: Tensor<f32, [N]>) {
+                for i in 0..8 step -1 {
+                    A[i] = 0.0
+                }
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        assert!(matches!(
+            compile(&program),
+            Err(CodegenError::StatementError { .. })
+        ));
+    }
+
+    #[test]
+    fn test_int_float_promotion_note() {
+        let source = r#"
+            kernel mix(A: Tensor<f32, [N]>, n: i32, scale: f32) {
+                let i = thread_idx.x
+                A[i] = n + scale
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let mut codegen = MetalCodegen::new();
+        let metal_code = codegen
+            .generate(&program)
+            .expect("failed to generate Metal code");
+
+        assert!(metal_code.contains("A[i] = (n + scale);"));
+        let notes: Vec<_> = codegen
+            .diagnostics()
+            .iter()
+            .filter(|d| d.severity == flare::Severity::Note)
+            .collect();
+        assert_eq!(notes.len(), 1);
+        assert!(notes[0]
+            .message
+            .contains("'int' operand promoted to 'float'"));
+    }
+
+    #[test]
+    fn test_implicit_narrowing_rejected() {
+        let source = r#"
+            kernel truncate(x: f32) {
+                let n: i32 = x
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let err = compile(&program).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("cannot implicitly convert 'float' to 'int'"));
+
+        let cast = r#"
+            kernel truncate(x: f32) {
+                let n: i32 = x as i32
+            }
+        "#;
+
+        let program = Flare::compile_from_string(cast).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+        assert!(metal_code.contains("const int n = int(x);"));
+    }
+
+    #[test]
+    fn test_symbolic_block_size_warns() {
+        let source = r#"
+            kernel tiled(A: Tensor<f32, [N]>) {
+                block: [BLK]
+            }
+
+            kernel fixed(A: Tensor<f32, [N]>) {
+                block: [16, 8]
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernels");
+        let kernels: Vec<_> = program
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                Stmt::Kernel(kernel) => Some(kernel),
+                _ => None,
+            })
+            .collect();
+
+        let config = KernelConfig::default();
+        let kernel_gen = KernelGenerator::with_config(config.clone());
+        let size = kernel_gen.get_threadgroup_size(kernels[0], None);
+        assert_eq!(size, config.default_threadgroup_size);
+        assert_eq!(
+            kernel_gen.get_threadgroup_size(kernels[1], None),
+            (16, 8, 1)
+        );
+
+        let mut codegen = MetalCodegen::new();
+        let metal_code = codegen
+            .generate(&program)
+            .expect("failed to generate Metal code");
+        assert!(metal_code.contains("// threadgroup size: (16, 8, 1)"));
+        let diagnostics = codegen.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, flare::Severity::Warning);
+        assert!(diagnostics[0].message.contains("unresolved"));
+
+        let metal_code = compile_with_options(
+            &program,
+            CodegenOptions {
+                emit_comments: false,
+                ..Default::default()
+            },
+        )
+        .expect("failed to generate Metal code");
+        assert!(!metal_code.contains("// threadgroup size"));
+    }
+
+    #[test]
+    fn test_memory_directive_places_variable() {
+        let source = r#"
+            kernel tiled(A: Tensor<f32, [N]>) {
+                let i = thread_idx.x
+                var tile: f32 = A[i]
+                var acc: f32 = 0.0
+            }
+
+            schedule tiled {
+                memory(tile, shared)
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+
+        assert!(metal_code.contains("threadgroup float tile;"));
+        assert!(metal_code.contains("tile = A[i];"));
+        assert!(metal_code.contains("float acc = 0.0f;"));
+        assert!(!metal_code.contains("threadgroup float acc"));
+    }
+
+    #[test]
+    fn test_memory_directive_rejects_invalid_placements() {
+        let placement_error = |body: &str, location: &str| {
+            let source = format!(
+                "kernel k(A: Tensor<f32, [N]>) {{\n{}\n}}\nschedule k {{ memory(tile, {}) }}",
+                body, location
+            );
+            let program = Flare::compile_from_string(&source).expect("failed to parse kernel");
+            match compile(&program) {
+                Err(CodegenError::InvalidMemoryConfig { message, .. }) => message,
+                other => panic!("expected a memory placement error, got {:?}", other),
+            }
+        };
+
+        let top_level = "let i = thread_idx.x\nvar tile: f32 = A[i]";
+        assert!(placement_error(top_level, "global").contains("device memory"));
+        assert!(placement_error(top_level, "streaming").contains("device memory"));
+        assert!(placement_error(top_level, "constant").contains("constant memory"));
+
+        let nested = "for i in 0..N {\nvar tile: f32 = A[i]\n}";
+        assert!(placement_error(nested, "shared").contains("must be declared at kernel scope"));
+    }
+
+    #[test]
+    fn test_stream_metadata_grouping() {
+        let source = r#"
+            kernel load(A: Tensor<f32, [N]>) {
+                block: [64]
+            }
+
+            kernel scale(A: Tensor<f32, [N]>) {}
+
+            kernel store(A: Tensor<f32, [N]>) {}
+
+            kernel reduce(A: Tensor<f32, [N]>) {}
+
+            schedule load {
+                stream(a)
+            }
+
+            schedule scale {
+                stream(a)
+            }
+
+            schedule store {
+                stream(b)
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernels");
+        let mut codegen = MetalCodegen::new();
+        let metadata = codegen
+            .generate_metadata(&program)
+            .expect("failed to build metadata");
+
+        assert_eq!(metadata.streams["a"], vec!["load", "scale"]);
+        assert_eq!(metadata.streams["b"], vec!["store"]);
+        assert_eq!(metadata.streams[metadata::DEFAULT_STREAM], vec!["reduce"]);
+        assert_eq!(metadata.kernels[0].threadgroup_size, [64, 1, 1]);
+
+        let json = metadata.to_json().expect("failed to serialize metadata");
+        assert!(json.contains("\"streams\""));
+    }
+
+    #[test]
+    fn test_half_precision_stays_half() {
+        let source = r#"
+            kernel softplus(A: Tensor<half, [N]>, a: half, b: half) {
+                let i = thread_idx.x
+                let sum = a + b
+                let scaled: half = 0.5
+                A[i] = exp(sum * 2.0) + scaled
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+
+        assert!(metal_code.contains("const auto sum = (a + b);"));
+        assert!(metal_code.contains("const half scaled = 0.5h;"));
+        assert!(metal_code.contains("A[i] = (metal::exp((sum * 2.0h)) + scaled);"));
+        assert!(!metal_code.contains("2.0f"));
+    }
+
+    #[test]
+    fn test_address_of_codegen() {
+        let source = r#"
+            kernel refs(out: Tensor<f32, [N]>) {
+                let i = thread_idx.x
+                let p = &out[i]
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+        assert!(metal_code.contains("const auto p = &(out[i]);"));
+
+        let temporary = r#"
+            kernel refs(a: f32, b: f32) {
+                let p = &(a + b)
+            }
+        "#;
+        let program = Flare::compile_from_string(temporary).expect("failed to parse kernel");
+        assert!(matches!(
+            compile(&program),
+            Err(CodegenError::ExpressionError { .. })
+        ));
+    }
+
+    #[test]
+    fn test_deref_codegen() {
+        let source = r#"
+            kernel ptrs(p: *f32, out: Tensor<f32, [N]>) {
+                let i = thread_idx.x
+                out[i] = *p * 2.0
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+        assert!(metal_code.contains("out[i] = ((*p) * 2.0f);"));
+
+        let not_pointer = r#"
+            kernel ptrs(x: f32) {
+                let y = *x;
+            }
+        "#;
+        let program = Flare::compile_from_string(not_pointer).expect("failed to parse kernel");
+        let err = compile(&program).unwrap_err();
+        assert!(err.to_string().contains("cannot dereference"));
+    }
+
+    #[test]
+    fn test_discard_binding() {
+        let source = r#"
+            kernel discard(A: Tensor<f32, [N]>, x: f32) {
+                let _ = x;
+                for _ in 0..N {
+                    A[0] = x
+                }
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+        assert!(metal_code.contains("(void)x;"));
+        assert!(!metal_code.contains("auto _"));
+
+        let kernel = match &program.items[0] {
+            Stmt::Kernel(kernel) => kernel,
+            other => panic!("expected kernel, found {:?}", other),
+        };
+        let mut stmt_gen = StmtGenerator::new();
+        stmt_gen.declare("x", Some(&flare::ast::Type::F32(0..0)));
+        stmt_gen
+            .generate(&kernel.body[0])
+            .expect("failed to generate discard");
+        assert!(stmt_gen.symbols().lookup("_").is_none());
+        assert!(stmt_gen.symbols().lookup("x").is_some());
+    }
+
+    #[test]
+    fn test_debug_line_comments() {
+        let source = "kernel copy(A: Tensor<f32, [N]>, B: Tensor<f32, [N]>) {\n    let i = thread_idx.x\n    B[i] = A[i]\n}\n";
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let mut options = CodegenOptions::default();
+        options.kernel_config.emit_debug = true;
+        let mut codegen = MetalCodegen::with_options(options);
+        codegen.set_source(source);
+        let metal_code = codegen
+            .generate(&program)
+            .expect("failed to generate Metal code");
+
+        assert!(metal_code
+            .contains("    // line 2\n    const auto i = thread_position_in_threadgroup.x;"));
+        assert!(metal_code.contains("    // line 3\n    B[i] = A[i];"));
+
+        let release_code = compile(&program).expect("failed to generate Metal code");
+        assert!(!release_code.contains("// line"));
+    }
+
+    #[test]
+    fn test_const_generic_specialization() {
+        let source = r#"
+            @specialize(TILE=16,32)
+            kernel matmul<const TILE: i32>(A: Tensor<f32, [N]>) {
+                let i = thread_idx.x
+                A[i] = A[i] * TILE
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+
+        assert!(metal_code.contains("kernel void matmul_16("));
+        assert!(metal_code.contains("kernel void matmul_32("));
+        assert!(metal_code.contains("const int TILE = 16;"));
+        assert!(metal_code.contains("const int TILE = 32;"));
+        assert!(!metal_code.contains("kernel void matmul("));
+
+        let unspecialized = r#"
+            kernel matmul<const TILE: i32>(A: Tensor<f32, [N]>) {}
+        "#;
+        let program = Flare::compile_from_string(unspecialized).expect("failed to parse kernel");
+        assert!(matches!(
+            compile(&program),
+            Err(CodegenError::InvalidKernelConfig { .. })
+        ));
+    }
+
+    #[test]
+    fn test_function_constant() {
+        let source = r#"
+            @function_constant(0)
+            const TILE: i32 = 16
+
+            kernel scale(A: Tensor<f32, [N]>) {
+                let i = thread_idx.x
+                A[i] = A[i] * TILE
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+
+        assert!(metal_code.contains("constant int TILE [[function_constant(0)]];"));
+        assert!(!metal_code.contains("TILE = 16"));
+    }
+
+    #[test]
+    fn test_vector_constructor_arity() {
+        let source = r#"
+            kernel colors(A: Tensor<f32, [N]>) {
+                let i = thread_idx.x
+                let v = float4(1.0, 2.0, 3.0, 4.0)
+                let lo = float2(1.0, 2.0)
+                let w = float4(lo, lo)
+                A[i] = v.x + w.y
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+        assert!(metal_code.contains("const auto v = float4(1.0f, 2.0f, 3.0f, 4.0f);"));
+        assert!(metal_code.contains("const auto w = float4(lo, lo);"));
+
+        let short = r#"
+            kernel colors(A: Tensor<f32, [N]>) {
+                let v = float4(1.0, 2.0)
+            }
+        "#;
+        let program = Flare::compile_from_string(short).expect("failed to parse kernel");
+        match compile(&program) {
+            Err(err @ CodegenError::ExpressionError { .. }) => {
+                assert!(err
+                    .to_string()
+                    .contains("float4 expects 4 components, got 2"));
+            }
+            other => panic!("expected arity error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_threadgroup_memory_limit() {
+        let source = r#"
+            kernel tiled(A: Tensor<f32, [N]>) {
+                shared_memory {
+                    tile: [16, 16]: f32
+                }
+                let i = thread_idx.x
+                A[i] = tile[i]
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+        assert!(metal_code.contains("threadgroup float tile[256];"));
+
+        let too_large = r#"
+            kernel tiled(A: Tensor<f32, [N]>) {
+                shared_memory {
+                    tile: [128, 128]: f32
+                }
+            }
+        "#;
+        let program = Flare::compile_from_string(too_large).expect("failed to parse kernel");
+        match compile(&program) {
+            Err(err @ CodegenError::ResourceLimitExceeded { .. }) => {
+                assert!(err.to_string().contains("65536 bytes"));
+            }
+            other => panic!("expected resource limit error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_block_value_initializer() {
+        let source = r#"
+            kernel blocky(A: Tensor<f32, [N]>, B: Tensor<f32, [N]>) {
+                let i = thread_idx.x
+                let y = {
+                    let t = A[i] * B[i]
+                    t + 1.0
+                }
+                A[i] = y
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+
+        assert!(metal_code.contains(
+            "    const auto y = ({\n        const auto t = (A[i] * B[i]);\n        (t + 1.0f);\n    });"
+        ));
+    }
+
+    #[test]
+    fn test_if_expr_min_max_lowering() {
+        let source = r#"
+            kernel clampish(A: Tensor<f32, [N]>, B: Tensor<f32, [N]>) {
+                let i = thread_idx.x
+                let a = A[i]
+                let b = B[i]
+                let hi = if a > b { a } else { b }
+                let lo = if a > b { b } else { a }
+                let lo2 = if a <= b { a } else { b }
+                let other = if a > b { b + 1.0 } else { a }
+                A[i] = hi + lo + lo2 + other
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+
+        assert!(metal_code.contains("const auto hi = max(a, b);"));
+        assert!(metal_code.contains("const auto lo = min(a, b);"));
+        assert!(metal_code.contains("const auto lo2 = min(a, b);"));
+        assert!(metal_code.contains("const auto other = ((a > b) ? (b + 1.0f) : a);"));
+    }
+
+    #[test]
+    fn test_pack_uniforms() {
+        let source = r#"
+            @pack_uniforms
+            kernel saxpy(X: Tensor<f32, [N]>, alpha: f32, beta: f32, n: u32) {
+                let i = thread_idx.x
+                if i < n {
+                    X[i] = alpha * X[i] + beta
+                }
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+
+        assert!(metal_code.contains(
+            "struct saxpy_Uniforms {\n    float alpha;\n    float beta;\n    uint n;\n};"
+        ));
+        assert!(metal_code.contains("device float *X [[buffer(0)]]"));
+        assert!(metal_code.contains("constant saxpy_Uniforms& u [[buffer(1)]]"));
+        assert!(!metal_code.contains("[[buffer(2)]]"));
+        assert!(metal_code.contains("((u.alpha * X[i]) + u.beta)"));
+        assert!(metal_code.contains("(i < u.n)"));
+
+        let metadata = MetalCodegen::new()
+            .generate_metadata(&program)
+            .expect("failed to build metadata");
+        let buffers = &metadata.kernels[0].buffers;
+        assert_eq!(buffers.len(), 2);
+        assert_eq!(buffers[1].name, "u");
+    }
+
+    #[test]
+    fn test_empty_kernel_bodies() {
+        let source = r#"
+            kernel noop() {}
+
+            kernel launch_only(A: Tensor<f32, [N]>) {
+                grid: [N]
+                block: [64]
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+
+        assert!(metal_code.contains("kernel void noop("));
+        assert!(metal_code.contains("kernel void launch_only("));
+        assert_eq!(
+            metal_code
+                .matches("[[threads_per_threadgroup]])\n{\n}\n")
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_shared_only_kernel() {
+        let source = r#"
+            kernel staging() {
+                shared_memory {
+                    tile: [16]: f32
+                }
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+
+        assert!(metal_code.contains("{\n    threadgroup float tile[16];\n}\n"));
+    }
+
+    #[test]
+    fn test_type_aliases_emitted_once_in_dependency_order() {
+        let source = r#"
+            type Pixels = Real[4]
+            type Real = f32
+            type Real = f32
+
+            kernel brighten(A: Tensor<Real, [N]>) {
+                let i = thread_idx.x
+                A[i] = A[i] * 2.0
+            }
+
+            kernel darken(A: Tensor<Real, [N]>) {
+                let i = thread_idx.x
+                let p: Pixels = [A[i], A[i], A[i], A[i]]
+                A[i] = p[0] * 0.5
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+
+        assert_eq!(metal_code.matches("typedef float Real;").count(), 1);
+        let real = metal_code.find("typedef float Real;").unwrap();
+        let pixels = metal_code.find("typedef Real Pixels[4];").unwrap();
+        assert!(real < pixels);
+        assert!(metal_code.contains("device Real *A [[buffer(0)]]"));
+
+        let conflicting = r#"
+            type Real = f32
+            type Real = f64
+        "#;
+        let program = Flare::compile_from_string(conflicting).expect("failed to parse types");
+        assert!(matches!(
+            compile(&program),
+            Err(CodegenError::InvalidIdentifier { .. })
+        ));
+    }
+
+    #[test]
+    fn test_buffer_index_base_and_reserved_slots() {
+        let source = r#"
+            kernel add(A: Tensor<f32, [N]>, B: Tensor<f32, [N]>, C: Tensor<f32, [N]>) {
+                let i = thread_idx.x
+                C[i] = A[i] + B[i]
+            }
+        "#;
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+
+        let mut options = CodegenOptions::default();
+        options.kernel_config.buffer_index_base = 2;
+        options.kernel_config.reserved_buffers = vec![3];
+        let metal_code =
+            compile_with_options(&program, options.clone()).expect("failed to generate Metal code");
+
+        assert!(metal_code.contains("device float *A [[buffer(2)]]"));
+        assert!(metal_code.contains("device float *B [[buffer(4)]]"));
+        assert!(metal_code.contains("device float *C [[buffer(5)]]"));
+
+        let metadata = MetalCodegen::with_options(options)
+            .generate_metadata(&program)
+            .expect("failed to build metadata");
+        let indices: Vec<usize> = metadata.kernels[0]
+            .buffers
+            .iter()
+            .map(|b| b.index)
+            .collect();
+        assert_eq!(indices, [2, 4, 5]);
+    }
+
+    #[test]
+    fn test_fn_emitted_as_device_function() {
+        let source = r#"
+            fn add(a: f32, b: f32) -> f32 {
+                a + b
+            }
+
+            kernel sum(A: Tensor<f32, [N]>, B: Tensor<f32, [N]>) {
+                let i = thread_idx.x
+                A[i] = add(A[i], B[i])
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse program");
+        let msl = compile(&program).expect("failed to generate code");
+
+        let add_at = msl
+            .find("float add(float a, float b) {\n")
+            .expect("add not emitted");
+        let kernel_at = msl.find("kernel void sum(").expect("kernel not emitted");
+        assert!(add_at < kernel_at);
+        assert!(!msl.contains("kernel float add"));
+        assert!(msl[add_at..kernel_at].contains("return (a + b);"));
+        assert!(!msl[add_at..kernel_at].contains("thread_position_in_threadgroup"));
+        assert!(msl.contains("A[i] = add(A[i], B[i]);"));
+    }
+
+    #[test]
+    fn test_line_directives_before_kernels() {
+        let source = "kernel first(A: Tensor<f32, [N]>) {\n    let i = thread_idx.x\n}\n\nkernel second(A: Tensor<f32, [N]>) {\n    let i = thread_idx.x\n}\n";
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let mut options = CodegenOptions::default();
+        options.kernel_config.emit_line_directives = true;
+        let mut codegen = MetalCodegen::with_options(options);
+        codegen.set_source(source);
+        let metal_code = codegen
+            .generate(&program)
+            .expect("failed to generate Metal code");
+
+        assert!(metal_code.contains("#line 1 \"source.flare\"\nkernel void first("));
+        assert!(metal_code.contains("#line 5 \"source.flare\"\nkernel void second("));
+        assert!(!metal_code.contains("// line"));
+
+        let plain_code = compile(&program).expect("failed to generate Metal code");
+        assert!(!plain_code.contains("#line"));
+    }
+
+    #[test]
+    fn test_block_dim_folds_to_block_config() {
+        let source = r#"
+            kernel scale(A: Tensor<f32, [N]>) {
+                block: [256]
+                let gid = thread_idx.x + block_idx.x * block_dim.x
+                A[gid] = A[gid] * 2.0
+            }
+
+            kernel unsized(A: Tensor<f32, [N]>) {
+                let gid = thread_idx.x + block_idx.x * block_dim.x
+                A[gid] = A[gid] * 2.0
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+
+        assert!(metal_code.contains(
+            "const auto gid = (thread_position_in_threadgroup.x + (threadgroup_position_in_grid.x * 256u));"
+        ));
+        assert_eq!(metal_code.matches("* threads_per_threadgroup.x").count(), 1);
+    }
+
+    #[test]
+    fn test_elementwise_kernel_guard() {
+        let source = r#"
+            @elementwise
+            kernel relu(A: Tensor<f32, [N]>) -> Tensor<f32, [N]> {
+                output = A[gid] * 2.0
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+
+        assert!(metal_code.contains("device float *output [[buffer(1)]]"));
+        assert!(metal_code.contains("uint3 thread_position_in_grid [[thread_position_in_grid]]"));
+        assert!(metal_code.contains(
+            "    const uint gid = thread_position_in_grid.x;\n    if (gid >= N) {\n        return;\n    }\n"
+        ));
+        assert!(metal_code.contains("output[gid] = "));
+
+        let source = r#"
+            @elementwise
+            kernel bad(A: Tensor<f32, [N, N]>) -> Tensor<f32, [N, N]> {
+                output = A[gid]
+            }
+        "#;
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        assert!(matches!(
+            compile(&program),
+            Err(CodegenError::InvalidKernelConfig { .. })
+        ));
+    }
+
+    #[test]
+    fn test_tensor_layout_strides() {
+        let source = r#"
+            kernel col(A: Tensor<f32, [M, K], col_major>, B: Tensor<f32, [M, K]>) {
+                let i = thread_idx.x
+                let j = thread_idx.y
+                A[i, j] = B[i, j]
+            }
+
+            @layout(A, [1, LDA])
+            kernel strided(A: Tensor<f32, [M, K]>) {
+                let i = thread_idx.x
+                let j = thread_idx.y
+                A[i, j] = 0.0
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+
+        assert!(metal_code.contains("A[i + j * M] = B[i * K + j];"));
+        assert!(metal_code.contains("A[i + j * LDA] = 0.0f;"));
+    }
+
+    #[test]
+    fn test_shared_memory_shape_folding() {
+        let source = r#"
+            const TILE = 16
+
+            kernel tiled(A: Tensor<f32, [N]>) {
+                shared_memory {
+                    tile: [TILE, TILE]: f32
+                }
+                let i = thread_idx.x
+                A[i] = 0.0
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+        assert!(metal_code.contains("threadgroup float tile[256];"));
+
+        let source = r#"
+            kernel dynamic_tile(A: Tensor<f32, [N]>) {
+                shared_memory {
+                    tile: [N]: f32
+                }
+                let i = thread_idx.x
+                A[i] = 0.0
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        match compile(&program) {
+            Err(CodegenError::InvalidMemoryConfig { message, span }) => {
+                assert!(message.contains("compile-time constant"), "{}", message);
+                assert_eq!(&source[span], "N");
+            }
+            other => panic!("expected shared memory error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_buffer_argument_limit() {
+        let params: Vec<String> = (0..32)
+            .map(|i| format!("B{}: Tensor<f32, [N]>", i))
+            .collect();
+        let source = format!("kernel wide({}) {{\n}}\n", params.join(", "));
+
+        let program = Flare::compile_from_string(&source).expect("failed to parse kernel");
+        match compile(&program) {
+            Err(CodegenError::ResourceLimitExceeded { message, .. }) => {
+                assert!(message.contains("buffer index 31"), "{}", message);
+            }
+            other => panic!("expected buffer limit error, got {:?}", other),
+        }
+
+        let source = format!("kernel wide({}) {{\n}}\n", params[..31].join(", "));
+        let program = Flare::compile_from_string(&source).expect("failed to parse kernel");
+        assert!(compile(&program).is_ok());
+    }
+
+    #[test]
+    fn test_uniform_alignment() {
+        let source = r#"
+            @pack_uniforms
+            kernel shade(A: Tensor<f32, [N]>, scale: f32, tint: Vector<f32, 3>) {
+                let i = thread_idx.x
+                A[i] = scale
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+
+        assert!(metal_code.contains("    float scale;\n    alignas(16) float3 tint;\n"));
+    }
+
+    #[test]
+    fn test_integer_division_note_and_true_div() {
+        let source = r#"
+            kernel ratio(A: Tensor<f32, [N]>, a: i32, b: i32) {
+                let i = thread_idx.x
+                let q: f32 = a / b
+                let r = true_div(a, b)
+                A[i] = q + r
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let mut codegen = MetalCodegen::new();
+        let metal_code = codegen
+            .generate(&program)
+            .expect("failed to generate Metal code");
+
+        assert!(metal_code.contains("const float q = (a / b);"));
+        assert!(metal_code.contains("const auto r = (float(a) / float(b));"));
+
+        let notes: Vec<_> = codegen
+            .diagnostics()
+            .iter()
+            .filter(|d| d.message.contains("integer division"))
+            .collect();
+        assert_eq!(notes.len(), 1);
+        assert_eq!(&source[notes[0].span.clone()], "a / b");
+    }
+
+    #[test]
+    fn test_compute_block_control_flow_indentation() {
+        let source = r#"
+            kernel rowsum(A: Tensor<f32, [N]>) -> Tensor<f32, [M]> {
+                grid: [M]
+                block: [64]
+
+                compute {
+                    let row = block_idx.x * block_dim.x + thread_idx.x
+                    var acc: f32 = 0.0
+                    var count: i32 = 0
+                    for j in 0..N {
+                        let v = A[j]
+                        if v > 0.0 {
+                            acc = acc + v
+                            count = count + 1
+                        } else {
+                            while count > 8 {
+                                count = count - 1
+                            }
+                        }
+                    }
+                    let mean = acc
+                    output[row] = mean
+                }
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+
+        let body = metal_code
+            .split_once("{\n")
+            .map(|(_, body)| body)
+            .expect("kernel body");
+        let expected = "    const auto row = ((threadgroup_position_in_grid.x * 64u) + thread_position_in_threadgroup.x);
+    float acc = 0.0f;
+    int count = 0;
+    for (int j = 0; j < N; j++) {
+        const auto v = A[j];
+        if ((v > 0.0f)) {
+            acc = (acc + v);
+            count = (count + 1);
+        } else {
+            while ((count > 8)) {
+                count = (count - 1);
+            }
+        }
+    }
+    const auto mean = acc;
+    output[row] = mean;
+}
+";
+        assert_eq!(body.trim_end(), expected.trim_end());
+    }
+
+    #[test]
+    fn test_pure_helper_hoisted_when_optimizing() {
+        let source = r#"
+            @pure
+            fn scale(x: f32) -> f32 {
+                x * 2.0
+            }
+
+            kernel k(A: Tensor<f32, [N]>, s: f32) {
+                for i in 0..N {
+                    let a = scale(s)
+                    A[i] = a
+                }
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let options = CodegenOptions {
+            opt_level: OptLevel::O1,
+            ..CodegenOptions::default()
+        };
+        let metal_code =
+            compile_with_options(&program, options).expect("failed to generate Metal code");
+
+        assert!(metal_code.contains("// pure: no side effects\nfloat scale(float x)"));
+        assert!(metal_code.contains(
+            "    const auto a = scale(s);\n    for (int i = 0; i < N; i++) {\n        A[i] = a;\n"
+        ));
+
+        let effectful = r#"
+            @pure
+            fn store(A: Tensor<f32, [N]>, x: f32) -> f32 {
+                A[0] = x
+                x
+            }
+
+            kernel k(A: Tensor<f32, [N]>, s: f32) {
+                A[1] = store(A, s)
+            }
+        "#;
+        let program = Flare::compile_from_string(effectful).expect("failed to parse kernel");
+        let err = compile(&program).expect_err("expected an impure @pure helper error");
+        match err {
+            CodegenError::InvalidKernel { message, span } => {
+                assert_eq!(message, "@pure function 'store' writes to 'A'");
+                assert_eq!(&effectful[span], "A[0] = x");
+            }
+            other => panic!("expected an invalid kernel error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_pure_helper_call_reused_when_optimizing() {
+        let source = r#"
+            @pure
+            fn scale(x: f32) -> f32 {
+                x * 2.0
+            }
+
+            kernel k(A: Tensor<f32, [N]>, s: f32) {
+                let a = scale(s) + 1.0
+                let b = scale(s) + 1.0
+                A[0] = a * b
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let options = CodegenOptions {
+            opt_level: OptLevel::O1,
+            ..CodegenOptions::default()
+        };
+        let (metal_code, report) =
+            compile_with_report(&program, options).expect("failed to generate Metal code");
+
+        assert!(metal_code.contains("const auto a = (scale(s) + 1.0f);\n    const auto b = a;\n"));
+        assert!(report
+            .lines()
+            .contains(&"reused 1 common subexpression".to_string()));
+    }
+
+    #[test]
+    fn test_unroll_jam_schedule() {
+        let source = r#"
+            kernel nest(A: Tensor<f32, [N]>, B: Tensor<f32, [N]>) {
+                for i in 0..M {
+                    for j in 0..N {
+                        A[j] = A[j] + B[i]
+                    }
+                }
+            }
+
+            schedule nest {
+                unroll_jam(2)
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+
+        assert!(metal_code.contains("// - unroll-and-jam factor: 2"));
+        assert!(metal_code.contains(
+            "    for (int i = 0; i < (M - (M % 2)); i += 2) {\n        for (int j = 0; j < N; j++) {\n            {\n                A[j] = (A[j] + B[i]);\n            }\n            {\n                A[j] = (A[j] + B[(i + 1)]);\n            }\n"
+        ));
+        assert!(metal_code.contains("    for (int i = (M - (M % 2)); i < M; i++) {\n"));
+    }
+
+    #[test]
+    fn test_async_copy_into_shared_memory() {
+        let source = r#"
+            kernel tiled(A: Tensor<f32, [N]>, out: Tensor<f32, [N]>) {
+                shared_memory {
+                    tile: [256]: f32
+                }
+                for t in 0..N {
+                    async_copy(tile, A[t * 256], 256)
+                    out[t] = tile[0]
+                }
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+
+        assert!(metal_code.contains(
+            "        {\n            simdgroup_event copy_event;\n            copy_event.async_copy(tile, &A[(t * 256)], 256);\n            simdgroup_event::wait(1, &copy_event);\n        }\n"
+        ));
+
+        let swapped = source.replace(
+            "async_copy(tile, A[t * 256], 256)",
+            "async_copy(A, tile, 256)",
+        );
+        let program = Flare::compile_from_string(&swapped).expect("failed to parse kernel");
+        let err = compile(&program).expect_err("expected a shared memory destination");
+        assert!(matches!(err, CodegenError::InvalidMemoryConfig { .. }));
+    }
+
+    #[test]
+    fn test_integer_literal_width() {
+        let source = r#"
+            kernel k(A: Tensor<i64, [N]>) {
+                let big: i64 = 5000000000
+                let small: i32 = -2147483648
+                A[0] = big + small
+            }
+        "#;
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+        assert!(metal_code.contains("const long big = 5000000000L;"));
+        assert!(metal_code.contains("const int small = (-2147483648);"));
+
+        let source = r#"
+            kernel k(A: Tensor<i32, [N]>) {
+                let big: i32 = 3000000000
+                A[0] = big
+            }
+        "#;
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let err = compile(&program).expect_err("expected an out of range literal");
+        assert!(err
+            .to_string()
+            .contains("integer literal 3000000000 does not fit in 'int'"));
+    }
+
+    #[test]
+    fn test_strength_reduction_at_o2() {
+        let source = r#"
+            kernel k(A: Tensor<f32, [N]>) {
+                for i in 0..N {
+                    A[i * 8 + i % 4] = 0.0
+                }
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let unoptimized = compile(&program).expect("failed to generate Metal code");
+        assert!(unoptimized.contains("A[((i * 8) + (i % 4))] = 0.0f;"));
+
+        let options = CodegenOptions {
+            opt_level: OptLevel::O2,
+            ..CodegenOptions::default()
+        };
+        let metal_code =
+            compile_with_options(&program, options).expect("failed to generate Metal code");
+        assert!(metal_code.contains("A[((i << 3) + (i & 3))] = 0.0f;"));
+    }
+
+    #[test]
+    fn test_impl_method_call_lowering() {
+        let source = r#"
+            type Point = Vector<f32, 2>
+
+            impl Point {
+                fn length2(p: Point) -> f32 {
+                    return p.x * p.x + p.y * p.y
+                }
+            }
+
+            kernel lengths(out: Tensor<f32, [N]>, p: Point) {
+                let i = thread_idx.x
+                out[i] = p.length2()
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+        assert!(metal_code.contains("float Point_length2(Point p) {"));
+        assert!(metal_code.contains("out[i] = Point_length2(p);"));
+    }
+
+    #[test]
+    fn test_load_shared_with_indices() {
+        let source = r#"
+            const TILE = 16
+
+            kernel tiled(A: Tensor<f32, [N, N]>) {
+                shared_memory {
+                    tile: [TILE, TILE]: f32
+                }
+                let tx = thread_idx.x
+                let ty = thread_idx.y
+                let row = block_idx.y * TILE + ty
+                let col = block_idx.x * TILE + tx
+                load_shared(tile[ty, tx], A[row, col])
+                sync_threads()
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+        assert!(metal_code.contains("tile[(ty * TILE) + tx] = A[(row * N) + col];"));
+
+        let source = source.replace("tile[ty, tx]", "tile[tx]");
+        let program = Flare::compile_from_string(&source).expect("failed to parse kernel");
+        match compile(&program) {
+            Err(CodegenError::InvalidMemoryConfig { message, .. }) => {
+                assert!(message.contains("needs 2 indices, got 1"), "{}", message);
+            }
+            other => panic!("expected an index count error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_staged_statements_reach_codegen() {
+        let source = r#"
+            kernel pipelined(A: Tensor<f32, [N]>, B: Tensor<f32, [N]>) {
+                shared_memory {
+                    tile: [64]: f32
+                }
+                compute {
+                    let t = thread_idx.x
+                    @stage(1) B[t] = tile[t] * 2.0
+                    @stage(0) load_shared(tile[t], A[t])
+                }
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let options = CodegenOptions {
+            opt_level: OptLevel::O2,
+            ..CodegenOptions::default()
+        };
+        let (metal_code, _) =
+            compile_with_report(&program, options).expect("failed to generate Metal code");
+        // stages keep their source order until something pipelines them
+        assert!(
+            metal_code.contains("    B[t] = (tile[t] * 2.0f);\n    tile[t] = A[t];\n"),
+            "{}",
+            metal_code
+        );
+    }
+
+    #[test]
+    fn test_unknown_type_span() {
+        let source = r#"
+            type P = Vector<f32, 2>
+            type P = Vector<f32, 2>
+
+            kernel k(A: Tensor<Widget, [N]>) {
+                A[0] = 0.0
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        match compile(&program) {
+            Err(CodegenError::UnsupportedType { message, span }) => {
+                assert!(message.contains("unknown type 'Widget'"), "{}", message);
+                assert_eq!(&source[span], "Widget");
+            }
+            other => panic!("expected an unknown type error, got {:?}", other),
+        }
+
+        let source = r#"
+            kernel k(m: Matrix<i32, 2, 2>) {}
+        "#;
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        match compile(&program) {
+            Err(CodegenError::UnsupportedType { message, span }) => {
+                assert!(message.contains("only support float/half"), "{}", message);
+                assert_eq!(&source[span], "i32");
+            }
+            other => panic!("expected an unsupported type error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_copy_loop_vectorized_at_o2() {
+        let source = r#"
+            kernel copy(dst: Tensor<f32, [N]>, src: Tensor<f32, [N]>) {
+                for i in 0..4 {
+                    dst[i] = src[i]
+                }
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let options = CodegenOptions {
+            opt_level: OptLevel::O2,
+            ..CodegenOptions::default()
+        };
+        let metal_code =
+            compile_with_options(&program, options).expect("failed to generate Metal code");
+        assert!(metal_code.contains(
+            "(*reinterpret_cast<device float4*>(&(dst[0]))) = \
+             (*reinterpret_cast<device float4*>(&(src[0])));"
+        ));
+        assert!(!metal_code.contains("for ("));
+    }
+
+    #[test]
+    fn test_c_header_for_matmul() {
+        let source = r#"
+            kernel matmul(A: Tensor<f32, [M, K]>, B: Tensor<f32, [K, N]>) {
+                block: [16, 16]
+                let row = thread_idx.y
+                let col = thread_idx.x
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let header = generate_c_header(&program).expect("failed to generate header");
+
+        assert!(header.contains("#define FLARE_MATMUL_THREADGROUP_SIZE { 16, 16, 1 }"));
+        assert!(header.contains(
+            "typedef struct flare_matmul_args {\n    uint32_t grid_size[3];\n    \
+             uint32_t threadgroup_size[3];\n    void *A; // [[buffer(0)]] device float*\n    \
+             void *B; // [[buffer(1)]] device float*\n} flare_matmul_args;"
+        ));
+        assert!(header.contains("extern void flare_launch_matmul(const flare_matmul_args *args);"));
+        assert!(header.ends_with("#endif // FLARE_KERNELS_H\n"));
+    }
+
+    #[test]
+    fn test_where_constraints() {
+        let source = r#"
+            const K = 6
+
+            kernel matmul(A: Tensor<f32, [M, K]>, B: Tensor<f32, [K, N]>) where K % 4 == 0 {
+                let i = thread_idx.x
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        match compile(&program) {
+            Err(CodegenError::InvalidKernelConfig { message, span }) => {
+                assert!(message.contains("requires ((K % 4) == 0)"), "{}", message);
+                assert_eq!(&source[span], "K % 4 == 0");
+            }
+            other => panic!("expected a constraint error, got {:?}", other),
+        }
+
+        let source = source
+            .replace("const K = 6", "const K = 8")
+            .replace("K % 4", "N % 4");
+        let program = Flare::compile_from_string(&source).expect("failed to parse kernel");
+        assert!(compile(&program).is_ok());
+        let metadata = MetalCodegen::new()
+            .generate_metadata(&program)
+            .expect("failed to build metadata");
+        assert_eq!(metadata.kernels[0].constraints, vec!["((N % 4) == 0)"]);
+    }
+
+    #[test]
+    fn test_unreachable_code_warning() {
+        let source = r#"
+            kernel early(A: Tensor<f32, [N]>) {
+                let i = thread_idx.x
+                return;
+                A[i] = 0.0
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let mut codegen = MetalCodegen::new();
+        let metal_code = codegen
+            .generate(&program)
+            .expect("failed to generate Metal code");
+        assert!(metal_code.contains("A[i] = 0.0f;"));
+        let warning = &codegen.diagnostics()[0];
+        assert_eq!(warning.severity, flare::Severity::Warning);
+        assert_eq!(&source[warning.span.clone()], "A[i] = 0.0");
+
+        let options = CodegenOptions {
+            opt_level: OptLevel::O1,
+            ..CodegenOptions::default()
+        };
+        let metal_code =
+            compile_with_options(&program, options).expect("failed to generate Metal code");
+        assert!(!metal_code.contains("A[i] = 0.0f;"));
+    }
+
+    #[test]
+    fn test_scalar_broadcasts_over_tensor() {
+        use flare::ast::{BinOp, Expr};
+        use typeck::{ScalarType, ValueType};
+
+        let mut generator = expr::ExprGenerator::new();
+        let tensor = ValueType::Tensor {
+            elem: Box::new(ValueType::Scalar(ScalarType::Float)),
+            shape: vec!["N".to_string()],
+            strides: None,
+        };
+        generator.symbols_mut().declare("A", tensor.clone());
+        generator
+            .symbols_mut()
+            .declare("alpha", ValueType::Scalar(ScalarType::Float));
+
+        let scaled = |left: &'static str, right: &'static str| Expr::Binary {
+            left: Box::new(Expr::Ident(left, 0..0)),
+            op: BinOp::Mul,
+            right: Box::new(Expr::Ident(right, 0..0)),
+            span: 0..0,
+        };
+        assert_eq!(
+            generator.infer_type(&scaled("A", "alpha")),
+            Some(tensor.clone())
+        );
+        assert_eq!(generator.infer_type(&scaled("alpha", "A")), Some(tensor));
+    }
+
+    #[test]
+    fn test_constant_lookup_table() {
+        let source = r#"
+            kernel k(A: Tensor<f32, [N]>) {
+                @constant
+                let lut: f32[4] = [0.0, 1.0, 2.0, 3.0];
+                let i = thread_idx.x
+                A[i] = lut[i % 4]
+            }
+        "#;
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+        assert!(
+            metal_code.contains("constant float lut[4] = { 0.0f, 1.0f, 2.0f, 3.0f };"),
+            "{}",
+            metal_code
+        );
+
+        let source = r#"
+            kernel k(A: Tensor<f32, [N]>) {
+                let i = thread_idx.x
+                @constant
+                let lut: f32[2] = [0.0, A[i]];
+                A[i] = lut[0]
+            }
+        "#;
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        match compile(&program) {
+            Err(CodegenError::StatementError { message, span }) => {
+                assert!(message.contains("compile-time constants"), "{}", message);
+                assert_eq!(&source[span], "A[i]");
+            }
+            other => panic!("expected constant array error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_hex_float_literal_codegen() {
+        let source = r#"
+            kernel k(A: Tensor<f32, [N]>) {
+                let i = thread_idx.x
+                A[i] = A[i] * 0x1.8p3 + 0x1p-4
+            }
+        "#;
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+        assert!(
+            metal_code.contains("A[i] = ((A[i] * 12.0f) + 0.0625f);"),
+            "{}",
+            metal_code
+        );
+    }
+
+    #[test]
+    fn test_shadowed_binding_warning() {
+        let source = r#"
+            kernel k(A: Tensor<f32, [N]>) {
+                let x = thread_idx.x
+                for i in 0..4 {
+                    let x = i
+                    A[x] = 0.0
+                }
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let mut codegen = MetalCodegen::new();
+        codegen
+            .generate(&program)
+            .expect("failed to generate Metal code");
+        let warnings: Vec<_> = codegen
+            .diagnostics()
+            .iter()
+            .filter(|diagnostic| diagnostic.message.contains("shadows"))
+            .collect();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(&source[warnings[0].span.clone()], "let x = i");
+    }
+
+    #[test]
+    fn test_simd_vote_builtins() {
+        let source = r#"
+            kernel vote(A: Tensor<f32, [N]>, B: Tensor<u32, [N]>) {
+                let i = thread_idx.x
+                let x = A[i]
+                if simd_any(x > 0.0) {
+                    B[i] = 1
+                }
+            }
+        "#;
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+        assert!(
+            metal_code.contains("if (simd_any((x > 0.0f)))"),
+            "{}",
+            metal_code
+        );
+        assert!(metal_code.contains("uint thread_index_in_simdgroup [[thread_index_in_simdgroup]]"));
+
+        let source = r#"
+            kernel vote(A: Tensor<f32, [N]>) {
+                let i = thread_idx.x
+                let all = simd_all(A[i])
+            }
+        "#;
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        match compile(&program) {
+            Err(CodegenError::ExpressionError { message, span }) => {
+                assert!(message.contains("expects a bool condition"), "{}", message);
+                assert_eq!(&source[span], "A[i]");
+            }
+            other => panic!("expected a condition error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_max_reduction_at_o1() {
+        let source = r#"
+            kernel k(A: Tensor<f32, [N]>, out: Tensor<f32, [1]>) {
+                var best: f32 = 0.0
+                for i in 0..N {
+                    if A[i] > best {
+                        best = A[i]
+                    }
+                }
+                out[0] = best
+            }
+        "#;
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let options = CodegenOptions {
+            opt_level: OptLevel::O1,
+            ..CodegenOptions::default()
+        };
+        let metal_code =
+            compile_with_options(&program, options).expect("failed to generate Metal code");
+        assert!(
+            metal_code.contains("best = max(best, A[i]);"),
+            "{}",
+            metal_code
+        );
+        assert!(!metal_code.contains("if ((A[i] > best))"));
+    }
+
+    #[test]
+    fn test_grid_dispatch_metadata() {
+        let source = r#"
+            kernel scale(A: Tensor<f32, [N]>) {
+                grid: [1024]
+                block: [256]
+                let i = thread_idx.x
+                A[i] = A[i] * 2.0
+            }
+        "#;
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metadata = MetalCodegen::new()
+            .generate_metadata(&program)
+            .expect("failed to build metadata");
+        assert_eq!(metadata.kernels[0].grid_size, Some([1024, 1, 1]));
+        assert_eq!(metadata.kernels[0].threadgroup_count, Some([4, 1, 1]));
+        assert!(metadata
+            .to_c_header()
+            .contains("#define FLARE_SCALE_THREADGROUP_COUNT { 4, 1, 1 }"));
+
+        let source = r#"
+            kernel scale(A: Tensor<f32, [N]>) {
+                grid: [1000]
+                block: [256]
+                let i = thread_idx.x
+                A[i] = A[i] * 2.0
+            }
+        "#;
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        match compile(&program) {
+            Err(CodegenError::InvalidKernelConfig { message, span }) => {
+                assert!(message.contains("not a multiple"), "{}", message);
+                assert_eq!(&source[span], "1000");
+            }
+            other => panic!("expected a grid error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_sizeof_folds_to_byte_size() {
+        let source = r#"
+            kernel k(A: Tensor<f32, [N]>) {
+                shared_memory {
+                    tile: [64 / sizeof(f32)]: f32
+                }
+                let i = thread_idx.x
+                let offset = i * sizeof(f32)
+                A[i] = 0.0
+            }
+        "#;
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+        assert!(
+            metal_code.contains("threadgroup float tile[16];"),
+            "{}",
+            metal_code
+        );
+        assert!(
+            metal_code.contains("const auto offset = (i * 4);"),
+            "{}",
+            metal_code
+        );
+    }
+
+    #[test]
+    fn test_metal_lowers_shared_intrinsics() {
+        use crate::builtins::MetalIntrinsics;
+        use flare_codegen_common::{Intrinsic, IntrinsicLowering};
+
+        assert_eq!(Intrinsic::from_name("sqrt"), Some(Intrinsic::Sqrt));
+        assert_eq!(MetalIntrinsics.lower_intrinsic(&Intrinsic::Sqrt), "sqrt");
+        assert_eq!(MetalIntrinsics.resolve("rsqrt").as_deref(), Some("rsqrt"));
+        assert_eq!(MetalIntrinsics.resolve("gather"), None);
+    }
+
+    #[test]
+    fn test_ternary_lowers_to_conditional_operator() {
+        let source = r#"
+            kernel k(A: Tensor<f32, [N]>) {
+                let i = thread_idx.x
+                A[i] = A[i] > 0.0 ? A[i] * 2.0 : 0.0
+            }
+        "#;
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+        assert!(
+            metal_code.contains("A[i] = ((A[i] > 0.0f) ? (A[i] * 2.0f) : 0.0f);"),
+            "{}",
+            metal_code
+        );
+    }
+
+    #[test]
+    fn test_module_id_mangles_device_functions() {
+        let source = r#"
+            fn helper(x: f32) -> f32 {
+                x * 2.0
+            }
+
+            kernel k(A: Tensor<f32, [N]>) {
+                let i = thread_idx.x
+                A[i] = helper(A[i])
+            }
+        "#;
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let compile_as = |module: &str| {
+            let options = CodegenOptions {
+                module_id: Some(module.to_string()),
+                ..CodegenOptions::default()
+            };
+            compile_with_options(&program, options).expect("failed to generate Metal code")
+        };
+
+        let first = compile_as("a");
+        let second = compile_as("b");
+        assert!(
+            first.contains("static float a_helper(float x)"),
+            "{}",
+            first
+        );
+        assert!(first.contains("A[i] = a_helper(A[i]);"), "{}", first);
+        assert!(
+            second.contains("static float b_helper(float x)"),
+            "{}",
+            second
+        );
+        assert!(!second.contains("a_helper"), "{}", second);
+
+        for module in ["my-mod", "1x", ""] {
+            let options = CodegenOptions {
+                module_id: Some(module.to_string()),
+                ..CodegenOptions::default()
+            };
+            assert!(matches!(
+                compile_with_options(&program, options),
+                Err(CodegenError::InvalidIdentifier { .. })
+            ));
+        }
+
+        // calls through a local named like a helper are left alone
+        let shadowed = source.replace(
+            "A[i] = helper(A[i])",
+            "let helper = A[i]\n A[i] = helper(1.0)",
+        );
+        let program = Flare::compile_from_string(&shadowed).expect("failed to parse kernel");
+        let options = CodegenOptions {
+            module_id: Some("a".to_string()),
+            ..CodegenOptions::default()
+        };
+        let metal_code =
+            compile_with_options(&program, options).expect("failed to generate Metal code");
+        assert!(
+            metal_code.contains("A[i] = helper(1.0f);"),
+            "{}",
+            metal_code
+        );
+    }
+
+    #[test]
+    fn test_raw_identifiers_emitted_as_safe_names() {
+        let source = r#"
+            kernel k(r#block: Tensor<f32, [N]>, r#device: Tensor<f32, [N]>) {
+                let i = thread_idx.x
+                let r#kernel = r#block[i] * 2.0
+                r#device[i] = r#kernel
+            }
+        "#;
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+        assert!(
+            metal_code.contains("device float *flare_kw_device [[buffer(1)]]"),
+            "{}",
+            metal_code
+        );
+        assert!(
+            metal_code.contains("const auto flare_kw_kernel = (block[i] * 2.0f);"),
+            "{}",
+            metal_code
+        );
+        assert!(
+            metal_code.contains("flare_kw_device[i] = flare_kw_kernel;"),
+            "{}",
+            metal_code
+        );
+
+        // names only reserved words are mangled to cannot clash with user names
+        let clashing = source.replace(
+            "let i = thread_idx.x",
+            "let flare_kw_kernel = 1.0\n let i = thread_idx.x",
+        );
+        assert!(matches!(
+            Flare::compile_from_string(&clashing),
+            Err(flare::FlareError::ReservedName { .. })
+        ));
+    }
+
+    #[test]
+    fn test_constructor_calls_keep_their_names() {
+        let source = r#"
+            kernel k(A: Tensor<f32, [N]>) {
+                let i = thread_idx.x
+                let h = half(1.0)
+                let u = uint(2)
+                A[i] = float(i) + float(h) + float(u)
+            }
+        "#;
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+        assert!(metal_code.contains("= half(1.0"), "{}", metal_code);
+        assert!(metal_code.contains("= uint(2);"), "{}", metal_code);
+        assert!(
+            metal_code.contains("A[i] = ((float(i) + float(h)) + float(u));"),
+            "{}",
+            metal_code
+        );
+    }
+
+    #[test]
+    fn test_array_size_from_const() {
+        let source = r#"
+            const TILE = 8
+
+            kernel k(A: Tensor<f32, [N]>) {
+                var tile: f32[TILE * 2]
+                let lut: f32[TILE] = [0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0]
+                tile[0] = lut[thread_idx.x]
+            }
+        "#;
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+        assert!(metal_code.contains("float tile[16];"), "{}", metal_code);
+        assert!(
+            metal_code.contains("const float lut[8] = "),
+            "{}",
+            metal_code
+        );
+    }
+
+    #[test]
+    fn test_unsigned_arithmetic_and_literals() {
+        let source = r#"
+            kernel k(A: Tensor<u32, [N]>, a: u32, b: u32) {
+                let d: u32 = a - b
+                A[thread_idx.x] = d * 3u + 1u
+            }
+        "#;
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+        assert!(metal_code.contains("const uint d = (a - b);"));
+        assert!(metal_code.contains("((d * 3u) + 1u)"));
+
+        let source = r#"
+            kernel k(A: Tensor<u32, [N]>) {
+                let x: u32 = -1;
+                A[0] = x
+            }
+        "#;
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let err = compile(&program).expect_err("expected a negative unsigned literal");
+        assert!(err
+            .to_string()
+            .contains("negative literal -1 cannot be stored in unsigned 'uint'"));
+    }
+
+    #[test]
+    fn test_as_type_reinterprets_bits() {
+        let source = r#"
+            kernel k(A: Tensor<f32, [N]>, B: Tensor<u32, [N]>, C: Tensor<u32, [N]>) {
+                let x = A[thread_idx.x]
+                B[thread_idx.x] = as_type<u32>(x)
+                C[thread_idx.x] = x as u32
+            }
+        "#;
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+        assert!(metal_code.contains("B[thread_position_in_threadgroup.x] = as_type<uint>(x);"));
+        assert!(metal_code.contains("C[thread_position_in_threadgroup.x] = uint(x);"));
+
+        let source = r#"
+            kernel k(A: Tensor<f32, [N]>, B: Tensor<u64, [N]>) {
+                let x = A[thread_idx.x]
+                B[thread_idx.x] = as_type<u64>(x)
+            }
+        "#;
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let err = compile(&program).expect_err("expected a size mismatch");
+        assert!(err
+            .to_string()
+            .contains("as_type cannot reinterpret 'float' (4 bytes) as 'ulong' (8 bytes)"));
+    }
+
+    #[test]
+    fn test_memory_attribute_on_param() {
+        let source = r#"
+            kernel scale(A: Tensor<f32, [N]>, @memory(constant) alpha: f32,
+                         @memory(constant) lut: Tensor<f32, [16]>) {
+                A[thread_idx.x] = A[thread_idx.x] * alpha + lut[0]
+            }
+        "#;
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+        assert!(metal_code.contains("device float *A [[buffer(0)]]"));
+        assert!(metal_code.contains("constant float &alpha [[buffer(1)]]"));
+        assert!(metal_code.contains("constant float *lut [[buffer(2)]]"));
+
+        // constant buffers are read-only
+        let source = r#"
+            kernel copy(@memory(constant) A: Tensor<f32, [N]>, B: Tensor<f32, [N]>) {
+                let i = thread_idx.x
+                A[i] = B[i]
+            }
+        "#;
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        match compile(&program) {
+            Err(CodegenError::InvalidMemoryConfig { message, span }) => {
+                assert!(message.contains("parameter 'A'"), "{}", message);
+                assert_eq!(&source[span], "A[i] = B[i]");
+            }
+            other => panic!("expected a constant write error, got {:?}", other),
+        }
+        let compound = source.replace("A[i] = B[i]", "A[i] += B[i]");
+        let program = Flare::compile_from_string(&compound).expect("failed to parse kernel");
+        assert!(matches!(
+            compile(&program),
+            Err(CodegenError::InvalidMemoryConfig { .. })
+        ));
+
+        // threadgroup arguments take their own indices, after the buffers
+        let source = r#"
+            kernel scale(@memory(shared) A: Tensor<f32, [N]>, B: Tensor<f32, [N]>,
+                         @memory(threadgroup) C: Tensor<f32, [N]>) {
+                A[thread_idx.x] = B[thread_idx.x] + C[thread_idx.x]
+            }
+        "#;
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+        assert!(
+            metal_code.contains(
+                "device float *B [[buffer(0)]],\n                       \
+                 threadgroup float *A [[threadgroup(0)]],\n                       \
+                 threadgroup float *C [[threadgroup(1)]]"
+            ),
+            "{}",
+            metal_code
+        );
+        let metadata = MetalCodegen::new()
+            .generate_metadata(&program)
+            .expect("failed to describe program");
+        let kernel = &metadata.kernels[0];
+        assert_eq!(kernel.buffers.len(), 1);
+        let threadgroup: Vec<_> = kernel
+            .threadgroup_buffers
+            .iter()
+            .map(|buffer| (buffer.name.as_str(), buffer.index, buffer.msl_type.as_str()))
+            .collect();
+        assert_eq!(
+            threadgroup,
+            [
+                ("A", 0, "threadgroup float*"),
+                ("C", 1, "threadgroup float*")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_if_expression_branch_types() {
+        let generate = |source: &str| {
+            let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+            let mut codegen = MetalCodegen::new();
+            let result = codegen.generate(&program);
+            (result, codegen.diagnostics().to_vec())
+        };
+
+        let (result, diagnostics) = generate(
+            r#"
+            kernel k(A: Tensor<f32, [N]>, s: f32) {
+                A[thread_idx.x] = s > 0.0 ? s : 1.0
+            }
+        "#,
+        );
+        assert!(result.is_ok());
+        assert!(diagnostics.is_empty());
+
+        let (result, diagnostics) = generate(
+            r#"
+            kernel k(A: Tensor<f32, [N]>, n: i32, s: f32) {
+                A[thread_idx.x] = s > 0.0 ? n : 2.0
+            }
+        "#,
+        );
+        assert!(result.is_ok());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, flare::Severity::Note);
+        assert!(diagnostics[0]
+            .message
+            .contains("branches mix 'int' and 'float'; the 'int' branch is promoted"));
+
+        let (result, _) = generate(
+            r#"
+            kernel k(A: Tensor<f32, [N]>, s: f32) {
+                A[thread_idx.x] = s > 0.0 ? true : 2.0
+            }
+        "#,
+        );
+        let err = result.expect_err("expected incompatible branches");
+        assert!(err
+            .to_string()
+            .contains("if expression branches have incompatible types 'bool' and 'float'"));
+    }
+
+    #[test]
+    fn test_all_reduce_recorded_in_metadata() {
+        let source = r#"
+            fn combine(a: f32, b: f32) -> f32 {
+                a * b + a
+            }
+
+            @all_reduce(grads, combine)
+            kernel step(grads: Tensor<f32, [N]>, loss: Tensor<f32, [N]>) {
+                grads[thread_idx.x] = grads[thread_idx.x] * 0.5
+            }
+
+            schedule step {
+                hints {
+                    @all_reduce(loss)
+                }
+            }
+        "#;
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metadata = MetalCodegen::new()
+            .generate_metadata(&program)
+            .expect("failed to build metadata");
+        let ops: Vec<_> = metadata.kernels[0]
+            .reductions
+            .iter()
+            .map(|reduce| (reduce.buffer.as_str(), reduce.op.as_str()))
+            .collect();
+        assert_eq!(ops, [("grads", "combine"), ("loss", "sum")]);
+    }
+
+    #[test]
+    fn test_opt_report_lists_folds_and_dead_lets() {
+        let source = r#"
+            kernel k(A: Tensor<f32, [N]>) {
+                let i = thread_idx.x
+                let unused = i * 3
+                A[i * (2 + 2)] = 1.0
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let options = CodegenOptions {
+            opt_level: OptLevel::O1,
+            ..CodegenOptions::default()
+        };
+        let (output, report) = compile_with_report(&program, options).unwrap();
+
+        assert!(output.contains("A[(i * 4)]"), "{}", output);
+        assert!(!output.contains("unused"), "{}", output);
+        assert_eq!(
+            report.lines(),
+            vec!["folded 1 constant", "eliminated 1 dead let"]
+        );
+    }
+
+    #[test]
+    fn test_guard_clause_keeps_kernel_body() {
+        let source = r#"
+            kernel scale(A: Tensor<f32, [N]>, B: Tensor<f32, [N]>) {
+                compute {
+                    let gid = block_idx.x * block_dim.x + thread_idx.x
+                    if gid >= N { return; }
+                    B[gid] = A[gid] * 2.0
+                }
+            }
+        "#;
+        // the semicolon after a bare `return` is optional
+        let without_semicolon = source.replace("return;", "return");
+
+        for source in [source, without_semicolon.as_str()] {
+            let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+            for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+                let options = CodegenOptions {
+                    opt_level,
+                    ..CodegenOptions::default()
+                };
+                let mut codegen = MetalCodegen::with_options(options);
+                let metal_code = codegen.generate(&program).unwrap();
+
+                let guard = "    if ((gid >= N)) {\n        return;\n    }\n";
+                let body = "    B[gid] = (A[gid] * 2.0f);\n";
+                assert!(
+                    metal_code.contains(&format!("{}{}", guard, body)),
+                    "{:?}: {}",
+                    opt_level,
+                    metal_code
+                );
+                assert!(codegen.diagnostics().is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn test_compound_assign_flattens_2d_index() {
+        let source = r#"
+            kernel accumulate(out: Tensor<f32, [M, N]>, A: Tensor<f32, [M, N]>) {
+                let i = thread_idx.y
+                let j = thread_idx.x
+                out[i, j] += A[i, j]
+            }
+
+            @layout(out, [1, LDO])
+            kernel strided(out: Tensor<f32, [M, N]>, v: f32) {
+                let i = thread_idx.y
+                let j = thread_idx.x
+                out[i, j] *= v
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+
+        assert!(metal_code.contains("out[i * N + j] += A[i * N + j];"));
+        assert!(metal_code.contains("out[i + j * LDO] *= v;"));
+        assert!(!metal_code.contains("]["));
+    }
+
+    #[test]
+    fn test_bool_operand_converted_in_arithmetic() {
+        let source = r#"
+            kernel k(A: Tensor<f32, [N]>, B: Tensor<i32, [N]>, x: f32) {
+                let i = thread_idx.x
+                let mask = A[i] > 0.0
+                A[i] = mask * x
+                B[i] = B[i] + mask
+            }
+        "#;
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal = compile(&program).expect("failed to generate Metal");
+        assert!(metal.contains("A[i] = (float(mask) * x);"));
+        assert!(metal.contains("B[i] = (B[i] + (mask ? 1 : 0));"));
+
+        let source = r#"
+            kernel k(B: Tensor<i32, [N]>) {
+                let i = thread_idx.x
+                B[i] = (i < 4) + (i > 2)
+            }
+        "#;
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let err = compile(&program).expect_err("expected ambiguous bool arithmetic");
+        assert!(err.to_string().contains("two 'bool' operands is ambiguous"));
+    }
+
+    #[test]
+    fn test_sibling_labeled_loops_scope_their_flags() {
+        let source = r#"
+            kernel find(A: Tensor<f32, [N, N]>) {
+                'rows: for i in 0..N {
+                    for j in 0..N {
+                        if A[i, j] > 0.0 {
+                            break 'rows;
+                        }
+                    }
+                }
+                'rows: for i in 0..N {
+                    for j in 0..N {
+                        if A[i, j] < 0.0 {
+                            break 'rows;
+                        }
+                    }
+                }
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+
+        assert_eq!(
+            metal_code
+                .matches("    {\n        bool flare_break_rows = false;\n        for (int i")
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_untyped_var_error_points_at_statement() {
+        let source = r#"
+            kernel k(A: Tensor<f32, [N]>) {
+                var x;
+            }
+        "#;
+        let program = flare::Parser::new(source)
+            .and_then(|mut parser| parser.parse())
+            .expect("failed to parse kernel");
+        let err = compile(&program).expect_err("expected an untyped var error");
+        assert_eq!(&source[err.span().clone()], "var x;");
+    }
+
+    #[test]
+    fn test_tensor_arithmetic_assigns_one_element_per_thread() {
+        let source = r#"
+            kernel scale(A: Tensor<f32, [M, N]>, B: Tensor<f32, [M, N]>, alpha: f32) {
+                B = alpha * A + A
+            }
+        "#;
+        let program = Flare::compile_from_string(source).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+        assert!(
+            metal_code.contains(
+                "    {\n        const uint flare_elem = threadgroup_position_in_grid.x * \
+                 threads_per_threadgroup.x + thread_position_in_threadgroup.x;\n        \
+                 if (flare_elem < M * N) {\n            \
+                 B[flare_elem] = ((alpha * A[flare_elem]) + A[flare_elem]);\n        }\n    }\n"
+            ),
+            "{}",
+            metal_code
+        );
+        assert!(!metal_code.contains("for ("), "{}", metal_code);
+
+        // in place, and into the element an @elementwise kernel computes
+        let in_place = r#"
+            kernel scale(B: Tensor<f32, [N]>, alpha: f32) {
+                B = B * alpha
+            }
+
+            @elementwise
+            kernel scaled(A: Tensor<f32, [N]>, alpha: f32) -> Tensor<f32, [N]> {
+                output = A * alpha
+            }
+        "#;
+        let program = Flare::compile_from_string(in_place).expect("failed to parse kernel");
+        let metal_code = compile(&program).expect("failed to generate Metal code");
+        assert!(
+            metal_code.contains("B[flare_elem] = (B[flare_elem] * alpha);"),
+            "{}",
+            metal_code
+        );
+        assert!(
+            metal_code.contains("    output[gid] = (A[gid] * alpha);\n}"),
+            "{}",
+            metal_code
+        );
+
+        let transposed = source.replace(
+            "A: Tensor<f32, [M, N]>",
+            "A: Tensor<f32, [M, N], col_major>",
+        );
+        let program = Flare::compile_from_string(&transposed).expect("failed to parse kernel");
+        let err = compile(&program).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("tensor 'A' has a different layout"),
+            "{}",
+            err
+        );
+
+        // outside such an assignment it would be pointer arithmetic
+        let not_assigned = source.replace("B = alpha * A + A", "let C = A * alpha");
+        let program = Flare::compile_from_string(&not_assigned).expect("failed to parse kernel");
+        assert!(matches!(
+            compile(&program),
+            Err(CodegenError::ExpressionError { .. })
+        ));
+
+        let mismatched = source.replace("B: Tensor<f32, [M, N]>", "B: Tensor<f32, [N]>");
+        let program = Flare::compile_from_string(&mismatched).expect("failed to parse kernel");
+        let err = compile(&program).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("tensor 'A' of shape [M, N] cannot be combined elementwise into shape [N]"));
+    }
 }
@@ -0,0 +1,372 @@
+use crate::builtins::is_builtin;
+use crate::error::{CodegenError, Result};
+use crate::typeck::ValueType;
+use flare::ast::{walk_expr, Expr, KernelDef, Program, Stmt, Type, Visitor};
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+/// Return types of the `impl` methods of a program, keyed by receiver type
+/// and method name.
+pub type MethodTable = HashMap<(String, String), Option<ValueType>>;
+
+/// Name of the free function an `impl` method is emitted as.
+pub fn method_name(receiver: &str, name: &str) -> String {
+    format!("{}_{}", receiver, name)
+}
+
+pub fn method_table(program: &Program) -> MethodTable {
+    program
+        .items
+        .iter()
+        .filter_map(|stmt| match stmt {
+            Stmt::Function {
+                name,
+                return_type,
+                receiver: Some(receiver),
+                ..
+            } => Some((
+                (receiver.to_string(), name.to_string()),
+                return_type.as_ref().and_then(ValueType::from_ast),
+            )),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Emitted symbol of every user `fn` when the program is compiled as module
+/// `module`, keyed by its unprefixed name (`Type_name` for methods), so
+/// helpers of programs merged into one translation unit cannot clash.
+pub fn function_symbols(program: &Program, module: &str) -> HashMap<String, String> {
+    program
+        .items
+        .iter()
+        .filter_map(|stmt| match stmt {
+            Stmt::Function { name, receiver, .. } => {
+                let name = match receiver {
+                    Some(ty) => method_name(ty, name),
+                    None => name.to_string(),
+                };
+                Some((name.clone(), format!("{}_{}", module, name)))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Rejects a module id that is not an identifier, such as `my-mod` or
+/// `1x`, since it is pasted in front of every helper's name.
+pub fn check_module_id(module: &str) -> Result<()> {
+    let mut chars = module.chars();
+    let starts_identifier = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_');
+    if starts_identifier && chars.all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Ok(());
+    }
+    Err(CodegenError::invalid_identifier(
+        module,
+        "module id must be an identifier, such as `my_mod`",
+        0..0,
+    ))
+}
+
+/// Call graph over the user `fn` definitions of a program, used to emit
+/// helpers ahead of their callers and to resolve call targets. Methods are
+/// keyed by their emitted `Type_name`.
+pub struct CallGraph<'a, 'src> {
+    functions: Vec<&'a Stmt<'src>>,
+
+    index: HashMap<String, usize>,
+
+    callees: Vec<Vec<String>>,
+}
+
+impl<'a, 'src> CallGraph<'a, 'src> {
+    pub fn build(program: &'a Program<'src>) -> Result<Self> {
+        let mut functions = Vec::new();
+        let mut index = HashMap::new();
+        // receiver types are only known during codegen, so a method call
+        // depends on every method of that name
+        let mut methods: HashMap<&'src str, Vec<String>> = HashMap::new();
+
+        for stmt in &program.items {
+            if let Stmt::Function {
+                name,
+                receiver,
+                span,
+                ..
+            } = stmt
+            {
+                let key = match receiver {
+                    Some(ty) => method_name(ty, name),
+                    None => name.to_string(),
+                };
+                if receiver.is_some() {
+                    methods.entry(*name).or_default().push(key.clone());
+                }
+                if index.insert(key.clone(), functions.len()).is_some() {
+                    return Err(CodegenError::invalid_identifier(
+                        key,
+                        "function is defined more than once",
+                        span.clone(),
+                    ));
+                }
+                functions.push(stmt);
+            }
+        }
+
+        let mut graph = Self {
+            functions,
+            index,
+            callees: Vec::new(),
+        };
+
+        for function in &graph.functions {
+            let collected = collect_calls(function);
+            graph.validate_calls(&collected.calls)?;
+
+            let mut callees: Vec<String> = Vec::new();
+            let targets = collected
+                .calls
+                .iter()
+                .map(|(name, _)| name.to_string())
+                .filter(|name| graph.index.contains_key(name))
+                .chain(
+                    collected
+                        .methods
+                        .iter()
+                        .flat_map(|name| methods.get(name).into_iter().flatten().cloned()),
+                );
+            for target in targets {
+                if !callees.contains(&target) {
+                    callees.push(target);
+                }
+            }
+            graph.callees.push(callees);
+        }
+
+        for stmt in &program.items {
+            if let Stmt::Kernel(_) = stmt {
+                graph.validate_calls(&collect_calls(stmt).calls)?;
+            }
+        }
+
+        Ok(graph)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty()
+    }
+
+    pub fn callees(&self, name: &str) -> &[String] {
+        match self.index.get(name) {
+            Some(&i) => &self.callees[i],
+            None => &[],
+        }
+    }
+
+    /// Functions ordered so every callee precedes its callers, keeping
+    /// declaration order otherwise.
+    pub fn emission_order(&self) -> Vec<&'a Stmt<'src>> {
+        let mut visited = HashSet::new();
+        let mut order = Vec::new();
+        for i in 0..self.functions.len() {
+            self.visit(i, &mut visited, &mut order);
+        }
+        order.into_iter().map(|i| self.functions[i]).collect()
+    }
+
+    /// Rejects recursion, which Metal does not support, reporting the first
+    /// cycle found as `a -> b -> a`.
+    pub fn check_recursion(&self) -> Result<()> {
+        let mut finished = HashSet::new();
+        let mut stack = Vec::new();
+        for i in 0..self.functions.len() {
+            if let Some(cycle) = self.find_cycle(i, &mut finished, &mut stack) {
+                let mut names: Vec<String> = cycle.iter().map(|&j| self.name(j)).collect();
+                names.push(self.name(cycle[0]));
+                return Err(CodegenError::unsupported_feature(
+                    format!("recursive function calls ({})", names.join(" -> ")),
+                    self.functions[cycle[0]].span(),
+                    Some("rewrite the recursion as a loop".to_string()),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn find_cycle(
+        &self,
+        i: usize,
+        finished: &mut HashSet<usize>,
+        stack: &mut Vec<usize>,
+    ) -> Option<Vec<usize>> {
+        if finished.contains(&i) {
+            return None;
+        }
+        if let Some(pos) = stack.iter().position(|&j| j == i) {
+            return Some(stack[pos..].to_vec());
+        }
+
+        stack.push(i);
+        for callee in &self.callees[i] {
+            if let Some(cycle) = self.find_cycle(self.index[callee.as_str()], finished, stack) {
+                return Some(cycle);
+            }
+        }
+        stack.pop();
+        finished.insert(i);
+        None
+    }
+
+    fn name(&self, i: usize) -> String {
+        match self.functions[i] {
+            Stmt::Function {
+                name,
+                receiver: Some(ty),
+                ..
+            } => method_name(ty, name),
+            Stmt::Function { name, .. } => name.to_string(),
+            _ => unreachable!("call graph only holds functions"),
+        }
+    }
+
+    fn visit(&self, i: usize, visited: &mut HashSet<usize>, order: &mut Vec<usize>) {
+        if !visited.insert(i) {
+            return;
+        }
+        for callee in &self.callees[i] {
+            self.visit(self.index[callee.as_str()], visited, order);
+        }
+        order.push(i);
+    }
+
+    fn validate_calls(&self, calls: &[(&'src str, Range<usize>)]) -> Result<()> {
+        for (name, span) in calls {
+            if !self.index.contains_key(*name) && !is_builtin(name) {
+                return Err(CodegenError::invalid_identifier(
+                    *name,
+                    "call to unknown function",
+                    span.clone(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Calls of named functions, and the names of methods called on a value.
+struct CallCollector<'src> {
+    calls: Vec<(&'src str, Range<usize>)>,
+    methods: Vec<&'src str>,
+}
+
+impl<'src> Visitor<'src> for CallCollector<'src> {
+    fn visit_expr(&mut self, expr: &Expr<'src>) {
+        if let Expr::Call { func, span, .. } = expr {
+            match func.as_ref() {
+                Expr::Ident(name, _) => self.calls.push((name, span.clone())),
+                Expr::Member { field, .. } => self.methods.push(field),
+                _ => {}
+            }
+        }
+        walk_expr(self, expr);
+    }
+}
+
+/// Whether the kernel's own statements call any of `names`.
+pub(crate) fn kernel_calls(kernel: &KernelDef, names: &[&str]) -> bool {
+    let mut collector = CallCollector {
+        calls: Vec::new(),
+        methods: Vec::new(),
+    };
+    for stmt in kernel.compute.iter().flatten().chain(&kernel.body) {
+        collector.visit_stmt(stmt);
+    }
+    collector.calls.iter().any(|(name, _)| names.contains(name))
+}
+
+fn collect_calls<'src>(stmt: &Stmt<'src>) -> CallCollector<'src> {
+    let mut collector = CallCollector {
+        calls: Vec::new(),
+        methods: Vec::new(),
+    };
+    collector.visit_stmt(stmt);
+    collector
+}
+
+/// The program's `type` aliases, each listed once and ordered so every
+/// alias follows the aliases its definition refers to.
+pub fn type_def_order<'a, 'src>(
+    program: &'a Program<'src>,
+) -> Result<Vec<(&'src str, &'a Type<'src>)>> {
+    let mut defs: Vec<(&'src str, &'a Type<'src>, Range<usize>)> = Vec::new();
+    for stmt in &program.items {
+        if let Stmt::TypeDef { name, ty, span } = stmt {
+            match defs.iter().find(|(existing, _, _)| existing == name) {
+                Some((_, existing_ty, _)) if existing_ty.same_as(ty) => {}
+                Some(_) => {
+                    return Err(CodegenError::invalid_identifier(
+                        *name,
+                        "type is defined more than once with different definitions",
+                        span.clone(),
+                    ))
+                }
+                None => defs.push((name, ty, span.clone())),
+            }
+        }
+    }
+
+    let mut order = Vec::new();
+    let mut finished = HashSet::new();
+    let mut in_progress = HashSet::new();
+    for i in 0..defs.len() {
+        visit_type_def(i, &defs, &mut finished, &mut in_progress, &mut order)?;
+    }
+    Ok(order.into_iter().map(|i| (defs[i].0, defs[i].1)).collect())
+}
+
+fn visit_type_def(
+    i: usize,
+    defs: &[(&str, &Type, Range<usize>)],
+    finished: &mut HashSet<usize>,
+    in_progress: &mut HashSet<usize>,
+    order: &mut Vec<usize>,
+) -> Result<()> {
+    if finished.contains(&i) {
+        return Ok(());
+    }
+    let (name, ty, span) = &defs[i];
+    if !in_progress.insert(i) {
+        return Err(CodegenError::invalid_identifier(
+            *name,
+            "type alias refers to itself",
+            span.clone(),
+        ));
+    }
+
+    let mut referenced = Vec::new();
+    named_types(ty, &mut referenced);
+    for dep in referenced {
+        if let Some(j) = defs.iter().position(|(other, _, _)| *other == dep) {
+            visit_type_def(j, defs, finished, in_progress, order)?;
+        }
+    }
+
+    in_progress.remove(&i);
+    finished.insert(i);
+    order.push(i);
+    Ok(())
+}
+
+fn named_types<'src>(ty: &Type<'src>, out: &mut Vec<&'src str>) {
+    match ty {
+        Type::Named(name, _) => out.push(name),
+        Type::Vector { dtype, .. }
+        | Type::Matrix { dtype, .. }
+        | Type::Tensor { dtype, .. }
+        | Type::Array { dtype, .. } => named_types(dtype, out),
+        Type::Ptr(inner) => named_types(inner, out),
+        _ => {}
+    }
+}
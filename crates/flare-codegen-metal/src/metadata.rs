@@ -0,0 +1,240 @@
+use crate::error::{CodegenError, Result};
+use crate::kernel::{
+    elementwise_output, is_threadgroup_parameter, packed_uniforms, uniforms_struct_name,
+    KernelGenerator, UNIFORMS_PARAM,
+};
+use flare::ast::{KernelDef, Program, ScheduleBlock, ScheduleDirective};
+use flare_ir::mir::resolve;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Stream used for kernels whose schedule does not name one.
+pub const DEFAULT_STREAM: &str = "default";
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BufferMetadata {
+    pub name: String,
+
+    pub index: usize,
+
+    pub msl_type: String,
+}
+
+/// An `@all_reduce` on one of the kernel's buffers, for distributed hosts
+/// that combine it across devices after the dispatch.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReductionMetadata {
+    pub buffer: String,
+
+    /// `sum`, `product`, `max`, `min` or the name of a user function.
+    pub op: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct KernelMetadata {
+    pub name: String,
+
+    pub threadgroup_size: [u32; 3],
+
+    /// Threads to dispatch, when the `grid` folds to constants.
+    pub grid_size: Option<[u32; 3]>,
+
+    /// `grid_size` divided by `threadgroup_size`, when both are known.
+    pub threadgroup_count: Option<[u32; 3]>,
+
+    pub buffers: Vec<BufferMetadata>,
+
+    /// `@memory(shared)` parameters bound with `[[threadgroup(index)]]`,
+    /// whose length the host sets per dispatch.
+    pub threadgroup_buffers: Vec<BufferMetadata>,
+
+    pub stream: String,
+
+    /// `where` constraints the host must check when it picks the dims.
+    pub constraints: Vec<String>,
+
+    pub reductions: Vec<ReductionMetadata>,
+}
+
+/// Host-side description of the kernels in a program, serialized to JSON
+/// next to the generated MSL.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProgramMetadata {
+    pub kernels: Vec<KernelMetadata>,
+
+    /// Kernel names grouped by stream so the host can give each stream its
+    /// own command queue.
+    pub streams: BTreeMap<String, Vec<String>>,
+}
+
+impl ProgramMetadata {
+    pub fn build(program: &Program, kernel_gen: &mut KernelGenerator) -> Result<Self> {
+        let mut kernels = Vec::new();
+        let mut streams: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+        for (kernel, schedule) in resolve::resolve_targets(program)? {
+            let metadata = kernel_metadata(kernel, schedule, kernel_gen)?;
+            streams
+                .entry(metadata.stream.clone())
+                .or_default()
+                .push(metadata.name.clone());
+            kernels.push(metadata);
+        }
+
+        Ok(Self { kernels, streams })
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self)
+            .map_err(|err| CodegenError::internal_error(err.to_string(), 0..0))
+    }
+
+    /// C header for statically-typed hosts: per kernel, an argument struct
+    /// holding the dispatch dims and one pointer per buffer in binding order,
+    /// and an `extern` launch function the host implements.
+    pub fn to_c_header(&self) -> String {
+        let mut header = String::from(
+            "// generated by Flare\n\n#ifndef FLARE_KERNELS_H\n#define FLARE_KERNELS_H\n\n\
+             #include <stdint.h>\n",
+        );
+        for kernel in &self.kernels {
+            let [x, y, z] = kernel.threadgroup_size;
+            header.push_str(&format!(
+                "\n// kernel {} on stream \"{}\"\n",
+                kernel.name, kernel.stream
+            ));
+            header.push_str(&format!(
+                "#define FLARE_{}_THREADGROUP_SIZE {{ {}, {}, {} }}\n\n",
+                kernel.name.to_uppercase(),
+                x,
+                y,
+                z
+            ));
+            if let Some([x, y, z]) = kernel.threadgroup_count {
+                header.push_str(&format!(
+                    "#define FLARE_{}_THREADGROUP_COUNT {{ {}, {}, {} }}\n\n",
+                    kernel.name.to_uppercase(),
+                    x,
+                    y,
+                    z
+                ));
+            }
+            for constraint in &kernel.constraints {
+                header.push_str(&format!("// requires {}\n", constraint));
+            }
+            header.push_str(&format!("typedef struct flare_{}_args {{\n", kernel.name));
+            header.push_str("    uint32_t grid_size[3];\n");
+            header.push_str("    uint32_t threadgroup_size[3];\n");
+            for buffer in &kernel.buffers {
+                header.push_str(&format!(
+                    "    void *{}; // [[buffer({})]] {}\n",
+                    buffer.name, buffer.index, buffer.msl_type
+                ));
+            }
+            header.push_str(&format!("}} flare_{}_args;\n\n", kernel.name));
+            header.push_str(&format!(
+                "extern void flare_launch_{}(const flare_{}_args *args);\n",
+                kernel.name, kernel.name
+            ));
+        }
+        header.push_str("\n#endif // FLARE_KERNELS_H\n");
+        header
+    }
+}
+
+fn kernel_metadata(
+    kernel: &KernelDef,
+    schedule: Option<&ScheduleBlock>,
+    kernel_gen: &mut KernelGenerator,
+) -> Result<KernelMetadata> {
+    let (x, y, z) = kernel_gen.get_threadgroup_size(kernel, schedule);
+    let dispatch = kernel_gen.dispatch_size(kernel, schedule)?;
+
+    let uniforms = packed_uniforms(kernel);
+    let indices: Vec<usize> = kernel_gen
+        .config()
+        .buffer_indices()
+        .take(kernel.params.len() + 2)
+        .collect();
+    let mut buffers = Vec::new();
+    let mut threadgroup_buffers = Vec::new();
+    for param in kernel
+        .params
+        .iter()
+        .filter(|param| !uniforms.contains(param))
+    {
+        let msl_type = kernel_gen.convert_type(&param.ty, param.span.clone())?;
+        if is_threadgroup_parameter(param)? {
+            threadgroup_buffers.push(BufferMetadata {
+                name: param.name.to_string(),
+                index: threadgroup_buffers.len(),
+                msl_type: msl_type.as_str().replacen("device ", "threadgroup ", 1),
+            });
+            continue;
+        }
+        buffers.push(BufferMetadata {
+            name: param.name.to_string(),
+            index: indices[buffers.len()],
+            msl_type: msl_type.as_str().to_string(),
+        });
+    }
+    if let Some((output, _)) = elementwise_output(kernel)? {
+        let msl_type = kernel_gen.convert_type(&output.ty, output.span.clone())?;
+        buffers.push(BufferMetadata {
+            name: output.name.to_string(),
+            index: indices[buffers.len()],
+            msl_type: msl_type.as_str().to_string(),
+        });
+    }
+    if !uniforms.is_empty() {
+        buffers.push(BufferMetadata {
+            name: UNIFORMS_PARAM.to_string(),
+            index: indices[buffers.len()],
+            msl_type: format!("constant {}&", uniforms_struct_name(kernel.name)),
+        });
+    }
+
+    let stream = schedule
+        .and_then(|sched| {
+            sched
+                .directives
+                .iter()
+                .rev()
+                .find_map(|directive| match directive {
+                    ScheduleDirective::Stream(name) => Some(name.to_string()),
+                    _ => None,
+                })
+        })
+        .unwrap_or_else(|| DEFAULT_STREAM.to_string());
+
+    let hints = schedule
+        .into_iter()
+        .flat_map(|sched| &sched.directives)
+        .filter_map(|directive| match directive {
+            ScheduleDirective::Hints(hints) => Some(hints),
+            _ => None,
+        })
+        .flatten();
+    let reductions = kernel
+        .attributes
+        .iter()
+        .chain(hints)
+        .filter_map(|attr| attr.as_all_reduce())
+        .map(|reduce| ReductionMetadata {
+            buffer: reduce.buffer.to_string(),
+            op: reduce.op.name().to_string(),
+        })
+        .collect();
+
+    Ok(KernelMetadata {
+        name: kernel.name.to_string(),
+        threadgroup_size: [x, y, z],
+        grid_size: dispatch.map(|dispatch| dispatch.grid),
+        threadgroup_count: dispatch.and_then(|dispatch| dispatch.threadgroups),
+        buffers,
+        threadgroup_buffers,
+        stream,
+        constraints: kernel_gen.symbolic_constraints(kernel)?,
+        reductions,
+    })
+}
@@ -1,13 +1,52 @@
+use crate::builtins::msl_identifier;
 use crate::error::{CodegenError, Result};
 use crate::expr::ExprGenerator;
-use crate::types::TypeConverter;
-use flare::ast::Stmt;
+use crate::kernel::ELEMENTWISE_INDEX;
+use crate::link::{method_name, MethodTable};
+use crate::typeck::{ScalarType, SymbolTable, ValueType};
+use crate::types::{MetalType, TypeConverter};
+use flare::ast::{
+    contiguous_strides, walk_expr_mut, Attribute, AttributeArg, MemoryLocation, ScheduleBlock,
+    ScheduleDirective, Stmt, VisitorMut,
+};
+use flare::LineMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Write;
+use std::ops::Range;
+
+/// Flat element index a thread assigns when it lowers an elementwise tensor
+/// assignment.
+const ELEMENT_INDEX: &str = "flare_elem";
+
+/// Binding name whose value is intentionally unused.
+pub const DISCARD: &str = "_";
+
+/// Declares `name` with MSL type `type_code`; array types name their extent
+/// after the variable: `float lut[4]`.
+fn declarator(type_code: &str, name: &str) -> String {
+    match type_code.split_once('[') {
+        Some((elem, extent)) => format!("{} {}[{}", elem, name, extent),
+        None => format!("{} {}", type_code, name),
+    }
+}
 
 pub struct StmtGenerator {
     expr_gen: ExprGenerator,
 
     indent_level: usize,
+
+    loop_labels: Vec<Option<String>>,
+
+    memory_placements: HashMap<String, &'static str>,
+
+    /// Blocks, branches and loops enclosing the statement being generated.
+    nesting: usize,
+
+    line_map: Option<LineMap>,
+
+    /// Element count of the `@elementwise` kernel being generated, whose
+    /// threads each compute the element at `ELEMENTWISE_INDEX`.
+    elementwise_len: Option<String>,
 }
 
 impl StmtGenerator {
@@ -15,6 +54,11 @@ impl StmtGenerator {
         Self {
             expr_gen: ExprGenerator::new(),
             indent_level: 0,
+            loop_labels: Vec::new(),
+            memory_placements: HashMap::new(),
+            nesting: 0,
+            line_map: None,
+            elementwise_len: None,
         }
     }
 
@@ -22,23 +66,191 @@ impl StmtGenerator {
         Self {
             expr_gen: ExprGenerator::with_indent(indent_level),
             indent_level,
+            loop_labels: Vec::new(),
+            memory_placements: HashMap::new(),
+            nesting: 0,
+            line_map: None,
+            elementwise_len: None,
         }
     }
 
     pub fn set_indent(&mut self, level: usize) {
         self.indent_level = level;
-        self.expr_gen = ExprGenerator::with_indent(level);
+        self.expr_gen.set_indent(level);
     }
 
     pub fn indent(&mut self) {
         self.indent_level += 1;
-        self.expr_gen = ExprGenerator::with_indent(self.indent_level);
+        self.expr_gen.set_indent(self.indent_level);
     }
 
     pub fn dedent(&mut self) {
         if self.indent_level > 0 {
             self.indent_level -= 1;
-            self.expr_gen = ExprGenerator::with_indent(self.indent_level);
+            self.expr_gen.set_indent(self.indent_level);
+        }
+    }
+
+    /// Starts a fresh symbol scope for a new kernel or function.
+    pub fn set_renames(&mut self, renames: HashMap<String, String>) {
+        self.expr_gen.set_renames(renames);
+    }
+
+    pub fn set_elementwise_len(&mut self, len: Option<&str>) {
+        self.elementwise_len = len.map(str::to_string);
+    }
+
+    pub fn set_block_dims(&mut self, dims: Option<(u32, u32, u32)>) {
+        self.expr_gen.set_block_dims(dims);
+    }
+
+    pub fn set_type_aliases(&mut self, aliases: HashSet<String>) {
+        self.expr_gen.set_type_aliases(aliases);
+    }
+
+    pub fn set_threadgroup_buffers(&mut self, buffers: HashMap<String, Vec<String>>) {
+        self.expr_gen.set_threadgroup_buffers(buffers);
+    }
+
+    pub fn generate_expr(&mut self, expr: &flare::ast::Expr) -> Result<String> {
+        self.expr_gen.generate(expr)
+    }
+
+    pub fn set_methods(&mut self, methods: MethodTable) {
+        self.expr_gen.set_methods(methods);
+    }
+
+    pub fn set_function_symbols(&mut self, symbols: HashMap<String, String>) {
+        self.expr_gen.set_function_symbols(symbols);
+    }
+
+    pub fn convert_type(&self, ty: &flare::ast::Type, span: Range<usize>) -> Result<MetalType> {
+        self.expr_gen.convert_type(ty, span)
+    }
+
+    pub fn reset_symbols(&mut self) {
+        self.expr_gen.symbols_mut().clear();
+        self.loop_labels.clear();
+        self.nesting = 0;
+    }
+
+    /// Applies the `memory(var, location)` directives of the schedule
+    /// targeting the kernel about to be generated.
+    pub fn set_memory_placements(&mut self, schedule: Option<&ScheduleBlock>) {
+        self.memory_placements.clear();
+        let Some(schedule) = schedule else {
+            return;
+        };
+
+        for directive in &schedule.directives {
+            if let ScheduleDirective::Memory { var, location } = directive {
+                let location = match location {
+                    MemoryLocation::Local => continue,
+                    MemoryLocation::Shared => "shared",
+                    MemoryLocation::Global => "global",
+                    MemoryLocation::Constant => "constant",
+                    MemoryLocation::Named(name) => name,
+                    MemoryLocation::Persistent
+                    | MemoryLocation::Temporary
+                    | MemoryLocation::Streaming => "device",
+                };
+                self.memory_placements.insert(
+                    var.to_string(),
+                    TypeConverter::address_space_for_location(location),
+                );
+            }
+        }
+    }
+
+    pub fn set_line_map(&mut self, line_map: Option<LineMap>) {
+        self.line_map = line_map;
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        self.expr_gen.symbols()
+    }
+
+    pub fn take_diagnostics(&mut self) -> Vec<flare::Diagnostic> {
+        self.expr_gen.take_diagnostics()
+    }
+
+    pub fn declare(&mut self, name: &str, ty: Option<&flare::ast::Type>) {
+        if let Some(value_ty) = ty.and_then(ValueType::from_ast) {
+            self.expr_gen.symbols_mut().declare(name, value_ty);
+        }
+    }
+
+    fn check_binding(&self, ty: Option<&flare::ast::Type>, value: &flare::ast::Expr) -> Result<()> {
+        match ty.and_then(ValueType::from_ast) {
+            Some(target) => self.expr_gen.check_assignment(&target, value),
+            None => Ok(()),
+        }
+    }
+
+    fn generate_value(
+        &mut self,
+        ty: Option<&flare::ast::Type>,
+        value: &flare::ast::Expr,
+    ) -> Result<String> {
+        let target = ty.and_then(ValueType::from_ast);
+        match value {
+            flare::ast::Expr::Block { statements, span } => {
+                self.generate_value_block(statements, target.as_ref(), span.clone())
+            }
+            _ => self.expr_gen.generate_for_type(value, target.as_ref()),
+        }
+    }
+
+    /// Lowers a block used as an initializer to a statement expression
+    /// `({ ...; value; })` whose value is the block's trailing expression.
+    fn generate_value_block(
+        &mut self,
+        statements: &[Stmt],
+        target: Option<&ValueType>,
+        span: Range<usize>,
+    ) -> Result<String> {
+        let Some((Stmt::Expr(result), leading)) = statements.split_last() else {
+            return Err(CodegenError::unsupported_feature(
+                "block expressions without a trailing value",
+                span,
+                Some("end the block with the expression it evaluates to".to_string()),
+            ));
+        };
+
+        let mut output = String::from("({\n");
+        self.indent();
+        self.expr_gen.symbols_mut().push_scope();
+
+        let body = leading
+            .iter()
+            .try_for_each(|stmt| {
+                output.push_str(&self.generate(stmt)?);
+                Ok(())
+            })
+            .and_then(|()| self.expr_gen.generate_for_type(result, target));
+        if let Ok(result_code) = &body {
+            output.push_str(&format!("{}{};\n", self.get_indent(), result_code));
+        }
+
+        self.expr_gen.symbols_mut().pop_scope();
+        self.dedent();
+        body?;
+
+        output.push_str(&format!("{}}})", self.get_indent()));
+        Ok(output)
+    }
+
+    fn declare_binding(
+        &mut self,
+        name: &str,
+        ty: Option<&flare::ast::Type>,
+        value: Option<&flare::ast::Expr>,
+    ) {
+        let value_ty = ty
+            .and_then(ValueType::from_ast)
+            .or_else(|| value.and_then(|v| self.expr_gen.infer_type(v)));
+        if let Some(value_ty) = value_ty {
+            self.expr_gen.symbols_mut().declare(name, value_ty);
         }
     }
 
@@ -46,7 +258,20 @@ impl StmtGenerator {
         "    ".repeat(self.indent_level)
     }
 
+    /// Generates `stmt`, prefixed with a `// line N` comment pointing back
+    /// at the flare source when a line map is set.
     pub fn generate(&mut self, stmt: &Stmt) -> Result<String> {
+        let code = self.generate_stmt(stmt)?;
+        match &self.line_map {
+            Some(line_map) if !code.is_empty() && !matches!(stmt, Stmt::Block { .. }) => {
+                let line = line_map.line(stmt.span().start);
+                Ok(format!("{}// line {}\n{}", self.get_indent(), line, code))
+            }
+            _ => Ok(code),
+        }
+    }
+
+    fn generate_stmt(&mut self, stmt: &Stmt) -> Result<String> {
         match stmt {
             Stmt::Kernel(_) => Err(CodegenError::statement_error(
                 "kernel statements should be handled by KernelGenerator",
@@ -60,20 +285,62 @@ impl StmtGenerator {
                 params,
                 return_type,
                 body,
+                attributes,
+                receiver,
                 span,
-            } => self.generate_function(name, params, return_type.as_ref(), body, span.clone()),
+            } => {
+                let mut output = String::new();
+                if attributes.iter().any(|attr| attr.name == "pure") {
+                    writeln!(&mut output, "{}// pure: no side effects", self.get_indent())?;
+                }
+                let name = match receiver {
+                    Some(ty) => method_name(ty, name),
+                    None => name.to_string(),
+                };
+                output.push_str(&self.generate_function(
+                    &name,
+                    params,
+                    return_type.as_ref(),
+                    body,
+                    span.clone(),
+                )?);
+                Ok(output)
+            }
+
+            Stmt::Let {
+                name,
+                ty,
+                value,
+                attributes,
+                ..
+            } if attributes.iter().any(|attr| attr.name == "constant") => {
+                self.generate_constant_let(name, ty.as_ref(), value)
+            }
 
             Stmt::Let {
                 name, ty, value, ..
             } => self.generate_let(name, ty.as_ref(), value),
 
             Stmt::Var {
-                name, ty, value, ..
-            } => self.generate_var(name, ty.as_ref(), value.as_ref()),
+                name,
+                ty,
+                value,
+                span,
+                ..
+            } => self.generate_var(name, ty.as_ref(), value.as_ref(), span.clone()),
 
             Stmt::Const {
-                name, ty, value, ..
-            } => self.generate_const(name, ty.as_ref(), value),
+                name,
+                ty,
+                value,
+                attributes,
+                span,
+            } => match attributes.iter().find(|a| a.name == "function_constant") {
+                Some(attribute) => {
+                    self.generate_function_constant(name, ty.as_ref(), value, attribute, span)
+                }
+                None => self.generate_const(name, ty.as_ref(), value),
+            },
 
             Stmt::If {
                 condition,
@@ -83,18 +350,48 @@ impl StmtGenerator {
             } => self.generate_if(condition, then_branch, else_branch.as_ref()),
 
             Stmt::While {
-                condition, body, ..
-            } => self.generate_while(condition, body),
+                label,
+                condition,
+                body,
+                ..
+            } => self.generate_while(*label, condition, body),
 
             Stmt::For {
+                label,
                 var,
                 iterator,
                 body,
                 span,
-            } => self.generate_for(var, iterator, body, span.clone()),
+            } => self.generate_for(*label, var, iterator, body, span.clone()),
 
             Stmt::Return { value, .. } => self.generate_return(value.as_ref()),
 
+            Stmt::Break { label, span } => self.generate_break(*label, span.clone()),
+
+            Stmt::Continue { label, span } => self.generate_continue(*label, span.clone()),
+
+            Stmt::Expr(expr @ flare::ast::Expr::Call { args, span, .. })
+                if ExprGenerator::is_scatter_call(expr) =>
+            {
+                let lines = self.expr_gen.generate_scatter(args, span.clone())?;
+                Ok(self.generate_scoped_lines(lines))
+            }
+
+            Stmt::Expr(expr @ flare::ast::Expr::Call { args, span, .. })
+                if ExprGenerator::is_async_copy_call(expr) =>
+            {
+                let lines = self.expr_gen.generate_async_copy(args, span.clone())?;
+                Ok(self.generate_scoped_lines(lines))
+            }
+
+            Stmt::Expr(flare::ast::Expr::Assign {
+                target,
+                value,
+                span,
+            }) if self.is_tensor_arithmetic(value) => {
+                self.generate_elementwise_assign(target, value, span.clone())
+            }
+
             Stmt::Expr(expr) => {
                 let expr_code = self.expr_gen.generate(expr)?;
                 Ok(format!("{}{};\n", self.get_indent(), expr_code))
@@ -102,14 +399,32 @@ impl StmtGenerator {
 
             Stmt::Block { statements, .. } => self.generate_block(statements),
 
+            // nothing pipelines stages yet, so they run in source order
+            Stmt::Staged { stmt, .. } => self.generate_stmt(stmt),
+
             Stmt::SyncThreads { .. } => Ok(format!(
                 "{}threadgroup_barrier(mem_flags::mem_threadgroup);\n",
                 self.get_indent()
             )),
 
-            Stmt::LoadShared { dest, src, .. } => {
-                let src_code = self.expr_gen.generate(src)?;
-                Ok(format!("{}{} = {};\n", self.get_indent(), dest, src_code))
+            Stmt::LoadShared {
+                dest,
+                indices,
+                src,
+                span,
+            } => {
+                let src_code = self.expr_gen.generate_flat_load(src)?;
+                if indices.is_empty() {
+                    return Ok(format!("{}{} = {};\n", self.get_indent(), dest, src_code));
+                }
+                let offset = self.expr_gen.shared_offset(dest, indices, span.clone())?;
+                Ok(format!(
+                    "{}{}[{}] = {};\n",
+                    self.get_indent(),
+                    dest,
+                    offset,
+                    src_code
+                ))
             }
 
             Stmt::TypeDef { .. } => Ok(String::new()),
@@ -127,7 +442,9 @@ impl StmtGenerator {
         let mut output = String::new();
 
         let ret_type = match return_type {
-            Some(ty) => TypeConverter::convert(ty, span.clone())?
+            Some(ty) => self
+                .expr_gen
+                .convert_type(ty, span.clone())?
                 .as_str()
                 .to_string(),
             None => "void".to_string(),
@@ -135,45 +452,101 @@ impl StmtGenerator {
 
         let mut param_strs = Vec::new();
         for param in params {
-            let param_type = TypeConverter::convert(&param.ty, param.span.clone())?;
-            param_strs.push(format!("{} {}", param_type.as_str(), param.name));
+            let param_type = self.expr_gen.convert_type(&param.ty, param.span.clone())?;
+            param_strs.push(format!(
+                "{} {}",
+                param_type.as_str(),
+                msl_identifier(param.name)
+            ));
         }
 
+        // module-prefixed helpers are local to their translation unit
+        let (name, prefixed) = self.expr_gen.function_symbol(name);
+        let linkage = if prefixed { "static " } else { "" };
         write!(
             &mut output,
-            "{}{} {}({})",
+            "{}{}{} {}({})",
             self.get_indent(),
+            linkage,
             ret_type,
             name,
             param_strs.join(", ")
         )?;
 
-        let body_code = self.expr_gen.generate(body)?;
         writeln!(&mut output, " {{")?;
         self.indent();
-        writeln!(&mut output, "{}return {};", self.get_indent(), body_code)?;
+        self.expr_gen.symbols_mut().push_scope();
+        for param in params {
+            self.declare(param.name, Some(&param.ty));
+        }
+
+        let body_code = match body {
+            flare::ast::Expr::Block { statements, .. } => {
+                self.generate_function_body(statements, return_type.is_some())
+            }
+            _ => self
+                .expr_gen
+                .generate(body)
+                .map(|code| format!("{}return {};\n", self.get_indent(), code)),
+        };
+
+        self.expr_gen.symbols_mut().pop_scope();
         self.dedent();
+        output.push_str(&body_code?);
         writeln!(&mut output, "{}}}", self.get_indent())?;
 
         Ok(output)
     }
 
+    /// Emits a function body, returning a trailing bare expression as the
+    /// function's value when the function declares a return type.
+    fn generate_function_body(
+        &mut self,
+        statements: &[Stmt],
+        returns_value: bool,
+    ) -> Result<String> {
+        let mut output = String::new();
+
+        for (i, stmt) in statements.iter().enumerate() {
+            let is_last = i + 1 == statements.len();
+            match stmt {
+                Stmt::Expr(expr) if is_last && returns_value => {
+                    let expr_code = self.expr_gen.generate(expr)?;
+                    writeln!(&mut output, "{}return {};", self.get_indent(), expr_code)?;
+                }
+                _ => output.push_str(&self.generate(stmt)?),
+            }
+        }
+
+        Ok(output)
+    }
+
     fn generate_let(
         &mut self,
         name: &str,
         ty: Option<&flare::ast::Type>,
         value: &flare::ast::Expr,
     ) -> Result<String> {
-        let value_code = self.expr_gen.generate(value)?;
+        self.check_binding(ty, value)?;
+        if name == DISCARD {
+            return self.generate_discard(ty, value);
+        }
+        if let Some(space) = self.memory_placements.get(name).copied() {
+            self.check_placement(name, space, value.span())?;
+            return self.generate_placed(name, ty, Some(value), space, value.span());
+        }
+
+        let value_code = self.generate_value(ty, value)?;
+        self.declare_binding(name, ty, Some(value));
+        let name = msl_identifier(name);
 
         match ty {
             Some(t) => {
-                let type_code = TypeConverter::convert(t, value.span())?;
+                let type_code = self.expr_gen.convert_type(t, value.span())?;
                 Ok(format!(
-                    "{}const {} {} = {};\n",
+                    "{}const {} = {};\n",
                     self.get_indent(),
-                    type_code.as_str(),
-                    name,
+                    declarator(type_code.as_str(), &name),
                     value_code
                 ))
             }
@@ -186,31 +559,78 @@ impl StmtGenerator {
         }
     }
 
+    /// Emits a `@constant` lookup table as a `constant` array, which the
+    /// GPU can cache; every element must be a literal.
+    fn generate_constant_let(
+        &mut self,
+        name: &str,
+        ty: Option<&flare::ast::Type>,
+        value: &flare::ast::Expr,
+    ) -> Result<String> {
+        if !matches!(value, flare::ast::Expr::Array { .. }) {
+            return Err(CodegenError::statement_error(
+                format!(
+                    "@constant binding '{}' must be initialized with an array",
+                    name
+                ),
+                value.span(),
+            ));
+        }
+        if let Some(element) = non_constant_element(value) {
+            return Err(CodegenError::statement_error(
+                format!(
+                    "@constant array '{}' elements must be compile-time constants",
+                    name
+                ),
+                element.span(),
+            ));
+        }
+        self.check_binding(ty, value)?;
+        self.generate_placed(name, ty, Some(value), "constant", value.span())
+    }
+
     fn generate_var(
         &mut self,
         name: &str,
         ty: Option<&flare::ast::Type>,
         value: Option<&flare::ast::Expr>,
+        span: std::ops::Range<usize>,
     ) -> Result<String> {
+        if name == DISCARD {
+            return match value {
+                Some(v) => self.generate_discard(ty, v),
+                None => Ok(String::new()),
+            };
+        }
+        if let Some(space) = self.memory_placements.get(name).copied() {
+            self.check_placement(name, space, span.clone())?;
+            if let (Some(t), Some(v)) = (ty, value) {
+                self.check_binding(Some(t), v)?;
+            }
+            return self.generate_placed(name, ty, value, space, span);
+        }
+
+        self.declare_binding(name, ty, value);
+        let symbol = msl_identifier(name);
+
         match (ty, value) {
             (Some(t), Some(v)) => {
-                let type_code = TypeConverter::convert(t, v.span())?;
-                let value_code = self.expr_gen.generate(v)?;
+                self.check_binding(Some(t), v)?;
+                let type_code = self.expr_gen.convert_type(t, v.span())?;
+                let value_code = self.generate_value(Some(t), v)?;
                 Ok(format!(
-                    "{}{} {} = {};\n",
+                    "{}{} = {};\n",
                     self.get_indent(),
-                    type_code.as_str(),
-                    name,
+                    declarator(type_code.as_str(), &symbol),
                     value_code
                 ))
             }
             (Some(t), None) => {
-                let type_code = TypeConverter::convert(t, 0..0)?;
+                let type_code = self.expr_gen.convert_type(t, span)?;
                 Ok(format!(
-                    "{}{} {};\n",
+                    "{}{};\n",
                     self.get_indent(),
-                    type_code.as_str(),
-                    name
+                    declarator(type_code.as_str(), &symbol)
                 ))
             }
             (None, Some(v)) => {
@@ -218,28 +638,117 @@ impl StmtGenerator {
                 Ok(format!(
                     "{}auto {} = {};\n",
                     self.get_indent(),
-                    name,
+                    symbol,
                     value_code
                 ))
             }
             (None, None) => Err(CodegenError::statement_error(
-                "var statement requires either type or value",
-                0..0,
+                format!(
+                    "variable '{}' needs a type annotation or an initializer",
+                    name
+                ),
+                span,
             )),
         }
     }
 
+    /// Evaluates a value bound to `_` for its side effects only; the binding
+    /// is never registered in scope.
+    fn generate_discard(
+        &mut self,
+        ty: Option<&flare::ast::Type>,
+        value: &flare::ast::Expr,
+    ) -> Result<String> {
+        let value_code = self.generate_value(ty, value)?;
+        Ok(format!("{}(void){};\n", self.get_indent(), value_code))
+    }
+
+    /// Rejects `memory(var, location)` placements MSL cannot express for a
+    /// local binding: device memory is only reachable through buffer
+    /// parameters, constant memory needs a compile-time initializer, and
+    /// threadgroup variables must be declared at kernel scope.
+    fn check_placement(&self, name: &str, space: &str, span: std::ops::Range<usize>) -> Result<()> {
+        let message = match space {
+            "threadgroup" if self.nesting == 0 => return Ok(()),
+            "threadgroup" => format!(
+                "threadgroup variable '{}' must be declared at kernel scope, \
+                 not inside a block, branch or loop",
+                name
+            ),
+            "constant" => format!(
+                "cannot place '{}' in constant memory; use a program-scope `const` \
+                 for values known at compile time",
+                name
+            ),
+            _ => format!(
+                "cannot place '{}' in {} memory; device memory is only reachable \
+                 through buffer parameters",
+                name, space
+            ),
+        };
+        Err(CodegenError::invalid_memory_config(message, span))
+    }
+
+    /// Emits a binding placed in an explicit address space by the kernel's
+    /// schedule. Threadgroup variables cannot have initializers, so their
+    /// value is stored after the declaration.
+    fn generate_placed(
+        &mut self,
+        name: &str,
+        ty: Option<&flare::ast::Type>,
+        value: Option<&flare::ast::Expr>,
+        space: &str,
+        span: std::ops::Range<usize>,
+    ) -> Result<String> {
+        let type_code = match ty {
+            Some(t) => self.expr_gen.convert_type(t, span.clone())?.as_str().to_string(),
+            None => match value.and_then(|v| self.expr_gen.infer_type(v)) {
+                Some(value_ty) => value_ty.msl_name(),
+                None => {
+                    return Err(CodegenError::statement_error(
+                        format!(
+                            "cannot place '{}' in {} memory without a known type; add a type annotation",
+                            name, space
+                        ),
+                        span,
+                    ))
+                }
+            },
+        };
+
+        let value_code = match value {
+            Some(v) => Some(self.generate_value(ty, v)?),
+            None => None,
+        };
+        self.declare_binding(name, ty, value);
+        let name = msl_identifier(name);
+
+        let declarator = declarator(&type_code, &name);
+        let indent = self.get_indent();
+        match value_code {
+            Some(code) if space == "threadgroup" => Ok(format!(
+                "{}{} {};\n{}{} = {};\n",
+                indent, space, declarator, indent, name, code
+            )),
+            Some(code) => Ok(format!("{}{} {} = {};\n", indent, space, declarator, code)),
+            None => Ok(format!("{}{} {};\n", indent, space, declarator)),
+        }
+    }
+
     fn generate_const(
         &mut self,
         name: &str,
         ty: Option<&flare::ast::Type>,
         value: &flare::ast::Expr,
     ) -> Result<String> {
-        let value_code = self.expr_gen.generate(value)?;
+        // program-scope constants cannot hold statement expressions, so
+        // block values are left to the expression generator to reject
+        let target = ty.and_then(ValueType::from_ast);
+        let value_code = self.expr_gen.generate_for_type(value, target.as_ref())?;
 
         match ty {
             Some(t) => {
-                let type_code = TypeConverter::convert(t, value.span())?;
+                let type_code = self.expr_gen.convert_type(t, value.span())?;
                 Ok(format!(
                     "{}constant {} {} = {};\n",
                     self.get_indent(),
@@ -257,6 +766,59 @@ impl StmtGenerator {
         }
     }
 
+    /// Declares a `@function_constant(index)` const as an MSL function
+    /// constant, whose value the host supplies when creating the pipeline.
+    fn generate_function_constant(
+        &mut self,
+        name: &str,
+        ty: Option<&flare::ast::Type>,
+        value: &flare::ast::Expr,
+        attribute: &Attribute,
+        span: &Range<usize>,
+    ) -> Result<String> {
+        let index = match attribute.args.as_slice() {
+            [AttributeArg::IntLiteral(index)] if *index >= 0 => *index,
+            _ => {
+                return Err(CodegenError::statement_error(
+                    format!(
+                        "@function_constant on '{}' expects a single non-negative index, e.g. @function_constant(0)",
+                        name
+                    ),
+                    attribute.span.clone(),
+                ))
+            }
+        };
+
+        let type_code = match ty {
+            Some(t) => self
+                .expr_gen
+                .convert_type(t, span.clone())?
+                .as_str()
+                .to_string(),
+            None => match self.expr_gen.infer_type(value) {
+                Some(value_ty) => value_ty.msl_name(),
+                None => {
+                    return Err(CodegenError::statement_error(
+                        format!(
+                        "cannot infer the type of function constant '{}'; add a type annotation",
+                        name
+                    ),
+                        span.clone(),
+                    ))
+                }
+            },
+        };
+        self.declare_binding(name, ty, Some(value));
+
+        Ok(format!(
+            "{}constant {} {} [[function_constant({})]];\n",
+            self.get_indent(),
+            type_code,
+            name,
+            index
+        ))
+    }
+
     fn generate_if(
         &mut self,
         condition: &flare::ast::Expr,
@@ -269,14 +831,14 @@ impl StmtGenerator {
         writeln!(&mut output, "{}if ({}) {{", self.get_indent(), cond_code)?;
 
         self.indent();
-        let then_code = self.generate(then_branch)?;
+        let then_code = self.generate_body(then_branch)?;
         output.push_str(&then_code);
         self.dedent();
 
         if let Some(else_stmt) = else_branch {
             writeln!(&mut output, "{}}} else {{", self.get_indent())?;
             self.indent();
-            let else_code = self.generate(else_stmt)?;
+            let else_code = self.generate_body(else_stmt)?;
             output.push_str(&else_code);
             self.dedent();
         }
@@ -286,71 +848,181 @@ impl StmtGenerator {
         Ok(output)
     }
 
-    fn generate_while(&mut self, condition: &flare::ast::Expr, body: &Stmt) -> Result<String> {
-        let mut output = String::new();
-
-        let cond_code = self.expr_gen.generate(condition)?;
-        writeln!(&mut output, "{}while ({}) {{", self.get_indent(), cond_code)?;
+    fn generate_while(
+        &mut self,
+        label: Option<&str>,
+        condition: &flare::ast::Expr,
+        body: &Stmt,
+    ) -> Result<String> {
+        self.with_loop_flags(label, body, |this| {
+            let mut output = String::new();
+            let cond_code = this.expr_gen.generate(condition)?;
+            writeln!(&mut output, "{}while ({}) {{", this.get_indent(), cond_code)?;
 
-        self.indent();
-        let body_code = self.generate(body)?;
-        output.push_str(&body_code);
-        self.dedent();
+            this.loop_labels.push(label.map(str::to_string));
+            this.indent();
+            let body_code = this.generate_body(body);
+            this.dedent();
+            this.loop_labels.pop();
+            output.push_str(&body_code?);
 
-        writeln!(&mut output, "{}}}", self.get_indent())?;
+            writeln!(&mut output, "{}}}", this.get_indent())?;
+            output.push_str(&this.generate_loop_exit_checks(body)?);
 
-        Ok(output)
+            Ok(output)
+        })
     }
 
     fn generate_for(
         &mut self,
+        label: Option<&str>,
         var: &str,
         iterator: &flare::ast::Expr,
         body: &Stmt,
         span: std::ops::Range<usize>,
     ) -> Result<String> {
-        match iterator {
-            flare::ast::Expr::Range { start, end, .. } => {
-                let start_code = match start {
-                    Some(s) => self.expr_gen.generate(s)?,
-                    None => "0".to_string(),
-                };
+        let header = self.generate_for_header(var, iterator, span)?;
 
-                let end_code = match end {
-                    Some(e) => self.expr_gen.generate(e)?,
-                    None => {
-                        return Err(CodegenError::statement_error(
-                            "for loop range requires end value",
-                            span,
-                        ));
-                    }
-                };
+        self.with_loop_flags(label, body, |this| {
+            let mut output = String::new();
+            writeln!(&mut output, "{}{} {{", this.get_indent(), header)?;
 
-                let mut output = String::new();
-                writeln!(
-                    &mut output,
-                    "{}for (int {} = {}; {} < {}; {}++) {{",
-                    self.get_indent(),
-                    var,
-                    start_code,
-                    var,
-                    end_code,
-                    var
-                )?;
+            this.loop_labels.push(label.map(str::to_string));
+            this.expr_gen.symbols_mut().push_scope();
+            if var != DISCARD {
+                this.expr_gen
+                    .symbols_mut()
+                    .declare(var, ValueType::Scalar(ScalarType::Int));
+            }
+            this.indent();
+            let body_code = this.generate_body(body);
+            this.dedent();
+            this.expr_gen.symbols_mut().pop_scope();
+            this.loop_labels.pop();
+            output.push_str(&body_code?);
 
-                self.indent();
-                let body_code = self.generate(body)?;
-                output.push_str(&body_code);
-                self.dedent();
+            writeln!(&mut output, "{}}}", this.get_indent())?;
+            output.push_str(&this.generate_loop_exit_checks(body)?);
 
-                writeln!(&mut output, "{}}}", self.get_indent())?;
+            Ok(output)
+        })
+    }
 
-                Ok(output)
+    fn generate_for_header(
+        &mut self,
+        var: &str,
+        iterator: &flare::ast::Expr,
+        span: std::ops::Range<usize>,
+    ) -> Result<String> {
+        let (range, reversed) = match iterator {
+            flare::ast::Expr::Call { func, args, .. } if args.is_empty() => match func.as_ref() {
+                flare::ast::Expr::Member { object, field, .. } if *field == "rev" => {
+                    (object.as_ref(), true)
+                }
+                _ => (iterator, false),
+            },
+            _ => (iterator, false),
+        };
+
+        let (start, end, step) = match range {
+            flare::ast::Expr::Range {
+                start, end, step, ..
+            } => (start, end, step),
+            _ => {
+                return Err(CodegenError::statement_error(
+                    "for loop iterator must be a range expression in Metal codegen",
+                    span,
+                ))
             }
-            _ => Err(CodegenError::statement_error(
-                "for loop iterator must be a range expression in Metal codegen",
-                span,
-            )),
+        };
+
+        let start_code = match start {
+            Some(s) => self.expr_gen.generate(s)?,
+            None => "0".to_string(),
+        };
+
+        let end_code = match end {
+            Some(e) => self.expr_gen.generate(e)?,
+            None => {
+                return Err(CodegenError::statement_error(
+                    "for loop range requires end value",
+                    span,
+                ));
+            }
+        };
+
+        let step_value = match step {
+            Some(step) if reversed => {
+                return Err(CodegenError::statement_error(
+                    "`rev()` cannot be combined with an explicit `step`",
+                    step.span(),
+                ))
+            }
+            Some(step) => match Self::const_int(step) {
+                Some(0) => {
+                    return Err(CodegenError::statement_error(
+                        "for loop step cannot be zero",
+                        step.span(),
+                    ))
+                }
+                Some(n) => n,
+                None => {
+                    return Err(CodegenError::statement_error(
+                        "for loop step must be a constant integer",
+                        step.span(),
+                    ))
+                }
+            },
+            None => 1,
+        };
+
+        let bounds = (
+            start.as_deref().map_or(Some(0), Self::const_int),
+            end.as_deref().and_then(Self::const_int),
+        );
+        if let (Some(lo), Some(hi)) = bounds {
+            if (step_value > 0 && lo > hi) || (step_value < 0 && lo < hi) {
+                return Err(CodegenError::statement_error(
+                    format!(
+                        "for loop step {} never reaches the end of range {}..{}",
+                        step_value, lo, hi
+                    ),
+                    span,
+                ));
+            }
+        }
+
+        let var = msl_identifier(var);
+        let header = if reversed {
+            format!(
+                "for (int {} = {} - 1; {} >= {}; {}--)",
+                var, end_code, var, start_code, var
+            )
+        } else {
+            let (cmp, incr) = match step_value {
+                1 => ("<", format!("{}++", var)),
+                -1 => (">", format!("{}--", var)),
+                n if n > 0 => ("<", format!("{} += {}", var, n)),
+                n => (">", format!("{} -= {}", var, -n)),
+            };
+            format!(
+                "for (int {} = {}; {} {} {}; {})",
+                var, start_code, var, cmp, end_code, incr
+            )
+        };
+
+        Ok(header)
+    }
+
+    fn const_int(expr: &flare::ast::Expr) -> Option<i64> {
+        match expr {
+            flare::ast::Expr::IntLiteral(n, _) => Some(*n),
+            flare::ast::Expr::Unary {
+                op: flare::ast::UnOp::Neg,
+                expr,
+                ..
+            } => Self::const_int(expr).map(|n| -n),
+            _ => None,
         }
     }
 
@@ -364,26 +1036,484 @@ impl StmtGenerator {
         }
     }
 
-    fn generate_block(&mut self, statements: &[Stmt]) -> Result<String> {
-        let mut output = String::new();
+    fn generate_break(
+        &mut self,
+        label: Option<&str>,
+        span: std::ops::Range<usize>,
+    ) -> Result<String> {
+        match self.resolve_jump_target(label, span)? {
+            None => Ok(format!("{}break;\n", self.get_indent())),
+            Some(label) => Ok(format!(
+                "{}{} = true;\n{}break;\n",
+                self.get_indent(),
+                Self::break_flag(&label),
+                self.get_indent()
+            )),
+        }
+    }
+
+    fn generate_continue(
+        &mut self,
+        label: Option<&str>,
+        span: std::ops::Range<usize>,
+    ) -> Result<String> {
+        match self.resolve_jump_target(label, span)? {
+            None => Ok(format!("{}continue;\n", self.get_indent())),
+            Some(label) => Ok(format!(
+                "{}{} = true;\n{}break;\n",
+                self.get_indent(),
+                Self::continue_flag(&label),
+                self.get_indent()
+            )),
+        }
+    }
+
+    /// Resolves the loop a `break`/`continue` jumps out of. Returns `None` when
+    /// the target is the innermost loop, or the label of an outer loop that has
+    /// to be reached through flag variables since MSL has no `goto`.
+    fn resolve_jump_target(
+        &self,
+        label: Option<&str>,
+        span: std::ops::Range<usize>,
+    ) -> Result<Option<String>> {
+        let innermost = match self.loop_labels.last() {
+            Some(innermost) => innermost,
+            None => {
+                return Err(CodegenError::statement_error(
+                    "`break`/`continue` used outside of a loop",
+                    span,
+                ))
+            }
+        };
+
+        let label = match label {
+            Some(label) => label,
+            None => return Ok(None),
+        };
 
+        if innermost.as_deref() == Some(label) {
+            return Ok(None);
+        }
+
+        if self.loop_labels.iter().any(|l| l.as_deref() == Some(label)) {
+            Ok(Some(label.to_string()))
+        } else {
+            Err(CodegenError::statement_error(
+                format!("unknown loop label '{}", label),
+                span,
+            ))
+        }
+    }
+
+    fn break_flag(label: &str) -> String {
+        format!("flare_break_{}", label)
+    }
+
+    fn continue_flag(label: &str) -> String {
+        format!("flare_continue_{}", label)
+    }
+
+    /// Generates a labeled loop together with the flags its nested jumps set.
+    /// The flags and the loop are wrapped in their own block so sibling loops
+    /// sharing a label don't redeclare them.
+    fn with_loop_flags(
+        &mut self,
+        label: Option<&str>,
+        body: &Stmt,
+        generate_loop: impl FnOnce(&mut Self) -> Result<String>,
+    ) -> Result<String> {
+        let needs_flags = label.is_some_and(|label| {
+            let (breaks, continues) = nested_jumps_to(body, label, 1);
+            breaks || continues
+        });
+        if !needs_flags {
+            return generate_loop(self);
+        }
+
+        let mut output = String::new();
         writeln!(&mut output, "{}{{", self.get_indent())?;
         self.indent();
+        let scoped = self
+            .generate_loop_flags(label, body)
+            .and_then(|flags| Ok(flags + &generate_loop(self)?));
+        self.dedent();
+        output.push_str(&scoped?);
+        writeln!(&mut output, "{}}}", self.get_indent())?;
+
+        Ok(output)
+    }
 
-        for stmt in statements {
-            let stmt_code = self.generate(stmt)?;
-            output.push_str(&stmt_code);
+    fn generate_loop_flags(&self, label: Option<&str>, body: &Stmt) -> Result<String> {
+        let mut output = String::new();
+
+        if let Some(label) = label {
+            let (breaks, continues) = nested_jumps_to(body, label, 1);
+            if breaks {
+                writeln!(
+                    &mut output,
+                    "{}bool {} = false;",
+                    self.get_indent(),
+                    Self::break_flag(label)
+                )?;
+            }
+            if continues {
+                writeln!(
+                    &mut output,
+                    "{}bool {} = false;",
+                    self.get_indent(),
+                    Self::continue_flag(label)
+                )?;
+            }
+        }
+
+        Ok(output)
+    }
+
+    /// After a nested loop finishes, forwards labeled jumps set inside it to the
+    /// enclosing loops until the labeled loop is reached.
+    fn generate_loop_exit_checks(&self, body: &Stmt) -> Result<String> {
+        let mut output = String::new();
+        let indent = self.get_indent();
+
+        for (depth, label) in self.loop_labels.iter().enumerate().rev() {
+            let label = match label {
+                Some(label) => label,
+                None => continue,
+            };
+            let is_innermost = depth + 1 == self.loop_labels.len();
+            let (breaks, continues) = nested_jumps_to(body, label, 0);
+
+            if breaks {
+                writeln!(
+                    &mut output,
+                    "{}if ({}) break;",
+                    indent,
+                    Self::break_flag(label)
+                )?;
+            }
+            if continues {
+                let flag = Self::continue_flag(label);
+                if is_innermost {
+                    writeln!(
+                        &mut output,
+                        "{}if ({}) {{ {} = false; continue; }}",
+                        indent, flag, flag
+                    )?;
+                } else {
+                    writeln!(&mut output, "{}if ({}) break;", indent, flag)?;
+                }
+            }
         }
 
+        Ok(output)
+    }
+
+    /// Generates the body of an `if` or loop, which already opens its own
+    /// braces, so a block body contributes only its statements.
+    fn generate_body(&mut self, body: &Stmt) -> Result<String> {
+        self.nesting += 1;
+        let result = match body {
+            Stmt::Block { statements, .. } => self.generate_scoped(statements),
+            _ => self.generate(body),
+        };
+        self.nesting -= 1;
+        result
+    }
+
+    /// Generates `statements` in a new symbol scope.
+    fn generate_scoped(&mut self, statements: &[Stmt]) -> Result<String> {
+        let mut output = String::new();
+        self.expr_gen.symbols_mut().push_scope();
+        let result = statements.iter().try_for_each(|stmt| {
+            output.push_str(&self.generate(stmt)?);
+            Ok(())
+        });
+        self.expr_gen.symbols_mut().pop_scope();
+        result.map(|()| output)
+    }
+
+    /// Whether `value` is arithmetic on whole tensors, such as `A * alpha`.
+    fn is_tensor_arithmetic(&self, value: &flare::ast::Expr) -> bool {
+        matches!(
+            value,
+            flare::ast::Expr::Binary { .. } | flare::ast::Expr::Unary { .. }
+        ) && matches!(
+            self.expr_gen.infer_type(value),
+            Some(ValueType::Tensor { .. })
+        )
+    }
+
+    /// Lowers `B = A * alpha`, where the value combines whole tensors, to
+    /// one element of `B` per thread, at the thread's index along x of the
+    /// grid, so the dispatch must cover every element of `B` along x. Every
+    /// tensor in the value is read at the same flat index, so all of them need
+    /// the shape and layout of `B`, and `B` must be densely packed. In an
+    /// `@elementwise` kernel, `output = A * alpha` reads each tensor at the
+    /// element the thread already computes.
+    fn generate_elementwise_assign(
+        &mut self,
+        target: &flare::ast::Expr,
+        value: &flare::ast::Expr,
+        span: Range<usize>,
+    ) -> Result<String> {
+        let target_ty = self.expr_gen.infer_type(target);
+        if let (Some(len), Some(ValueType::Scalar(_))) = (&self.elementwise_len, &target_ty) {
+            let (shape, strides) = (vec![len.clone()], vec!["1".to_string()]);
+            let element = self.element_value(value, &shape, &strides, ELEMENTWISE_INDEX)?;
+            let assign = flare::ast::Expr::Assign {
+                target: Box::new(target.clone()),
+                value: Box::new(element),
+                span,
+            };
+            let code = self.expr_gen.generate(&assign)?;
+            return Ok(format!("{}{};\n", self.get_indent(), code));
+        }
+
+        let strides = target_ty.as_ref().and_then(ValueType::tensor_strides);
+        let (shape, strides) = match (target_ty, strides) {
+            (Some(ValueType::Tensor { shape, .. }), Some(strides))
+                if is_dense(&shape, &strides) =>
+            {
+                (shape, strides)
+            }
+            _ => {
+                return Err(CodegenError::statement_error(
+                    "tensor arithmetic must be assigned to a densely packed tensor",
+                    span,
+                ))
+            }
+        };
+        let element = self.element_value(value, &shape, &strides, ELEMENT_INDEX)?;
+
+        let index = flare::ast::Expr::Ident(ELEMENT_INDEX, span.clone());
+        let assign = flare::ast::Expr::Assign {
+            target: Box::new(flare::ast::Expr::Index {
+                object: Box::new(target.clone()),
+                indices: vec![index],
+                span: span.clone(),
+            }),
+            value: Box::new(element),
+            span,
+        };
+        self.expr_gen.symbols_mut().push_scope();
+        self.expr_gen
+            .symbols_mut()
+            .declare(ELEMENT_INDEX, ValueType::Scalar(ScalarType::UInt));
+        let code = self.expr_gen.generate(&assign);
+        self.expr_gen.symbols_mut().pop_scope();
+
+        let indent = self.get_indent();
+        Ok(format!(
+            "{indent}{{\n\
+             {indent}    const uint {idx} = {gid};\n\
+             {indent}    if ({idx} < {len}) {{\n\
+             {indent}        {code};\n\
+             {indent}    }}\n\
+             {indent}}}\n",
+            idx = ELEMENT_INDEX,
+            gid = "threadgroup_position_in_grid.x * threads_per_threadgroup.x + \
+                   thread_position_in_threadgroup.x",
+            len = element_count(&shape),
+            code = code?,
+        ))
+    }
+
+    /// `value` with every whole tensor in it read at `index`.
+    fn element_value<'src>(
+        &self,
+        value: &flare::ast::Expr<'src>,
+        shape: &[String],
+        strides: &[String],
+        index: &'static str,
+    ) -> Result<flare::ast::Expr<'src>> {
+        let mut element = value.clone();
+        let mut rewrite = ElementRewrite {
+            expr_gen: &self.expr_gen,
+            shape,
+            strides,
+            index,
+            error: None,
+        };
+        rewrite.visit_expr_mut(&mut element);
+        match rewrite.error {
+            Some(error) => Err(error),
+            None => Ok(element),
+        }
+    }
+
+    /// Emits builtin statement lines, wrapping several in a block so the
+    /// temporaries they declare stay local to it.
+    fn generate_scoped_lines(&self, lines: Vec<String>) -> String {
+        let indent = self.get_indent();
+        if let [line] = lines.as_slice() {
+            return format!("{}{}\n", indent, line);
+        }
+
+        let mut output = format!("{}{{\n", indent);
+        for line in lines {
+            output.push_str(&format!("{}    {}\n", indent, line));
+        }
+        output.push_str(&format!("{}}}\n", indent));
+        output
+    }
+
+    fn generate_block(&mut self, statements: &[Stmt]) -> Result<String> {
+        let mut output = String::new();
+
+        writeln!(&mut output, "{}{{", self.get_indent())?;
+        self.indent();
+        self.nesting += 1;
+        let body = self.generate_scoped(statements);
+        self.nesting -= 1;
         self.dedent();
+        output.push_str(&body?);
         writeln!(&mut output, "{}}}", self.get_indent())?;
 
         Ok(output)
     }
 }
 
+/// Reports whether `stmt` contains a `break`/`continue` targeting `label` from
+/// inside at least `min_depth` nested loops.
+fn nested_jumps_to(stmt: &Stmt, label: &str, min_depth: usize) -> (bool, bool) {
+    fn visit(stmt: &Stmt, label: &str, depth: usize, min_depth: usize, found: &mut (bool, bool)) {
+        match stmt {
+            Stmt::Break { label: Some(l), .. } if *l == label && depth >= min_depth => {
+                found.0 = true
+            }
+            Stmt::Continue { label: Some(l), .. } if *l == label && depth >= min_depth => {
+                found.1 = true
+            }
+            Stmt::Block { statements, .. } => {
+                for s in statements {
+                    visit(s, label, depth, min_depth, found);
+                }
+            }
+            Stmt::If {
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                visit(then_branch, label, depth, min_depth, found);
+                if let Some(else_stmt) = else_branch {
+                    visit(else_stmt, label, depth, min_depth, found);
+                }
+            }
+            Stmt::For { body, .. } | Stmt::While { body, .. } => {
+                visit(body, label, depth + 1, min_depth, found)
+            }
+            Stmt::Staged { stmt, .. } => visit(stmt, label, depth, min_depth, found),
+            _ => {}
+        }
+    }
+
+    let mut found = (false, false);
+    visit(stmt, label, 0, min_depth, &mut found);
+    found
+}
+
 impl Default for StmtGenerator {
     fn default() -> Self {
         Self::new()
     }
 }
+
+/// First element of an array initializer that is not a literal, looking
+/// through negation and nested arrays.
+fn non_constant_element<'a, 'src>(
+    expr: &'a flare::ast::Expr<'src>,
+) -> Option<&'a flare::ast::Expr<'src>> {
+    use flare::ast::{Expr, UnOp};
+    match expr {
+        Expr::IntLiteral(..)
+        | Expr::UIntLiteral(..)
+        | Expr::FloatLiteral(..)
+        | Expr::BoolLiteral(..) => None,
+        Expr::Unary {
+            op: UnOp::Neg,
+            expr: inner,
+            ..
+        } => non_constant_element(inner).map(|_| expr),
+        Expr::Array { elements, .. } => elements.iter().find_map(non_constant_element),
+        _ => Some(expr),
+    }
+}
+
+/// Whether a tensor with `strides` packs its `shape` without gaps, in
+/// row-major or column-major order.
+fn is_dense(shape: &[String], strides: &[String]) -> bool {
+    let shape: Vec<&str> = shape.iter().map(String::as_str).collect();
+    strides == contiguous_strides(&shape, true) || strides == contiguous_strides(&shape, false)
+}
+
+/// Number of elements of a tensor of `shape`, such as `M * N`, with any
+/// dimension that is not a single name or literal parenthesized.
+fn element_count(shape: &[String]) -> String {
+    let dims: Vec<String> = shape
+        .iter()
+        .map(|dim| {
+            if dim.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                dim.clone()
+            } else {
+                format!("({})", dim)
+            }
+        })
+        .collect();
+    dims.join(" * ")
+}
+
+/// Reads each whole tensor in an elementwise value at `index`, rejecting
+/// tensors whose shape or layout differs from the target's.
+struct ElementRewrite<'g> {
+    expr_gen: &'g ExprGenerator,
+    shape: &'g [String],
+    strides: &'g [String],
+    index: &'static str,
+    error: Option<CodegenError>,
+}
+
+impl<'src> VisitorMut<'src> for ElementRewrite<'_> {
+    fn visit_expr_mut(&mut self, expr: &mut flare::ast::Expr<'src>) {
+        match expr {
+            // an indexed tensor is already one element
+            flare::ast::Expr::Index { indices, .. } => {
+                for index in indices {
+                    self.visit_expr_mut(index);
+                }
+            }
+            flare::ast::Expr::Ident(name, span) => {
+                let (name, span) = (*name, span.clone());
+                let Some(tensor @ ValueType::Tensor { shape, .. }) =
+                    self.expr_gen.symbols().lookup(name)
+                else {
+                    return;
+                };
+                let message = if shape.as_slice() != self.shape {
+                    Some(format!(
+                        "tensor '{}' of shape [{}] cannot be combined elementwise into shape [{}]",
+                        name,
+                        shape.join(", "),
+                        self.shape.join(", ")
+                    ))
+                } else if tensor.tensor_strides().as_deref() != Some(self.strides) {
+                    Some(format!(
+                        "tensor '{}' has a different layout from the tensor it is assigned to",
+                        name
+                    ))
+                } else {
+                    None
+                };
+                if let Some(message) = message {
+                    self.error
+                        .get_or_insert(CodegenError::expression_error(message, span.clone()));
+                }
+                *expr = flare::ast::Expr::Index {
+                    object: Box::new(flare::ast::Expr::Ident(name, span.clone())),
+                    indices: vec![flare::ast::Expr::Ident(self.index, span.clone())],
+                    span,
+                };
+            }
+            _ => walk_expr_mut(self, expr),
+        }
+    }
+}
@@ -0,0 +1,307 @@
+use flare::ast::{contiguous_strides, Type};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ScalarType {
+    Bool,
+    Int,
+    UInt,
+    Long,
+    ULong,
+    Half,
+    Float,
+    Double,
+}
+
+impl ScalarType {
+    pub fn is_integer(&self) -> bool {
+        matches!(
+            self,
+            ScalarType::Int | ScalarType::UInt | ScalarType::Long | ScalarType::ULong
+        )
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(
+            self,
+            ScalarType::Half | ScalarType::Float | ScalarType::Double
+        )
+    }
+
+    pub fn msl_name(&self) -> &'static str {
+        match self {
+            ScalarType::Bool => "bool",
+            ScalarType::Int => "int",
+            ScalarType::UInt => "uint",
+            ScalarType::Long => "long",
+            ScalarType::ULong => "ulong",
+            ScalarType::Half => "half",
+            ScalarType::Float => "float",
+            ScalarType::Double => "double",
+        }
+    }
+
+    pub fn from_msl_name(name: &str) -> Option<Self> {
+        match name {
+            "bool" => Some(ScalarType::Bool),
+            "int" => Some(ScalarType::Int),
+            "uint" => Some(ScalarType::UInt),
+            "long" => Some(ScalarType::Long),
+            "ulong" => Some(ScalarType::ULong),
+            "half" => Some(ScalarType::Half),
+            "float" => Some(ScalarType::Float),
+            "double" => Some(ScalarType::Double),
+            _ => None,
+        }
+    }
+}
+
+/// Backend view of a flare type, owned so it can outlive the AST it was
+/// resolved from while generators keep it in their symbol tables.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValueType {
+    Scalar(ScalarType),
+    Vector {
+        elem: ScalarType,
+        len: usize,
+    },
+    Matrix {
+        elem: ScalarType,
+        cols: usize,
+        rows: usize,
+    },
+    Tensor {
+        elem: Box<ValueType>,
+        shape: Vec<String>,
+        strides: Option<Vec<String>>,
+    },
+    Array {
+        elem: Box<ValueType>,
+        size: Option<usize>,
+    },
+    Pointer(Box<ValueType>),
+    Named(String),
+}
+
+impl ValueType {
+    pub fn from_ast(ty: &Type) -> Option<Self> {
+        match ty {
+            Type::I32(_) => Some(ValueType::Scalar(ScalarType::Int)),
+            Type::I64(_) => Some(ValueType::Scalar(ScalarType::Long)),
+            Type::U32(_) => Some(ValueType::Scalar(ScalarType::UInt)),
+            Type::U64(_) => Some(ValueType::Scalar(ScalarType::ULong)),
+            Type::F32(_) => Some(ValueType::Scalar(ScalarType::Float)),
+            Type::F64(_) => Some(ValueType::Scalar(ScalarType::Double)),
+            Type::Bool(_) => Some(ValueType::Scalar(ScalarType::Bool)),
+            Type::Named(name, _) => Some(Self::from_msl_name(name)),
+            Type::Vector { dtype, len, .. } => {
+                let elem = Self::from_ast(dtype)?.as_scalar()?;
+                let len = match (*len)? {
+                    "2" | "x" => 2,
+                    "3" | "y" => 3,
+                    "4" | "z" => 4,
+                    other => other.parse().ok()?,
+                };
+                Some(ValueType::Vector { elem, len })
+            }
+            Type::Matrix {
+                dtype, rows, cols, ..
+            } => {
+                let elem = Self::from_ast(dtype)?.as_scalar()?;
+                let rows = (*rows)?.parse().ok()?;
+                let cols = (*cols)?.parse().ok()?;
+                Some(ValueType::Matrix { elem, cols, rows })
+            }
+            Type::Tensor {
+                dtype,
+                shape,
+                strides,
+            } => Some(ValueType::Tensor {
+                elem: Box::new(Self::from_ast(dtype)?),
+                shape: shape.iter().map(|dim| dim.to_string()).collect(),
+                strides: strides.clone(),
+            }),
+            Type::Array { dtype, size, .. } => Some(ValueType::Array {
+                elem: Box::new(Self::from_ast(dtype)?),
+                size: *size,
+            }),
+            Type::Ptr(inner) => Some(ValueType::Pointer(Box::new(Self::from_ast(inner)?))),
+        }
+    }
+
+    /// Parses MSL spellings such as `half`, `float4` or `float3x3`, falling
+    /// back to a named type for anything else.
+    pub fn from_msl_name(name: &str) -> Self {
+        if let Some(scalar) = ScalarType::from_msl_name(name) {
+            return ValueType::Scalar(scalar);
+        }
+
+        let digits_at = name.find(|c: char| c.is_ascii_digit());
+        if let Some(idx) = digits_at {
+            if let Some(elem) = ScalarType::from_msl_name(&name[..idx]) {
+                let dims = &name[idx..];
+                if let Some((cols, rows)) = dims.split_once('x') {
+                    if let (Ok(cols), Ok(rows)) = (cols.parse(), rows.parse()) {
+                        return ValueType::Matrix { elem, cols, rows };
+                    }
+                } else if let Ok(len) = dims.parse() {
+                    return ValueType::Vector { elem, len };
+                }
+            }
+        }
+
+        ValueType::Named(name.to_string())
+    }
+
+    pub fn as_scalar(&self) -> Option<ScalarType> {
+        match self {
+            ValueType::Scalar(scalar) => Some(*scalar),
+            _ => None,
+        }
+    }
+
+    pub fn is_integer(&self) -> bool {
+        matches!(self, ValueType::Scalar(s) if s.is_integer())
+    }
+
+    pub fn is_buffer(&self) -> bool {
+        matches!(
+            self,
+            ValueType::Tensor { .. } | ValueType::Array { .. } | ValueType::Pointer(_)
+        )
+    }
+
+    /// Element stride of each dimension of a tensor: its declared layout,
+    /// or row-major when it declares none.
+    pub fn tensor_strides(&self) -> Option<Vec<String>> {
+        let ValueType::Tensor { shape, strides, .. } = self else {
+            return None;
+        };
+        Some(strides.clone().unwrap_or_else(|| {
+            let shape: Vec<&str> = shape.iter().map(String::as_str).collect();
+            contiguous_strides(&shape, true)
+        }))
+    }
+
+    /// Element type read by indexing into this value.
+    pub fn element_type(&self) -> Option<ValueType> {
+        match self {
+            ValueType::Tensor { elem, .. }
+            | ValueType::Array { elem, .. }
+            | ValueType::Pointer(elem) => Some((**elem).clone()),
+            ValueType::Vector { elem, .. } => Some(ValueType::Scalar(*elem)),
+            ValueType::Matrix { elem, rows, .. } => Some(ValueType::Vector {
+                elem: *elem,
+                len: *rows,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn msl_name(&self) -> String {
+        match self {
+            ValueType::Scalar(scalar) => scalar.msl_name().to_string(),
+            ValueType::Vector { elem, len } => format!("{}{}", elem.msl_name(), len),
+            ValueType::Matrix { elem, cols, rows } => {
+                format!("{}{}x{}", elem.msl_name(), cols, rows)
+            }
+            ValueType::Tensor { elem, .. } | ValueType::Pointer(elem) => {
+                format!("device {}*", elem.msl_name())
+            }
+            ValueType::Array { elem, size } => match size {
+                Some(n) => format!("{}[{}]", elem.msl_name(), n),
+                None => format!("device {}*", elem.msl_name()),
+            },
+            ValueType::Named(name) => name.clone(),
+        }
+    }
+}
+
+/// Result type of an arithmetic operation between two values, following the
+/// usual C promotion rules that MSL inherits. A scalar broadcasts against a
+/// tensor elementwise, giving a tensor of the same shape, which only an
+/// assignment to a whole tensor can lower (see `StmtGenerator`).
+pub fn promote(left: &ValueType, right: &ValueType) -> Option<ValueType> {
+    match (left, right) {
+        (ValueType::Scalar(l), ValueType::Scalar(r)) => Some(ValueType::Scalar(*l.max(r))),
+        (ValueType::Vector { .. }, ValueType::Scalar(_)) => Some(left.clone()),
+        (ValueType::Scalar(_), ValueType::Vector { .. }) => Some(right.clone()),
+        (
+            ValueType::Tensor {
+                elem,
+                shape,
+                strides,
+            },
+            scalar @ ValueType::Scalar(_),
+        )
+        | (
+            scalar @ ValueType::Scalar(_),
+            ValueType::Tensor {
+                elem,
+                shape,
+                strides,
+            },
+        ) => Some(ValueType::Tensor {
+            elem: Box::new(promote(elem, scalar)?),
+            shape: shape.clone(),
+            strides: strides.clone(),
+        }),
+        _ if left == right => Some(left.clone()),
+        _ => None,
+    }
+}
+
+/// Whether storing a `from` value into a `to` slot silently drops the
+/// fractional part, which flare requires to be spelled out with `as`.
+pub fn is_narrowing(from: &ValueType, to: &ValueType) -> bool {
+    match (from, to) {
+        (ValueType::Scalar(from), ValueType::Scalar(to)) => from.is_float() && to.is_integer(),
+        (ValueType::Vector { elem: from, .. }, ValueType::Vector { elem: to, .. }) => {
+            from.is_float() && to.is_integer()
+        }
+        _ => false,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SymbolTable {
+    scopes: Vec<HashMap<String, ValueType>>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self {
+            scopes: vec![HashMap::new()],
+        }
+    }
+
+    pub fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    pub fn pop_scope(&mut self) {
+        if self.scopes.len() > 1 {
+            self.scopes.pop();
+        }
+    }
+
+    pub fn declare(&mut self, name: impl Into<String>, ty: ValueType) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.into(), ty);
+        }
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&ValueType> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    pub fn clear(&mut self) {
+        self.scopes = vec![HashMap::new()];
+    }
+}
+
+impl Default for SymbolTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,5 +1,7 @@
 use crate::error::{CodegenError, Result};
+use crate::typeck::{ScalarType, ValueType};
 use flare::ast::Type;
+use std::collections::HashSet;
 use std::ops::Range;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -37,42 +39,64 @@ pub struct TypeConverter;
 
 impl TypeConverter {
     pub fn convert(ty: &Type, span: Range<usize>) -> Result<MetalType> {
+        Self::convert_with(ty, span, &HashSet::new())
+    }
+
+    /// Like `convert`, also accepting the program's `type` aliases, which
+    /// the driver emits as MSL typedefs.
+    pub fn convert_with(
+        ty: &Type,
+        span: Range<usize>,
+        aliases: &HashSet<String>,
+    ) -> Result<MetalType> {
+        // errors point at the type itself when the parser recorded where it is
+        let own_span = ty.span();
+        let span = if own_span.is_empty() { span } else { own_span };
+
         match ty {
-            Type::I32 => Ok(MetalType::with_layout("int", 4, 4)),
-            Type::I64 => Ok(MetalType::with_layout("long", 8, 8)),
-            Type::U32 => Ok(MetalType::with_layout("uint", 4, 4)),
-            Type::U64 => Ok(MetalType::with_layout("ulong", 8, 8)),
-            Type::F32 => Ok(MetalType::with_layout("float", 4, 4)),
-            Type::F64 => Ok(MetalType::with_layout("double", 8, 8)),
-            Type::Bool => Ok(MetalType::with_layout("bool", 1, 1)),
-
-            Type::Vector { dtype, len } => Self::convert_vector(dtype, len.as_ref(), span),
-
-            Type::Matrix { dtype, rows, cols } => {
-                Self::convert_matrix(dtype, rows.as_ref(), cols.as_ref(), span)
-            }
+            Type::I32(_) => Ok(MetalType::with_layout("int", 4, 4)),
+            Type::I64(_) => Ok(MetalType::with_layout("long", 8, 8)),
+            Type::U32(_) => Ok(MetalType::with_layout("uint", 4, 4)),
+            Type::U64(_) => Ok(MetalType::with_layout("ulong", 8, 8)),
+            Type::F32(_) => Ok(MetalType::with_layout("float", 4, 4)),
+            Type::F64(_) => Ok(MetalType::with_layout("double", 8, 8)),
+            Type::Bool(_) => Ok(MetalType::with_layout("bool", 1, 1)),
+
+            Type::Vector { dtype, len, .. } => Self::convert_vector(dtype, len.as_ref(), span),
+
+            Type::Matrix {
+                dtype, rows, cols, ..
+            } => Self::convert_matrix(dtype, rows.as_ref(), cols.as_ref(), span),
 
             Type::Ptr(inner) => {
-                let inner_type = Self::convert(inner, span.clone())?;
+                let inner_type = Self::convert_with(inner, span.clone(), aliases)?;
 
                 Ok(MetalType::new(format!("device {}*", inner_type.as_str())))
             }
 
-            Type::Array { dtype, size } => {
-                let elem_type = Self::convert(dtype, span.clone())?;
-                match size {
-                    Some(n) => Ok(MetalType::new(format!("{}[{}]", elem_type.as_str(), n))),
-                    None => Ok(MetalType::new(format!("device {}*", elem_type.as_str()))),
+            Type::Array {
+                dtype,
+                size,
+                size_expr,
+            } => {
+                let elem_type = Self::convert_with(dtype, span.clone(), aliases)?;
+                match (size, size_expr) {
+                    (Some(n), _) => Ok(MetalType::new(format!("{}[{}]", elem_type.as_str(), n))),
+                    (None, Some(expr)) => Err(CodegenError::unsupported_type(
+                        "array size must be a compile-time constant",
+                        expr.span(),
+                    )),
+                    (None, None) => Ok(MetalType::new(format!("device {}*", elem_type.as_str()))),
                 }
             }
 
             Type::Tensor { dtype, .. } => {
-                let elem_type = Self::convert(dtype, span.clone())?;
+                let elem_type = Self::convert_with(dtype, span.clone(), aliases)?;
                 Ok(MetalType::new(format!("device {}*", elem_type.as_str())))
             }
 
-            Type::Named(name) => {
-                if Self::is_known_metal_type(name) {
+            Type::Named(name, _) => {
+                if Self::is_known_metal_type(name) || aliases.contains(*name) {
                     Ok(MetalType::new(*name))
                 } else {
                     Err(CodegenError::unsupported_type(
@@ -84,6 +108,29 @@ impl TypeConverter {
         }
     }
 
+    /// Byte size of a value of `ty`, laid out as `convert` lays out the
+    /// flare type it came from; `None` for buffers and named types.
+    pub fn value_size(ty: &ValueType) -> Option<usize> {
+        let padded = |len: usize| if len == 3 { 4 } else { len };
+        match ty {
+            ValueType::Scalar(scalar) => Some(Self::scalar_size(*scalar)),
+            ValueType::Vector { elem, len } => Some(Self::scalar_size(*elem) * padded(*len)),
+            ValueType::Matrix { elem, cols, rows } => {
+                Some(Self::scalar_size(*elem) * padded(*rows) * cols)
+            }
+            _ => None,
+        }
+    }
+
+    fn scalar_size(scalar: ScalarType) -> usize {
+        match scalar {
+            ScalarType::Bool => 1,
+            ScalarType::Half => 2,
+            ScalarType::Int | ScalarType::UInt | ScalarType::Float => 4,
+            ScalarType::Long | ScalarType::ULong | ScalarType::Double => 8,
+        }
+    }
+
     fn convert_vector(dtype: &Type, len: Option<&&str>, span: Range<usize>) -> Result<MetalType> {
         let base_type = Self::convert(dtype, span.clone())?;
 
@@ -126,12 +173,25 @@ impl TypeConverter {
             _ => {
                 return Err(CodegenError::unsupported_type(
                     format!("cannot create vector of type '{}'", base_type.as_str()),
-                    span,
+                    element_span(dtype, span),
                 ));
             }
         };
 
-        Ok(MetalType::new(format!("{}{}", type_prefix, length)))
+        let msl_type = format!("{}{}", type_prefix, length);
+        match base_type.size_bytes {
+            // three-component vectors are padded to four
+            Some(scalar) => {
+                let padded = if length == "3" {
+                    4
+                } else {
+                    length.parse().unwrap_or(4)
+                };
+                let size = scalar * padded;
+                Ok(MetalType::with_layout(msl_type, size, size))
+            }
+            None => Ok(MetalType::new(msl_type)),
+        }
     }
 
     fn convert_matrix(
@@ -147,7 +207,7 @@ impl TypeConverter {
             other => {
                 return Err(CodegenError::unsupported_type(
                     format!("Metal matrices only support float/half, got '{}'", other),
-                    span,
+                    element_span(dtype, span),
                 ));
             }
         }
@@ -165,12 +225,15 @@ impl TypeConverter {
             ));
         }
 
-        Ok(MetalType::new(format!(
-            "{}{}x{}",
-            base_type.as_str(),
-            cols_num,
-            rows_num
-        )))
+        let msl_type = format!("{}{}x{}", base_type.as_str(), cols_num, rows_num);
+        match base_type.size_bytes {
+            // stored as `cols` column vectors of `rows` components
+            Some(scalar) => {
+                let column = scalar * if rows_num == 3 { 4 } else { rows_num };
+                Ok(MetalType::with_layout(msl_type, column * cols_num, column))
+            }
+            None => Ok(MetalType::new(msl_type)),
+        }
     }
 
     fn parse_dimension(dim: Option<&&str>, name: &str, span: &Range<usize>) -> Result<usize> {
@@ -235,3 +298,14 @@ impl TypeConverter {
         }
     }
 }
+
+/// Where an error about the element type of a vector or matrix points: the
+/// element type itself when the parser recorded it, else the whole type.
+fn element_span(dtype: &Type, span: Range<usize>) -> Range<usize> {
+    let own_span = dtype.span();
+    if own_span.is_empty() {
+        span
+    } else {
+        own_span
+    }
+}
@@ -0,0 +1,283 @@
+use super::error::{LoweringError, Result};
+use flare::ast::{walk_expr, Expr, KernelDef, Stmt, Visitor};
+use std::collections::HashSet;
+
+/// `@auto_barrier`: inserts a `sync_threads()` after each `load_shared` whose
+/// destination is read later in the same statement list, unless a barrier
+/// already separates the load from that read.
+pub fn apply_auto_barriers(kernel: &mut KernelDef) {
+    if !kernel
+        .attributes
+        .iter()
+        .any(|attr| attr.name == "auto_barrier")
+    {
+        return;
+    }
+
+    if let Some(compute) = &mut kernel.compute {
+        insert_barriers(compute);
+    }
+    insert_barriers(&mut kernel.body);
+}
+
+fn insert_barriers(stmts: &mut Vec<Stmt>) {
+    for stmt in stmts.iter_mut() {
+        insert_nested_barriers(stmt);
+    }
+
+    let mut i = 0;
+    while i < stmts.len() {
+        if let Stmt::LoadShared { dest, span, .. } = unstaged(&stmts[i]) {
+            let (dest, span) = (*dest, span.clone());
+            for later in &stmts[i + 1..] {
+                if matches!(unstaged(later), Stmt::SyncThreads { .. }) {
+                    break;
+                }
+                if reads(later, dest) {
+                    stmts.insert(i + 1, Stmt::SyncThreads { span });
+                    i += 1;
+                    break;
+                }
+            }
+        }
+        i += 1;
+    }
+}
+
+/// `stmt` without its `@stage` wrapper.
+fn unstaged<'a, 'src>(stmt: &'a Stmt<'src>) -> &'a Stmt<'src> {
+    match stmt {
+        Stmt::Staged { stmt, .. } => stmt,
+        stmt => stmt,
+    }
+}
+
+fn insert_nested_barriers(stmt: &mut Stmt) {
+    match stmt {
+        Stmt::Block { statements, .. } => insert_barriers(statements),
+        Stmt::If {
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            insert_nested_barriers(then_branch);
+            if let Some(else_stmt) = else_branch {
+                insert_nested_barriers(else_stmt);
+            }
+        }
+        Stmt::While { body, .. } | Stmt::For { body, .. } | Stmt::Staged { stmt: body, .. } => {
+            insert_nested_barriers(body)
+        }
+        _ => {}
+    }
+}
+
+fn reads(stmt: &Stmt, name: &str) -> bool {
+    let mut finder = ReadFinder { name, found: false };
+    finder.visit_stmt(stmt);
+    finder.found
+}
+
+struct ReadFinder<'n> {
+    name: &'n str,
+    found: bool,
+}
+
+impl<'src> Visitor<'src> for ReadFinder<'_> {
+    fn visit_expr(&mut self, expr: &Expr<'src>) {
+        if matches!(expr, Expr::Ident(name, _) if *name == self.name) {
+            self.found = true;
+        }
+        walk_expr(self, expr);
+    }
+}
+
+/// Rejects a `sync_threads()` nested in an `if`, loop or `while` whose
+/// condition depends on the thread index, since threads that skip the
+/// barrier leave the rest of the threadgroup waiting on it. Bindings and
+/// assignments computed from `thread_idx`, or made under such control
+/// flow, count as thread-dependent too.
+pub fn check_divergent_barriers(kernel: &KernelDef) -> Result<()> {
+    let mut thread_values = HashSet::new();
+    for stmt in kernel.compute.iter().flatten().chain(&kernel.body) {
+        check_barriers(stmt, false, &mut thread_values)?;
+    }
+    Ok(())
+}
+
+fn check_barriers<'src>(
+    stmt: &Stmt<'src>,
+    divergent: bool,
+    thread_values: &mut HashSet<&'src str>,
+) -> Result<()> {
+    match stmt {
+        Stmt::SyncThreads { span } if divergent => Err(LoweringError::lowering_error(
+            "sync_threads() is under control flow that depends on the thread index, so not \
+             every thread in the threadgroup reaches it",
+            span.clone(),
+        )),
+        Stmt::Let { name, value, .. }
+        | Stmt::Var {
+            name,
+            value: Some(value),
+            ..
+        } => {
+            if divergent || thread_dependent(value, thread_values) {
+                thread_values.insert(name);
+            }
+            Ok(())
+        }
+        Stmt::Expr(Expr::Assign { target, value, .. })
+        | Stmt::Expr(Expr::CompoundAssign { target, value, .. }) => {
+            if let Expr::Ident(name, _) = target.as_ref() {
+                if divergent || thread_dependent(value, thread_values) {
+                    thread_values.insert(name);
+                }
+            }
+            Ok(())
+        }
+        Stmt::Block { statements, .. } => statements
+            .iter()
+            .try_for_each(|stmt| check_barriers(stmt, divergent, thread_values)),
+        Stmt::Staged { stmt, .. } => check_barriers(stmt, divergent, thread_values),
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            let divergent = divergent || thread_dependent(condition, thread_values);
+            check_barriers(then_branch, divergent, thread_values)?;
+            match else_branch {
+                Some(else_stmt) => check_barriers(else_stmt, divergent, thread_values),
+                None => Ok(()),
+            }
+        }
+        Stmt::While {
+            condition, body, ..
+        } => {
+            let divergent = divergent || thread_dependent(condition, thread_values);
+            check_barriers(body, divergent, thread_values)
+        }
+        Stmt::For {
+            var,
+            iterator,
+            body,
+            ..
+        } => {
+            let divergent = divergent || thread_dependent(iterator, thread_values);
+            if divergent {
+                thread_values.insert(var);
+            }
+            check_barriers(body, divergent, thread_values)
+        }
+        _ => Ok(()),
+    }
+}
+
+fn thread_dependent(expr: &Expr, thread_values: &HashSet<&str>) -> bool {
+    let mut finder = ThreadIndexFinder {
+        thread_values,
+        found: false,
+    };
+    finder.visit_expr(expr);
+    finder.found
+}
+
+struct ThreadIndexFinder<'a, 'n> {
+    thread_values: &'a HashSet<&'n str>,
+    found: bool,
+}
+
+impl<'src> Visitor<'src> for ThreadIndexFinder<'_, '_> {
+    fn visit_expr(&mut self, expr: &Expr<'src>) {
+        match expr {
+            Expr::ThreadIdx { .. } => self.found = true,
+            Expr::Ident(name, _) if self.thread_values.contains(name) => self.found = true,
+            _ => walk_expr(self, expr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use flare::Flare;
+
+    use super::*;
+
+    fn kinds(stmts: &[Stmt]) -> Vec<&'static str> {
+        stmts
+            .iter()
+            .map(|stmt| match stmt {
+                Stmt::LoadShared { .. } => "load",
+                Stmt::SyncThreads { .. } => "barrier",
+                _ => "other",
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_auto_barrier_after_read_load() {
+        let source = r#"
+            @auto_barrier
+            kernel tiled(A: Tensor<f32, [N]>, B: Tensor<f32, [N]>) {
+                shared_memory {
+                    tile: [16]: f32
+                    unused: [16]: f32
+                }
+                for t in 0..N {
+                    load_shared(tile, A[t])
+                    load_shared(unused, B[t])
+                    let x = tile[t]
+                }
+            }
+        "#;
+        let program = Flare::compile_from_string(source).unwrap();
+        let Some(Stmt::Kernel(kernel)) = program.items.first() else {
+            panic!("expected a kernel");
+        };
+        let mut kernel = kernel.clone();
+        apply_auto_barriers(&mut kernel);
+
+        let Stmt::For { body, .. } = &kernel.body[0] else {
+            panic!("expected a for loop");
+        };
+        let Stmt::Block { statements, .. } = body.as_ref() else {
+            panic!("expected a loop body block");
+        };
+        assert_eq!(kinds(statements), ["load", "barrier", "load", "other"]);
+    }
+
+    #[test]
+    fn test_barrier_in_divergent_if_rejected() {
+        let divergent = r#"
+            kernel k(A: Tensor<f32, [N]>) {
+                let tid = thread_idx.x
+                if tid < 16 {
+                    A[tid] = 0.0
+                    sync_threads()
+                }
+            }
+        "#;
+        let program = Flare::compile_from_string(divergent).unwrap();
+        let Some(Stmt::Kernel(kernel)) = program.items.first() else {
+            panic!("expected a kernel");
+        };
+        let err = check_divergent_barriers(kernel).unwrap_err();
+        assert_eq!(&divergent[err.span().clone()], "sync_threads()");
+
+        let uniform = r#"
+            kernel k(A: Tensor<f32, [N]>) {
+                if block_idx.x == 0 {
+                    A[thread_idx.x] = 0.0
+                    sync_threads()
+                }
+            }
+        "#;
+        let program = Flare::compile_from_string(uniform).unwrap();
+        let Some(Stmt::Kernel(kernel)) = program.items.first() else {
+            panic!("expected a kernel");
+        };
+        assert!(check_divergent_barriers(kernel).is_ok());
+    }
+}
@@ -0,0 +1,209 @@
+use flare::ast::{Expr, KernelDef, Stmt, Type, UnOp};
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Copy vectorization: rewrites `for i in a..a+n { dst[i] = src[i] }` over
+/// two tensor parameters into one vector load and store,
+/// `*(&dst[a] as *Vector<T, n>) = *(&src[a] as *Vector<T, n>)`, for `n` of
+/// 2 or 4 and a start aligned to `n`. Returns the number of loops rewritten.
+pub fn vectorize_copies(kernel: &mut KernelDef) -> usize {
+    let tensors: HashMap<&str, Type> = kernel
+        .params
+        .iter()
+        .filter_map(|param| match &param.ty {
+            Type::Tensor { dtype, .. } => Some((param.name, dtype.as_ref().clone())),
+            _ => None,
+        })
+        .collect();
+
+    let mut rewritten = 0;
+    if let Some(compute) = &mut kernel.compute {
+        rewritten += rewrite_list(compute, &tensors);
+    }
+    rewritten + rewrite_list(&mut kernel.body, &tensors)
+}
+
+fn rewrite_list<'src>(stmts: &mut [Stmt<'src>], tensors: &HashMap<&str, Type<'src>>) -> usize {
+    let mut rewritten = 0;
+    for stmt in stmts {
+        if let Some(copy) = vector_copy(stmt, tensors) {
+            *stmt = copy;
+            rewritten += 1;
+            continue;
+        }
+        rewritten += match stmt {
+            Stmt::Block { statements, .. } => rewrite_list(statements, tensors),
+            Stmt::If {
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                let nested = rewrite_list(std::slice::from_mut(then_branch.as_mut()), tensors);
+                nested
+                    + else_branch.as_mut().map_or(0, |else_stmt| {
+                        rewrite_list(std::slice::from_mut(else_stmt.as_mut()), tensors)
+                    })
+            }
+            Stmt::While { body, .. } | Stmt::For { body, .. } | Stmt::Staged { stmt: body, .. } => {
+                rewrite_list(std::slice::from_mut(body.as_mut()), tensors)
+            }
+            _ => 0,
+        };
+    }
+    rewritten
+}
+
+fn vector_copy<'src>(stmt: &Stmt<'src>, tensors: &HashMap<&str, Type<'src>>) -> Option<Stmt<'src>> {
+    let Stmt::For {
+        label: None,
+        var,
+        iterator:
+            Expr::Range {
+                start,
+                end: Some(end),
+                step: None,
+                ..
+            },
+        body,
+        span,
+    } = stmt
+    else {
+        return None;
+    };
+
+    let start = match start.as_deref() {
+        None => 0,
+        Some(Expr::IntLiteral(n, _)) => *n,
+        Some(_) => return None,
+    };
+    let Expr::IntLiteral(end, _) = end.as_ref() else {
+        return None;
+    };
+    let len = match end - start {
+        2 => "2",
+        4 => "4",
+        _ => return None,
+    };
+    // vector loads need the start aligned to the vector size
+    if start % (end - start) != 0 {
+        return None;
+    }
+
+    let assign = match body.as_ref() {
+        Stmt::Block { statements, .. } => match statements.as_slice() {
+            [Stmt::Expr(assign)] => assign,
+            _ => return None,
+        },
+        Stmt::Expr(assign) => assign,
+        _ => return None,
+    };
+    let Expr::Assign { target, value, .. } = assign else {
+        return None;
+    };
+    let dst = indexed_by(target, var)?;
+    let src = indexed_by(value, var)?;
+
+    let elem = tensors.get(dst)?;
+    if !matches!(elem, Type::I32(_) | Type::U32(_) | Type::F32(_))
+        || !tensors.get(src)?.same_as(elem)
+    {
+        return None;
+    }
+    let vector = Type::Ptr(Box::new(Type::Vector {
+        dtype: Box::new(elem.clone()),
+        len: Some(len),
+        span: 0..0,
+    }));
+
+    Some(Stmt::Expr(Expr::Assign {
+        target: Box::new(vector_access(dst, start, &vector, span)),
+        value: Box::new(vector_access(src, start, &vector, span)),
+        span: span.clone(),
+    }))
+}
+
+/// The tensor name of `name[var]`.
+fn indexed_by<'src>(expr: &Expr<'src>, var: &str) -> Option<&'src str> {
+    let Expr::Index {
+        object, indices, ..
+    } = expr
+    else {
+        return None;
+    };
+    match (object.as_ref(), indices.as_slice()) {
+        (Expr::Ident(name, _), [Expr::Ident(index, _)]) if *index == var => Some(name),
+        _ => None,
+    }
+}
+
+/// `*(&name[start] as *Vector<T, n>)`.
+fn vector_access<'src>(
+    name: &'src str,
+    start: i64,
+    vector: &Type<'src>,
+    span: &Range<usize>,
+) -> Expr<'src> {
+    let element = Expr::Index {
+        object: Box::new(Expr::Ident(name, span.clone())),
+        indices: vec![Expr::IntLiteral(start, span.clone())],
+        span: span.clone(),
+    };
+    let address = Expr::Unary {
+        op: UnOp::AddrOf,
+        expr: Box::new(element),
+        span: span.clone(),
+    };
+    Expr::Unary {
+        op: UnOp::Deref,
+        expr: Box::new(Expr::Cast {
+            expr: Box::new(address),
+            target_type: vector.clone(),
+            span: span.clone(),
+        }),
+        span: span.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use flare::Flare;
+
+    use super::*;
+
+    fn vectorized_body(source: &str) -> Vec<Stmt<'_>> {
+        let program = Flare::compile_from_string(source).unwrap();
+        let Some(Stmt::Kernel(kernel)) = program.items.into_iter().next() else {
+            panic!("expected a kernel");
+        };
+        let mut kernel = kernel;
+        vectorize_copies(&mut kernel);
+        kernel.body
+    }
+
+    #[test]
+    fn test_vectorize_unit_stride_copy() {
+        let source = r#"
+            kernel copy(dst: Tensor<f32, [N]>, src: Tensor<f32, [N]>) {
+                for i in 0..4 {
+                    dst[i] = src[i]
+                }
+                for i in 1..3 {
+                    dst[i] = src[i]
+                }
+                for i in 0..4 {
+                    dst[i] = src[i + 1]
+                }
+            }
+        "#;
+        let body = vectorized_body(source);
+
+        assert!(matches!(
+            &body[0],
+            Stmt::Expr(Expr::Assign { target, .. })
+                if matches!(target.as_ref(), Expr::Unary { op: UnOp::Deref, .. })
+        ));
+        // misaligned starts and shifted sources stay loops
+        assert!(matches!(&body[1], Stmt::For { .. }));
+        assert!(matches!(&body[2], Stmt::For { .. }));
+    }
+}
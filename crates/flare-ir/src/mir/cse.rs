@@ -0,0 +1,192 @@
+use super::licm::{declared_name, is_invariant};
+use flare::ast::{walk_expr, walk_stmt, Expr, KernelDef, Stmt, Visitor};
+use std::collections::HashSet;
+
+/// Common subexpression elimination: replaces the value of an untyped `let`
+/// with an earlier binding in the same block that computes the same
+/// expression, as long as nothing between them assigns a name it reads.
+/// Only values that read no buffers and call only `pure_fns` are reused.
+/// Returns the number of values replaced.
+pub fn eliminate_common_subexpressions(kernel: &mut KernelDef, pure_fns: &HashSet<&str>) -> usize {
+    let mut replaced = 0;
+    if let Some(compute) = &mut kernel.compute {
+        replaced += eliminate_in_list(compute, pure_fns);
+    }
+    replaced + eliminate_in_list(&mut kernel.body, pure_fns)
+}
+
+fn eliminate_in_list<'src>(stmts: &mut [Stmt<'src>], pure_fns: &HashSet<&str>) -> usize {
+    let mut replaced = 0;
+    let mut available: Vec<(&'src str, Expr<'src>)> = Vec::new();
+    for stmt in stmts {
+        if let Stmt::Let {
+            name,
+            ty: None,
+            value,
+            attributes,
+            ..
+        } = stmt
+        {
+            if attributes.is_empty() && is_candidate(value, pure_fns) {
+                match available
+                    .iter()
+                    .find(|(_, seen)| seen.semantically_eq(value))
+                {
+                    Some((earlier, _)) => {
+                        *value = Expr::Ident(earlier, value.span());
+                        replaced += 1;
+                    }
+                    None => available.push((name, value.clone())),
+                }
+                continue;
+            }
+        }
+
+        let mut writes = Writes::default();
+        writes.visit_stmt(stmt);
+        available.retain(|(name, value)| {
+            !writes.names.contains(name) && !reads_any(value, &writes.names)
+        });
+        replaced += eliminate_nested(stmt, pure_fns);
+    }
+    replaced
+}
+
+fn eliminate_nested(stmt: &mut Stmt, pure_fns: &HashSet<&str>) -> usize {
+    match stmt {
+        Stmt::Block { statements, .. } => eliminate_in_list(statements, pure_fns),
+        Stmt::If {
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            let replaced = eliminate_nested(then_branch, pure_fns);
+            replaced
+                + else_branch
+                    .as_mut()
+                    .map_or(0, |else_stmt| eliminate_nested(else_stmt, pure_fns))
+        }
+        Stmt::While { body, .. } | Stmt::For { body, .. } | Stmt::Staged { stmt: body, .. } => {
+            eliminate_nested(body, pure_fns)
+        }
+        _ => 0,
+    }
+}
+
+/// Whether `value` is worth reusing: it computes something and evaluating
+/// it twice gives the same result.
+fn is_candidate(value: &Expr, pure_fns: &HashSet<&str>) -> bool {
+    matches!(
+        value,
+        Expr::Binary { .. } | Expr::Unary { .. } | Expr::Call { .. } | Expr::Cast { .. }
+    ) && is_invariant(value, &HashSet::new(), pure_fns)
+}
+
+fn reads_any(expr: &Expr, names: &HashSet<&str>) -> bool {
+    let mut reads = Reads {
+        names,
+        found: false,
+    };
+    reads.visit_expr(expr);
+    reads.found
+}
+
+/// Names a statement declares or assigns, including loop variables.
+#[derive(Default)]
+struct Writes<'src> {
+    names: HashSet<&'src str>,
+}
+
+impl<'src> Visitor<'src> for Writes<'src> {
+    fn visit_stmt(&mut self, stmt: &Stmt<'src>) {
+        match stmt {
+            Stmt::For { var, .. } => {
+                self.names.insert(var);
+            }
+            Stmt::LoadShared { dest, .. } => {
+                self.names.insert(dest);
+            }
+            _ => self.names.extend(declared_name(stmt)),
+        }
+        walk_stmt(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr<'src>) {
+        if let Expr::Assign { target, .. } | Expr::CompoundAssign { target, .. } = expr {
+            let mut root = target.as_ref();
+            while let Expr::Index { object, .. } | Expr::Member { object, .. } = root {
+                root = object;
+            }
+            if let Expr::Ident(name, _) = root {
+                self.names.insert(name);
+            }
+        }
+        walk_expr(self, expr);
+    }
+}
+
+struct Reads<'a> {
+    names: &'a HashSet<&'a str>,
+    found: bool,
+}
+
+impl<'src> Visitor<'src> for Reads<'_> {
+    fn visit_expr(&mut self, expr: &Expr<'src>) {
+        if let Expr::Ident(name, _) = expr {
+            self.found |= self.names.contains(name);
+        }
+        walk_expr(self, expr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use flare::Flare;
+
+    use super::*;
+    use crate::mir::purity::pure_functions;
+
+    #[test]
+    fn test_pure_call_reused_until_operand_changes() {
+        let source = r#"
+            @pure
+            fn scale(x: f32) -> f32 {
+                x * 2.0
+            }
+
+            fn noisy(x: f32) -> f32 {
+                x * 3.0
+            }
+
+            kernel k(A: Tensor<f32, [N]>, s: f32) {
+                var t = s
+                let a = scale(t) + 1.0
+                let b = scale(t) + 1.0
+                let c = noisy(t)
+                let d = noisy(t)
+                t = t + 1.0
+                let e = scale(t) + 1.0
+                A[0] = a + b + c + d + e
+            }
+        "#;
+        let program = Flare::compile_from_string(source).unwrap();
+        let pure_fns = pure_functions(&program.items);
+        let Some(Stmt::Kernel(kernel)) = program.items.last() else {
+            panic!("expected a kernel");
+        };
+        let mut kernel = kernel.clone();
+        assert_eq!(eliminate_common_subexpressions(&mut kernel, &pure_fns), 1);
+
+        let values: Vec<_> = kernel
+            .body
+            .iter()
+            .filter_map(|stmt| match stmt {
+                Stmt::Let { name, value, .. } => Some((*name, value)),
+                _ => None,
+            })
+            .collect();
+        assert!(matches!(values[1], ("b", Expr::Ident("a", _))));
+        assert!(matches!(values[3], ("d", Expr::Call { .. })));
+        assert!(matches!(values[4], ("e", Expr::Binary { .. })));
+    }
+}
@@ -0,0 +1,73 @@
+use flare::ast::{AttributeArg, Program, Stmt};
+
+/// Renders the kernels of `program` as a Graphviz DOT digraph. An
+/// `@depends_on(a)` on kernel `b` draws `a -> b`; a `fuse a, b` block draws
+/// dashed `fuse` edges between consecutive targets.
+pub fn dependency_dot(program: &Program) -> String {
+    let mut nodes = Vec::new();
+    let mut edges = Vec::new();
+
+    for item in &program.items {
+        match item {
+            Stmt::Kernel(kernel) => {
+                nodes.push(kernel.name);
+                for attr in kernel.attributes.iter().filter(|a| a.name == "depends_on") {
+                    for arg in &attr.args {
+                        if let AttributeArg::Ident(dep) = arg {
+                            edges.push(format!("\"{}\" -> \"{}\";", dep, kernel.name));
+                        }
+                    }
+                }
+            }
+            Stmt::Fusion(fusion) => {
+                for pair in fusion.targets.windows(2) {
+                    edges.push(format!(
+                        "\"{}\" -> \"{}\" [style=dashed, label=\"fuse\"];",
+                        pair[0], pair[1]
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut dot = String::from("digraph kernels {\n");
+    for node in nodes {
+        dot.push_str(&format!("    \"{}\";\n", node));
+    }
+    for edge in edges {
+        dot.push_str(&format!("    {}\n", edge));
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+#[cfg(test)]
+mod tests {
+    use flare::Flare;
+
+    use super::*;
+
+    #[test]
+    fn test_dependency_dot_edges() {
+        let source = r#"
+            kernel produce(A: Tensor<f32, [N]>) {
+                A[0] = 1.0
+            }
+
+            @depends_on(produce)
+            kernel consume(A: Tensor<f32, [N]>) {
+                A[0] = A[0] + 1.0
+            }
+
+            fuse produce, consume
+        "#;
+        let program = Flare::compile_from_string(source).unwrap();
+        let dot = dependency_dot(&program);
+
+        assert!(dot.starts_with("digraph kernels {\n"));
+        assert!(dot.contains("    \"produce\";\n    \"consume\";\n"));
+        assert!(dot.contains("    \"produce\" -> \"consume\";\n"));
+        assert!(dot.contains("    \"produce\" -> \"consume\" [style=dashed, label=\"fuse\"];\n"));
+    }
+}
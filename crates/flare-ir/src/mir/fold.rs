@@ -0,0 +1,122 @@
+use flare::ast::{walk_expr_mut, BinOp, Expr, KernelDef, UnOp, VisitorMut};
+use flare::fold::fold_binary;
+
+/// Constant folding: replaces integer and boolean arithmetic, comparisons
+/// and logic on literal operands with the literal result. Operations that
+/// would overflow a 32-bit `int` or divide by zero are left for the backend
+/// to report. Returns the number of expressions folded.
+pub fn fold_constants(kernel: &mut KernelDef) -> usize {
+    let mut rewrite = Rewrite { folded: 0 };
+    for stmt in kernel.compute.iter_mut().flatten().chain(&mut kernel.body) {
+        rewrite.visit_stmt_mut(stmt);
+    }
+    rewrite.folded
+}
+
+struct Rewrite {
+    folded: usize,
+}
+
+impl<'src> VisitorMut<'src> for Rewrite {
+    fn visit_expr_mut(&mut self, expr: &mut Expr<'src>) {
+        walk_expr_mut(self, expr);
+        if let Some(folded) = fold(expr) {
+            *expr = folded;
+            self.folded += 1;
+        }
+    }
+}
+
+fn fold<'src>(expr: &Expr<'src>) -> Option<Expr<'src>> {
+    let span = expr.span();
+    match expr {
+        Expr::Unary { op, expr, .. } => match (op, expr.as_ref()) {
+            (UnOp::Neg, Expr::IntLiteral(value, _)) => int_literal(value.checked_neg()?, span),
+            (UnOp::Not, Expr::BoolLiteral(value, _)) => Some(Expr::BoolLiteral(!value, span)),
+            _ => None,
+        },
+        Expr::Binary {
+            left, op, right, ..
+        } => match (left.as_ref(), right.as_ref()) {
+            (Expr::IntLiteral(left, _), Expr::IntLiteral(right, _)) => {
+                fold_ints(*left, *op, *right, span)
+            }
+            (Expr::BoolLiteral(left, _), Expr::BoolLiteral(right, _)) => {
+                let value = match op {
+                    BinOp::And => *left && *right,
+                    BinOp::Or => *left || *right,
+                    BinOp::Equal => left == right,
+                    BinOp::NotEqual => left != right,
+                    _ => return None,
+                };
+                Some(Expr::BoolLiteral(value, span))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn fold_ints<'src>(
+    left: i64,
+    op: BinOp,
+    right: i64,
+    span: std::ops::Range<usize>,
+) -> Option<Expr<'src>> {
+    let value = fold_binary(left, op, right)?;
+    match op {
+        BinOp::Equal
+        | BinOp::NotEqual
+        | BinOp::Less
+        | BinOp::Greater
+        | BinOp::LessEqual
+        | BinOp::GreaterEqual => Some(Expr::BoolLiteral(value != 0, span)),
+        // `&&` and `||` take booleans, so integer operands are a type error
+        BinOp::And | BinOp::Or => None,
+        _ => int_literal(value, span),
+    }
+}
+
+fn int_literal<'src>(value: i64, span: std::ops::Range<usize>) -> Option<Expr<'src>> {
+    i32::try_from(value).ok()?;
+    Some(Expr::IntLiteral(value, span))
+}
+
+#[cfg(test)]
+mod tests {
+    use flare::ast::Stmt;
+    use flare::Flare;
+
+    use super::*;
+
+    #[test]
+    fn test_literal_arithmetic_folded() {
+        let source = r#"
+            kernel k(A: Tensor<f32, [N]>) {
+                let n = 4 * 16 + 2
+                let big = n > 3 * 2
+            }
+        "#;
+        let program = Flare::compile_from_string(source).unwrap();
+        let Some(Stmt::Kernel(mut kernel)) = program.items.into_iter().next() else {
+            panic!("expected a kernel");
+        };
+
+        fold_constants(&mut kernel);
+        assert!(matches!(
+            &kernel.body[0],
+            Stmt::Let {
+                value: Expr::IntLiteral(66, _),
+                ..
+            }
+        ));
+        // `n` is a binding, not a literal, so the comparison stays
+        let Stmt::Let { value, .. } = &kernel.body[1] else {
+            panic!("expected a let binding");
+        };
+        assert!(matches!(
+            value,
+            Expr::Binary { right, .. } if matches!(right.as_ref(), Expr::IntLiteral(6, _))
+        ));
+    }
+}
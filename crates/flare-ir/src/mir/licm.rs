@@ -0,0 +1,315 @@
+use flare::ast::{walk_expr, walk_stmt, Expr, KernelDef, Stmt, Visitor};
+use std::collections::{HashMap, HashSet};
+
+/// Loop-invariant code motion: moves a `let` out of a loop body when its
+/// value reads nothing the loop assigns, reads no buffers, and calls only
+/// `pure_fns`. Returns the number of bindings hoisted.
+pub fn hoist_loop_invariants(kernel: &mut KernelDef, pure_fns: &HashSet<&str>) -> usize {
+    let bound: HashSet<&str> = kernel
+        .params
+        .iter()
+        .map(|param| param.name)
+        .chain(kernel.const_params.iter().map(|param| param.name))
+        .chain(kernel.shared_memory.iter().flatten().map(|decl| decl.name))
+        .chain(kernel.compute.iter().flatten().filter_map(declared_name))
+        .chain(kernel.body.iter().filter_map(declared_name))
+        .collect();
+
+    let mut hoisted = 0;
+    if let Some(compute) = &mut kernel.compute {
+        hoisted += hoist_in_list(compute, &bound, pure_fns);
+    }
+    hoisted + hoist_in_list(&mut kernel.body, &bound, pure_fns)
+}
+
+/// Hoists out of the loops in `stmts`. `bound` holds every name visible in
+/// the list: kernel parameters and the bindings of the enclosing scopes and
+/// of the list itself.
+fn hoist_in_list<'src>(
+    stmts: &mut Vec<Stmt<'src>>,
+    bound: &HashSet<&'src str>,
+    pure_fns: &HashSet<&str>,
+) -> usize {
+    let mut visible = bound.clone();
+    let mut total = 0;
+    let mut i = 0;
+    while i < stmts.len() {
+        total += hoist_nested(&mut stmts[i], &visible, pure_fns);
+
+        let hoisted = hoist_from_loop(&mut stmts[i], &visible, pure_fns);
+        visible.extend(hoisted.iter().filter_map(declared_name));
+        let count = hoisted.len();
+        stmts.splice(i..i, hoisted);
+        total += count;
+        i += count + 1;
+    }
+    total
+}
+
+fn hoist_nested<'src>(
+    stmt: &mut Stmt<'src>,
+    bound: &HashSet<&'src str>,
+    pure_fns: &HashSet<&str>,
+) -> usize {
+    match stmt {
+        Stmt::Block { statements, .. } => {
+            let mut inner = bound.clone();
+            inner.extend(statements.iter().filter_map(declared_name));
+            hoist_in_list(statements, &inner, pure_fns)
+        }
+        Stmt::If {
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            let hoisted = hoist_nested(then_branch, bound, pure_fns);
+            hoisted
+                + else_branch
+                    .as_mut()
+                    .map_or(0, |else_stmt| hoist_nested(else_stmt, bound, pure_fns))
+        }
+        Stmt::For { var, body, .. } => {
+            let mut inner = bound.clone();
+            inner.insert(var);
+            hoist_nested(body, &inner, pure_fns)
+        }
+        Stmt::While { body, .. } | Stmt::Staged { stmt: body, .. } => {
+            hoist_nested(body, bound, pure_fns)
+        }
+        _ => 0,
+    }
+}
+
+/// Removes the invariant `let`s from the body of `stmt` when it is a loop
+/// and returns them in order. `outer` holds every name visible around the
+/// loop; a binding that shadows one of them stays in the loop, since moving
+/// it out would change what later reads of that name see.
+fn hoist_from_loop<'src>(
+    stmt: &mut Stmt<'src>,
+    outer: &HashSet<&str>,
+    pure_fns: &HashSet<&str>,
+) -> Vec<Stmt<'src>> {
+    let mut writes = WriteCollector::default();
+    writes.visit_stmt(stmt);
+
+    let body = match stmt {
+        Stmt::For { body, .. } | Stmt::While { body, .. } => body,
+        _ => return Vec::new(),
+    };
+    let Stmt::Block { statements, .. } = body.as_mut() else {
+        return Vec::new();
+    };
+
+    let mut hoisted = Vec::new();
+    let mut i = 0;
+    while i < statements.len() {
+        let invariant = match &statements[i] {
+            Stmt::Let { name, value, .. } => {
+                writes.declarations.get(name) == Some(&1)
+                    && !outer.contains(name)
+                    && is_invariant(value, &writes.names, pure_fns)
+            }
+            _ => false,
+        };
+
+        if invariant {
+            let stmt = statements.remove(i);
+            if let Stmt::Let { name, .. } = &stmt {
+                // later bindings may now depend on it
+                writes.names.remove(name);
+            }
+            hoisted.push(stmt);
+        } else {
+            i += 1;
+        }
+    }
+    hoisted
+}
+
+pub(crate) fn declared_name<'src>(stmt: &Stmt<'src>) -> Option<&'src str> {
+    match stmt {
+        Stmt::Let { name, .. } | Stmt::Var { name, .. } | Stmt::Const { name, .. } => Some(name),
+        _ => None,
+    }
+}
+
+/// Whether `expr` reads none of `written`, reads no buffers, and calls only
+/// `pure_fns`.
+pub(crate) fn is_invariant(expr: &Expr, written: &HashSet<&str>, pure_fns: &HashSet<&str>) -> bool {
+    let mut check = InvariantCheck {
+        written,
+        pure_fns,
+        invariant: true,
+    };
+    check.visit_expr(expr);
+    check.invariant
+}
+
+/// Names a loop declares or assigns, including its induction variable.
+#[derive(Default)]
+struct WriteCollector<'src> {
+    names: HashSet<&'src str>,
+    declarations: HashMap<&'src str, usize>,
+}
+
+impl<'src> Visitor<'src> for WriteCollector<'src> {
+    fn visit_stmt(&mut self, stmt: &Stmt<'src>) {
+        match stmt {
+            Stmt::For { var, .. } => {
+                self.names.insert(var);
+            }
+            Stmt::LoadShared { dest, .. } => {
+                self.names.insert(dest);
+            }
+            _ => {
+                if let Some(name) = declared_name(stmt) {
+                    self.names.insert(name);
+                    *self.declarations.entry(name).or_default() += 1;
+                }
+            }
+        }
+        walk_stmt(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr<'src>) {
+        if let Expr::Assign { target, .. } | Expr::CompoundAssign { target, .. } = expr {
+            if let Some(name) = root_name(target) {
+                self.names.insert(name);
+            }
+        }
+        walk_expr(self, expr);
+    }
+}
+
+fn root_name<'src>(expr: &Expr<'src>) -> Option<&'src str> {
+    match expr {
+        Expr::Ident(name, _) => Some(name),
+        Expr::Index { object, .. } | Expr::Member { object, .. } => root_name(object),
+        Expr::Unary { expr, .. } => root_name(expr),
+        _ => None,
+    }
+}
+
+struct InvariantCheck<'a> {
+    written: &'a HashSet<&'a str>,
+    pure_fns: &'a HashSet<&'a str>,
+    invariant: bool,
+}
+
+impl<'src> Visitor<'src> for InvariantCheck<'_> {
+    fn visit_expr(&mut self, expr: &Expr<'src>) {
+        match expr {
+            Expr::Ident(name, _) if self.written.contains(name) => self.invariant = false,
+            Expr::Call { func, args, .. } => match func.as_ref() {
+                Expr::Ident(name, _) if self.pure_fns.contains(name) => {
+                    args.iter().for_each(|arg| self.visit_expr(arg));
+                }
+                _ => self.invariant = false,
+            },
+            // buffers may be written by the loop; blocks and assignments may
+            // have effects of their own
+            Expr::Index { .. }
+            | Expr::Assign { .. }
+            | Expr::CompoundAssign { .. }
+            | Expr::Block { .. } => self.invariant = false,
+            _ => walk_expr(self, expr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use flare::Flare;
+
+    use super::*;
+    use crate::mir::purity::pure_functions;
+
+    #[test]
+    fn test_pure_call_hoisted_out_of_loop() {
+        let source = r#"
+            @pure
+            fn scale(x: f32) -> f32 {
+                x * 2.0
+            }
+
+            fn noisy(x: f32) -> f32 {
+                x * 3.0
+            }
+
+            kernel k(A: Tensor<f32, [N]>, s: f32) {
+                for i in 0..N {
+                    let a = scale(s)
+                    let b = noisy(s)
+                    let c = scale(a)
+                    A[i] = a + b + c
+                }
+            }
+        "#;
+        let program = Flare::compile_from_string(source).unwrap();
+        let pure_fns = pure_functions(&program.items);
+        assert_eq!(pure_fns, HashSet::from(["scale"]));
+
+        let Some(Stmt::Kernel(kernel)) = program.items.last() else {
+            panic!("expected a kernel");
+        };
+        let mut kernel = kernel.clone();
+        hoist_loop_invariants(&mut kernel, &pure_fns);
+
+        let names: Vec<_> = kernel.body.iter().filter_map(declared_name).collect();
+        assert_eq!(names, ["a", "c"]);
+
+        let Some(Stmt::For { body, .. }) = kernel.body.last() else {
+            panic!("expected the loop last");
+        };
+        let Stmt::Block { statements, .. } = body.as_ref() else {
+            panic!("expected a loop body block");
+        };
+        let names: Vec<_> = statements.iter().filter_map(declared_name).collect();
+        assert_eq!(names, ["b"]);
+    }
+
+    #[test]
+    fn test_shadowing_let_stays_in_loop() {
+        let source = r#"
+            kernel k(A: Tensor<f32, [N]>, s: f32) {
+                for i in 0..N {
+                    let s = 2.0
+                    let t = 1.0
+                    let c = 4.0
+                    for j in 0..N {
+                        let t = 3.0
+                        A[j] = t + s
+                    }
+                    A[i] = t + c
+                }
+                A[0] = s
+            }
+        "#;
+        let program = Flare::compile_from_string(source).unwrap();
+        let Some(Stmt::Kernel(kernel)) = program.items.last() else {
+            panic!("expected a kernel");
+        };
+        let mut kernel = kernel.clone();
+        assert_eq!(hoist_loop_invariants(&mut kernel, &HashSet::new()), 1);
+
+        let names: Vec<_> = kernel.body.iter().filter_map(declared_name).collect();
+        assert_eq!(names, ["c"]);
+
+        let Stmt::For { body, .. } = &kernel.body[1] else {
+            panic!("expected the outer loop after the hoisted binding");
+        };
+        let Stmt::Block { statements, .. } = body.as_ref() else {
+            panic!("expected a loop body block");
+        };
+        let names: Vec<_> = statements.iter().filter_map(declared_name).collect();
+        assert_eq!(names, ["s", "t"]);
+        let Stmt::For { body, .. } = &statements[2] else {
+            panic!("expected the inner loop");
+        };
+        let Stmt::Block { statements, .. } = body.as_ref() else {
+            panic!("expected a loop body block");
+        };
+        let names: Vec<_> = statements.iter().filter_map(declared_name).collect();
+        assert_eq!(names, ["t"]);
+    }
+}
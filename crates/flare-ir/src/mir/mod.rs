@@ -1,3 +1,16 @@
+pub mod barrier;
+pub mod copy;
 pub mod core;
+pub mod cse;
+pub mod deps;
 pub mod error;
+pub mod fold;
 pub mod kernel;
+pub mod licm;
+pub mod pass;
+pub mod purity;
+pub mod reach;
+pub mod resolve;
+pub mod select;
+pub mod strength;
+pub mod unroll_jam;
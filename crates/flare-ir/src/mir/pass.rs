@@ -0,0 +1,491 @@
+use super::error::Result;
+use super::{copy, cse, fold, licm, purity, reach, select, strength};
+use flare::ast::{KernelDef, Program, ScheduleMode, Stmt};
+use std::collections::HashSet;
+use std::fmt;
+
+/// An AST-to-AST transform over a whole program, recording what it changed
+/// in `report`.
+pub trait Pass {
+    fn run(&self, program: &mut Program, report: &mut OptReport) -> Result<()>;
+}
+
+/// A kind of change the passes count in an `OptReport`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rewrite {
+    FoldedConstant,
+    DeadCode,
+    DeadLet,
+    SelectedReduction,
+    HoistedInvariant,
+    CommonSubexpression,
+    ReducedStrength,
+    VectorizedCopy,
+}
+
+impl Rewrite {
+    fn describe(self, count: usize) -> String {
+        let plural = |noun: &str, nouns: &str| if count == 1 { noun } else { nouns }.to_string();
+        match self {
+            Rewrite::FoldedConstant => {
+                format!("folded {} {}", count, plural("constant", "constants"))
+            }
+            Rewrite::DeadCode => format!(
+                "removed {} dead {}",
+                count,
+                plural("branch or statement", "branches and statements")
+            ),
+            Rewrite::DeadLet => format!("eliminated {} dead {}", count, plural("let", "lets")),
+            Rewrite::SelectedReduction => format!(
+                "turned {} conditional {} into min/max",
+                count,
+                plural("update", "updates")
+            ),
+            Rewrite::HoistedInvariant => {
+                format!("hoisted {} {}", count, plural("invariant", "invariants"))
+            }
+            Rewrite::CommonSubexpression => format!(
+                "reused {} common {}",
+                count,
+                plural("subexpression", "subexpressions")
+            ),
+            Rewrite::ReducedStrength => format!(
+                "reduced {} {} to shifts and masks",
+                count,
+                plural("operation", "operations")
+            ),
+            Rewrite::VectorizedCopy => {
+                format!("vectorized {} copy {}", count, plural("loop", "loops"))
+            }
+        }
+    }
+}
+
+/// What a `PassManager` run changed, as a count per kind of rewrite in the
+/// order the rewrites were first made.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OptReport {
+    counts: Vec<(Rewrite, usize)>,
+}
+
+impl OptReport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, rewrite: Rewrite, count: usize) {
+        if count == 0 {
+            return;
+        }
+        match self.counts.iter_mut().find(|(kind, _)| *kind == rewrite) {
+            Some((_, total)) => *total += count,
+            None => self.counts.push((rewrite, count)),
+        }
+    }
+
+    pub fn count(&self, rewrite: Rewrite) -> usize {
+        self.counts
+            .iter()
+            .find(|(kind, _)| *kind == rewrite)
+            .map_or(0, |(_, count)| *count)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    /// One line per kind of rewrite, such as `folded 3 constants`.
+    pub fn lines(&self) -> Vec<String> {
+        self.counts
+            .iter()
+            .map(|(rewrite, count)| rewrite.describe(*count))
+            .collect()
+    }
+}
+
+impl fmt::Display for OptReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.lines().join("\n"))
+    }
+}
+
+/// Optimization level for the flare-ir passes. `O1` folds constants,
+/// eliminates dead branches, unreachable statements and unused `let`
+/// bindings, turns conditional accumulator updates into `min`/`max`, hoists
+/// loop-invariant bindings, and reuses repeated pure values; `O2` also
+/// reduces multiplications, divisions, and remainders by powers of two to
+/// shifts and masks, and turns short element-wise copy loops into vector
+/// loads and stores. Kernels targeted by a `schedule manual` block
+/// are only folded and cleaned up; the passes that restructure code leave
+/// them to their listed directives.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptLevel {
+    #[default]
+    O0,
+    O1,
+    O2,
+}
+
+/// Ordered list of passes run in sequence, optionally repeated until the
+/// program stops changing.
+pub struct PassManager {
+    passes: Vec<Box<dyn Pass>>,
+    max_iterations: usize,
+}
+
+impl PassManager {
+    pub fn new() -> Self {
+        Self {
+            passes: Vec::new(),
+            max_iterations: 1,
+        }
+    }
+
+    /// The pipeline `generate` runs at `level`.
+    pub fn for_opt_level(level: OptLevel) -> Self {
+        let mut manager = Self::new();
+        if level >= OptLevel::O1 {
+            manager.add(FoldConstants);
+            manager.add(EliminateDeadCode);
+            manager.add(EliminateDeadLets);
+            manager.add(SelectReductions);
+            manager.add(HoistLoopInvariants);
+            manager.add(EliminateCommonSubexpressions);
+        }
+        if level >= OptLevel::O2 {
+            manager.add(ReduceStrength);
+            manager.add(VectorizeCopies);
+        }
+        manager
+    }
+
+    pub fn add(&mut self, pass: impl Pass + 'static) -> &mut Self {
+        self.passes.push(Box::new(pass));
+        self
+    }
+
+    /// Repeats the whole sequence until a round leaves the program
+    /// unchanged, at most `max_iterations` times.
+    pub fn with_fixpoint(mut self, max_iterations: usize) -> Self {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    pub fn run(&self, program: &mut Program) -> Result<OptReport> {
+        let mut report = OptReport::new();
+        for _ in 0..self.max_iterations.max(1) {
+            let before = (self.max_iterations > 1).then(|| program.clone());
+            for pass in &self.passes {
+                pass.run(program, &mut report)?;
+            }
+            if before.is_none_or(|before| before == *program) {
+                break;
+            }
+        }
+        Ok(report)
+    }
+}
+
+impl Default for PassManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Runs `f` on every kernel, summing the rewrites it reports.
+fn for_each_kernel(program: &mut Program, mut f: impl FnMut(&mut KernelDef) -> usize) -> usize {
+    program
+        .items
+        .iter_mut()
+        .map(|item| match item {
+            Stmt::Kernel(kernel) => f(kernel),
+            _ => 0,
+        })
+        .sum()
+}
+
+/// Like `for_each_kernel`, skipping kernels whose schedule is `manual`.
+fn for_each_auto_kernel(
+    program: &mut Program,
+    mut f: impl FnMut(&mut KernelDef) -> usize,
+) -> usize {
+    let manual = manual_kernels(program);
+    for_each_kernel(program, |kernel| {
+        if manual.contains(kernel.name) {
+            0
+        } else {
+            f(kernel)
+        }
+    })
+}
+
+/// Kernels whose schedule, the last one targeting them, is `manual`.
+fn manual_kernels<'src>(program: &Program<'src>) -> HashSet<&'src str> {
+    let mut manual = HashSet::new();
+    for schedule in program.schedules() {
+        let Some(target) = schedule.target else {
+            continue;
+        };
+        match schedule.mode {
+            ScheduleMode::Manual => manual.insert(target),
+            ScheduleMode::Auto => manual.remove(target),
+        };
+    }
+    manual
+}
+
+/// `fold::fold_constants` on every kernel.
+pub struct FoldConstants;
+
+impl Pass for FoldConstants {
+    fn run(&self, program: &mut Program, report: &mut OptReport) -> Result<()> {
+        report.record(
+            Rewrite::FoldedConstant,
+            for_each_kernel(program, fold::fold_constants),
+        );
+        Ok(())
+    }
+}
+
+/// `reach::eliminate_dead_code` on every kernel.
+pub struct EliminateDeadCode;
+
+impl Pass for EliminateDeadCode {
+    fn run(&self, program: &mut Program, report: &mut OptReport) -> Result<()> {
+        report.record(
+            Rewrite::DeadCode,
+            for_each_kernel(program, reach::eliminate_dead_code),
+        );
+        Ok(())
+    }
+}
+
+/// `reach::eliminate_dead_lets` on every kernel.
+pub struct EliminateDeadLets;
+
+impl Pass for EliminateDeadLets {
+    fn run(&self, program: &mut Program, report: &mut OptReport) -> Result<()> {
+        report.record(
+            Rewrite::DeadLet,
+            for_each_kernel(program, reach::eliminate_dead_lets),
+        );
+        Ok(())
+    }
+}
+
+/// `select::select_reductions` on every kernel.
+pub struct SelectReductions;
+
+impl Pass for SelectReductions {
+    fn run(&self, program: &mut Program, report: &mut OptReport) -> Result<()> {
+        report.record(
+            Rewrite::SelectedReduction,
+            for_each_auto_kernel(program, select::select_reductions),
+        );
+        Ok(())
+    }
+}
+
+/// `licm::hoist_loop_invariants` on every kernel, treating the program's
+/// pure functions as invariant calls.
+pub struct HoistLoopInvariants;
+
+impl Pass for HoistLoopInvariants {
+    fn run(&self, program: &mut Program, report: &mut OptReport) -> Result<()> {
+        let pure_fns = purity::pure_functions(&program.items);
+        let hoisted = for_each_auto_kernel(program, |kernel| {
+            licm::hoist_loop_invariants(kernel, &pure_fns)
+        });
+        report.record(Rewrite::HoistedInvariant, hoisted);
+        Ok(())
+    }
+}
+
+/// `cse::eliminate_common_subexpressions` on every kernel, reusing calls to
+/// the program's pure functions like any other pure value.
+pub struct EliminateCommonSubexpressions;
+
+impl Pass for EliminateCommonSubexpressions {
+    fn run(&self, program: &mut Program, report: &mut OptReport) -> Result<()> {
+        let pure_fns = purity::pure_functions(&program.items);
+        let replaced = for_each_auto_kernel(program, |kernel| {
+            cse::eliminate_common_subexpressions(kernel, &pure_fns)
+        });
+        report.record(Rewrite::CommonSubexpression, replaced);
+        Ok(())
+    }
+}
+
+/// `strength::reduce_strength` on every kernel.
+pub struct ReduceStrength;
+
+impl Pass for ReduceStrength {
+    fn run(&self, program: &mut Program, report: &mut OptReport) -> Result<()> {
+        report.record(
+            Rewrite::ReducedStrength,
+            for_each_auto_kernel(program, strength::reduce_strength),
+        );
+        Ok(())
+    }
+}
+
+/// `copy::vectorize_copies` on every kernel.
+pub struct VectorizeCopies;
+
+impl Pass for VectorizeCopies {
+    fn run(&self, program: &mut Program, report: &mut OptReport) -> Result<()> {
+        report.record(
+            Rewrite::VectorizedCopy,
+            for_each_auto_kernel(program, copy::vectorize_copies),
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use flare::Flare;
+
+    use super::*;
+
+    fn kernel_body<'p, 'src>(program: &'p Program<'src>) -> &'p [Stmt<'src>] {
+        match &program.items[0] {
+            Stmt::Kernel(kernel) => &kernel.body,
+            _ => panic!("expected a kernel"),
+        }
+    }
+
+    #[test]
+    fn test_fold_then_dead_code_elimination() {
+        let source = r#"
+            kernel k(A: Tensor<f32, [N]>) {
+                let i = thread_idx.x
+                if 2 * 8 > 4 {
+                    A[i] = 1.0
+                } else {
+                    A[i] = 2.0
+                }
+                if 1 > 2 {
+                    A[i] = 3.0
+                }
+            }
+        "#;
+        let mut program = Flare::compile_from_string(source).unwrap();
+        let expected = Flare::compile_from_string(
+            r#"
+            kernel k(A: Tensor<f32, [N]>) {
+                let i = thread_idx.x
+                {
+                    A[i] = 1.0
+                }
+            }
+        "#,
+        )
+        .unwrap();
+
+        let mut manager = PassManager::new();
+        manager.add(FoldConstants).add(EliminateDeadCode);
+        manager.run(&mut program).unwrap();
+
+        let body = kernel_body(&program);
+        let expected = kernel_body(&expected);
+        assert_eq!(body.len(), expected.len());
+        assert!(body
+            .iter()
+            .zip(expected)
+            .all(|(stmt, expected)| stmt.semantically_eq(expected)));
+    }
+
+    #[test]
+    fn test_fixpoint_reaches_else_if_chain() {
+        let source = r#"
+            kernel k(A: Tensor<f32, [N]>) {
+                if 1 > 2 {
+                    A[0] = 1.0
+                } else if 2 > 1 {
+                    A[0] = 2.0
+                }
+            }
+        "#;
+        let mut once = Flare::compile_from_string(source).unwrap();
+        let mut fixpoint = once.clone();
+
+        let mut manager = PassManager::new();
+        manager.add(FoldConstants).add(EliminateDeadCode);
+        manager.run(&mut once).unwrap();
+        assert!(matches!(kernel_body(&once), [Stmt::If { .. }]));
+
+        manager.with_fixpoint(4).run(&mut fixpoint).unwrap();
+        assert!(matches!(kernel_body(&fixpoint), [Stmt::Block { .. }]));
+    }
+
+    #[test]
+    fn test_manual_schedule_skips_vectorization() {
+        let source = |mode: &str| {
+            format!(
+                r#"
+                kernel copy(dst: Tensor<f32, [N]>, src: Tensor<f32, [N]>) {{
+                    for i in 0..4 {{
+                        dst[i] = src[i]
+                    }}
+                }}
+
+                schedule {} copy {{
+                    unroll(2)
+                }}
+            "#,
+                mode
+            )
+        };
+        let manager = PassManager::for_opt_level(OptLevel::O2);
+
+        for (mode, vectorized) in [("", 1), ("auto", 1), ("manual", 0)] {
+            let source = source(mode);
+            let mut program = Flare::compile_from_string(&source).unwrap();
+            let report = manager.run(&mut program).unwrap();
+            assert_eq!(
+                report.count(Rewrite::VectorizedCopy),
+                vectorized,
+                "{}",
+                mode
+            );
+            assert_eq!(
+                matches!(kernel_body(&program), [Stmt::For { .. }]),
+                vectorized == 0
+            );
+        }
+    }
+
+    #[test]
+    fn test_stages_survive_optimization() {
+        let source = r#"
+            kernel pipelined(A: Tensor<f32, [N]>, B: Tensor<f32, [N]>) {
+                shared_memory {
+                    tile: [64]: f32
+                }
+                compute {
+                    let t = thread_idx.x
+                    @stage(1) B[t] = tile[t] * 2.0
+                    @stage(0) load_shared(tile[t], A[t])
+                    @stage(1) sync_threads()
+                    @stage(0) sync_threads()
+                }
+            }
+        "#;
+        let mut program = Flare::compile_from_string(source).unwrap();
+        PassManager::for_opt_level(OptLevel::O2)
+            .run(&mut program)
+            .unwrap();
+
+        let Stmt::Kernel(kernel) = &program.items[0] else {
+            panic!("expected a kernel");
+        };
+        let compute = kernel.compute.as_deref().unwrap();
+        let stages: Vec<Option<i64>> = compute.iter().map(Stmt::stage).collect();
+        assert_eq!(stages, [None, Some(1), Some(0), Some(1), Some(0)]);
+        assert!(matches!(
+            &compute[2],
+            Stmt::Staged { stmt, .. } if matches!(stmt.as_ref(), Stmt::LoadShared { dest: "tile", .. })
+        ));
+    }
+}
@@ -0,0 +1,215 @@
+use super::error::{LoweringError, Result};
+use flare::ast::{walk_expr, walk_stmt, Expr, Stmt, UnOp, Visitor};
+use std::collections::HashSet;
+use std::ops::Range;
+
+/// Builtins that write memory, which a `@pure` helper may not call.
+const EFFECTFUL_BUILTINS: &[&str] = &["scatter", "async_copy"];
+
+/// Names of the program's `@pure` helpers whose bodies have no side
+/// effects, so their calls depend only on their arguments. A helper
+/// calling another user function is pure only if that one is too.
+pub fn pure_functions<'src>(items: &[Stmt<'src>]) -> HashSet<&'src str> {
+    let functions = user_functions(items);
+    let mut pure: HashSet<&str> = annotated(items).map(|(name, _)| name).collect();
+    loop {
+        let impure: Vec<&str> = annotated(items)
+            .filter(|(name, body)| {
+                pure.contains(name) && first_effect(body, &functions, &pure).is_some()
+            })
+            .map(|(name, _)| name)
+            .collect();
+        if impure.is_empty() {
+            return pure;
+        }
+        for name in impure {
+            pure.remove(name);
+        }
+    }
+}
+
+/// Rejects a `@pure` helper whose body writes memory outside its own
+/// bindings or calls something that may.
+pub fn check_pure_functions(items: &[Stmt]) -> Result<()> {
+    let functions = user_functions(items);
+    let pure = pure_functions(items);
+    for (name, body) in annotated(items) {
+        if let Some((effect, span)) = first_effect(body, &functions, &pure) {
+            return Err(LoweringError::lowering_error(
+                format!("@pure function '{}' {}", name, effect),
+                span,
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn user_functions<'src>(items: &[Stmt<'src>]) -> HashSet<&'src str> {
+    items
+        .iter()
+        .filter_map(|item| match item {
+            Stmt::Function { name, .. } => Some(*name),
+            _ => None,
+        })
+        .collect()
+}
+
+fn annotated<'a, 'src>(
+    items: &'a [Stmt<'src>],
+) -> impl Iterator<Item = (&'src str, &'a Expr<'src>)> + 'a {
+    items.iter().filter_map(|item| match item {
+        Stmt::Function {
+            name,
+            body,
+            attributes,
+            ..
+        } if attributes.iter().any(|attr| attr.name == "pure") => Some((*name, body.as_ref())),
+        _ => None,
+    })
+}
+
+/// The first side effect in a helper body, described for an error.
+fn first_effect(
+    body: &Expr,
+    functions: &HashSet<&str>,
+    pure: &HashSet<&str>,
+) -> Option<(String, Range<usize>)> {
+    let mut locals = LocalCollector::default();
+    locals.visit_expr(body);
+
+    let mut check = EffectCheck {
+        locals: &locals.names,
+        functions,
+        pure,
+        effect: None,
+    };
+    check.visit_expr(body);
+    check.effect
+}
+
+/// Names a helper body binds itself, which it may freely assign.
+#[derive(Default)]
+struct LocalCollector<'src> {
+    names: HashSet<&'src str>,
+}
+
+impl<'src> Visitor<'src> for LocalCollector<'src> {
+    fn visit_stmt(&mut self, stmt: &Stmt<'src>) {
+        match stmt {
+            Stmt::Let { name, .. } | Stmt::Var { name, .. } | Stmt::For { var: name, .. } => {
+                self.names.insert(name);
+            }
+            _ => {}
+        }
+        walk_stmt(self, stmt);
+    }
+}
+
+struct EffectCheck<'a> {
+    locals: &'a HashSet<&'a str>,
+    functions: &'a HashSet<&'a str>,
+    pure: &'a HashSet<&'a str>,
+    effect: Option<(String, Range<usize>)>,
+}
+
+impl EffectCheck<'_> {
+    fn found(&mut self, effect: String, span: Range<usize>) {
+        self.effect.get_or_insert((effect, span));
+    }
+}
+
+impl<'src> Visitor<'src> for EffectCheck<'_> {
+    fn visit_stmt(&mut self, stmt: &Stmt<'src>) {
+        if let Stmt::LoadShared { span, .. } = stmt {
+            self.found("loads into shared memory".to_string(), span.clone());
+        }
+        walk_stmt(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr<'src>) {
+        match expr {
+            Expr::Assign { target, span, .. } | Expr::CompoundAssign { target, span, .. } => {
+                match local_root(target) {
+                    Some(name) if self.locals.contains(name) => {}
+                    Some(name) => self.found(format!("writes to '{}'", name), span.clone()),
+                    None => self.found("writes through a pointer".to_string(), span.clone()),
+                }
+            }
+            Expr::Call { func, span, .. } => match func.as_ref() {
+                Expr::Ident(name, _) if EFFECTFUL_BUILTINS.contains(name) => self.found(
+                    format!("calls '{}', which writes memory", name),
+                    span.clone(),
+                ),
+                Expr::Ident(name, _)
+                    if self.functions.contains(name) && !self.pure.contains(name) =>
+                {
+                    self.found(
+                        format!("calls '{}', which is not @pure", name),
+                        span.clone(),
+                    )
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+        walk_expr(self, expr);
+    }
+}
+
+/// The binding an assignment target writes, or `None` when it writes
+/// through a dereferenced pointer.
+fn local_root<'src>(target: &Expr<'src>) -> Option<&'src str> {
+    match target {
+        Expr::Ident(name, _) => Some(name),
+        Expr::Index { object, .. } | Expr::Member { object, .. } => local_root(object),
+        Expr::Unary {
+            op: UnOp::Deref, ..
+        } => None,
+        Expr::Unary { expr, .. } => local_root(expr),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use flare::Flare;
+
+    use super::*;
+
+    #[test]
+    fn test_effectful_pure_function_rejected() {
+        let source = r#"
+            @pure
+            fn half(x: f32) -> f32 {
+                var y = x
+                y = y * 0.5
+                y
+            }
+
+            fn noisy(x: f32) -> f32 {
+                x * 3.0
+            }
+
+            @pure
+            fn calls_noisy(x: f32) -> f32 {
+                noisy(x) + 1.0
+            }
+
+            @pure
+            fn calls_pure(x: f32) -> f32 {
+                half(x) + 1.0
+            }
+        "#;
+        let program = Flare::compile_from_string(source).unwrap();
+        assert_eq!(
+            pure_functions(&program.items),
+            HashSet::from(["half", "calls_pure"])
+        );
+
+        let err = check_pure_functions(&program.items).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("@pure function 'calls_noisy' calls 'noisy', which is not @pure"));
+        assert_eq!(&source[err.span().clone()], "noisy(x)");
+    }
+}
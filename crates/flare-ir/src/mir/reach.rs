@@ -0,0 +1,264 @@
+use flare::ast::{walk_expr, Expr, KernelDef, Stmt, Visitor};
+use flare::lint::terminates;
+use std::collections::HashSet;
+
+use super::select::is_pure;
+
+/// Drops the statements `flare::lint::unreachable_code` warns about,
+/// returning how many.
+pub fn prune_unreachable(kernel: &mut KernelDef) -> usize {
+    let mut pruned = 0;
+    if let Some(compute) = &mut kernel.compute {
+        pruned += prune_list(compute);
+    }
+    pruned + prune_list(&mut kernel.body)
+}
+
+/// Dead code elimination: replaces an `if` on a literal condition with the
+/// branch it always takes, dropping it when that branch is a missing `else`,
+/// then prunes the statements left unreachable. Returns the number of
+/// branches and statements removed.
+pub fn eliminate_dead_code(kernel: &mut KernelDef) -> usize {
+    let mut eliminated = 0;
+    if let Some(compute) = &mut kernel.compute {
+        eliminated += eliminate_list(compute);
+    }
+    eliminated += eliminate_list(&mut kernel.body);
+    eliminated + prune_unreachable(kernel)
+}
+
+/// Drops a `let` whose name is never read and whose value has no side
+/// effects, repeating until the bindings left are all used. Returns the
+/// number of bindings removed.
+pub fn eliminate_dead_lets(kernel: &mut KernelDef) -> usize {
+    let mut removed = 0;
+    loop {
+        let mut reads = ReadCollector::default();
+        for stmt in kernel.compute.iter().flatten().chain(&kernel.body) {
+            reads.visit_stmt(stmt);
+        }
+
+        let mut dropped = 0;
+        if let Some(compute) = &mut kernel.compute {
+            dropped += drop_dead_lets(compute, &reads.names);
+        }
+        dropped += drop_dead_lets(&mut kernel.body, &reads.names);
+        if dropped == 0 {
+            return removed;
+        }
+        removed += dropped;
+    }
+}
+
+fn drop_dead_lets(stmts: &mut Vec<Stmt>, reads: &HashSet<&str>) -> usize {
+    let before = stmts.len();
+    stmts.retain(|stmt| !is_dead_let(stmt, reads));
+    let mut dropped = before - stmts.len();
+    for stmt in stmts {
+        dropped += drop_nested_lets(stmt, reads);
+    }
+    dropped
+}
+
+fn drop_nested_lets(stmt: &mut Stmt, reads: &HashSet<&str>) -> usize {
+    match stmt {
+        Stmt::Block { statements, .. } => drop_dead_lets(statements, reads),
+        Stmt::If {
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            let dropped = drop_nested_lets(then_branch, reads);
+            dropped
+                + else_branch
+                    .as_mut()
+                    .map_or(0, |else_stmt| drop_nested_lets(else_stmt, reads))
+        }
+        Stmt::While { body, .. } | Stmt::For { body, .. } | Stmt::Staged { stmt: body, .. } => {
+            drop_nested_lets(body, reads)
+        }
+        _ => 0,
+    }
+}
+
+fn is_dead_let(stmt: &Stmt, reads: &HashSet<&str>) -> bool {
+    match stmt {
+        Stmt::Let {
+            name,
+            value,
+            attributes,
+            ..
+        } => attributes.is_empty() && !reads.contains(name) && is_pure(value),
+        _ => false,
+    }
+}
+
+/// Every name an expression in the kernel reads.
+#[derive(Default)]
+struct ReadCollector<'src> {
+    names: HashSet<&'src str>,
+}
+
+impl<'src> Visitor<'src> for ReadCollector<'src> {
+    fn visit_expr(&mut self, expr: &Expr<'src>) {
+        if let Expr::Ident(name, _) = expr {
+            self.names.insert(name);
+        }
+        walk_expr(self, expr);
+    }
+}
+
+fn eliminate_list(stmts: &mut Vec<Stmt>) -> usize {
+    let mut eliminated = 0;
+    *stmts = std::mem::take(stmts)
+        .into_iter()
+        .filter_map(|stmt| {
+            let literal = matches!(
+                stmt,
+                Stmt::If {
+                    condition: Expr::BoolLiteral(..),
+                    ..
+                }
+            );
+            eliminated += usize::from(literal);
+            taken_branch(stmt)
+        })
+        .collect();
+    for stmt in stmts {
+        eliminated += eliminate_nested(stmt);
+    }
+    eliminated
+}
+
+fn eliminate_nested(stmt: &mut Stmt) -> usize {
+    match stmt {
+        Stmt::Block { statements, .. } => eliminate_list(statements),
+        Stmt::If {
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            let eliminated = eliminate_nested(then_branch);
+            eliminated
+                + else_branch
+                    .as_mut()
+                    .map_or(0, |else_stmt| eliminate_nested(else_stmt))
+        }
+        Stmt::While { body, .. } | Stmt::For { body, .. } | Stmt::Staged { stmt: body, .. } => {
+            eliminate_nested(body)
+        }
+        _ => 0,
+    }
+}
+
+/// What an `if` on a literal condition reduces to; other statements are
+/// kept as they are.
+fn taken_branch(stmt: Stmt) -> Option<Stmt> {
+    match stmt {
+        Stmt::If {
+            condition: Expr::BoolLiteral(taken, _),
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            if taken {
+                Some(*then_branch)
+            } else {
+                else_branch.map(|else_stmt| *else_stmt)
+            }
+        }
+        stmt => Some(stmt),
+    }
+}
+
+fn prune_list(stmts: &mut Vec<Stmt>) -> usize {
+    let mut pruned = 0;
+    if let Some(last) = stmts.iter().position(terminates) {
+        pruned += stmts.len() - (last + 1);
+        stmts.truncate(last + 1);
+    }
+    for stmt in stmts {
+        pruned += prune_nested(stmt);
+    }
+    pruned
+}
+
+fn prune_nested(stmt: &mut Stmt) -> usize {
+    match stmt {
+        Stmt::Block { statements, .. } => prune_list(statements),
+        Stmt::If {
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            let pruned = prune_nested(then_branch);
+            pruned
+                + else_branch
+                    .as_mut()
+                    .map_or(0, |else_stmt| prune_nested(else_stmt))
+        }
+        Stmt::While { body, .. } | Stmt::For { body, .. } | Stmt::Staged { stmt: body, .. } => {
+            prune_nested(body)
+        }
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use flare::lint::unreachable_code;
+    use flare::Flare;
+
+    use super::*;
+
+    fn kernel_from(source: &str) -> KernelDef<'_> {
+        let program = Flare::compile_from_string(source).unwrap();
+        match program.items.into_iter().next() {
+            Some(Stmt::Kernel(kernel)) => kernel,
+            _ => panic!("expected a kernel"),
+        }
+    }
+
+    #[test]
+    fn test_statement_after_return_flagged() {
+        let source = r#"
+            kernel k(A: Tensor<f32, [N]>) {
+                let i = thread_idx.x
+                if i > 4 {
+                    return;
+                } else {
+                    return;
+                }
+                A[i] = 1.0
+                A[i] = 2.0
+            }
+        "#;
+        let mut kernel = kernel_from(source);
+
+        let warnings = unreachable_code(&kernel);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(&source[warnings[0].span.clone()], "A[i] = 1.0");
+
+        prune_unreachable(&mut kernel);
+        assert_eq!(kernel.body.len(), 2);
+        assert!(unreachable_code(&kernel).is_empty());
+    }
+
+    #[test]
+    fn test_dead_lets_eliminated_transitively() {
+        let source = r#"
+            kernel k(A: Tensor<f32, [N]>) {
+                let i = thread_idx.x
+                let j = i + 1
+                let k = j * 2
+                let v = A[i]
+                A[i] = 1.0
+            }
+        "#;
+        let mut kernel = kernel_from(source);
+
+        assert_eq!(eliminate_dead_lets(&mut kernel), 3);
+        assert_eq!(kernel.body.len(), 2);
+        assert!(matches!(&kernel.body[0], Stmt::Let { name: "i", .. }));
+    }
+}
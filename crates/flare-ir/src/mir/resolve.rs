@@ -0,0 +1,95 @@
+use super::error::{LoweringError, Result};
+use flare::ast::{KernelDef, Program, ScheduleBlock};
+
+/// A kernel paired with the schedule that targets it, if any.
+pub type ScheduledKernel<'a, 'src> = (&'a KernelDef<'src>, Option<&'a ScheduleBlock<'src>>);
+
+/// Resolves the kernel names `schedule` and `fuse` blocks refer to and pairs
+/// every kernel, in source order, with its schedule. A name no kernel has is
+/// an error; when several schedules target the same kernel the last one
+/// applies.
+pub fn resolve_targets<'a, 'src>(
+    program: &'a Program<'src>,
+) -> Result<Vec<ScheduledKernel<'a, 'src>>> {
+    if let Some(target) = program
+        .targets()
+        .find(|target| program.kernel_by_name(target.name).is_none())
+    {
+        return Err(LoweringError::lowering_error(
+            format!(
+                "{} block targets unknown kernel '{}'",
+                target.block, target.name
+            ),
+            target.span.clone(),
+        ));
+    }
+
+    let mut pairs: Vec<ScheduledKernel> = program.kernels().map(|kernel| (kernel, None)).collect();
+    for schedule in program.schedules() {
+        if let Some(pair) = pairs
+            .iter_mut()
+            .find(|(kernel, _)| Some(kernel.name) == schedule.target)
+        {
+            pair.1 = Some(schedule);
+        }
+    }
+    Ok(pairs)
+}
+
+#[cfg(test)]
+mod tests {
+    use flare::{Flare, Parser};
+
+    use super::*;
+
+    #[test]
+    fn test_schedule_paired_with_target() {
+        let source = r#"
+            kernel scale(A: Tensor<f32, [N]>) {}
+
+            kernel store(A: Tensor<f32, [N]>) {}
+
+            schedule store {
+                stream(b)
+            }
+
+            fuse scale, store
+        "#;
+        let program = Flare::compile_from_string(source).unwrap();
+
+        let pairs = resolve_targets(&program).unwrap();
+        let names: Vec<_> = pairs
+            .iter()
+            .map(|(kernel, schedule)| (kernel.name, schedule.and_then(|s| s.target)))
+            .collect();
+        assert_eq!(names, [("scale", None), ("store", Some("store"))]);
+    }
+
+    #[test]
+    fn test_unknown_target_rejected() {
+        let schedule = r#"
+            kernel scale(A: Tensor<f32, [N]>) {}
+
+            schedule sclae {
+                stream(b)
+            }
+        "#;
+        // `compile_from_string` already rejects these, so parse without it
+        let program = Parser::new(schedule).unwrap().parse().unwrap();
+        let err = resolve_targets(&program).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("schedule block targets unknown kernel 'sclae'"));
+
+        let fusion = r#"
+            kernel scale(A: Tensor<f32, [N]>) {}
+
+            fuse scale, store
+        "#;
+        let program = Parser::new(fusion).unwrap().parse().unwrap();
+        let err = resolve_targets(&program).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("fuse block targets unknown kernel 'store'"));
+    }
+}
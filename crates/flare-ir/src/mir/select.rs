@@ -0,0 +1,174 @@
+use flare::ast::{walk_stmt_mut, BinOp, Expr, KernelDef, Stmt, VisitorMut};
+
+/// Min/max reductions: rewrites `if v > best { best = v }` into
+/// `best = max(best, v)`, and the `<` form into `min`, so a reduction loop
+/// updates its accumulator without a divergent branch. `v` must be free of
+/// calls and assignments, since the rewrite evaluates it unconditionally.
+/// Returns the number of updates rewritten.
+pub fn select_reductions(kernel: &mut KernelDef) -> usize {
+    let mut rewrite = Rewrite { selected: 0 };
+    for stmt in kernel.compute.iter_mut().flatten().chain(&mut kernel.body) {
+        rewrite.visit_stmt_mut(stmt);
+    }
+    rewrite.selected
+}
+
+struct Rewrite {
+    selected: usize,
+}
+
+impl<'src> VisitorMut<'src> for Rewrite {
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt<'src>) {
+        walk_stmt_mut(self, stmt);
+        if let Some(reduction) = min_max_update(stmt) {
+            *stmt = reduction;
+            self.selected += 1;
+        }
+    }
+}
+
+fn min_max_update<'src>(stmt: &Stmt<'src>) -> Option<Stmt<'src>> {
+    let Stmt::If {
+        condition: Expr::Binary {
+            left, op, right, ..
+        },
+        then_branch,
+        else_branch: None,
+        span,
+    } = stmt
+    else {
+        return None;
+    };
+
+    let assign = match then_branch.as_ref() {
+        Stmt::Block { statements, .. } => match statements.as_slice() {
+            [Stmt::Expr(assign)] => assign,
+            _ => return None,
+        },
+        Stmt::Expr(assign) => assign,
+        _ => return None,
+    };
+    let Expr::Assign { target, value, .. } = assign else {
+        return None;
+    };
+    let Expr::Ident(acc, _) = target.as_ref() else {
+        return None;
+    };
+    if !is_pure(value) {
+        return None;
+    }
+
+    // `v > best` and `best < v` keep the larger value
+    let is_acc = |expr: &Expr| matches!(expr, Expr::Ident(name, _) if name == acc);
+    let candidate_first = if value.semantically_eq(left) && is_acc(right) {
+        true
+    } else if is_acc(left) && value.semantically_eq(right) {
+        false
+    } else {
+        return None;
+    };
+    let name =
+        match (op, candidate_first) {
+            (BinOp::Greater | BinOp::GreaterEqual, true)
+            | (BinOp::Less | BinOp::LessEqual, false) => "max",
+            (BinOp::Less | BinOp::LessEqual, true)
+            | (BinOp::Greater | BinOp::GreaterEqual, false) => "min",
+            _ => return None,
+        };
+
+    Some(Stmt::Expr(Expr::Assign {
+        target: target.clone(),
+        value: Box::new(Expr::Call {
+            func: Box::new(Expr::Ident(name, span.clone())),
+            args: vec![target.as_ref().clone(), value.as_ref().clone()],
+            span: span.clone(),
+        }),
+        span: span.clone(),
+    }))
+}
+
+/// Whether evaluating `expr` has no effects beyond reading memory.
+pub(crate) fn is_pure(expr: &Expr) -> bool {
+    match expr {
+        Expr::IntLiteral(..)
+        | Expr::UIntLiteral(..)
+        | Expr::FloatLiteral(..)
+        | Expr::BoolLiteral(..)
+        | Expr::Ident(..)
+        | Expr::ThreadIdx { .. }
+        | Expr::BlockIdx { .. }
+        | Expr::BlockDim { .. }
+        | Expr::SizeOf { .. } => true,
+        Expr::Binary { left, right, .. } => is_pure(left) && is_pure(right),
+        Expr::Unary { expr, .. } | Expr::Cast { expr, .. } | Expr::Bitcast { expr, .. } => {
+            is_pure(expr)
+        }
+        Expr::Member { object, .. } => is_pure(object),
+        Expr::Index {
+            object, indices, ..
+        } => is_pure(object) && indices.iter().all(is_pure),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use flare::Flare;
+
+    use super::*;
+
+    fn reduced_body(source: &str) -> Vec<Stmt<'_>> {
+        let program = Flare::compile_from_string(source).unwrap();
+        let Some(Stmt::Kernel(mut kernel)) = program.items.into_iter().next() else {
+            panic!("expected a kernel");
+        };
+        select_reductions(&mut kernel);
+        kernel.body
+    }
+
+    #[test]
+    fn test_conditional_update_becomes_max() {
+        let source = r#"
+            kernel k(A: Tensor<f32, [N]>, B: Tensor<f32, [N]>) {
+                var best: f32 = 0.0
+                var low: f32 = 0.0
+                for i in 0..N {
+                    if A[i] > best {
+                        best = A[i]
+                    }
+                    if low > A[i] {
+                        low = A[i]
+                    }
+                    if A[i] > best {
+                        best = B[i]
+                    }
+                }
+            }
+        "#;
+        let body = reduced_body(source);
+        let Stmt::For {
+            body: loop_body, ..
+        } = &body[2]
+        else {
+            panic!("expected a loop");
+        };
+        let Stmt::Block { statements, .. } = loop_body.as_ref() else {
+            panic!("expected a block");
+        };
+
+        let callee = |stmt: &Stmt| match stmt {
+            Stmt::Expr(Expr::Assign { value, .. }) => match value.as_ref() {
+                Expr::Call { func, .. } => match func.as_ref() {
+                    Expr::Ident(name, _) => Some(name.to_string()),
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        };
+        assert_eq!(callee(&statements[0]).as_deref(), Some("max"));
+        assert_eq!(callee(&statements[1]).as_deref(), Some("min"));
+        // assigning something other than the compared value stays a branch
+        assert!(matches!(&statements[2], Stmt::If { .. }));
+    }
+}
@@ -0,0 +1,345 @@
+use flare::ast::{
+    walk_expr_mut, walk_stmt, BinOp, Expr, KernelDef, Stmt, Type, Visitor, VisitorMut,
+};
+use std::collections::HashMap;
+
+/// What is known about the values a name or expression can take.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct IntInfo {
+    non_negative: bool,
+    /// Unsigned values wrap rather than go negative, so they stay
+    /// non-negative under subtraction.
+    unsigned: bool,
+}
+
+impl IntInfo {
+    const SIGNED: IntInfo = IntInfo {
+        non_negative: false,
+        unsigned: false,
+    };
+    const UNSIGNED: IntInfo = IntInfo {
+        non_negative: true,
+        unsigned: true,
+    };
+
+    fn signed(non_negative: bool) -> IntInfo {
+        IntInfo {
+            non_negative,
+            unsigned: false,
+        }
+    }
+}
+
+/// Strength reduction: rewrites integer `x * 2^k`, `x / 2^k` and `x % 2^k`
+/// into `x << k`, `x >> k` and `x & (2^k - 1)` when `x` is known to be
+/// non-negative, where the shift and mask agree with truncating division and
+/// no negative value is shifted left. Returns the number of operations
+/// rewritten.
+pub fn reduce_strength(kernel: &mut KernelDef) -> usize {
+    let mut env = IntEnv::default();
+    for param in &kernel.params {
+        env.declare(param.name, int_type_info(&param.ty));
+    }
+    for stmt in kernel.compute.iter().flatten().chain(&kernel.body) {
+        env.visit_stmt(stmt);
+    }
+
+    let mut rewrite = Rewrite {
+        ints: &env.ints,
+        reduced: 0,
+    };
+    for stmt in kernel.compute.iter_mut().flatten().chain(&mut kernel.body) {
+        rewrite.visit_stmt_mut(stmt);
+    }
+    rewrite.reduced
+}
+
+fn int_type_info(ty: &Type) -> Option<IntInfo> {
+    match ty {
+        Type::I32(_) | Type::I64(_) => Some(IntInfo::SIGNED),
+        Type::U32(_) | Type::U64(_) => Some(IntInfo::UNSIGNED),
+        _ => None,
+    }
+}
+
+/// Power-of-two exponent of a literal `2^k` with `k >= 1`.
+fn power_of_two(expr: &Expr) -> Option<u32> {
+    match expr {
+        Expr::IntLiteral(n, _) if *n > 1 && (*n as u64).is_power_of_two() => {
+            Some(n.trailing_zeros())
+        }
+        Expr::UIntLiteral(n, _) if *n > 1 && n.is_power_of_two() => Some(n.trailing_zeros()),
+        _ => None,
+    }
+}
+
+fn classify(expr: &Expr, ints: &HashMap<&str, Option<IntInfo>>) -> Option<IntInfo> {
+    match expr {
+        Expr::IntLiteral(n, _) => Some(IntInfo::signed(*n >= 0)),
+        Expr::UIntLiteral(..) => Some(IntInfo::UNSIGNED),
+        Expr::Ident(name, _) => ints.get(name).copied().flatten(),
+        Expr::ThreadIdx { .. } | Expr::BlockIdx { .. } | Expr::BlockDim { .. } => {
+            Some(IntInfo::UNSIGNED)
+        }
+        Expr::Binary {
+            left, op, right, ..
+        } => {
+            let left = classify(left, ints)?;
+            let right = classify(right, ints)?;
+            // as in C, a signed operand converts to unsigned
+            let unsigned = left.unsigned || right.unsigned;
+            let non_negative = unsigned || (left.non_negative && right.non_negative);
+            match op {
+                BinOp::Add
+                | BinOp::Mul
+                | BinOp::Div
+                | BinOp::Mod
+                | BinOp::Shl
+                | BinOp::Shr
+                | BinOp::BitAnd => Some(IntInfo {
+                    non_negative,
+                    unsigned,
+                }),
+                BinOp::Sub => Some(IntInfo {
+                    non_negative: unsigned,
+                    unsigned,
+                }),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Integer facts for the names a kernel binds. A name bound more than once
+/// maps to `None`, since the facts of one binding may not hold for another.
+#[derive(Default)]
+struct IntEnv<'src> {
+    ints: HashMap<&'src str, Option<IntInfo>>,
+}
+
+impl<'src> IntEnv<'src> {
+    fn declare(&mut self, name: &'src str, info: Option<IntInfo>) {
+        self.ints
+            .entry(name)
+            .and_modify(|known| *known = None)
+            .or_insert(info);
+    }
+}
+
+impl<'src> Visitor<'src> for IntEnv<'src> {
+    fn visit_stmt(&mut self, stmt: &Stmt<'src>) {
+        match stmt {
+            Stmt::Let {
+                name, ty, value, ..
+            }
+            | Stmt::Const {
+                name, ty, value, ..
+            } => {
+                let info = match ty {
+                    Some(ty) => int_type_info(ty).map(|declared| IntInfo {
+                        non_negative: declared.non_negative
+                            || classify(value, &self.ints).is_some_and(|v| v.non_negative),
+                        unsigned: declared.unsigned,
+                    }),
+                    None => classify(value, &self.ints),
+                };
+                self.declare(name, info);
+            }
+            Stmt::Var { name, ty, .. } => {
+                // reassignment may make it negative
+                let info = ty.as_ref().and_then(int_type_info);
+                self.declare(name, info.filter(|info| info.non_negative));
+            }
+            Stmt::For { var, iterator, .. } => {
+                let non_negative = match iterator {
+                    Expr::Range { start, step, .. } => {
+                        let start_ok = start.as_deref().is_none_or(|start| {
+                            classify(start, &self.ints).is_some_and(|s| s.non_negative)
+                        });
+                        let step_ok = step
+                            .as_deref()
+                            .is_none_or(|step| matches!(step, Expr::IntLiteral(n, _) if *n > 0));
+                        start_ok && step_ok
+                    }
+                    _ => false,
+                };
+                self.declare(var, Some(IntInfo::signed(non_negative)));
+            }
+            _ => {}
+        }
+        walk_stmt(self, stmt);
+    }
+}
+
+struct Rewrite<'a, 'src> {
+    ints: &'a HashMap<&'src str, Option<IntInfo>>,
+    reduced: usize,
+}
+
+impl<'src> VisitorMut<'src> for Rewrite<'_, 'src> {
+    fn visit_expr_mut(&mut self, expr: &mut Expr<'src>) {
+        walk_expr_mut(self, expr);
+
+        let Expr::Binary {
+            left, op, right, ..
+        } = expr
+        else {
+            return;
+        };
+
+        let swapped = *op == BinOp::Mul && power_of_two(left).is_some();
+        let (value, factor) = if swapped {
+            (&**right, &**left)
+        } else {
+            (&**left, &**right)
+        };
+        let Some(k) = power_of_two(factor) else {
+            return;
+        };
+        let Some(info) = classify(value, self.ints) else {
+            return;
+        };
+        if !info.non_negative {
+            return;
+        }
+        if swapped {
+            std::mem::swap(left, right);
+        }
+
+        let span = right.span();
+        match op {
+            BinOp::Mul => {
+                *op = BinOp::Shl;
+                **right = Expr::IntLiteral(i64::from(k), span);
+            }
+            BinOp::Div => {
+                *op = BinOp::Shr;
+                **right = Expr::IntLiteral(i64::from(k), span);
+            }
+            BinOp::Mod => {
+                *op = BinOp::BitAnd;
+                // `k` is at most 63, so the mask always fits
+                **right = Expr::IntLiteral(i64::MAX >> (63 - k), span);
+            }
+            _ => return,
+        }
+        self.reduced += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use flare::Flare;
+
+    use super::*;
+
+    fn reduced_body(source: &str) -> Vec<Stmt<'_>> {
+        let program = Flare::compile_from_string(source).unwrap();
+        let Some(Stmt::Kernel(kernel)) = program.items.into_iter().next() else {
+            panic!("expected a kernel");
+        };
+        let mut kernel = kernel;
+        reduce_strength(&mut kernel);
+        kernel.body
+    }
+
+    fn let_value<'a, 'src>(stmts: &'a [Stmt<'src>], name: &str) -> &'a Expr<'src> {
+        stmts
+            .iter()
+            .find_map(|stmt| match stmt {
+                Stmt::Let { name: n, value, .. } if *n == name => Some(value),
+                _ => None,
+            })
+            .expect("missing binding")
+    }
+
+    fn shape(expr: &Expr) -> Option<(BinOp, i64)> {
+        match expr {
+            Expr::Binary { op, right, .. } => match right.as_ref() {
+                Expr::IntLiteral(n, _) => Some((*op, *n)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_strength_reduction_mul_div_mod() {
+        let source = r#"
+            kernel k(A: Tensor<f32, [N]>, n: u32, s: i32) {
+                let a = n * 8
+                let b = 16 * s
+                let c = n / 4
+                let d = n % 32
+                let e = s / 4
+                let f = s % 32
+                let g = n * 6
+                let h = (n - 1u) / 4
+            }
+        "#;
+        let body = reduced_body(source);
+
+        assert_eq!(shape(let_value(&body, "a")), Some((BinOp::Shl, 3)));
+        // shifting a negative value left is undefined
+        assert!(matches!(
+            let_value(&body, "b"),
+            Expr::Binary { op: BinOp::Mul, .. }
+        ));
+        assert_eq!(shape(let_value(&body, "c")), Some((BinOp::Shr, 2)));
+        assert_eq!(shape(let_value(&body, "d")), Some((BinOp::BitAnd, 31)));
+        // signed operands keep the division semantics
+        assert_eq!(shape(let_value(&body, "e")), Some((BinOp::Div, 4)));
+        assert_eq!(shape(let_value(&body, "f")), Some((BinOp::Mod, 32)));
+        assert_eq!(shape(let_value(&body, "g")), Some((BinOp::Mul, 6)));
+        // unsigned subtraction wraps instead of going negative
+        assert_eq!(shape(let_value(&body, "h")), Some((BinOp::Shr, 2)));
+    }
+
+    #[test]
+    fn test_strength_reduction_loop_index() {
+        let source = r#"
+            kernel k(A: Tensor<f32, [N]>) {
+                for i in 0..N {
+                    let row = i / 8
+                    let col = i % 8
+                    let offset = 4 * i
+                }
+            }
+        "#;
+        let body = reduced_body(source);
+        let Stmt::For { body, .. } = &body[0] else {
+            panic!("expected a for loop");
+        };
+        let Stmt::Block { statements, .. } = body.as_ref() else {
+            panic!("expected a loop body block");
+        };
+
+        assert_eq!(shape(let_value(statements, "row")), Some((BinOp::Shr, 3)));
+        assert_eq!(
+            shape(let_value(statements, "col")),
+            Some((BinOp::BitAnd, 7))
+        );
+        assert_eq!(
+            shape(let_value(statements, "offset")),
+            Some((BinOp::Shl, 2))
+        );
+    }
+
+    #[test]
+    fn test_strength_reduction_largest_power_of_two() {
+        let source = r#"
+            kernel k(A: Tensor<f32, [N]>) {
+                let i = thread_idx.x % 9223372036854775808u
+                let j = thread_idx.x / 9223372036854775808u
+            }
+        "#;
+        let body = reduced_body(source);
+
+        assert_eq!(
+            shape(let_value(&body, "i")),
+            Some((BinOp::BitAnd, i64::MAX))
+        );
+        assert_eq!(shape(let_value(&body, "j")), Some((BinOp::Shr, 63)));
+    }
+}
@@ -0,0 +1,376 @@
+use super::error::{LoweringError, Result};
+use flare::ast::{walk_expr, walk_expr_mut, BinOp, Expr, KernelDef, Stmt, Visitor, VisitorMut};
+use std::ops::Range;
+
+/// Unroll-and-jam: for every perfect two-level nest
+/// `for i in a..b { for j in .. { body } }`, steps the outer loop by `factor`
+/// and replicates `body` inside the single inner loop with `i` replaced by
+/// `i + 1`, ..., `i + factor - 1`. A remainder nest runs the last
+/// `(b - a) % factor` outer iterations unchanged.
+pub fn unroll_and_jam(kernel: &mut KernelDef, factor: i64) -> Result<()> {
+    if factor < 1 {
+        return Err(LoweringError::lowering_error(
+            format!("unroll_jam factor must be positive, got {}", factor),
+            kernel.span.clone(),
+        ));
+    }
+    if factor == 1 {
+        return Ok(());
+    }
+
+    let mut jammed = 0;
+    if let Some(compute) = &mut kernel.compute {
+        jammed += jam_in_list(compute, factor);
+    }
+    jammed += jam_in_list(&mut kernel.body, factor);
+
+    if jammed == 0 {
+        return Err(LoweringError::lowering_error(
+            format!(
+                "unroll_jam on kernel '{}' needs a perfectly nested two-level for loop",
+                kernel.name
+            ),
+            kernel.span.clone(),
+        ));
+    }
+    Ok(())
+}
+
+fn jam_in_list(stmts: &mut Vec<Stmt>, factor: i64) -> usize {
+    let mut jammed = 0;
+    let mut i = 0;
+    while i < stmts.len() {
+        match jam_nest(&stmts[i], factor) {
+            Some(replacement) => {
+                let count = replacement.len();
+                stmts.splice(i..=i, replacement);
+                i += count;
+                jammed += 1;
+            }
+            None => {
+                jammed += jam_nested(&mut stmts[i], factor);
+                i += 1;
+            }
+        }
+    }
+    jammed
+}
+
+fn jam_nested(stmt: &mut Stmt, factor: i64) -> usize {
+    match stmt {
+        Stmt::Block { statements, .. } => jam_in_list(statements, factor),
+        Stmt::If {
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            let mut jammed = jam_nested(then_branch, factor);
+            if let Some(else_stmt) = else_branch {
+                jammed += jam_nested(else_stmt, factor);
+            }
+            jammed
+        }
+        Stmt::While { body, .. } | Stmt::For { body, .. } | Stmt::Staged { stmt: body, .. } => {
+            jam_nested(body, factor)
+        }
+        _ => 0,
+    }
+}
+
+/// Rewrites `stmt` into the jammed main nest plus its remainder nest, or
+/// returns `None` when it is not a nest the transform applies to.
+fn jam_nest<'src>(stmt: &Stmt<'src>, factor: i64) -> Option<Vec<Stmt<'src>>> {
+    let Stmt::For {
+        label: None,
+        var,
+        iterator:
+            Expr::Range {
+                start,
+                end: Some(end),
+                step: None,
+                span: range_span,
+            },
+        body,
+        span,
+    } = stmt
+    else {
+        return None;
+    };
+
+    let inner = match body.as_ref() {
+        Stmt::Block { statements, .. } if statements.len() == 1 => &statements[0],
+        other => other,
+    };
+    let Stmt::For {
+        var: inner_var,
+        iterator: inner_iterator,
+        body: inner_body,
+        ..
+    } = inner
+    else {
+        return None;
+    };
+
+    // the copies share one inner loop, so its bounds must not depend on the
+    // outer index, and jumps or writes to the index would skip or alias
+    // another copy
+    let mut check = NestCheck {
+        var,
+        reads_var: false,
+        writes_var: false,
+        jumps: false,
+    };
+    check.visit_expr(inner_iterator);
+    if *inner_var == *var || check.reads_var {
+        return None;
+    }
+    check.visit_stmt(inner_body);
+    if check.writes_var || check.jumps {
+        return None;
+    }
+
+    let start_value = match start.as_deref() {
+        None => Some(0),
+        Some(Expr::IntLiteral(n, _)) => Some(*n),
+        Some(_) => None,
+    };
+    let end_value = match end.as_ref() {
+        Expr::IntLiteral(n, _) => Some(*n),
+        _ => None,
+    };
+
+    let (main_end, remainder) = match (start_value, end_value) {
+        (Some(lo), Some(hi)) => {
+            let trips = (hi - lo).max(0);
+            let main_end = lo + trips - trips % factor;
+            (
+                (main_end > lo).then(|| Expr::IntLiteral(main_end, range_span.clone())),
+                (main_end < hi).then(|| Expr::IntLiteral(main_end, range_span.clone())),
+            )
+        }
+        _ => {
+            // b - (b - a) % factor, or b - b % factor when a is zero
+            let trips = match start.as_deref() {
+                Some(start) if start_value != Some(0) => {
+                    binary(end.as_ref().clone(), BinOp::Sub, start.clone())
+                }
+                _ => end.as_ref().clone(),
+            };
+            let leftover = binary(trips, BinOp::Mod, int(factor, range_span));
+            let main_end = binary(end.as_ref().clone(), BinOp::Sub, leftover);
+            (Some(main_end.clone()), Some(main_end))
+        }
+    };
+
+    let mut nest = Vec::new();
+
+    if let Some(main_end) = main_end {
+        let copies = (0..factor)
+            .map(|k| {
+                let mut copy = inner_body.as_ref().clone();
+                if k > 0 {
+                    let mut offset = OffsetIndex {
+                        var,
+                        offset: k,
+                        span: range_span.clone(),
+                    };
+                    offset.visit_stmt_mut(&mut copy);
+                }
+                copy
+            })
+            .collect();
+        let jammed_inner = set_body(
+            inner,
+            Stmt::Block {
+                statements: copies,
+                span: inner_body.span(),
+            },
+        );
+        nest.push(Stmt::For {
+            label: None,
+            var,
+            iterator: Expr::Range {
+                start: start.clone(),
+                end: Some(Box::new(main_end)),
+                step: Some(Box::new(int(factor, range_span))),
+                span: range_span.clone(),
+            },
+            body: Box::new(jammed_inner),
+            span: span.clone(),
+        });
+    }
+
+    if let Some(remainder_start) = remainder {
+        nest.push(Stmt::For {
+            label: None,
+            var,
+            iterator: Expr::Range {
+                start: Some(Box::new(remainder_start)),
+                end: Some(end.clone()),
+                step: None,
+                span: range_span.clone(),
+            },
+            body: body.clone(),
+            span: span.clone(),
+        });
+    }
+
+    Some(nest)
+}
+
+fn set_body<'src>(for_stmt: &Stmt<'src>, new_body: Stmt<'src>) -> Stmt<'src> {
+    let mut stmt = for_stmt.clone();
+    if let Stmt::For { body, .. } = &mut stmt {
+        **body = new_body;
+    }
+    stmt
+}
+
+fn int<'src>(value: i64, span: &Range<usize>) -> Expr<'src> {
+    Expr::IntLiteral(value, span.clone())
+}
+
+fn binary<'src>(left: Expr<'src>, op: BinOp, right: Expr<'src>) -> Expr<'src> {
+    let span = left.span().start..right.span().end;
+    Expr::Binary {
+        left: Box::new(left),
+        op,
+        right: Box::new(right),
+        span,
+    }
+}
+
+struct NestCheck<'a> {
+    var: &'a str,
+    reads_var: bool,
+    writes_var: bool,
+    jumps: bool,
+}
+
+impl<'src> Visitor<'src> for NestCheck<'_> {
+    fn visit_stmt(&mut self, stmt: &Stmt<'src>) {
+        match stmt {
+            Stmt::Break { .. } | Stmt::Continue { .. } | Stmt::Return { .. } => {
+                self.jumps = true;
+            }
+            Stmt::Let { name, .. } | Stmt::Var { name, .. } | Stmt::Const { name, .. }
+                if *name == self.var =>
+            {
+                self.writes_var = true;
+            }
+            Stmt::For { var, .. } if *var == self.var => self.writes_var = true,
+            _ => {}
+        }
+        flare::ast::walk_stmt(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr<'src>) {
+        match expr {
+            Expr::Ident(name, _) if *name == self.var => self.reads_var = true,
+            Expr::Assign { target, .. } | Expr::CompoundAssign { target, .. } => {
+                if matches!(target.as_ref(), Expr::Ident(name, _) if *name == self.var) {
+                    self.writes_var = true;
+                }
+            }
+            _ => {}
+        }
+        walk_expr(self, expr);
+    }
+}
+
+/// Replaces reads of the outer index `var` with `var + offset`.
+struct OffsetIndex<'a> {
+    var: &'a str,
+    offset: i64,
+    span: Range<usize>,
+}
+
+impl<'src> VisitorMut<'src> for OffsetIndex<'_> {
+    fn visit_expr_mut(&mut self, expr: &mut Expr<'src>) {
+        match expr {
+            Expr::Ident(name, span) if *name == self.var => {
+                let ident = Expr::Ident(name, span.clone());
+                *expr = binary(ident, BinOp::Add, int(self.offset, &self.span));
+            }
+            _ => walk_expr_mut(self, expr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use flare::Flare;
+
+    use super::*;
+
+    fn kernel_from(source: &str) -> KernelDef<'_> {
+        let program = Flare::compile_from_string(source).unwrap();
+        match program.items.into_iter().next() {
+            Some(Stmt::Kernel(kernel)) => kernel,
+            _ => panic!("expected a kernel"),
+        }
+    }
+
+    fn range_bounds(stmt: &Stmt) -> (Option<i64>, Option<i64>, Option<i64>) {
+        let literal = |e: &Option<Box<Expr>>| match e.as_deref() {
+            Some(Expr::IntLiteral(n, _)) => Some(*n),
+            _ => None,
+        };
+        match stmt {
+            Stmt::For {
+                iterator: Expr::Range {
+                    start, end, step, ..
+                },
+                ..
+            } => (literal(start), literal(end), literal(step)),
+            _ => panic!("expected a range for loop"),
+        }
+    }
+
+    #[test]
+    fn test_unroll_jam_splits_remainder() {
+        let source = r#"
+            kernel nest(A: Tensor<f32, [N]>) {
+                for i in 0..7 {
+                    for j in 0..N {
+                        A[j] = A[j] + i
+                    }
+                }
+            }
+        "#;
+        let mut kernel = kernel_from(source);
+        unroll_and_jam(&mut kernel, 2).unwrap();
+
+        assert_eq!(kernel.body.len(), 2);
+        assert_eq!(range_bounds(&kernel.body[0]), (Some(0), Some(6), Some(2)));
+        assert_eq!(range_bounds(&kernel.body[1]), (Some(6), Some(7), None));
+
+        let Stmt::For { body, .. } = &kernel.body[0] else {
+            panic!("expected the jammed loop");
+        };
+        let Stmt::For {
+            body: inner_body, ..
+        } = body.as_ref()
+        else {
+            panic!("expected the fused inner loop");
+        };
+        let Stmt::Block { statements, .. } = inner_body.as_ref() else {
+            panic!("expected the jammed body");
+        };
+        assert_eq!(statements.len(), 2);
+    }
+
+    #[test]
+    fn test_unroll_jam_rejects_missing_nest() {
+        let source = r#"
+            kernel flat(A: Tensor<f32, [N]>) {
+                for i in 0..N {
+                    A[i] = 0.0
+                }
+            }
+        "#;
+        let mut kernel = kernel_from(source);
+        assert!(unroll_and_jam(&mut kernel, 2).is_err());
+        assert!(unroll_and_jam(&mut kernel, 0).is_err());
+    }
+}
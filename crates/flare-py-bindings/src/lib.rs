@@ -1,6 +1,8 @@
-use flare::Flare;
-use flare_codegen_metal::compile as compile_metal;
-use pyo3::exceptions::PyRuntimeError;
+use flare::{Flare, FlareError, Lexer, LineMap};
+use flare_codegen_metal::{
+    compile as compile_metal, compile_with_report, CodegenOptions, OptLevel,
+};
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
 use pyo3::prelude::*;
 
 #[pyclass]
@@ -20,6 +22,83 @@ impl FlareCompiler {
             .map_err(|e| PyRuntimeError::new_err(format!("failed to generate Metal : {:?}", e)))?;
         Ok(metal_code)
     }
+
+    /// Compiles `source` at `opt_level` (0, 1 or 2), returning the Metal code
+    /// and one line per kind of optimization applied.
+    pub fn compile_to_metal_with_report(
+        &self,
+        source: &str,
+        opt_level: u8,
+    ) -> PyResult<(String, Vec<String>)> {
+        let opt_level = match opt_level {
+            0 => OptLevel::O0,
+            1 => OptLevel::O1,
+            2 => OptLevel::O2,
+            other => {
+                return Err(PyValueError::new_err(format!(
+                    "unknown optimization level {}, expected 0, 1 or 2",
+                    other
+                )))
+            }
+        };
+        let program = Flare::compile_from_string(source)
+            .map_err(|e| PyRuntimeError::new_err(format!("failed to parse kernel: {:?}", e)))?;
+        let options = CodegenOptions {
+            opt_level,
+            ..CodegenOptions::default()
+        };
+        let (metal_code, report) = compile_with_report(&program, options)
+            .map_err(|e| PyRuntimeError::new_err(format!("failed to generate Metal : {:?}", e)))?;
+        Ok((metal_code, report.lines()))
+    }
+
+    /// Runs `Flare::check` on `source` without generating code, returning
+    /// the messages of its warnings. Raises with every error found, type
+    /// and shape errors included, one per line, each prefixed with its
+    /// `line:column`.
+    pub fn check(&self, source: &str) -> PyResult<Vec<String>> {
+        match Flare::check(source) {
+            Ok(warnings) => Ok(warnings.into_iter().map(|w| w.message).collect()),
+            Err(errors) => {
+                let line_map = LineMap::new(source);
+                let messages: Vec<String> = errors
+                    .iter()
+                    .map(|error| render_error(&line_map, error))
+                    .collect();
+                Err(PyValueError::new_err(messages.join("\n")))
+            }
+        }
+    }
+
+    /// Lexes `source` into `(kind_name, span_start, span_end)` tuples for
+    /// syntax highlighting.
+    pub fn tokenize(&self, source: &str) -> PyResult<Vec<(String, usize, usize)>> {
+        tokenize_source(source).map_err(|e| match e {
+            FlareError::InvalidToken { error, span } => PyValueError::new_err(format!(
+                "invalid token at {}..{}: {}",
+                span.start, span.end, error
+            )),
+            other => PyRuntimeError::new_err(format!("failed to tokenize: {:?}", other)),
+        })
+    }
+}
+
+fn render_error(line_map: &LineMap, error: &FlareError) -> String {
+    let (line, col) = line_map.line_col(error.span().start);
+    format!("{}:{}: {}", line, col, error)
+}
+
+fn tokenize_source(source: &str) -> Result<Vec<(String, usize, usize)>, FlareError> {
+    let mut lexer = Lexer::new(source);
+    let mut tokens = Vec::new();
+    while let Some(token) = lexer.peek() {
+        let token = token?;
+        // `Identifier("x")` reports as `Identifier`
+        let kind = format!("{:?}", token.kind);
+        let name = kind.split('(').next().unwrap_or_default().to_string();
+        tokens.push((name, token.span.start, token.span.end));
+    }
+    Ok(tokens)
 }
 
 #[pymodule]
@@ -27,3 +106,34 @@ fn flare_py_bindings(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<FlareCompiler>()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize_kind_names() {
+        let tokens = tokenize_source("kernel f() {}").expect("failed to tokenize");
+        let kinds: Vec<&str> = tokens.iter().map(|(kind, _, _)| kind.as_str()).collect();
+        assert_eq!(
+            kinds,
+            [
+                "Kernel",
+                "Identifier",
+                "LeftParen",
+                "RightParen",
+                "LeftBrace",
+                "RightBrace"
+            ]
+        );
+        assert_eq!(tokens[1], ("Identifier".to_string(), 7, 8));
+    }
+
+    #[test]
+    fn test_render_error_line_col() {
+        let source = "kernel k() {\n    let x = 1 +\n}";
+        let error = Flare::compile_from_string(source).unwrap_err();
+        let rendered = render_error(&LineMap::new(source), &error);
+        assert!(rendered.starts_with("3:1: "), "{}", rendered);
+    }
+}
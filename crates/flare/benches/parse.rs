@@ -0,0 +1,58 @@
+//! Parse time of a large generated source, with the token buffer reserved
+//! from the source length (`Parser::new`) and grown on demand.
+//!
+//! Run with `cargo bench -p flare --bench parse`.
+
+use flare::Parser;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const KERNELS: usize = 2000;
+const ROUNDS: usize = 20;
+
+fn synthetic_source() -> String {
+    let mut source = String::new();
+    for i in 0..KERNELS {
+        source.push_str(&format!(
+            "kernel k{i}(A: Tensor<f32, [N]>, B: Tensor<f32, [N]>, C: Tensor<f32, [N]>) {{\n    \
+             let idx = block_idx.x * block_dim.x + thread_idx.x\n    \
+             if idx < N {{\n        \
+             C[idx] = A[idx] * {i}.0 + B[idx] / 2.0\n    \
+             }}\n\
+             }}\n\n"
+        ));
+    }
+    source
+}
+
+fn parse_reserved(source: &str) {
+    let mut parser = Parser::new(source).unwrap();
+    black_box(parser.parse().unwrap());
+}
+
+fn parse_grown(source: &str) {
+    let mut parser = Parser::with_token_capacity(source, 0).unwrap();
+    black_box(parser.parse().unwrap());
+}
+
+/// Best of `ROUNDS` runs, interleaving the two variants so neither one
+/// benefits from a warmer cache or allocator.
+fn main() {
+    let source = synthetic_source();
+
+    let mut reserved = Duration::MAX;
+    let mut grown = Duration::MAX;
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        parse_reserved(&source);
+        reserved = reserved.min(start.elapsed());
+
+        let start = Instant::now();
+        parse_grown(&source);
+        grown = grown.min(start.elapsed());
+    }
+
+    println!("source: {} bytes, {} kernels", source.len(), KERNELS);
+    println!("reserved capacity: {:?}", reserved);
+    println!("default growth:    {:?}", grown);
+}
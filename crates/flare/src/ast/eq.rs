@@ -0,0 +1,494 @@
+use super::{
+    Attribute, ConstParam, Expr, FusionBlock, KernelDef, Param, ScheduleBlock, ScheduleDirective,
+    SharedMemoryDecl, Stmt, Type,
+};
+
+/// Equality that ignores the source spans the derived `PartialEq` compares,
+/// so the same code written in two places compares equal.
+pub(crate) trait SemanticEq {
+    fn semantic_eq(&self, other: &Self) -> bool;
+}
+
+impl<T: SemanticEq + ?Sized> SemanticEq for Box<T> {
+    fn semantic_eq(&self, other: &Self) -> bool {
+        (**self).semantic_eq(other)
+    }
+}
+
+impl<T: SemanticEq> SemanticEq for Option<T> {
+    fn semantic_eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Some(a), Some(b)) => a.semantic_eq(b),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+}
+
+impl<T: SemanticEq> SemanticEq for [T] {
+    fn semantic_eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().zip(other).all(|(a, b)| a.semantic_eq(b))
+    }
+}
+
+impl<T: SemanticEq> SemanticEq for Vec<T> {
+    fn semantic_eq(&self, other: &Self) -> bool {
+        self.as_slice().semantic_eq(other.as_slice())
+    }
+}
+
+impl SemanticEq for Type<'_> {
+    fn semantic_eq(&self, other: &Self) -> bool {
+        self.same_as(other)
+    }
+}
+
+impl SemanticEq for Attribute<'_> {
+    fn semantic_eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.args == other.args
+    }
+}
+
+impl SemanticEq for Param<'_> {
+    fn semantic_eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.ty.same_as(&other.ty)
+            && self.attributes.semantic_eq(&other.attributes)
+    }
+}
+
+impl SemanticEq for ConstParam<'_> {
+    fn semantic_eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.ty.same_as(&other.ty)
+    }
+}
+
+impl SemanticEq for SharedMemoryDecl<'_> {
+    fn semantic_eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.shape.semantic_eq(&other.shape)
+            && self.ty.semantic_eq(&other.ty)
+    }
+}
+
+impl SemanticEq for KernelDef<'_> {
+    fn semantic_eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.generic_params == other.generic_params
+            && self.const_params.semantic_eq(&other.const_params)
+            && self.params.semantic_eq(&other.params)
+            && self.return_type.semantic_eq(&other.return_type)
+            && self.constraints.semantic_eq(&other.constraints)
+            && self.grid.semantic_eq(&other.grid)
+            && self.block.semantic_eq(&other.block)
+            && self.shared_memory.semantic_eq(&other.shared_memory)
+            && self.compute.semantic_eq(&other.compute)
+            && self.body.semantic_eq(&other.body)
+            && self.attributes.semantic_eq(&other.attributes)
+    }
+}
+
+impl SemanticEq for FusionBlock<'_> {
+    fn semantic_eq(&self, other: &Self) -> bool {
+        self.targets == other.targets
+            && self.strategy == other.strategy
+            && self.barriers == other.barriers
+    }
+}
+
+impl SemanticEq for ScheduleDirective<'_> {
+    fn semantic_eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (ScheduleDirective::Hints(a), ScheduleDirective::Hints(b)) => a.semantic_eq(b),
+            _ => self == other,
+        }
+    }
+}
+
+impl SemanticEq for ScheduleBlock<'_> {
+    fn semantic_eq(&self, other: &Self) -> bool {
+        self.target == other.target
+            && self.mode == other.mode
+            && self.directives.semantic_eq(&other.directives)
+    }
+}
+
+impl SemanticEq for Expr<'_> {
+    fn semantic_eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Expr::IntLiteral(a, _), Expr::IntLiteral(b, _)) => a == b,
+            (Expr::UIntLiteral(a, _), Expr::UIntLiteral(b, _)) => a == b,
+            (Expr::FloatLiteral(a, _), Expr::FloatLiteral(b, _)) => a == b,
+            (Expr::StringLiteral(a, _), Expr::StringLiteral(b, _)) => a == b,
+            (Expr::BoolLiteral(a, _), Expr::BoolLiteral(b, _)) => a == b,
+            (Expr::Ident(a, _), Expr::Ident(b, _)) => a == b,
+            (
+                Expr::Binary {
+                    left: a_left,
+                    op: a_op,
+                    right: a_right,
+                    ..
+                },
+                Expr::Binary {
+                    left: b_left,
+                    op: b_op,
+                    right: b_right,
+                    ..
+                },
+            ) => a_op == b_op && a_left.semantic_eq(b_left) && a_right.semantic_eq(b_right),
+            (
+                Expr::Unary {
+                    op: a_op, expr: a, ..
+                },
+                Expr::Unary {
+                    op: b_op, expr: b, ..
+                },
+            ) => a_op == b_op && a.semantic_eq(b),
+            (
+                Expr::Call {
+                    func: a_func,
+                    args: a_args,
+                    ..
+                },
+                Expr::Call {
+                    func: b_func,
+                    args: b_args,
+                    ..
+                },
+            ) => a_func.semantic_eq(b_func) && a_args.semantic_eq(b_args),
+            (
+                Expr::Member {
+                    object: a,
+                    field: a_field,
+                    ..
+                },
+                Expr::Member {
+                    object: b,
+                    field: b_field,
+                    ..
+                },
+            ) => a_field == b_field && a.semantic_eq(b),
+            (
+                Expr::Index {
+                    object: a,
+                    indices: a_indices,
+                    ..
+                },
+                Expr::Index {
+                    object: b,
+                    indices: b_indices,
+                    ..
+                },
+            ) => a.semantic_eq(b) && a_indices.semantic_eq(b_indices),
+            (
+                Expr::Range {
+                    start: a_start,
+                    end: a_end,
+                    step: a_step,
+                    ..
+                },
+                Expr::Range {
+                    start: b_start,
+                    end: b_end,
+                    step: b_step,
+                    ..
+                },
+            ) => {
+                a_start.semantic_eq(b_start)
+                    && a_end.semantic_eq(b_end)
+                    && a_step.semantic_eq(b_step)
+            }
+            (Expr::Array { elements: a, .. }, Expr::Array { elements: b, .. }) => a.semantic_eq(b),
+            (
+                Expr::TensorInit {
+                    dtype: a_dtype,
+                    shape: a_shape,
+                    ..
+                },
+                Expr::TensorInit {
+                    dtype: b_dtype,
+                    shape: b_shape,
+                    ..
+                },
+            ) => a_dtype.same_as(b_dtype) && a_shape.semantic_eq(b_shape),
+            (
+                Expr::If {
+                    condition: a_cond,
+                    then_branch: a_then,
+                    else_branch: a_else,
+                    ..
+                },
+                Expr::If {
+                    condition: b_cond,
+                    then_branch: b_then,
+                    else_branch: b_else,
+                    ..
+                },
+            ) => {
+                a_cond.semantic_eq(b_cond)
+                    && a_then.semantic_eq(b_then)
+                    && a_else.semantic_eq(b_else)
+            }
+            (Expr::Block { statements: a, .. }, Expr::Block { statements: b, .. }) => {
+                a.semantic_eq(b)
+            }
+            (
+                Expr::Assign {
+                    target: a_target,
+                    value: a_value,
+                    ..
+                },
+                Expr::Assign {
+                    target: b_target,
+                    value: b_value,
+                    ..
+                },
+            ) => a_target.semantic_eq(b_target) && a_value.semantic_eq(b_value),
+            (
+                Expr::CompoundAssign {
+                    target: a_target,
+                    op: a_op,
+                    value: a_value,
+                    ..
+                },
+                Expr::CompoundAssign {
+                    target: b_target,
+                    op: b_op,
+                    value: b_value,
+                    ..
+                },
+            ) => a_op == b_op && a_target.semantic_eq(b_target) && a_value.semantic_eq(b_value),
+            (
+                Expr::Cast {
+                    expr: a,
+                    target_type: a_ty,
+                    ..
+                },
+                Expr::Cast {
+                    expr: b,
+                    target_type: b_ty,
+                    ..
+                },
+            ) => a_ty.same_as(b_ty) && a.semantic_eq(b),
+            (
+                Expr::Bitcast {
+                    expr: a,
+                    target_type: a_ty,
+                    ..
+                },
+                Expr::Bitcast {
+                    expr: b,
+                    target_type: b_ty,
+                    ..
+                },
+            ) => a_ty.same_as(b_ty) && a.semantic_eq(b),
+            (Expr::SizeOf { ty: a, .. }, Expr::SizeOf { ty: b, .. }) => a.same_as(b),
+            (Expr::ThreadIdx { dim: a, .. }, Expr::ThreadIdx { dim: b, .. })
+            | (Expr::BlockIdx { dim: a, .. }, Expr::BlockIdx { dim: b, .. })
+            | (Expr::BlockDim { dim: a, .. }, Expr::BlockDim { dim: b, .. }) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl SemanticEq for Stmt<'_> {
+    fn semantic_eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Stmt::Kernel(a), Stmt::Kernel(b)) => a.semantic_eq(b),
+            (Stmt::Fusion(a), Stmt::Fusion(b)) => a.semantic_eq(b),
+            (Stmt::Schedule(a), Stmt::Schedule(b)) => a.semantic_eq(b),
+            (
+                Stmt::Function {
+                    name: a_name,
+                    params: a_params,
+                    return_type: a_ret,
+                    body: a_body,
+                    attributes: a_attrs,
+                    receiver: a_receiver,
+                    ..
+                },
+                Stmt::Function {
+                    name: b_name,
+                    params: b_params,
+                    return_type: b_ret,
+                    body: b_body,
+                    attributes: b_attrs,
+                    receiver: b_receiver,
+                    ..
+                },
+            ) => {
+                a_name == b_name
+                    && a_receiver == b_receiver
+                    && a_params.semantic_eq(b_params)
+                    && a_ret.semantic_eq(b_ret)
+                    && a_body.semantic_eq(b_body)
+                    && a_attrs.semantic_eq(b_attrs)
+            }
+            (
+                Stmt::Let {
+                    name: a_name,
+                    ty: a_ty,
+                    value: a_value,
+                    attributes: a_attrs,
+                    ..
+                },
+                Stmt::Let {
+                    name: b_name,
+                    ty: b_ty,
+                    value: b_value,
+                    attributes: b_attrs,
+                    ..
+                },
+            )
+            | (
+                Stmt::Const {
+                    name: a_name,
+                    ty: a_ty,
+                    value: a_value,
+                    attributes: a_attrs,
+                    ..
+                },
+                Stmt::Const {
+                    name: b_name,
+                    ty: b_ty,
+                    value: b_value,
+                    attributes: b_attrs,
+                    ..
+                },
+            ) => {
+                a_name == b_name
+                    && a_ty.semantic_eq(b_ty)
+                    && a_value.semantic_eq(b_value)
+                    && a_attrs.semantic_eq(b_attrs)
+            }
+            (
+                Stmt::Var {
+                    name: a_name,
+                    ty: a_ty,
+                    value: a_value,
+                    ..
+                },
+                Stmt::Var {
+                    name: b_name,
+                    ty: b_ty,
+                    value: b_value,
+                    ..
+                },
+            ) => a_name == b_name && a_ty.semantic_eq(b_ty) && a_value.semantic_eq(b_value),
+            (
+                Stmt::If {
+                    condition: a_cond,
+                    then_branch: a_then,
+                    else_branch: a_else,
+                    ..
+                },
+                Stmt::If {
+                    condition: b_cond,
+                    then_branch: b_then,
+                    else_branch: b_else,
+                    ..
+                },
+            ) => {
+                a_cond.semantic_eq(b_cond)
+                    && a_then.semantic_eq(b_then)
+                    && a_else.semantic_eq(b_else)
+            }
+            (
+                Stmt::While {
+                    label: a_label,
+                    condition: a_cond,
+                    body: a_body,
+                    ..
+                },
+                Stmt::While {
+                    label: b_label,
+                    condition: b_cond,
+                    body: b_body,
+                    ..
+                },
+            ) => a_label == b_label && a_cond.semantic_eq(b_cond) && a_body.semantic_eq(b_body),
+            (
+                Stmt::For {
+                    label: a_label,
+                    var: a_var,
+                    iterator: a_iter,
+                    body: a_body,
+                    ..
+                },
+                Stmt::For {
+                    label: b_label,
+                    var: b_var,
+                    iterator: b_iter,
+                    body: b_body,
+                    ..
+                },
+            ) => {
+                a_label == b_label
+                    && a_var == b_var
+                    && a_iter.semantic_eq(b_iter)
+                    && a_body.semantic_eq(b_body)
+            }
+            (Stmt::Return { value: a, .. }, Stmt::Return { value: b, .. }) => a.semantic_eq(b),
+            (Stmt::Break { label: a, .. }, Stmt::Break { label: b, .. })
+            | (Stmt::Continue { label: a, .. }, Stmt::Continue { label: b, .. }) => a == b,
+            (Stmt::Expr(a), Stmt::Expr(b)) => a.semantic_eq(b),
+            (Stmt::Block { statements: a, .. }, Stmt::Block { statements: b, .. }) => {
+                a.semantic_eq(b)
+            }
+            (Stmt::SyncThreads { .. }, Stmt::SyncThreads { .. }) => true,
+            (
+                Stmt::Staged {
+                    stage: a_stage,
+                    stmt: a_stmt,
+                    ..
+                },
+                Stmt::Staged {
+                    stage: b_stage,
+                    stmt: b_stmt,
+                    ..
+                },
+            ) => a_stage == b_stage && a_stmt.semantic_eq(b_stmt),
+            (
+                Stmt::LoadShared {
+                    dest: a_dest,
+                    indices: a_indices,
+                    src: a_src,
+                    ..
+                },
+                Stmt::LoadShared {
+                    dest: b_dest,
+                    indices: b_indices,
+                    src: b_src,
+                    ..
+                },
+            ) => a_dest == b_dest && a_indices.semantic_eq(b_indices) && a_src.semantic_eq(b_src),
+            (
+                Stmt::TypeDef {
+                    name: a_name,
+                    ty: a_ty,
+                    ..
+                },
+                Stmt::TypeDef {
+                    name: b_name,
+                    ty: b_ty,
+                    ..
+                },
+            ) => a_name == b_name && a_ty.same_as(b_ty),
+            _ => false,
+        }
+    }
+}
+
+impl Expr<'_> {
+    /// Structural equality ignoring spans, unlike the derived `==`.
+    pub fn semantically_eq(&self, other: &Self) -> bool {
+        self.semantic_eq(other)
+    }
+}
+
+impl Stmt<'_> {
+    /// Structural equality ignoring spans, unlike the derived `==`.
+    pub fn semantically_eq(&self, other: &Self) -> bool {
+        self.semantic_eq(other)
+    }
+}
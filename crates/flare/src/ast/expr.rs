@@ -4,6 +4,7 @@ use std::ops::Range;
 #[derive(Debug, Clone, PartialEq)]
 pub enum Expr<'src> {
     IntLiteral(i64, Range<usize>),
+    UIntLiteral(u64, Range<usize>),
     FloatLiteral(f64, Range<usize>),
     StringLiteral(String, Range<usize>),
     BoolLiteral(bool, Range<usize>),
@@ -44,6 +45,7 @@ pub enum Expr<'src> {
     Range {
         start: Option<Box<Expr<'src>>>,
         end: Option<Box<Expr<'src>>>,
+        step: Option<Box<Expr<'src>>>,
         span: Range<usize>,
     },
 
@@ -89,6 +91,20 @@ pub enum Expr<'src> {
         span: Range<usize>,
     },
 
+    /// `as_type<u32>(x)`: the bits of `x` read as a type of the same size,
+    /// where an `as` cast converts the value.
+    Bitcast {
+        expr: Box<Expr<'src>>,
+        target_type: Type<'src>,
+        span: Range<usize>,
+    },
+
+    /// `sizeof(f32)`: byte size of a type, folded by the backend.
+    SizeOf {
+        ty: Type<'src>,
+        span: Range<usize>,
+    },
+
     ThreadIdx {
         dim: Option<&'src str>,
         span: Range<usize>,
@@ -113,6 +129,11 @@ pub enum BinOp {
     Div,
     Mod,
 
+    // produced by the flare-ir strength reduction pass
+    Shl,
+    Shr,
+    BitAnd,
+
     Equal,
     NotEqual,
     Less,
@@ -128,12 +149,15 @@ pub enum BinOp {
 pub enum UnOp {
     Neg,
     Not,
+    AddrOf,
+    Deref,
 }
 
 impl<'src> Expr<'src> {
     pub fn span(&self) -> Range<usize> {
         match self {
             Expr::IntLiteral(_, span)
+            | Expr::UIntLiteral(_, span)
             | Expr::FloatLiteral(_, span)
             | Expr::StringLiteral(_, span)
             | Expr::BoolLiteral(_, span)
@@ -151,6 +175,8 @@ impl<'src> Expr<'src> {
             | Expr::Assign { span, .. }
             | Expr::CompoundAssign { span, .. }
             | Expr::Cast { span, .. }
+            | Expr::Bitcast { span, .. }
+            | Expr::SizeOf { span, .. }
             | Expr::ThreadIdx { span, .. }
             | Expr::BlockIdx { span, .. }
             | Expr::BlockDim { span, .. } => span.clone(),
@@ -5,8 +5,11 @@ use std::ops::Range;
 pub struct KernelDef<'src> {
     pub name: &'src str,
     pub generic_params: Vec<&'src str>,
+    pub const_params: Vec<ConstParam<'src>>,
     pub params: Vec<Param<'src>>,
     pub return_type: Option<Type<'src>>,
+    /// `where K % 4 == 0, N > 0` constraints on the kernel's dimensions.
+    pub constraints: Vec<Expr<'src>>,
     pub grid: Option<Vec<Expr<'src>>>,
     pub block: Option<Vec<Expr<'src>>>,
     pub shared_memory: Option<Vec<SharedMemoryDecl<'src>>>,
@@ -18,6 +21,14 @@ pub struct KernelDef<'src> {
 
 use super::Stmt;
 
+/// Compile-time numeric kernel parameter, `kernel k<const TILE: i32>`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConstParam<'src> {
+    pub name: &'src str,
+    pub ty: Type<'src>,
+    pub span: Range<usize>,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct SharedMemoryDecl<'src> {
     pub name: &'src str,
@@ -38,4 +49,73 @@ pub enum AttributeArg<'src> {
     Ident(&'src str),
     IntLiteral(i64),
     StringLiteral(String),
+    KeyValue {
+        key: &'src str,
+        value: Box<AttributeArg<'src>>,
+    },
+    List(Vec<AttributeArg<'src>>),
+}
+
+/// Combining operation of an `@all_reduce(buffer, op)` attribute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReduceOp<'src> {
+    Sum,
+    Product,
+    Max,
+    Min,
+    /// A user `fn(a: T, b: T) -> T` named by the attribute.
+    Custom(&'src str),
+}
+
+impl<'src> ReduceOp<'src> {
+    pub fn from_name(name: &'src str) -> Self {
+        match name {
+            "sum" => ReduceOp::Sum,
+            "product" => ReduceOp::Product,
+            "max" => ReduceOp::Max,
+            "min" => ReduceOp::Min,
+            other => ReduceOp::Custom(other),
+        }
+    }
+
+    pub fn name(&self) -> &'src str {
+        match self {
+            ReduceOp::Sum => "sum",
+            ReduceOp::Product => "product",
+            ReduceOp::Max => "max",
+            ReduceOp::Min => "min",
+            ReduceOp::Custom(name) => name,
+        }
+    }
+}
+
+/// `@all_reduce(grads, op)`: combine `grads` across devices with `op`,
+/// or `sum` when the operation is left out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AllReduce<'src> {
+    pub buffer: &'src str,
+    pub op: ReduceOp<'src>,
+    pub span: Range<usize>,
+}
+
+impl<'src> Attribute<'src> {
+    /// This attribute as an all-reduce request, or `None` when it is not an
+    /// `@all_reduce` or its arguments are not `(buffer)` or `(buffer, op)`.
+    pub fn as_all_reduce(&self) -> Option<AllReduce<'src>> {
+        if self.name != "all_reduce" {
+            return None;
+        }
+        let (buffer, op) = match self.args.as_slice() {
+            [AttributeArg::Ident(buffer)] => (*buffer, ReduceOp::Sum),
+            [AttributeArg::Ident(buffer), AttributeArg::Ident(op)] => {
+                (*buffer, ReduceOp::from_name(op))
+            }
+            _ => return None,
+        };
+        Some(AllReduce {
+            buffer,
+            op,
+            span: self.span.clone(),
+        })
+    }
 }
@@ -1,3 +1,4 @@
+mod eq;
 pub mod expr;
 pub mod fusion;
 pub mod kernel;
@@ -5,6 +6,7 @@ pub mod program;
 pub mod schedule;
 pub mod stmt;
 pub mod types;
+pub mod visit;
 
 pub use expr::*;
 pub use fusion::*;
@@ -13,3 +15,4 @@ pub use program::*;
 pub use schedule::*;
 pub use stmt::*;
 pub use types::*;
+pub use visit::*;
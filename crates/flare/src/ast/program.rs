@@ -1,4 +1,4 @@
-use super::Stmt;
+use super::{FusionBlock, KernelDef, ScheduleBlock, Stmt};
 use std::ops::Range;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -6,3 +6,61 @@ pub struct Program<'src> {
     pub items: Vec<Stmt<'src>>,
     pub span: Range<usize>,
 }
+
+impl<'src> Program<'src> {
+    /// Kernel definitions, in source order.
+    pub fn kernels(&self) -> impl Iterator<Item = &KernelDef<'src>> {
+        self.items.iter().filter_map(|item| match item {
+            Stmt::Kernel(kernel) => Some(kernel),
+            _ => None,
+        })
+    }
+
+    pub fn schedules(&self) -> impl Iterator<Item = &ScheduleBlock<'src>> {
+        self.items.iter().filter_map(|item| match item {
+            Stmt::Schedule(schedule) => Some(schedule),
+            _ => None,
+        })
+    }
+
+    pub fn fusions(&self) -> impl Iterator<Item = &FusionBlock<'src>> {
+        self.items.iter().filter_map(|item| match item {
+            Stmt::Fusion(fusion) => Some(fusion),
+            _ => None,
+        })
+    }
+
+    pub fn kernel_by_name(&self, name: &str) -> Option<&KernelDef<'src>> {
+        self.kernels().find(|kernel| kernel.name == name)
+    }
+
+    /// Kernel names `schedule` blocks and then `fuse` blocks refer to, in
+    /// source order within each kind.
+    pub fn targets(&self) -> impl Iterator<Item = Target<'_, 'src>> {
+        let schedules = self.schedules().filter_map(|schedule| {
+            Some(Target {
+                block: "schedule",
+                name: schedule.target?,
+                span: &schedule.span,
+            })
+        });
+        let fusions = self.fusions().flat_map(|fusion| {
+            fusion.targets.iter().map(move |name| Target {
+                block: "fuse",
+                name,
+                span: &fusion.span,
+            })
+        });
+        schedules.chain(fusions)
+    }
+}
+
+/// A kernel name written in a `schedule` or `fuse` block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Target<'a, 'src> {
+    /// `"schedule"` or `"fuse"`.
+    pub block: &'static str,
+    pub name: &'src str,
+    /// Span of the whole block.
+    pub span: &'a Range<usize>,
+}
@@ -1,12 +1,23 @@
+use super::Attribute;
 use std::ops::Range;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct ScheduleBlock<'src> {
     pub target: Option<&'src str>, 
+    pub mode: ScheduleMode,
     pub directives: Vec<ScheduleDirective<'src>>,
     pub span: Range<usize>,
 }
 
+/// Whether the optimization passes may transform the target kernel beyond
+/// the directives listed: `schedule manual k { ... }` applies only those.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ScheduleMode {
+    #[default]
+    Auto,
+    Manual,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum ScheduleDirective<'src> {
     Tile {
@@ -16,6 +27,9 @@ pub enum ScheduleDirective<'src> {
     },
     Vectorize(i64),
     Unroll(i64),
+    /// Unrolls the outer loop of a two-level nest by the factor and fuses
+    /// the copies of the inner loop body.
+    UnrollJam(i64),
     Threads {
         x: i64,
         y: Option<i64>,
@@ -29,6 +43,9 @@ pub enum ScheduleDirective<'src> {
         depth: Option<i64>,
     },
     Parallel,
+    /// Annotations from a `hints { ... }` section, applied to the target
+    /// kernel for the scheduling and fusion passes.
+    Hints(Vec<Attribute<'src>>),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -1,4 +1,4 @@
-use super::{Expr, FusionBlock, KernelDef, ScheduleBlock, Type};
+use super::{Attribute, Expr, FusionBlock, KernelDef, ScheduleBlock, Type};
 use std::ops::Range;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -12,6 +12,10 @@ pub enum Stmt<'src> {
         params: Vec<Param<'src>>,
         return_type: Option<Type<'src>>,
         body: Box<Expr<'src>>,
+        attributes: Vec<Attribute<'src>>,
+        /// Type of the enclosing `impl` block. Methods take the receiver as
+        /// their first parameter and are emitted as `Type_name`.
+        receiver: Option<&'src str>,
         span: Range<usize>,
     },
 
@@ -19,10 +23,12 @@ pub enum Stmt<'src> {
         name: &'src str,
         ty: Option<Type<'src>>,
         value: Expr<'src>,
+        attributes: Vec<Attribute<'src>>,
         span: Range<usize>,
     },
     Var {
         name: &'src str,
+        name_span: Range<usize>,
         ty: Option<Type<'src>>,
         value: Option<Expr<'src>>,
         span: Range<usize>,
@@ -31,6 +37,7 @@ pub enum Stmt<'src> {
         name: &'src str,
         ty: Option<Type<'src>>,
         value: Expr<'src>,
+        attributes: Vec<Attribute<'src>>,
         span: Range<usize>,
     },
 
@@ -41,11 +48,13 @@ pub enum Stmt<'src> {
         span: Range<usize>,
     },
     While {
+        label: Option<&'src str>,
         condition: Expr<'src>,
         body: Box<Stmt<'src>>,
         span: Range<usize>,
     },
     For {
+        label: Option<&'src str>,
         var: &'src str,
         iterator: Expr<'src>,
         body: Box<Stmt<'src>>,
@@ -56,6 +65,14 @@ pub enum Stmt<'src> {
         value: Option<Expr<'src>>,
         span: Range<usize>,
     },
+    Break {
+        label: Option<&'src str>,
+        span: Range<usize>,
+    },
+    Continue {
+        label: Option<&'src str>,
+        span: Range<usize>,
+    },
 
     Expr(Expr<'src>),
 
@@ -68,8 +85,11 @@ pub enum Stmt<'src> {
         span: Range<usize>,
     },
 
+    /// `load_shared(tile[ty, tx], A[row, col])`; `indices` is empty when the
+    /// destination is a bare name.
     LoadShared {
         dest: &'src str,
+        indices: Vec<Expr<'src>>,
         src: Expr<'src>,
         span: Range<usize>,
     },
@@ -79,12 +99,22 @@ pub enum Stmt<'src> {
         ty: Type<'src>,
         span: Range<usize>,
     },
+
+    /// `@stage(n) stmt`: places `stmt` in stage `n` of a hand-written
+    /// software pipeline.
+    Staged {
+        stage: i64,
+        stmt: Box<Stmt<'src>>,
+        span: Range<usize>,
+    },
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Param<'src> {
     pub name: &'src str,
     pub ty: Type<'src>,
+    /// Attributes written before the name, such as `@memory(constant)`.
+    pub attributes: Vec<Attribute<'src>>,
     pub span: Range<usize>,
 }
 
@@ -102,11 +132,22 @@ impl<'src> Stmt<'src> {
             | Stmt::While { span, .. }
             | Stmt::For { span, .. }
             | Stmt::Return { span, .. }
+            | Stmt::Break { span, .. }
+            | Stmt::Continue { span, .. }
             | Stmt::Block { span, .. }
             | Stmt::SyncThreads { span, .. }
             | Stmt::LoadShared { span, .. }
-            | Stmt::TypeDef { span, .. } => span.clone(),
+            | Stmt::TypeDef { span, .. }
+            | Stmt::Staged { span, .. } => span.clone(),
             Stmt::Expr(e) => e.span(),
         }
     }
+
+    /// Pipeline stage assigned by `@stage(n)`, if any.
+    pub fn stage(&self) -> Option<i64> {
+        match self {
+            Stmt::Staged { stage, .. } => Some(*stage),
+            _ => None,
+        }
+    }
 }
@@ -1,29 +1,35 @@
+use super::Expr;
 use std::ops::Range;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Type<'src> {
-    Named(&'src str),
+    Named(&'src str, Range<usize>),
 
-    I32,
-    I64,
-    U32,
-    U64,
-    F32,
-    F64,
-    Bool,
+    I32(Range<usize>),
+    I64(Range<usize>),
+    U32(Range<usize>),
+    U64(Range<usize>),
+    F32(Range<usize>),
+    F64(Range<usize>),
+    Bool(Range<usize>),
 
     Tensor {
         dtype: Box<Type<'src>>,
         shape: Vec<&'src str>,
+        /// Element stride of each dimension, set by a `col_major`/`row_major`
+        /// layout or `@layout`; `None` is row-major.
+        strides: Option<Vec<String>>,
     },
     Matrix {
         dtype: Box<Type<'src>>,
         rows: Option<&'src str>,
         cols: Option<&'src str>,
+        span: Range<usize>,
     },
     Vector {
         dtype: Box<Type<'src>>,
         len: Option<&'src str>,
+        span: Range<usize>,
     },
 
     Ptr(Box<Type<'src>>),
@@ -31,11 +37,124 @@ pub enum Type<'src> {
     Array {
         dtype: Box<Type<'src>>,
         size: Option<usize>,
+        /// A size written as a const expression, such as `f32[TILE]`; `size`
+        /// stays `None` until a backend folds it against program consts.
+        size_expr: Option<Box<Expr<'src>>>,
     },
 }
 
 impl<'src> Type<'src> {
+    /// Source range of a scalar, named, vector or matrix type; tensors,
+    /// pointers and arrays report their element type. Types the compiler
+    /// makes up rather than parses use `0..0`.
     pub fn span(&self) -> Range<usize> {
-        0..0
+        match self {
+            Type::I32(span)
+            | Type::I64(span)
+            | Type::U32(span)
+            | Type::U64(span)
+            | Type::F32(span)
+            | Type::F64(span)
+            | Type::Bool(span)
+            | Type::Named(_, span)
+            | Type::Matrix { span, .. }
+            | Type::Vector { span, .. } => span.clone(),
+            Type::Tensor { dtype, .. } | Type::Ptr(dtype) | Type::Array { dtype, .. } => {
+                dtype.span()
+            }
+        }
     }
+
+    /// Structural equality that ignores where each type was written.
+    pub fn same_as(&self, other: &Type) -> bool {
+        match (self, other) {
+            (Type::Named(a, _), Type::Named(b, _)) => a == b,
+            (
+                Type::Tensor {
+                    dtype: a,
+                    shape: a_shape,
+                    strides: a_strides,
+                },
+                Type::Tensor {
+                    dtype: b,
+                    shape: b_shape,
+                    strides: b_strides,
+                },
+            ) => a.same_as(b) && a_shape == b_shape && a_strides == b_strides,
+            (
+                Type::Matrix {
+                    dtype: a,
+                    rows: a_rows,
+                    cols: a_cols,
+                    ..
+                },
+                Type::Matrix {
+                    dtype: b,
+                    rows: b_rows,
+                    cols: b_cols,
+                    ..
+                },
+            ) => a.same_as(b) && a_rows == b_rows && a_cols == b_cols,
+            (
+                Type::Vector {
+                    dtype: a,
+                    len: a_len,
+                    ..
+                },
+                Type::Vector {
+                    dtype: b,
+                    len: b_len,
+                    ..
+                },
+            ) => a.same_as(b) && a_len == b_len,
+            (Type::Ptr(a), Type::Ptr(b)) => a.same_as(b),
+            (
+                Type::Array {
+                    dtype: a,
+                    size: a_size,
+                    size_expr: a_expr,
+                },
+                Type::Array {
+                    dtype: b,
+                    size: b_size,
+                    size_expr: b_expr,
+                },
+            ) => {
+                let same_expr = match (a_expr, b_expr) {
+                    (Some(a), Some(b)) => a.semantically_eq(b),
+                    (a, b) => a.is_none() && b.is_none(),
+                };
+                a.same_as(b) && a_size == b_size && same_expr
+            }
+            (Type::I32(_), Type::I32(_))
+            | (Type::I64(_), Type::I64(_))
+            | (Type::U32(_), Type::U32(_))
+            | (Type::U64(_), Type::U64(_))
+            | (Type::F32(_), Type::F32(_))
+            | (Type::F64(_), Type::F64(_))
+            | (Type::Bool(_), Type::Bool(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Strides of a densely packed tensor of `shape`; row-major keeps the last
+/// dimension contiguous, column-major the first.
+pub fn contiguous_strides(shape: &[&str], row_major: bool) -> Vec<String> {
+    let mut strides = vec![String::new(); shape.len()];
+    let mut inner: Vec<&str> = Vec::new();
+    let order: Vec<usize> = if row_major {
+        (0..shape.len()).rev().collect()
+    } else {
+        (0..shape.len()).collect()
+    };
+    for i in order {
+        strides[i] = if inner.is_empty() {
+            "1".to_string()
+        } else {
+            inner.join(" * ")
+        };
+        inner.push(shape[i]);
+    }
+    strides
 }
@@ -0,0 +1,322 @@
+use super::{Expr, Stmt};
+
+/// Read-only traversal over the AST. Override `visit_*` to inspect nodes and
+/// call the matching `walk_*` function to keep descending into children.
+pub trait Visitor<'src> {
+    fn visit_stmt(&mut self, stmt: &Stmt<'src>) {
+        walk_stmt(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr<'src>) {
+        walk_expr(self, expr);
+    }
+}
+
+pub fn walk_stmt<'src, V: Visitor<'src> + ?Sized>(visitor: &mut V, stmt: &Stmt<'src>) {
+    match stmt {
+        Stmt::Kernel(kernel) => {
+            for dim in kernel.grid.iter().chain(&kernel.block).flatten() {
+                visitor.visit_expr(dim);
+            }
+            for constraint in &kernel.constraints {
+                visitor.visit_expr(constraint);
+            }
+            for decl in kernel.shared_memory.iter().flatten() {
+                for dim in &decl.shape {
+                    visitor.visit_expr(dim);
+                }
+            }
+            for s in kernel.compute.iter().flatten().chain(&kernel.body) {
+                visitor.visit_stmt(s);
+            }
+        }
+        Stmt::Function { body, .. } => visitor.visit_expr(body),
+        Stmt::Let { value, .. } | Stmt::Const { value, .. } => visitor.visit_expr(value),
+        Stmt::Var { value, .. } => {
+            if let Some(value) = value {
+                visitor.visit_expr(value);
+            }
+        }
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            visitor.visit_expr(condition);
+            visitor.visit_stmt(then_branch);
+            if let Some(else_stmt) = else_branch {
+                visitor.visit_stmt(else_stmt);
+            }
+        }
+        Stmt::While {
+            condition, body, ..
+        } => {
+            visitor.visit_expr(condition);
+            visitor.visit_stmt(body);
+        }
+        Stmt::For { iterator, body, .. } => {
+            visitor.visit_expr(iterator);
+            visitor.visit_stmt(body);
+        }
+        Stmt::Return { value, .. } => {
+            if let Some(value) = value {
+                visitor.visit_expr(value);
+            }
+        }
+        Stmt::Expr(expr) => visitor.visit_expr(expr),
+        Stmt::Block { statements, .. } => {
+            for s in statements {
+                visitor.visit_stmt(s);
+            }
+        }
+        Stmt::Staged { stmt, .. } => visitor.visit_stmt(stmt),
+        Stmt::LoadShared { indices, src, .. } => {
+            for index in indices {
+                visitor.visit_expr(index);
+            }
+            visitor.visit_expr(src);
+        }
+        Stmt::Fusion(_)
+        | Stmt::Schedule(_)
+        | Stmt::Break { .. }
+        | Stmt::Continue { .. }
+        | Stmt::SyncThreads { .. }
+        | Stmt::TypeDef { .. } => {}
+    }
+}
+
+pub fn walk_expr<'src, V: Visitor<'src> + ?Sized>(visitor: &mut V, expr: &Expr<'src>) {
+    match expr {
+        Expr::Binary { left, right, .. } => {
+            visitor.visit_expr(left);
+            visitor.visit_expr(right);
+        }
+        Expr::Unary { expr, .. } | Expr::Cast { expr, .. } | Expr::Bitcast { expr, .. } => {
+            visitor.visit_expr(expr)
+        }
+        Expr::Call { func, args, .. } => {
+            visitor.visit_expr(func);
+            for arg in args {
+                visitor.visit_expr(arg);
+            }
+        }
+        Expr::Member { object, .. } => visitor.visit_expr(object),
+        Expr::Index {
+            object, indices, ..
+        } => {
+            visitor.visit_expr(object);
+            for index in indices {
+                visitor.visit_expr(index);
+            }
+        }
+        Expr::Range {
+            start, end, step, ..
+        } => {
+            for bound in [start, end, step].into_iter().flatten() {
+                visitor.visit_expr(bound);
+            }
+        }
+        Expr::Array { elements, .. } => {
+            for element in elements {
+                visitor.visit_expr(element);
+            }
+        }
+        Expr::TensorInit { shape, .. } => {
+            for dim in shape {
+                visitor.visit_expr(dim);
+            }
+        }
+        Expr::If {
+            condition,
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            visitor.visit_expr(condition);
+            visitor.visit_expr(then_branch);
+            if let Some(else_expr) = else_branch {
+                visitor.visit_expr(else_expr);
+            }
+        }
+        Expr::Block { statements, .. } => {
+            for s in statements {
+                visitor.visit_stmt(s);
+            }
+        }
+        Expr::Assign { target, value, .. } | Expr::CompoundAssign { target, value, .. } => {
+            visitor.visit_expr(target);
+            visitor.visit_expr(value);
+        }
+        Expr::IntLiteral(..)
+        | Expr::UIntLiteral(..)
+        | Expr::FloatLiteral(..)
+        | Expr::StringLiteral(..)
+        | Expr::BoolLiteral(..)
+        | Expr::Ident(..)
+        | Expr::ThreadIdx { .. }
+        | Expr::BlockIdx { .. }
+        | Expr::BlockDim { .. }
+        | Expr::SizeOf { .. } => {}
+    }
+}
+
+/// Mutable counterpart of `Visitor`, for passes that rewrite the AST in place.
+pub trait VisitorMut<'src> {
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt<'src>) {
+        walk_stmt_mut(self, stmt);
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr<'src>) {
+        walk_expr_mut(self, expr);
+    }
+}
+
+pub fn walk_stmt_mut<'src, V: VisitorMut<'src> + ?Sized>(visitor: &mut V, stmt: &mut Stmt<'src>) {
+    match stmt {
+        Stmt::Kernel(kernel) => {
+            for dim in kernel.grid.iter_mut().chain(&mut kernel.block).flatten() {
+                visitor.visit_expr_mut(dim);
+            }
+            for constraint in &mut kernel.constraints {
+                visitor.visit_expr_mut(constraint);
+            }
+            for decl in kernel.shared_memory.iter_mut().flatten() {
+                for dim in &mut decl.shape {
+                    visitor.visit_expr_mut(dim);
+                }
+            }
+            for s in kernel.compute.iter_mut().flatten().chain(&mut kernel.body) {
+                visitor.visit_stmt_mut(s);
+            }
+        }
+        Stmt::Function { body, .. } => visitor.visit_expr_mut(body),
+        Stmt::Let { value, .. } | Stmt::Const { value, .. } => visitor.visit_expr_mut(value),
+        Stmt::Var { value, .. } => {
+            if let Some(value) = value {
+                visitor.visit_expr_mut(value);
+            }
+        }
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            visitor.visit_expr_mut(condition);
+            visitor.visit_stmt_mut(then_branch);
+            if let Some(else_stmt) = else_branch {
+                visitor.visit_stmt_mut(else_stmt);
+            }
+        }
+        Stmt::While {
+            condition, body, ..
+        } => {
+            visitor.visit_expr_mut(condition);
+            visitor.visit_stmt_mut(body);
+        }
+        Stmt::For { iterator, body, .. } => {
+            visitor.visit_expr_mut(iterator);
+            visitor.visit_stmt_mut(body);
+        }
+        Stmt::Return { value, .. } => {
+            if let Some(value) = value {
+                visitor.visit_expr_mut(value);
+            }
+        }
+        Stmt::Expr(expr) => visitor.visit_expr_mut(expr),
+        Stmt::Block { statements, .. } => {
+            for s in statements {
+                visitor.visit_stmt_mut(s);
+            }
+        }
+        Stmt::Staged { stmt, .. } => visitor.visit_stmt_mut(stmt),
+        Stmt::LoadShared { indices, src, .. } => {
+            for index in indices {
+                visitor.visit_expr_mut(index);
+            }
+            visitor.visit_expr_mut(src);
+        }
+        Stmt::Fusion(_)
+        | Stmt::Schedule(_)
+        | Stmt::Break { .. }
+        | Stmt::Continue { .. }
+        | Stmt::SyncThreads { .. }
+        | Stmt::TypeDef { .. } => {}
+    }
+}
+
+pub fn walk_expr_mut<'src, V: VisitorMut<'src> + ?Sized>(visitor: &mut V, expr: &mut Expr<'src>) {
+    match expr {
+        Expr::Binary { left, right, .. } => {
+            visitor.visit_expr_mut(left);
+            visitor.visit_expr_mut(right);
+        }
+        Expr::Unary { expr, .. } | Expr::Cast { expr, .. } | Expr::Bitcast { expr, .. } => {
+            visitor.visit_expr_mut(expr)
+        }
+        Expr::Call { func, args, .. } => {
+            visitor.visit_expr_mut(func);
+            for arg in args {
+                visitor.visit_expr_mut(arg);
+            }
+        }
+        Expr::Member { object, .. } => visitor.visit_expr_mut(object),
+        Expr::Index {
+            object, indices, ..
+        } => {
+            visitor.visit_expr_mut(object);
+            for index in indices {
+                visitor.visit_expr_mut(index);
+            }
+        }
+        Expr::Range {
+            start, end, step, ..
+        } => {
+            for bound in [start, end, step].into_iter().flatten() {
+                visitor.visit_expr_mut(bound);
+            }
+        }
+        Expr::Array { elements, .. } => {
+            for element in elements {
+                visitor.visit_expr_mut(element);
+            }
+        }
+        Expr::TensorInit { shape, .. } => {
+            for dim in shape {
+                visitor.visit_expr_mut(dim);
+            }
+        }
+        Expr::If {
+            condition,
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            visitor.visit_expr_mut(condition);
+            visitor.visit_expr_mut(then_branch);
+            if let Some(else_expr) = else_branch {
+                visitor.visit_expr_mut(else_expr);
+            }
+        }
+        Expr::Block { statements, .. } => {
+            for s in statements {
+                visitor.visit_stmt_mut(s);
+            }
+        }
+        Expr::Assign { target, value, .. } | Expr::CompoundAssign { target, value, .. } => {
+            visitor.visit_expr_mut(target);
+            visitor.visit_expr_mut(value);
+        }
+        Expr::IntLiteral(..)
+        | Expr::UIntLiteral(..)
+        | Expr::FloatLiteral(..)
+        | Expr::StringLiteral(..)
+        | Expr::BoolLiteral(..)
+        | Expr::Ident(..)
+        | Expr::ThreadIdx { .. }
+        | Expr::BlockIdx { .. }
+        | Expr::BlockDim { .. }
+        | Expr::SizeOf { .. } => {}
+    }
+}
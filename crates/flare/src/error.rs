@@ -9,9 +9,178 @@ pub enum FlareError {
         error: String,
         span: std::ops::Range<usize>,
     },
-    #[error("unexpectedEof")]
-    UnexpectedEof,
+    #[error("expected {expected}, reached end of input near line {line}")]
+    UnexpectedEof {
+        expected: String,
+        line: usize,
+        span: std::ops::Range<usize>,
+    },
+
+    #[error("{message} at {span:?}")]
+    UnexpectedToken {
+        message: String,
+        span: std::ops::Range<usize>,
+    },
+
+    #[error("unknown attribute '@{name}' at {span:?}{}", did_you_mean(.suggestion))]
+    UnknownAttribute {
+        name: String,
+        suggestion: Option<String>,
+        span: std::ops::Range<usize>,
+    },
+
+    #[error(
+        "range at {span:?} is only valid as a `for` iterator or a slice index; \
+         iterate it with `for i in start..end {{ ... }}`"
+    )]
+    MisplacedRange { span: std::ops::Range<usize> },
+
+    #[error("variable '{name}' at {span:?} needs a type annotation or an initializer")]
+    UntypedVar {
+        name: String,
+        span: std::ops::Range<usize>,
+    },
+
+    #[error(
+        "load_shared at {span:?} writes to '{name}', which is not declared in the kernel's \
+         `shared_memory` block"
+    )]
+    UndeclaredSharedBuffer {
+        name: String,
+        span: std::ops::Range<usize>,
+    },
+
+    #[error(
+        "write to `output` at {span:?} uses {found} indices, but the kernel returns a \
+         rank-{expected} tensor"
+    )]
+    OutputRankMismatch {
+        expected: usize,
+        found: usize,
+        span: std::ops::Range<usize>,
+    },
+
+    #[error("comparisons at {span:?} cannot be chained; write `{suggestion}`")]
+    ChainedComparison {
+        suggestion: String,
+        span: std::ops::Range<usize>,
+    },
+
+    #[error("{block} block at {span:?} targets unknown kernel '{name}'")]
+    UnknownTarget {
+        block: String,
+        name: String,
+        span: std::ops::Range<usize>,
+    },
+
+    #[error("@all_reduce at {span:?}: {reason}")]
+    InvalidReduction {
+        reason: String,
+        span: std::ops::Range<usize>,
+    },
+
+    #[error("'{name}' at {span:?} starts with `flare_`, which is reserved for generated names")]
+    ReservedName {
+        name: String,
+        span: std::ops::Range<usize>,
+    },
+
+    #[error("{what} at {span:?} must be positive, got {value}")]
+    NonPositiveScheduleValue {
+        what: String,
+        value: i64,
+        span: std::ops::Range<usize>,
+    },
+
+    #[error("cannot implicitly convert '{from}' to '{to}' at {span:?}; use an explicit `as` cast")]
+    ImplicitNarrowing {
+        from: String,
+        to: String,
+        span: std::ops::Range<usize>,
+    },
+
+    #[error(
+        "tensor '{name}' at {span:?} of shape [{}] cannot be combined elementwise into shape [{}]",
+        .found.join(", "),
+        .expected.join(", ")
+    )]
+    ShapeMismatch {
+        name: String,
+        found: Vec<String>,
+        expected: Vec<String>,
+        span: std::ops::Range<usize>,
+    },
+}
+
+impl FlareError {
+    /// Source range the error points at.
+    pub fn span(&self) -> std::ops::Range<usize> {
+        match self {
+            FlareError::UnexpectedChar { pos, .. } => *pos..*pos + 1,
+            FlareError::InvalidToken { span, .. }
+            | FlareError::UnexpectedEof { span, .. }
+            | FlareError::UnexpectedToken { span, .. }
+            | FlareError::UnknownAttribute { span, .. }
+            | FlareError::MisplacedRange { span }
+            | FlareError::UntypedVar { span, .. }
+            | FlareError::UndeclaredSharedBuffer { span, .. }
+            | FlareError::OutputRankMismatch { span, .. }
+            | FlareError::ChainedComparison { span, .. }
+            | FlareError::UnknownTarget { span, .. }
+            | FlareError::InvalidReduction { span, .. }
+            | FlareError::ReservedName { span, .. }
+            | FlareError::NonPositiveScheduleValue { span, .. }
+            | FlareError::ImplicitNarrowing { span, .. }
+            | FlareError::ShapeMismatch { span, .. } => span.clone(),
+        }
+    }
+}
+
+fn did_you_mean(suggestion: &Option<String>) -> String {
+    match suggestion {
+        Some(name) => format!("; did you mean '@{}'?", name),
+        None => String::new(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Note,
+    Warning,
+}
+
+/// Non-fatal message reported alongside a successful compilation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: std::ops::Range<usize>,
+    /// Second location the message refers to, such as the binding a
+    /// shadowing warning points back at.
+    pub related: Option<std::ops::Range<usize>>,
+}
+
+impl Diagnostic {
+    pub fn note(message: impl Into<String>, span: std::ops::Range<usize>) -> Self {
+        Self {
+            severity: Severity::Note,
+            message: message.into(),
+            span,
+            related: None,
+        }
+    }
+
+    pub fn warning(message: impl Into<String>, span: std::ops::Range<usize>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            message: message.into(),
+            span,
+            related: None,
+        }
+    }
 
-    #[error("unexpectedToken {0}")]
-    UnexpectedToken(String),
+    pub fn with_related(mut self, span: std::ops::Range<usize>) -> Self {
+        self.related = Some(span);
+        self
+    }
 }
@@ -0,0 +1,97 @@
+use crate::ast::{BinOp, Expr, Type, UnOp};
+use std::collections::HashMap;
+
+/// What an integer constant expression may refer to besides literals.
+pub trait ConstEnv {
+    /// Value of the constant bound to `name`.
+    fn value(&self, name: &str) -> Option<i64>;
+
+    /// Size in bytes of `ty`, for `sizeof`. Layouts are up to the backend,
+    /// so by default `sizeof` does not fold.
+    fn size_of(&self, _ty: &Type) -> Option<usize> {
+        None
+    }
+}
+
+impl ConstEnv for HashMap<&str, i64> {
+    fn value(&self, name: &str) -> Option<i64> {
+        self.get(name).copied()
+    }
+}
+
+impl ConstEnv for HashMap<String, i64> {
+    fn value(&self, name: &str) -> Option<i64> {
+        self.get(name).copied()
+    }
+}
+
+/// Value of an integer expression built from literals, names bound in
+/// `env`, `sizeof`, arithmetic and comparisons (`1` for true, `0` for
+/// false), or `None` when it is not a compile-time constant.
+pub fn fold_int(expr: &Expr, env: &impl ConstEnv) -> Option<i64> {
+    match expr {
+        Expr::IntLiteral(value, _) => Some(*value),
+        Expr::Ident(name, _) => env.value(name),
+        Expr::SizeOf { ty, .. } => i64::try_from(env.size_of(ty)?).ok(),
+        Expr::Unary {
+            op: UnOp::Neg,
+            expr,
+            ..
+        } => fold_int(expr, env)?.checked_neg(),
+        Expr::Binary {
+            left, op, right, ..
+        } => fold_binary(fold_int(left, env)?, *op, fold_int(right, env)?),
+        _ => None,
+    }
+}
+
+/// Result of `left op right` on integers, or `None` on overflow, division
+/// by zero or an out-of-range shift. Comparisons and logic give `1` or `0`.
+pub fn fold_binary(left: i64, op: BinOp, right: i64) -> Option<i64> {
+    match op {
+        BinOp::Add => left.checked_add(right),
+        BinOp::Sub => left.checked_sub(right),
+        BinOp::Mul => left.checked_mul(right),
+        BinOp::Div => left.checked_div(right),
+        BinOp::Mod => left.checked_rem(right),
+        BinOp::Shl => left.checked_shl(u32::try_from(right).ok()?),
+        BinOp::Shr => left.checked_shr(u32::try_from(right).ok()?),
+        BinOp::BitAnd => Some(left & right),
+        BinOp::Equal => Some(i64::from(left == right)),
+        BinOp::NotEqual => Some(i64::from(left != right)),
+        BinOp::Less => Some(i64::from(left < right)),
+        BinOp::Greater => Some(i64::from(left > right)),
+        BinOp::LessEqual => Some(i64::from(left <= right)),
+        BinOp::GreaterEqual => Some(i64::from(left >= right)),
+        BinOp::And => Some(i64::from(left != 0 && right != 0)),
+        BinOp::Or => Some(i64::from(left != 0 || right != 0)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Parser;
+
+    fn value_of(source: &str) -> Expr<'_> {
+        let program = Parser::new(source).unwrap().parse().unwrap();
+        match program.items.into_iter().last() {
+            Some(crate::ast::Stmt::Const { value, .. }) => value,
+            other => panic!("expected a const, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_fold_int_over_names_and_comparisons() {
+        let env = HashMap::from([("N", 8)]);
+        assert_eq!(
+            fold_int(&value_of("const X = (N + 2) * 3 - 1"), &env),
+            Some(29)
+        );
+        assert_eq!(fold_int(&value_of("const X = N * 4 > 31"), &env), Some(1));
+        assert_eq!(fold_int(&value_of("const X = N / 0"), &env), None);
+        assert_eq!(fold_int(&value_of("const X = M + 1"), &env), None);
+        // layouts are the backend's, so `sizeof` needs an environment that knows them
+        assert_eq!(fold_int(&value_of("const X = sizeof(f32)"), &env), None);
+    }
+}
@@ -0,0 +1,132 @@
+use crate::lexer::core::{Lexer, Trivia};
+use crate::lexer::token::{Token, TokenKind};
+use crate::FlareError;
+
+/// Reformats flare source: re-indents lines by brace depth, collapses runs
+/// of spaces and blank lines, and re-emits comments where they appeared.
+pub fn to_source(source: &str) -> Result<String, FlareError> {
+    let mut lexer = Lexer::with_trivia(source);
+    let mut tokens = Vec::new();
+    while let Some(token) = lexer.peek() {
+        tokens.push(token?);
+    }
+    let trivia = lexer.take_trivia();
+
+    let mut printer = Printer::default();
+    let mut prev_end = None;
+    for token in &tokens {
+        printer.emit_gap(prev_end, token.span.start, &trivia);
+        printer.emit_token(token);
+        prev_end = Some(token.span.end);
+    }
+    printer.emit_gap(prev_end, source.len(), &trivia);
+    Ok(printer.finish())
+}
+
+#[derive(Default)]
+struct Printer {
+    lines: Vec<String>,
+    line: String,
+    line_depth: usize,
+    depth: usize,
+}
+
+impl Printer {
+    /// Emits the comments before the token at `next_start`, separated by a
+    /// single space wherever the source had whitespace.
+    fn emit_gap(&mut self, prev_end: Option<usize>, next_start: usize, trivia: &[Trivia]) {
+        let gap_start = prev_end.unwrap_or(0);
+        let mut pos = gap_start;
+        for comment in trivia.iter().filter(|t| t.token_start == next_start) {
+            if comment.span.start > pos {
+                self.push_space();
+            }
+            self.push_text(comment.text);
+            pos = comment.span.end;
+        }
+        if next_start > pos {
+            self.push_space();
+        }
+    }
+
+    fn emit_token(&mut self, token: &Token) {
+        match token.kind {
+            TokenKind::Newline => self.end_line(),
+            TokenKind::RightBrace => {
+                self.depth = self.depth.saturating_sub(1);
+                self.push_text(token.text);
+            }
+            TokenKind::LeftBrace => {
+                self.push_text(token.text);
+                self.depth += 1;
+            }
+            _ => self.push_text(token.text),
+        }
+    }
+
+    fn push_space(&mut self) {
+        if !self.line.is_empty() && !self.line.ends_with(' ') {
+            self.line.push(' ');
+        }
+    }
+
+    fn push_text(&mut self, text: &str) {
+        if self.line.is_empty() {
+            self.line_depth = self.depth;
+        }
+        self.line.push_str(text);
+    }
+
+    fn end_line(&mut self) {
+        let line = std::mem::take(&mut self.line);
+        let line = line.trim_end();
+        if line.is_empty() {
+            // keep at most one blank line, and none at the start
+            if self.lines.last().is_some_and(|last| !last.is_empty()) {
+                self.lines.push(String::new());
+            }
+        } else {
+            self.lines
+                .push(format!("{}{}", "    ".repeat(self.line_depth), line));
+        }
+    }
+
+    fn finish(mut self) -> String {
+        if !self.line.is_empty() {
+            self.end_line();
+        }
+        while self.lines.last().is_some_and(|last| last.is_empty()) {
+            self.lines.pop();
+        }
+        let mut output = self.lines.join("\n");
+        output.push('\n');
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_leading_comment_round_trips() {
+        let source = "\
+// scales every element
+kernel scale(A: Tensor<f32, [N]>, s: f32) {
+    /* one element per thread */
+    let i = thread_idx.x
+    A[i] = A[i] * s // in place
+}
+";
+        assert_eq!(to_source(source).unwrap(), source);
+    }
+
+    #[test]
+    fn test_reindents_by_brace_depth() {
+        let source =
+            "kernel k(A: Tensor<f32, [N]>) {\n  for i in 0..N {\n A[i]  =  0.0\n}\n\n\n}\n";
+        let expected =
+            "kernel k(A: Tensor<f32, [N]>) {\n    for i in 0..N {\n        A[i] = 0.0\n    }\n\n}\n";
+        assert_eq!(to_source(source).unwrap(), expected);
+    }
+}
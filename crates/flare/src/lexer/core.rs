@@ -1,12 +1,25 @@
-use crate::lexer::token::Token;
+use crate::lexer::token::{LexMode, Token};
 use crate::{error::FlareError, lexer::token::TokenKind};
 use logos::{Lexer as LogosLexer, Logos};
+use std::ops::Range;
+
+/// A comment the lexer skipped, kept so the formatter can re-emit it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trivia<'src> {
+    pub text: &'src str,
+    pub span: Range<usize>,
+    /// Start of the token the comment precedes, or the input length for
+    /// comments after the last token.
+    pub token_start: usize,
+}
 
 pub struct Lexer<'src> {
     pub input: &'src str,
     pub inner: LogosLexer<'src, TokenKind>,
     current: usize,
     pub peeked: Option<Result<Token<'src>, FlareError>>,
+    trivia: Option<Vec<Trivia<'src>>>,
+    trivia_end: usize,
 }
 
 impl<'src> Lexer<'src> {
@@ -16,21 +29,83 @@ impl<'src> Lexer<'src> {
             inner: TokenKind::lexer(input),
             current: 0,
             peeked: None,
+            trivia: None,
+            trivia_end: 0,
+        }
+    }
+
+    /// A lexer that emits a `Newline` token for each line break instead of
+    /// skipping it.
+    pub fn with_newlines(input: &'src str) -> Self {
+        Self {
+            inner: TokenKind::lexer_with_extras(input, LexMode { newlines: true }),
+            ..Self::new(input)
         }
     }
 
+    /// A lexer that records the comments it skips, read with `take_trivia`,
+    /// and emits `Newline` tokens, so the source layout can be rebuilt.
+    pub fn with_trivia(input: &'src str) -> Self {
+        Self {
+            trivia: Some(Vec::new()),
+            ..Self::with_newlines(input)
+        }
+    }
+
+    pub fn take_trivia(&mut self) -> Vec<Trivia<'src>> {
+        self.trivia.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
     pub fn peek(&mut self) -> Option<Result<Token<'src>, FlareError>> {
-        let new_peek = Some(Ok(Token::new(
-            self.inner
-                .next()
-                .ok_or_else(|| FlareError::UnexpectedToken(String::from(self.inner.slice())))
-                .ok()?
-                .unwrap(),
-            self.current,
-            self.inner.slice(),
-            self.inner.span(),
-        )));
-        new_peek
+        let Some(next) = self.inner.next() else {
+            self.record_trivia(self.input.len());
+            return None;
+        };
+        self.record_trivia(self.inner.span().start);
+        self.trivia_end = self.inner.span().end;
+
+        let new_peek = match next {
+            Ok(kind) => Ok(Token::new(
+                kind,
+                self.current,
+                self.inner.slice(),
+                self.inner.span(),
+            )),
+            Err(()) => Err(FlareError::InvalidToken {
+                error: format!("unrecognized input '{}'", self.inner.slice()),
+                span: self.inner.span(),
+            }),
+        };
+        Some(new_peek)
+    }
+
+    /// Records the comments between the previous token and `token_start`.
+    /// Everything in that gap was skipped, so it is only whitespace and
+    /// comments.
+    fn record_trivia(&mut self, token_start: usize) {
+        let Some(trivia) = &mut self.trivia else {
+            return;
+        };
+
+        let mut pos = self.trivia_end;
+        while pos < token_start {
+            let rest = &self.input[pos..token_start];
+            let len = if rest.starts_with("//") {
+                rest.find(['\r', '\n']).unwrap_or(rest.len())
+            } else if rest.starts_with("/*") {
+                rest.find("*/").map_or(rest.len(), |end| end + 2)
+            } else {
+                pos += rest.chars().next().map_or(1, char::len_utf8);
+                continue;
+            };
+            trivia.push(Trivia {
+                text: &self.input[pos..pos + len],
+                span: pos..pos + len,
+                token_start,
+            });
+            pos += len;
+        }
+        self.trivia_end = token_start;
     }
 }
 
@@ -61,4 +136,80 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn test_trivia_records_comments() {
+        let source = "// leading\nlet x = 1 /* inline */ + 2";
+        let mut lexer = Lexer::with_trivia(source);
+        while lexer.peek().is_some() {}
+        let trivia = lexer.take_trivia();
+
+        assert_eq!(trivia.len(), 2);
+        assert_eq!(trivia[0].text, "// leading");
+        assert_eq!(trivia[0].token_start, 10);
+        assert_eq!(trivia[1].text, "/* inline */");
+        assert_eq!(&source[trivia[1].token_start..], "+ 2");
+    }
+
+    #[test]
+    fn test_crlf_and_bare_cr_are_newlines() {
+        let mut lexer = Lexer::with_newlines("a\r\nb\rc\n");
+        let mut newlines = Vec::new();
+        while let Some(token) = lexer.peek() {
+            let token = token.unwrap();
+            if token.kind == TokenKind::Newline {
+                newlines.push(token.span);
+            }
+        }
+        assert_eq!(newlines, vec![1..3, 4..5, 6..7]);
+    }
+
+    #[test]
+    fn test_hex_float_literals() {
+        let mut lexer = Lexer::new("0x1.8p3 0x1p-4 0xAp0");
+        let mut values = Vec::new();
+        while let Some(token) = lexer.peek() {
+            if let TokenKind::FloatLiteral(value) = token.unwrap().kind {
+                values.push(value);
+            }
+        }
+        assert_eq!(values, vec![12.0, 0.0625, 10.0]);
+    }
+
+    #[test]
+    fn test_raw_identifier() {
+        let mut lexer = Lexer::new("r#block block");
+        let raw = lexer.peek().unwrap().unwrap();
+        assert_eq!(raw.kind, TokenKind::Identifier("block".to_string()));
+        assert_eq!(raw.span, 0..7);
+        let keyword = lexer.peek().unwrap().unwrap();
+        assert!(!matches!(keyword.kind, TokenKind::Identifier(_)));
+    }
+
+    #[test]
+    fn test_unsigned_literal() {
+        let mut lexer = Lexer::new("7u 7");
+        let unsigned = lexer.peek().unwrap().unwrap();
+        assert_eq!(unsigned.kind, TokenKind::UIntLiteral(7));
+        let signed = lexer.peek().unwrap().unwrap();
+        assert_eq!(signed.kind, TokenKind::IntLiteral(7));
+    }
+
+    #[test]
+    fn test_newlines_skipped_by_default() {
+        let mut lexer = Lexer::new("a\nb\r\n");
+        let mut kinds = Vec::new();
+        while let Some(token) = lexer.peek() {
+            kinds.push(token.unwrap().kind);
+        }
+        assert_eq!(
+            kinds,
+            vec![
+                TokenKind::Identifier("a".to_string()),
+                TokenKind::Identifier("b".to_string())
+            ]
+        );
+        // skipped by logos itself, not filtered afterwards
+        assert!(TokenKind::lexer("a\nb\r\n").all(|kind| kind != Ok(TokenKind::Newline)));
+    }
 }
@@ -1,8 +1,17 @@
-use logos::Logos;
+use logos::{Filter, Logos};
+
+/// Lexing mode carried through logos callbacks.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LexMode {
+    /// Whether line breaks are significant and lexed as `Newline` tokens.
+    /// The grammar ignores them, so only the formatter turns this on.
+    pub newlines: bool,
+}
 
 #[derive(Logos, Debug, Clone, PartialEq)]
-#[logos(skip r"[ \t\r]+")]
-#[logos(skip r"//[^\n]*")]
+#[logos(extras = LexMode)]
+#[logos(skip r"[ \t]+")]
+#[logos(skip r"//[^\r\n]*")]
 #[logos(skip r"/\*([^*]|\*[^/])*\*/")]
 pub enum TokenKind {
     #[token("kernel")]
@@ -17,6 +26,10 @@ pub enum TokenKind {
     Const,
     #[token("return")]
     Return,
+    #[token("break")]
+    Break,
+    #[token("continue")]
+    Continue,
     #[token("if")]
     If,
     #[token("else")]
@@ -27,6 +40,8 @@ pub enum TokenKind {
     While,
     #[token("in")]
     In,
+    #[token("as")]
+    As,
     #[token("where")]
     Where,
     #[token("type")]
@@ -194,6 +209,8 @@ pub enum TokenKind {
     P2PTransferAnnotation,
     #[token("@all_reduce")]
     AllReduceAnnotation,
+    #[token("@stage")]
+    StageAnnotation,
 
     #[token("@")]
     At,
@@ -208,6 +225,8 @@ pub enum TokenKind {
     Slash,
     #[token("%")]
     Percent,
+    #[token("&")]
+    Ampersand,
 
     #[token("==")]
     Equal,
@@ -281,7 +300,11 @@ pub enum TokenKind {
 
     #[regex(r"[0-9]+", |lex| lex.slice().parse::<i64>().ok())]
     IntLiteral(i64),
+    /// An unsigned literal such as `7u`.
+    #[regex(r"[0-9]+[uU]", |lex| { let s = lex.slice(); s[..s.len() - 1].parse::<u64>().ok() })]
+    UIntLiteral(u64),
     #[regex(r"[0-9]+\.[0-9]+", |lex| lex.slice().parse::<f64>().ok())]
+    #[regex(r"0[xX][0-9a-fA-F]+(\.[0-9a-fA-F]*)?[pP][+-]?[0-9]+", |lex| parse_hex_float(lex.slice()))]
     FloatLiteral(f64),
     #[regex(r#""([^"\\]|\\["\\bnfrt]|u[a-fA-F0-9]{4})*""#, |lex| {
         let s = lex.slice();
@@ -289,8 +312,15 @@ pub enum TokenKind {
     })]
     StringLiteral(String),
     #[regex(r"[a-zA-Z_][a-zA-Z0-9_]*", |lex| lex.slice().to_string())]
+    // raw identifiers such as `r#block` name things that would otherwise
+    // lex as keywords
+    #[regex(r"r#[a-zA-Z_][a-zA-Z0-9_]*", |lex| lex.slice()[2..].to_string())]
     Identifier(String),
-    #[token("\n")]
+    #[regex(r"'[a-zA-Z_][a-zA-Z0-9_]*", |lex| lex.slice()[1..].to_string())]
+    Label(String),
+    /// A line break: `\n`, `\r\n`, or a bare `\r`. Skipped like other
+    /// whitespace unless the lexer is in significant-newline mode.
+    #[regex(r"\r\n|\r|\n", |lex| if lex.extras.newlines { Filter::Emit(()) } else { Filter::Skip })]
     Newline,
     
     
@@ -314,3 +344,17 @@ impl<'src> Token<'src> {
         }
     }
 }
+
+/// Value of a C-style hexadecimal float such as `0x1.8p3`: a hex mantissa
+/// scaled by a power of two, so the bits written are the bits produced.
+fn parse_hex_float(text: &str) -> Option<f64> {
+    let (mantissa, exponent) = text[2..].split_once(['p', 'P'])?;
+    let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let digits = format!("{}{}", whole, fraction);
+    if digits.len() > 16 {
+        return None;
+    }
+    let mantissa = u64::from_str_radix(&digits, 16).ok()?;
+    let exponent = exponent.parse::<i32>().ok()? - 4 * fraction.len() as i32;
+    Some(mantissa as f64 * 2f64.powi(exponent))
+}
@@ -1,13 +1,20 @@
 pub mod ast;
 pub mod error;
+pub mod fold;
+pub mod format;
 pub mod lexer;
+pub mod lint;
 pub mod parser;
+pub mod reader;
+pub mod typeck;
+pub mod validate;
 
 pub use crate::lexer::token::Token;
 pub use ast::Program;
-pub use error::FlareError;
+pub use error::{Diagnostic, FlareError, Severity};
 pub use lexer::core::Lexer;
-pub use parser::core::Parser;
+pub use parser::core::{Items, Parser};
+pub use reader::LineMap;
 
 pub struct Flare;
 
@@ -15,8 +22,72 @@ impl Flare {
     pub fn compile_from_string(source: &str) -> Result<Program<'_>, FlareError> {
         let mut parser = Parser::new(source)?;
         let program = parser.parse()?;
+        validate::validate_program(&program)?;
         Ok(program)
     }
+
+    /// Parses `source` and runs the checks `compile_from_string` runs, then
+    /// the type and shape checks of `typeck`, for editors that want
+    /// diagnostics without a backend. Every item is checked, so all of these
+    /// errors are reported together; a syntax error stops parsing and is the
+    /// only one returned. On success the warnings of `lint` are returned,
+    /// such as shadowed bindings and unreachable code.
+    pub fn check(source: &str) -> Result<Vec<Diagnostic>, Vec<FlareError>> {
+        let program = Parser::new(source)
+            .and_then(|mut parser| parser.parse())
+            .map_err(|error| vec![error])?;
+
+        let mut errors: Vec<FlareError> = program
+            .items
+            .iter()
+            .flat_map(|item| {
+                let mut errors = validate::item_errors(item);
+                errors.extend(typeck::type_errors(item));
+                errors
+            })
+            .collect();
+        errors.extend(validate::program_errors(&program));
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(lint::program_warnings(&program))
+    }
+
+    /// Parses and validates `source` one top-level item at a time, so a
+    /// caller can start on early kernels before later ones are parsed. The
+    /// iterator ends after the first error. Checks relating items to each
+    /// other, such as a reduction naming a function defined further down,
+    /// run once the last item is parsed, so the items `compile_from_string`
+    /// rejects for them are yielded before the error is.
+    pub fn compile_iter(
+        source: &str,
+    ) -> impl Iterator<Item = Result<ast::Stmt<'_>, FlareError>> + '_ {
+        let mut items = Items::new(source);
+        let mut parsed = Vec::new();
+        let mut done = false;
+        std::iter::from_fn(move || {
+            if done {
+                return None;
+            }
+            let Some(item) = items.next() else {
+                done = true;
+                let program = Program {
+                    items: std::mem::take(&mut parsed),
+                    span: 0..source.len(),
+                };
+                return validate::program_errors(&program)
+                    .into_iter()
+                    .next()
+                    .map(Err);
+            };
+            let item = item.and_then(|item| validate::validate_item(&item).map(|()| item));
+            match &item {
+                Ok(item) => parsed.push(item.clone()),
+                Err(_) => done = true,
+            }
+            Some(item)
+        })
+    }
 }
 
 #[cfg(test)]
@@ -67,4 +138,933 @@ mod tests {
         println!("result {:?}", lexer.inner);
         println!("result {:?}", ast);
     }
+
+    #[test]
+    fn test_labeled_loops() {
+        let source = r#"
+            kernel search(A: Tensor<f32, [N, N]>) {
+                'outer: for i in 0..N {
+                    'inner: while true {
+                        break 'outer;
+                        continue 'inner;
+                    }
+                    continue;
+                }
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse labeled loops");
+        let kernel = match &program.items[0] {
+            ast::Stmt::Kernel(kernel) => kernel,
+            other => panic!("expected kernel, found {:?}", other),
+        };
+
+        let (label, body) = match &kernel.body[0] {
+            ast::Stmt::For { label, body, .. } => (label, body),
+            other => panic!("expected for loop, found {:?}", other),
+        };
+        assert_eq!(*label, Some("outer"));
+
+        let statements = match body.as_ref() {
+            ast::Stmt::Block { statements, .. } => statements,
+            other => panic!("expected block, found {:?}", other),
+        };
+        let inner_body = match &statements[0] {
+            ast::Stmt::While { label, body, .. } => {
+                assert_eq!(*label, Some("inner"));
+                body
+            }
+            other => panic!("expected while loop, found {:?}", other),
+        };
+        assert!(matches!(
+            &statements[1],
+            ast::Stmt::Continue { label: None, .. }
+        ));
+
+        match inner_body.as_ref() {
+            ast::Stmt::Block { statements, .. } => {
+                assert!(matches!(
+                    &statements[0],
+                    ast::Stmt::Break {
+                        label: Some("outer"),
+                        ..
+                    }
+                ));
+                assert!(matches!(
+                    &statements[1],
+                    ast::Stmt::Continue {
+                        label: Some("inner"),
+                        ..
+                    }
+                ));
+            }
+            other => panic!("expected block, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_attribute_keyword_and_list_args() {
+        let source = r#"
+            @auto_tune(tile=[8, 16], unroll=4)
+            @memory(loc=shared)
+            kernel tuned(A: Tensor<f32, [N]>) {
+                let i = thread_idx.x
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse attributes");
+        let kernel = match &program.items[0] {
+            ast::Stmt::Kernel(kernel) => kernel,
+            other => panic!("expected kernel, found {:?}", other),
+        };
+
+        assert_eq!(kernel.attributes.len(), 2);
+        assert_eq!(kernel.attributes[0].name, "auto_tune");
+        assert_eq!(
+            kernel.attributes[0].args,
+            vec![
+                ast::AttributeArg::KeyValue {
+                    key: "tile",
+                    value: Box::new(ast::AttributeArg::List(vec![
+                        ast::AttributeArg::IntLiteral(8),
+                        ast::AttributeArg::IntLiteral(16),
+                    ])),
+                },
+                ast::AttributeArg::KeyValue {
+                    key: "unroll",
+                    value: Box::new(ast::AttributeArg::IntLiteral(4)),
+                },
+            ]
+        );
+        assert_eq!(
+            kernel.attributes[1].args,
+            vec![ast::AttributeArg::KeyValue {
+                key: "loc",
+                value: Box::new(ast::AttributeArg::Ident("shared")),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_unknown_attribute_suggestion() {
+        let source = r#"
+            @optimze
+            kernel fast(A: Tensor<f32, [N]>) {
+                let i = thread_idx.x
+            }
+        "#;
+
+        let err = Flare::compile_from_string(source).unwrap_err();
+        match &err {
+            FlareError::UnknownAttribute {
+                name, suggestion, ..
+            } => {
+                assert_eq!(name, "optimze");
+                assert_eq!(suggestion.as_deref(), Some("optimize"));
+            }
+            other => panic!("expected unknown attribute error, found {:?}", other),
+        }
+        assert!(err.to_string().contains("did you mean '@optimize'?"));
+    }
+
+    #[test]
+    fn test_address_of_parse() {
+        let source = r#"
+            kernel refs(out: Tensor<f32, [N]>) {
+                let i = thread_idx.x
+                let p = &out[i]
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse address-of");
+        let kernel = match &program.items[0] {
+            ast::Stmt::Kernel(kernel) => kernel,
+            other => panic!("expected kernel, found {:?}", other),
+        };
+
+        match &kernel.body[1] {
+            ast::Stmt::Let { value, .. } => match value {
+                ast::Expr::Unary {
+                    op: ast::UnOp::AddrOf,
+                    expr,
+                    ..
+                } => assert!(matches!(expr.as_ref(), ast::Expr::Index { .. })),
+                other => panic!("expected address-of, found {:?}", other),
+            },
+            other => panic!("expected let, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_deref_vs_multiply_parse() {
+        let source = r#"
+            kernel ptrs(p: *f32, a: f32, b: f32) {
+                let x = *p;
+                let y = a * b;
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse dereference");
+        let kernel = match &program.items[0] {
+            ast::Stmt::Kernel(kernel) => kernel,
+            other => panic!("expected kernel, found {:?}", other),
+        };
+
+        assert!(matches!(
+            &kernel.body[0],
+            ast::Stmt::Let {
+                value: ast::Expr::Unary {
+                    op: ast::UnOp::Deref,
+                    ..
+                },
+                ..
+            }
+        ));
+        assert!(matches!(
+            &kernel.body[1],
+            ast::Stmt::Let {
+                value: ast::Expr::Binary {
+                    op: ast::BinOp::Mul,
+                    ..
+                },
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_untyped_var_rejected() {
+        let source = r#"
+            kernel k(A: Tensor<f32, [N]>) {
+                var x;
+            }
+        "#;
+
+        match Flare::compile_from_string(source) {
+            Err(FlareError::UntypedVar { name, span }) => {
+                assert_eq!(name, "x");
+                assert_eq!(&source[span], "x");
+            }
+            other => panic!("expected UntypedVar error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_schedule_hints_block() {
+        let source = r#"
+            kernel k(A: Tensor<f32, [N]>) {}
+
+            schedule k {
+                threads(64)
+                hints {
+                    @prefer_parallel
+                    @pipeline_depth(2)
+                }
+            }
+        "#;
+
+        let program = Flare::compile_from_string(source).expect("failed to parse schedule");
+        let ast::Stmt::Schedule(schedule) = &program.items[1] else {
+            panic!("expected schedule, got {:?}", program.items[1]);
+        };
+        let ast::ScheduleDirective::Hints(hints) = &schedule.directives[1] else {
+            panic!("expected hints, got {:?}", schedule.directives[1]);
+        };
+
+        let names: Vec<&str> = hints.iter().map(|hint| hint.name).collect();
+        assert_eq!(names, ["prefer_parallel", "pipeline_depth"]);
+        assert_eq!(hints[1].args, [ast::AttributeArg::IntLiteral(2)]);
+    }
+
+    #[test]
+    fn test_unclosed_kernel_eof_error() {
+        let source = "kernel k(A: Tensor<f32, [N]>) {\n    let x = A[0]\n    A[1] = x\n";
+
+        match Flare::compile_from_string(source) {
+            Err(err @ FlareError::UnexpectedEof { line, .. }) => {
+                assert_eq!(line, 3);
+                let message = err.to_string();
+                assert!(
+                    message.contains("expected `}` after kernel body"),
+                    "{}",
+                    message
+                );
+                assert!(message.contains("near line 3"), "{}", message);
+            }
+            other => panic!("expected end of input error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_misplaced_range_rejected() {
+        let source = r#"
+            kernel k(A: Tensor<f32, [N]>) {
+                let r = 0..N
+            }
+        "#;
+
+        match Flare::compile_from_string(source) {
+            Err(FlareError::MisplacedRange { span }) => assert_eq!(&source[span], "0..N"),
+            other => panic!("expected misplaced range error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_loop_and_slice_ranges_accepted() {
+        let source = r#"
+            kernel k(A: Tensor<f32, [N]>) {
+                for i in 0..N step 2 {
+                    A[i] = 0.0
+                }
+                let head = A[0..4]
+            }
+        "#;
+
+        assert!(Flare::compile_from_string(source).is_ok());
+    }
+
+    #[test]
+    fn test_impl_method_needs_receiver() {
+        let source = r#"
+            impl Point {
+                fn scale(s: f32) -> f32 {
+                    return s
+                }
+            }
+        "#;
+        let err = Flare::compile_from_string(source).expect_err("expected a receiver error");
+        assert!(err
+            .to_string()
+            .contains("must take a Point as its first parameter"));
+    }
+
+    #[test]
+    fn test_load_shared_needs_declared_buffer() {
+        let declared = r#"
+            kernel tiled(A: Tensor<f32, [N]>) {
+                shared_memory {
+                    tile: [16]: f32
+                }
+                load_shared(tile, A[thread_idx.x])
+            }
+        "#;
+        assert!(Flare::compile_from_string(declared).is_ok());
+
+        let typo = r#"
+            kernel tiled(A: Tensor<f32, [N]>) {
+                shared_memory {
+                    tile: [16]: f32
+                }
+                load_shared(tiel, A[thread_idx.x])
+            }
+        "#;
+        match Flare::compile_from_string(typo) {
+            Err(FlareError::UndeclaredSharedBuffer { name, .. }) => assert_eq!(name, "tiel"),
+            other => panic!(
+                "expected an undeclared shared buffer error, got {:?}",
+                other
+            ),
+        }
+    }
+
+    #[test]
+    fn test_schedule_factors_must_be_positive() {
+        let source = r#"
+            schedule k {
+                tile(0)
+            }
+        "#;
+        match Flare::compile_from_string(source) {
+            Err(FlareError::NonPositiveScheduleValue { what, value, span }) => {
+                assert_eq!((what.as_str(), value), ("tile x", 0));
+                assert_eq!(&source[span], "0");
+            }
+            other => panic!("expected a non-positive factor error, got {:?}", other),
+        }
+
+        let source = r#"
+            schedule k {
+                unroll(-2)
+            }
+        "#;
+        assert!(matches!(
+            Flare::compile_from_string(source),
+            Err(FlareError::NonPositiveScheduleValue { value: -2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_error_span() {
+        let source = r#"
+            kernel k(A: Tensor<f32, [N], diagonal>) {}
+        "#;
+        match Flare::compile_from_string(source) {
+            Err(error @ FlareError::UnexpectedToken { .. }) => {
+                assert_eq!(&source[error.span()], "diagonal");
+                assert!(error
+                    .to_string()
+                    .starts_with("unknown tensor layout 'diagonal'"));
+            }
+            other => panic!("expected an unexpected token error, got {:?}", other),
+        }
+
+        let source = "kernel k() { let x = 1 + }";
+        let error = Flare::compile_from_string(source).unwrap_err();
+        assert_eq!(&source[error.span()], "}");
+    }
+
+    #[test]
+    fn test_schedule_factor_from_const() {
+        let source = r#"
+            const TILE = 8
+            const HALF = TILE / 2
+
+            kernel k(A: Tensor<f32, [N]>) {}
+
+            schedule k {
+                tile(TILE, HALF)
+                pipeline(2)
+            }
+        "#;
+        let program = Flare::compile_from_string(source).unwrap();
+        let Some(ast::Stmt::Schedule(schedule)) = program.items.last() else {
+            panic!("expected a schedule");
+        };
+        assert_eq!(
+            schedule.directives[0],
+            ast::ScheduleDirective::Tile {
+                x: 8,
+                y: Some(4),
+                z: None
+            }
+        );
+    }
+
+    #[test]
+    fn test_stage_attribute_on_statements() {
+        let source = r#"
+            kernel k(A: Tensor<f32, [N]>) {
+                compute {
+                    @stage(0) @constant let c = 2.0
+                    @stage(1) A[0] = c
+                }
+            }
+        "#;
+        let program = Flare::compile_from_string(source).unwrap();
+        let kernel = program.kernels().next().unwrap();
+        let compute = kernel.compute.as_ref().unwrap();
+        let stages: Vec<_> = compute.iter().map(ast::Stmt::stage).collect();
+        assert_eq!(stages, [Some(0), Some(1)]);
+        let ast::Stmt::Staged { stmt, .. } = &compute[0] else {
+            panic!("expected a staged statement");
+        };
+        assert!(
+            matches!(stmt.as_ref(), ast::Stmt::Let { attributes, .. } if attributes[0].name == "constant")
+        );
+
+        for source in [
+            "kernel k() { @stage(x) sync_threads() }",
+            "kernel k() { @stage(0) @stage(1) sync_threads() }",
+        ] {
+            assert!(matches!(
+                Flare::compile_from_string(source),
+                Err(FlareError::UnexpectedToken { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_schedule_mode() {
+        let source = r#"
+            kernel a() {}
+            kernel b() {}
+            kernel c() {}
+
+            schedule manual a {
+                tile(8)
+            }
+
+            schedule auto b {}
+
+            schedule c {}
+        "#;
+        let program = Flare::compile_from_string(source).unwrap();
+        let modes: Vec<_> = program
+            .schedules()
+            .map(|schedule| (schedule.target, schedule.mode))
+            .collect();
+        assert_eq!(
+            modes,
+            vec![
+                (Some("a"), ast::ScheduleMode::Manual),
+                (Some("b"), ast::ScheduleMode::Auto),
+                (Some("c"), ast::ScheduleMode::Auto),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_kernel_where_clause() {
+        let source = r#"
+            kernel matmul<M, N, K>(A: Tensor<f32, [M, K]>) where K % 4 == 0, N > 0 {
+                let i = thread_idx.x
+            }
+        "#;
+        let program = Flare::compile_from_string(source).unwrap();
+        let ast::Stmt::Kernel(kernel) = &program.items[0] else {
+            panic!("expected a kernel");
+        };
+        let constraints: Vec<&str> = kernel
+            .constraints
+            .iter()
+            .map(|constraint| &source[constraint.span()])
+            .collect();
+        assert_eq!(constraints, ["K % 4 == 0", "N > 0"]);
+        assert_eq!(kernel.body.len(), 1);
+    }
+
+    #[test]
+    fn test_chained_comparison_rejected() {
+        let source = r#"
+            kernel k(A: Tensor<f32, [N]>, lo: i32, hi: i32) {
+                let i = thread_idx.x
+                let inside = lo < i < hi
+            }
+        "#;
+        match Flare::compile_from_string(source) {
+            Err(err @ FlareError::ChainedComparison { .. }) => {
+                assert!(
+                    err.to_string().contains("write `lo < i && i < hi`"),
+                    "{}",
+                    err
+                );
+            }
+            other => panic!("expected a chained comparison error, got {:?}", other),
+        }
+
+        let source = source.replace("lo < i < hi", "lo < i && i < hi");
+        assert!(Flare::compile_from_string(&source).is_ok());
+        let source = source.replace("lo < i && i < hi", "(lo < i) == (i < hi)");
+        assert!(Flare::compile_from_string(&source).is_ok());
+    }
+
+    #[test]
+    fn test_trailing_commas() {
+        let source = r#"
+            kernel produce(
+                A: Tensor<f32, [M, N,]>,
+                B: Tensor<f32, [N]>,
+            ) {
+                let weights = [1.0, 2.0, 3.0,]
+                A[0, 1,] = fma(B[0], B[1], B[2],)
+            }
+
+            kernel consume(A: Tensor<f32, [N]>,) {}
+
+            fuse produce, consume,
+        "#;
+        let program = Flare::compile_from_string(source).unwrap();
+        let ast::Stmt::Kernel(kernel) = &program.items[0] else {
+            panic!("expected a kernel");
+        };
+        assert_eq!(kernel.params.len(), 2);
+        let ast::Stmt::Let { value, .. } = &kernel.body[0] else {
+            panic!("expected a let binding");
+        };
+        assert!(matches!(value, ast::Expr::Array { elements, .. } if elements.len() == 3));
+        let Some(ast::Stmt::Fusion(fusion)) = program.items.last() else {
+            panic!("expected a fusion block");
+        };
+        assert_eq!(fusion.targets, ["produce", "consume"]);
+    }
+
+    #[test]
+    fn test_compile_iter_yields_items_in_order() {
+        let source = r#"
+            kernel first(A: Tensor<f32, [N]>) {
+                A[0] = 1.0
+            }
+            const WIDTH = 4
+            kernel second(A: Tensor<f32, [N]>) {
+                A[0] = 2.0
+            }
+            kernel broken(A: Tensor<f32, [N]>) {
+                var x
+            }
+            kernel never(A: Tensor<f32, [N]>) {
+                A[0] = 3.0
+            }
+        "#;
+        let items: Vec<_> = Flare::compile_iter(source).collect();
+        assert_eq!(items.len(), 4);
+        let names: Vec<_> = items
+            .iter()
+            .filter_map(|item| match item {
+                Ok(ast::Stmt::Kernel(kernel)) => Some(kernel.name),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(names, ["first", "second"]);
+        assert!(matches!(items[3], Err(FlareError::UntypedVar { .. })));
+    }
+
+    #[test]
+    fn test_semantic_equality_ignores_spans() {
+        let source = r#"
+            kernel a(A: Tensor<f32, [N]>) {
+                let i = thread_idx.x
+                A[i] = A[i] * 2.0 + 1.0
+            }
+            kernel a(A: Tensor<f32,[N]>) { let i = thread_idx.x; A[i] = A[i]*2.0+1.0 }
+            kernel a(A: Tensor<f32, [N]>) {
+                let i = thread_idx.x
+                A[i] = A[i] * 3.0 + 1.0
+            }
+        "#;
+        let program = Flare::compile_from_string(source).unwrap();
+        let [first, second, third] = program.items.as_slice() else {
+            panic!("expected three kernels");
+        };
+
+        assert_ne!(first, second);
+        assert!(first.semantically_eq(second));
+        assert!(!first.semantically_eq(third));
+
+        let ast::Stmt::Kernel(kernel) = first else {
+            panic!("expected a kernel");
+        };
+        let ast::Stmt::Kernel(other) = second else {
+            panic!("expected a kernel");
+        };
+        let (ast::Stmt::Expr(a), ast::Stmt::Expr(b)) = (&kernel.body[1], &other.body[1]) else {
+            panic!("expected assignments");
+        };
+        assert_ne!(a, b);
+        assert!(a.semantically_eq(b));
+    }
+
+    #[test]
+    fn test_sizeof_takes_a_type() {
+        let source = r#"
+            kernel k(A: Tensor<f32, [N]>) {
+                let bytes = sizeof(f32) * 4
+            }
+        "#;
+        let program = Flare::compile_from_string(source).unwrap();
+        let ast::Stmt::Kernel(kernel) = &program.items[0] else {
+            panic!("expected a kernel");
+        };
+        let ast::Stmt::Let { value, .. } = &kernel.body[0] else {
+            panic!("expected a let binding");
+        };
+        let ast::Expr::Binary { left, .. } = value else {
+            panic!("expected a product");
+        };
+        assert!(matches!(
+            left.as_ref(),
+            ast::Expr::SizeOf {
+                ty: ast::Type::F32(_),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_output_write_arity_must_match_return_rank() {
+        let source = r#"
+            kernel copy(A: Tensor<f32, [M, N]>) -> Tensor<f32, [M, N]> {
+                let i = thread_idx.y
+                let j = thread_idx.x
+                output[i, j, 0] = A[i, j]
+            }
+        "#;
+        match Flare::compile_from_string(source) {
+            Err(FlareError::OutputRankMismatch {
+                expected,
+                found,
+                span,
+            }) => {
+                assert_eq!((expected, found), (2, 3));
+                assert_eq!(&source[span], "output[i, j, 0] = A[i, j]");
+            }
+            other => panic!("expected an output rank error, got {:?}", other),
+        }
+
+        let fixed = source.replace("output[i, j, 0]", "output[i, j]");
+        assert!(Flare::compile_from_string(&fixed).is_ok());
+    }
+
+    #[test]
+    fn test_ternary_parses_as_if() {
+        let source = r#"
+            kernel k(a: f32, b: f32) {
+                let m = a > b ? a : b
+            }
+        "#;
+        let program = Flare::compile_from_string(source).unwrap();
+        let ast::Stmt::Kernel(kernel) = &program.items[0] else {
+            panic!("expected a kernel");
+        };
+        let ast::Stmt::Let { value, .. } = &kernel.body[0] else {
+            panic!("expected a let binding");
+        };
+        let ast::Expr::If {
+            condition,
+            then_branch,
+            else_branch: Some(else_branch),
+            span,
+        } = value
+        else {
+            panic!("expected an if expression, got {:?}", value);
+        };
+        assert_eq!(&source[span.clone()], "a > b ? a : b");
+        assert!(matches!(
+            condition.as_ref(),
+            ast::Expr::Binary {
+                op: ast::BinOp::Greater,
+                ..
+            }
+        ));
+        assert!(matches!(then_branch.as_ref(), ast::Expr::Ident("a", _)));
+        assert!(matches!(else_branch.as_ref(), ast::Expr::Ident("b", _)));
+    }
+
+    #[test]
+    fn test_program_kernels_accessor() {
+        let source = r#"
+            kernel matmul_naive(A: Tensor<f32, [M, K]>, B: Tensor<f32, [K, N]>) -> Tensor<f32, [M, N]> {
+                grid: [M, N]
+                block: [1]
+
+                compute {
+                    let row = block_idx.y
+                    let col = block_idx.x
+                    var sum: f32 = 0.0
+
+                    for k in 0..K {
+                        sum = sum + A[row, k] * B[k, col]
+                    }
+
+                    output[row, col] = sum
+                }
+            }
+        "#;
+        let program = Flare::compile_from_string(source).unwrap();
+
+        let names: Vec<_> = program.kernels().map(|kernel| kernel.name).collect();
+        assert_eq!(names, ["matmul_naive"]);
+        assert!(program.kernel_by_name("matmul_naive").is_some());
+        assert!(program.kernel_by_name("matmul").is_none());
+        assert_eq!(program.schedules().count(), 0);
+    }
+
+    #[test]
+    fn test_multi_line_expression_parses_identically() {
+        let source = r#"
+            kernel a(A: Tensor<f32, [N]>, B: Tensor<f32, [N]>) {
+                A[thread_idx.x] = A[thread_idx.x] * 2.0 + B[thread_idx.x] / 4.0
+            }
+            kernel a(A: Tensor<f32, [N]>,
+                     B: Tensor<f32, [N]>) {
+                A[thread_idx.x] =
+                    A[thread_idx.x] * 2.0
+                    + B[thread_idx.x]
+                    / 4.0
+            }
+        "#;
+        let program = Flare::compile_from_string(source).unwrap();
+        let [single, multi] = program.items.as_slice() else {
+            panic!("expected two kernels");
+        };
+        assert!(single.semantically_eq(multi));
+    }
+    #[test]
+    fn test_schedule_on_empty_kernel_warns() {
+        let source = r#"
+            kernel matmul(A: Tensor<f32, [N]>) {}
+
+            kernel scale(A: Tensor<f32, [N]>) {
+                A[thread_idx.x] = 0.0
+            }
+
+            schedule matmul {
+                tile(16)
+            }
+
+            schedule scale {
+                tile(16)
+            }
+        "#;
+        let program = Flare::compile_from_string(source).unwrap();
+
+        let warnings = validate::empty_targets(&program);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].message.contains("kernel 'matmul'"));
+        assert!(source[warnings[0].span.clone()].starts_with("schedule matmul"));
+        let related = warnings[0].related.clone().unwrap();
+        assert!(source[related].starts_with("kernel matmul"));
+    }
+
+    #[test]
+    fn test_check_clean_program() {
+        let source = r#"
+            kernel scale(A: Tensor<f32, [N]>, s: f32) {
+                A[thread_idx.x] = A[thread_idx.x] * s
+            }
+
+            schedule scale {
+                tile(16)
+            }
+        "#;
+        let warnings = Flare::check(source).expect("expected a clean program");
+        assert!(warnings.is_empty());
+
+        let source = r#"
+            kernel scale(A: Tensor<f32, [N]>, s: f32) {
+                let i = thread_idx.x
+                if i < 4 {
+                    let s = 2.0
+                    A[i] = s
+                }
+                return;
+                A[i] = 0.0
+            }
+        "#;
+        let warnings = Flare::check(source).expect("expected only warnings");
+        let spans: Vec<&str> = warnings.iter().map(|w| &source[w.span.clone()]).collect();
+        assert_eq!(spans, ["A[i] = 0.0", "let s = 2.0"]);
+    }
+
+    #[test]
+    fn test_check_reports_every_error() {
+        let source = r#"
+            kernel first(A: Tensor<f32, [N]>) {
+                var x
+                A[0] = 1.0
+            }
+
+            kernel second(A: Tensor<f32, [N]>) -> Tensor<f32, [N, N]> {
+                output[0] = A[0]
+            }
+
+            kernel third(A: Tensor<f32, [M, N]>, B: Tensor<f32, [N]>, s: f32) {
+                let n: i32 = s
+                B = A * s
+            }
+
+            schedule thrid {
+                tile(16)
+            }
+        "#;
+        let errors = Flare::check(source).expect_err("expected semantic errors");
+        assert_eq!(errors.len(), 5);
+        assert!(matches!(errors[0], FlareError::UntypedVar { .. }));
+        assert!(matches!(
+            errors[1],
+            FlareError::OutputRankMismatch {
+                expected: 2,
+                found: 1,
+                ..
+            }
+        ));
+        assert!(matches!(&errors[2], FlareError::ImplicitNarrowing { to, .. } if to == "i32"));
+        assert!(matches!(&errors[3], FlareError::ShapeMismatch { name, .. } if name == "A"));
+        assert!(matches!(&errors[4], FlareError::UnknownTarget { name, .. } if name == "thrid"));
+
+        let errors = Flare::check("kernel k( {").expect_err("expected a syntax error");
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn test_all_reduce_custom_op_parses() {
+        let source = r#"
+            fn combine(a: f32, b: f32) -> f32 {
+                a + b
+            }
+
+            @all_reduce(grads, combine)
+            kernel step(grads: Tensor<f32, [N]>) {}
+        "#;
+        let program = Flare::compile_from_string(source).unwrap();
+        let kernel = program.kernel_by_name("step").unwrap();
+        let reduce = kernel.attributes[0].as_all_reduce().unwrap();
+        assert_eq!(reduce.buffer, "grads");
+        assert_eq!(reduce.op, ast::ReduceOp::Custom("combine"));
+    }
+
+    #[test]
+    fn test_all_reduce_needs_binary_function() {
+        let source = r#"
+            fn combine(a: f32) -> f32 {
+                a
+            }
+
+            @all_reduce(grads, combine)
+            kernel step(grads: Tensor<f32, [N]>) {}
+        "#;
+        let err = Flare::compile_from_string(source).unwrap_err();
+        assert!(matches!(err, FlareError::InvalidReduction { .. }));
+        assert!(err
+            .to_string()
+            .contains("reduction function 'combine' must take two parameters, found 1"));
+
+        let source = r#"
+            fn combine(a: i32, b: i32) -> i32 {
+                a + b
+            }
+
+            @all_reduce(grads, combine)
+            kernel step(grads: Tensor<f32, [N]>) {}
+        "#;
+        let err = Flare::compile_from_string(source).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("'combine' does not combine the elements of 'grads'"));
+    }
+
+    #[test]
+    fn test_compile_iter_checks_items_against_each_other() {
+        let source = r#"
+            @all_reduce(A, combine)
+            kernel total(A: Tensor<f32, [N]>) {
+                A[0] = 1.0
+            }
+            fn combine(a: f32, b: f32, c: f32) -> f32 {
+                a + b + c
+            }
+        "#;
+        let items: Vec<_> = Flare::compile_iter(source).collect();
+        assert_eq!(items.len(), 3);
+        assert!(items[..2].iter().all(Result::is_ok));
+        assert!(matches!(items[2], Err(FlareError::InvalidReduction { .. })));
+        assert!(Flare::compile_from_string(source).is_err());
+
+        // kernels before a bad token are still parsed
+        let items: Vec<_> =
+            Flare::compile_iter("kernel first() {} kernel second() { $ }").collect();
+        assert!(matches!(&items[0], Ok(ast::Stmt::Kernel(kernel)) if kernel.name == "first"));
+        assert!(matches!(items[1], Err(FlareError::InvalidToken { .. })));
+    }
+
+    #[test]
+    fn test_entry_points_reject_unknown_targets() {
+        let source = r#"
+            kernel scale(A: Tensor<f32, [N]>) {}
+
+            schedule sclae {
+                tile(8)
+            }
+        "#;
+        assert!(matches!(
+            Flare::compile_from_string(source),
+            Err(FlareError::UnknownTarget { .. })
+        ));
+        let items: Vec<_> = Flare::compile_iter(source).collect();
+        assert!(matches!(
+            items.last(),
+            Some(Err(FlareError::UnknownTarget { .. }))
+        ));
+        let errors = Flare::check(source).expect_err("expected an unknown target");
+        assert!(matches!(errors[..], [FlareError::UnknownTarget { .. }]));
+    }
 }
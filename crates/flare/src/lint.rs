@@ -0,0 +1,240 @@
+use crate::ast::{KernelDef, Program, Stmt};
+use crate::{validate, Diagnostic};
+use std::ops::Range;
+
+/// Every warning the front end finds in `program`: `schedule` and `fuse`
+/// blocks targeting empty kernels, then unreachable code and shadowed
+/// bindings, kernel by kernel.
+pub fn program_warnings(program: &Program) -> Vec<Diagnostic> {
+    let mut warnings = validate::empty_targets(program);
+    for kernel in program.kernels() {
+        warnings.extend(unreachable_code(kernel));
+        warnings.extend(shadowed_bindings(kernel));
+    }
+    warnings
+}
+
+/// Warns about statements that follow a `return`, `break` or `continue` in
+/// the same block, or an `if` whose branches all end in one, once per block.
+pub fn unreachable_code(kernel: &KernelDef) -> Vec<Diagnostic> {
+    let mut warnings = Vec::new();
+    if let Some(compute) = &kernel.compute {
+        check_list(compute, &mut warnings);
+    }
+    check_list(&kernel.body, &mut warnings);
+    warnings
+}
+
+/// Warns about `let`, `var` and loop bindings that shadow a binding of an
+/// enclosing scope, including the kernel's parameters. Each warning points
+/// at the inner binding, with the outer one as its related span.
+pub fn shadowed_bindings(kernel: &KernelDef) -> Vec<Diagnostic> {
+    let params = kernel
+        .const_params
+        .iter()
+        .map(|param| (param.name, param.span.clone()))
+        .chain(
+            kernel
+                .params
+                .iter()
+                .map(|param| (param.name, param.span.clone())),
+        )
+        .collect();
+    let mut checker = ShadowChecker {
+        scopes: vec![params],
+        warnings: Vec::new(),
+    };
+    if let Some(compute) = &kernel.compute {
+        checker.check_block(compute);
+    }
+    checker.check_block(&kernel.body);
+    checker.warnings
+}
+
+struct ShadowChecker<'src> {
+    scopes: Vec<Vec<(&'src str, Range<usize>)>>,
+    warnings: Vec<Diagnostic>,
+}
+
+impl<'src> ShadowChecker<'src> {
+    fn check_block(&mut self, stmts: &[Stmt<'src>]) {
+        self.scopes.push(Vec::new());
+        for stmt in stmts {
+            self.check_stmt(stmt);
+        }
+        self.scopes.pop();
+    }
+
+    fn check_stmt(&mut self, stmt: &Stmt<'src>) {
+        match stmt {
+            Stmt::Let { name, span, .. } | Stmt::Var { name, span, .. } => {
+                self.bind(name, span.clone())
+            }
+            Stmt::Block { statements, .. } => self.check_block(statements),
+            Stmt::Staged { stmt, .. } => self.check_stmt(stmt),
+            Stmt::If {
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.check_block(std::slice::from_ref(then_branch.as_ref()));
+                if let Some(else_stmt) = else_branch {
+                    self.check_block(std::slice::from_ref(else_stmt.as_ref()));
+                }
+            }
+            Stmt::While { body, .. } => self.check_block(std::slice::from_ref(body.as_ref())),
+            Stmt::For {
+                var, body, span, ..
+            } => {
+                self.scopes.push(Vec::new());
+                self.bind(var, span.clone());
+                self.check_block(std::slice::from_ref(body.as_ref()));
+                self.scopes.pop();
+            }
+            _ => {}
+        }
+    }
+
+    fn bind(&mut self, name: &'src str, span: Range<usize>) {
+        // `_` discards its value and never names anything
+        if name != "_" {
+            let outer = self.scopes[..self.scopes.len() - 1]
+                .iter()
+                .rev()
+                .flatten()
+                .find(|(outer, _)| *outer == name);
+            if let Some((_, outer_span)) = outer {
+                self.warnings.push(
+                    Diagnostic::warning(
+                        format!("'{}' shadows a binding from an enclosing scope", name),
+                        span.clone(),
+                    )
+                    .with_related(outer_span.clone()),
+                );
+            }
+        }
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push((name, span));
+        }
+    }
+}
+
+fn check_list(stmts: &[Stmt], warnings: &mut Vec<Diagnostic>) {
+    for (i, stmt) in stmts.iter().enumerate() {
+        check_nested(stmt, warnings);
+        if terminates(stmt) {
+            if let Some(dead) = stmts.get(i + 1) {
+                warnings.push(Diagnostic::warning(
+                    "unreachable statement after return, break or continue",
+                    dead.span(),
+                ));
+            }
+            return;
+        }
+    }
+}
+
+fn check_nested(stmt: &Stmt, warnings: &mut Vec<Diagnostic>) {
+    match stmt {
+        Stmt::Block { statements, .. } => check_list(statements, warnings),
+        Stmt::If {
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            check_nested(then_branch, warnings);
+            if let Some(else_stmt) = else_branch {
+                check_nested(else_stmt, warnings);
+            }
+        }
+        Stmt::While { body, .. } | Stmt::For { body, .. } | Stmt::Staged { stmt: body, .. } => {
+            check_nested(body, warnings)
+        }
+        _ => {}
+    }
+}
+
+/// Whether control never falls through `stmt` to the next statement.
+pub fn terminates(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::Return { .. } | Stmt::Break { .. } | Stmt::Continue { .. } => true,
+        Stmt::Block { statements, .. } => statements.iter().any(terminates),
+        Stmt::Staged { stmt, .. } => terminates(stmt),
+        Stmt::If {
+            then_branch,
+            else_branch: Some(else_stmt),
+            ..
+        } => terminates(then_branch) && terminates(else_stmt),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Flare;
+
+    use super::*;
+
+    fn kernel_from(source: &str) -> KernelDef<'_> {
+        let program = Flare::compile_from_string(source).unwrap();
+        match program.items.into_iter().next() {
+            Some(Stmt::Kernel(kernel)) => kernel,
+            _ => panic!("expected a kernel"),
+        }
+    }
+
+    #[test]
+    fn test_nested_loop_break() {
+        let source = r#"
+            kernel k(A: Tensor<f32, [N]>) {
+                for i in 0..N {
+                    break;
+                    A[i] = 0.0
+                }
+                A[0] = 1.0
+            }
+        "#;
+        let kernel = kernel_from(source);
+
+        let warnings = unreachable_code(&kernel);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(&source[warnings[0].span.clone()], "A[i] = 0.0");
+    }
+
+    #[test]
+    fn test_inner_let_shadows_outer() {
+        let source = r#"
+            kernel k(A: Tensor<f32, [N]>) {
+                let x = thread_idx.x
+                if x > 4 {
+                    let x = 0
+                    A[x] = 1.0
+                }
+                let y = x
+            }
+        "#;
+        let warnings = shadowed_bindings(&kernel_from(source));
+
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(&source[warnings[0].span.clone()], "let x = 0");
+        assert_eq!(
+            &source[warnings[0].related.clone().unwrap()],
+            "let x = thread_idx.x"
+        );
+    }
+
+    #[test]
+    fn test_loop_var_shadows_param() {
+        let source = r#"
+            kernel k(A: Tensor<f32, [N]>, i: u32) {
+                for i in 0..N {
+                    A[i] = 0.0
+                }
+            }
+        "#;
+        let warnings = shadowed_bindings(&kernel_from(source));
+
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(&source[warnings[0].related.clone().unwrap()], "i: u32");
+    }
+}
@@ -1,36 +1,73 @@
+use super::kernel::apply_layout_attributes;
 use crate::ast::*;
+use crate::fold::fold_int;
 use crate::lexer::token::{Token, TokenKind};
-use crate::{FlareError, Lexer};
+use crate::{FlareError, Lexer, LineMap};
+use std::collections::{HashMap, VecDeque};
 use std::ops::Range;
 
 pub struct Parser<'src> {
     source: &'src str,
+    /// Tokens lexed so far. The lexer only runs as far as the parser has
+    /// looked, so a bad token late in the source does not stop earlier
+    /// items from parsing.
     tokens: Vec<Token<'src>>,
+    /// `None` once the input is exhausted or has failed to lex.
+    lexer: Option<Lexer<'src>>,
+    /// Lexing error ahead of the parser, reported once parsing reaches it.
+    lex_error: Option<FlareError>,
     current: usize,
+    /// Top-level integer `const`s parsed so far, for schedule factors.
+    consts: HashMap<&'src str, i64>,
+}
+
+/// Rough token count of `source`, at about one token per four bytes of
+/// typical kernel code, so the token buffer rarely has to grow.
+fn estimated_token_count(source: &str) -> usize {
+    source.len() / 4
 }
 
 impl<'src> Parser<'src> {
     pub fn new(source: &'src str) -> Result<Self, FlareError> {
-        let mut lexer = Lexer::new(source);
-        let mut tokens = Vec::new();
-
-        loop {
-            match lexer.peek() {
-                Some(Ok(token)) => {
-                    if token.kind != TokenKind::Newline {
-                        tokens.push(token);
-                    }
-                }
-                Some(Err(e)) => return Err(e),
-                None => break,
-            }
-        }
+        Self::with_token_capacity(source, estimated_token_count(source))
+    }
 
-        Ok(Self {
+    /// Like `new`, but reserves room for `capacity` tokens up front instead
+    /// of estimating it from the source length. Fails only when the first
+    /// token does not lex; later lexing errors are returned by whichever
+    /// parse reaches them.
+    pub fn with_token_capacity(source: &'src str, capacity: usize) -> Result<Self, FlareError> {
+        let mut parser = Self {
             source,
-            tokens,
+            tokens: Vec::with_capacity(capacity),
+            lexer: Some(Lexer::new(source)),
+            lex_error: None,
             current: 0,
-        })
+            consts: HashMap::new(),
+        };
+        parser.fill();
+        match parser.lex_error.take() {
+            Some(error) => Err(error),
+            None => Ok(parser),
+        }
+    }
+
+    /// Lexes the token at `current` if it has not been lexed yet.
+    fn fill(&mut self) {
+        if self.current < self.tokens.len() {
+            return;
+        }
+        let Some(lexer) = &mut self.lexer else {
+            return;
+        };
+        match lexer.peek() {
+            Some(Ok(token)) => self.tokens.push(token),
+            Some(Err(error)) => {
+                self.lex_error = Some(error);
+                self.lexer = None;
+            }
+            None => self.lexer = None,
+        }
     }
 
     pub fn parse(&mut self) -> Result<Program<'src>, FlareError> {
@@ -47,22 +84,70 @@ impl<'src> Parser<'src> {
 
     pub(crate) fn advance(&mut self) -> Result<&Token<'src>, FlareError> {
         if self.current >= self.tokens.len() {
-            return Err(FlareError::UnexpectedEof);
+            return Err(self.eof_error("more input".to_string()));
         }
-        let token = &self.tokens[self.current];
         self.current += 1;
-        Ok(token)
+        self.fill();
+        Ok(&self.tokens[self.current - 1])
     }
 
     pub(crate) fn expect(&mut self, expected: TokenKind) -> Result<&Token<'src>, FlareError> {
+        let description = describe_token(&expected);
+        self.expect_described(expected, description)
+    }
+
+    /// Like `expect`, naming the construct the token closes or follows,
+    /// e.g. "`}` after kernel body".
+    pub(crate) fn expect_after(
+        &mut self,
+        expected: TokenKind,
+        context: &str,
+    ) -> Result<&Token<'src>, FlareError> {
+        let description = format!("{} after {}", describe_token(&expected), context);
+        self.expect_described(expected, description)
+    }
+
+    fn expect_described(
+        &mut self,
+        expected: TokenKind,
+        description: String,
+    ) -> Result<&Token<'src>, FlareError> {
+        if self.current >= self.tokens.len() {
+            return Err(self.eof_error(description));
+        }
         let token = self.advance()?;
         if std::mem::discriminant(&token.kind) == std::mem::discriminant(&expected) {
             Ok(token)
         } else {
-            Err(FlareError::UnexpectedToken(format!(
-                "expected {:?}, found {:?} at {:?}",
-                expected, token.kind, token.span
-            )))
+            Err(FlareError::UnexpectedToken {
+                message: format!("expected {}, found {:?}", description, token.kind),
+                span: token.span.clone(),
+            })
+        }
+    }
+
+    /// End-of-input error located at the last token of the source, or the
+    /// lexing error that cut the input short.
+    pub(crate) fn eof_error(&self, expected: String) -> FlareError {
+        if let Some(error) = &self.lex_error {
+            return error.clone();
+        }
+        let span = self.tokens.last().map(|t| t.span.clone()).unwrap_or(0..0);
+        FlareError::UnexpectedEof {
+            expected,
+            line: LineMap::new(self.source).line(span.start),
+            span,
+        }
+    }
+
+    /// Span of the next token, or the end of the last one at end of input.
+    pub(crate) fn next_span(&self) -> Range<usize> {
+        match self.peek() {
+            Some(token) => token.span.clone(),
+            None => {
+                let end = self.tokens.last().map_or(0, |t| t.span.end);
+                end..end
+            }
         }
     }
 
@@ -77,14 +162,23 @@ impl<'src> Parser<'src> {
     pub(crate) fn match_token(&mut self, kind: &TokenKind) -> bool {
         if self.check(kind) {
             self.current += 1;
+            self.fill();
             true
         } else {
             false
         }
     }
 
+    /// Consumes the comma after a list element and reports whether another
+    /// element follows; a comma right before `closer` is a trailing comma.
+    pub(crate) fn list_continues(&mut self, closer: &TokenKind) -> bool {
+        self.match_token(&TokenKind::Comma) && !self.check(closer)
+    }
+
+    /// Source text of a token; a raw identifier `r#name` reads as `name`.
     pub(crate) fn get_string_from_span(&self, span: &Range<usize>) -> &'src str {
-        &self.source[span.start..span.end]
+        let text = &self.source[span.start..span.end];
+        text.strip_prefix("r#").unwrap_or(text)
     }
 
     pub(crate) fn span_from(&self, start: usize) -> Range<usize> {
@@ -100,17 +194,17 @@ impl<'src> Parser<'src> {
         let token = self.advance()?;
 
         let base_type = match &token.kind {
-            TokenKind::I32 => Type::I32,
-            TokenKind::I64 => Type::I64,
-            TokenKind::U32 => Type::U32,
-            TokenKind::U64 => Type::U64,
-            TokenKind::F32 => Type::F32,
-            TokenKind::F64 => Type::F64,
-            TokenKind::Bool => Type::Bool,
+            TokenKind::I32 => Type::I32(token.span.clone()),
+            TokenKind::I64 => Type::I64(token.span.clone()),
+            TokenKind::U32 => Type::U32(token.span.clone()),
+            TokenKind::U64 => Type::U64(token.span.clone()),
+            TokenKind::F32 => Type::F32(token.span.clone()),
+            TokenKind::F64 => Type::F64(token.span.clone()),
+            TokenKind::Bool => Type::Bool(token.span.clone()),
             TokenKind::Identifier(_) => {
                 let span = token.span.clone();
                 let name = self.get_string_from_span(&span);
-                Type::Named(name)
+                Type::Named(name, span)
             }
             TokenKind::Tensor => {
                 self.expect(TokenKind::Less)?;
@@ -127,13 +221,16 @@ impl<'src> Parser<'src> {
                             if let TokenKind::Identifier(_) | TokenKind::IntLiteral(_) = &tok.kind {
                                 shape.push(self.get_string_from_span(&tok_span));
                             } else {
-                                return Err(FlareError::UnexpectedToken(format!(
-                                    "expected dimension in tensor type, found {:?}",
-                                    tok.kind
-                                )));
+                                return Err(FlareError::UnexpectedToken {
+                                    message: format!(
+                                        "expected dimension in tensor type, found {:?}",
+                                        tok.kind
+                                    ),
+                                    span: tok_span,
+                                });
                             }
 
-                            if !self.match_token(&TokenKind::Comma) {
+                            if !self.list_continues(&TokenKind::RightBracket) {
                                 break;
                             }
                         }
@@ -142,10 +239,36 @@ impl<'src> Parser<'src> {
                     self.expect(TokenKind::RightBracket)?;
                 }
 
+                // optional layout: `Tensor<f32, [M, K], col_major>`
+                let mut strides = None;
+                if self.match_token(&TokenKind::Comma) {
+                    let layout_token = self.expect(TokenKind::Identifier(String::new()))?;
+                    let layout_span = layout_token.span.clone();
+                    strides = match self.get_string_from_span(&layout_span) {
+                        "row_major" => Some(contiguous_strides(&shape, true)),
+                        "col_major" => Some(contiguous_strides(&shape, false)),
+                        other => {
+                            let message = format!(
+                                "unknown tensor layout '{}'; expected `row_major` or `col_major`",
+                                other
+                            );
+                            return Err(FlareError::UnexpectedToken {
+                                message,
+                                span: layout_span,
+                            });
+                        }
+                    };
+                }
+
                 self.expect(TokenKind::Greater)?;
-                Type::Tensor { dtype, shape }
+                Type::Tensor {
+                    dtype,
+                    shape,
+                    strides,
+                }
             }
             TokenKind::Matrix => {
+                let start = token.span.start;
                 self.expect(TokenKind::Less)?;
                 let dtype = Box::new(self.parse_type()?);
                 let mut rows = None;
@@ -164,9 +287,15 @@ impl<'src> Parser<'src> {
                 }
 
                 self.expect(TokenKind::Greater)?;
-                Type::Matrix { dtype, rows, cols }
+                Type::Matrix {
+                    dtype,
+                    rows,
+                    cols,
+                    span: self.span_from(start),
+                }
             }
             TokenKind::Vector => {
+                let start = token.span.start;
                 self.expect(TokenKind::Less)?;
                 let dtype = Box::new(self.parse_type()?);
                 let mut len = None;
@@ -178,34 +307,41 @@ impl<'src> Parser<'src> {
                 }
 
                 self.expect(TokenKind::Greater)?;
-                Type::Vector { dtype, len }
+                Type::Vector {
+                    dtype,
+                    len,
+                    span: self.span_from(start),
+                }
             }
             TokenKind::Star => {
                 let inner = Box::new(self.parse_type()?);
                 Type::Ptr(inner)
             }
             _ => {
-                return Err(FlareError::UnexpectedToken(format!(
-                    "expected type, found {:?}",
-                    token.kind
-                )))
+                return Err(FlareError::UnexpectedToken {
+                    message: format!("expected type, found {:?}", token.kind),
+                    span: token.span.clone(),
+                })
             }
         };
 
         if self.check(&TokenKind::LeftBracket) {
             self.advance()?;
 
-            let size = if let Some(TokenKind::IntLiteral(n)) = self.peek_kind() {
-                let n = *n;
-                self.advance()?;
-                Some(n as usize)
+            // `f32[TILE]` keeps its size expression for the backend to fold
+            let (size, size_expr) = if self.check(&TokenKind::RightBracket) {
+                (None, None)
             } else {
-                None
+                match self.parse_expression()? {
+                    Expr::IntLiteral(n, _) => (Some(n as usize), None),
+                    expr => (None, Some(Box::new(expr))),
+                }
             };
             self.expect(TokenKind::RightBracket)?;
             return Ok(Type::Array {
                 dtype: Box::new(base_type),
                 size,
+                size_expr,
             });
         }
 
@@ -215,6 +351,13 @@ impl<'src> Parser<'src> {
     pub(crate) fn parse_schedule(&mut self) -> Result<ScheduleBlock<'src>, FlareError> {
         let start = self.expect(TokenKind::Schedule)?.span.start;
 
+        let mode = if self.match_token(&TokenKind::Manual) {
+            ScheduleMode::Manual
+        } else {
+            self.match_token(&TokenKind::Auto);
+            ScheduleMode::Auto
+        };
+
         let target = if !self.check(&TokenKind::LeftBrace) {
             let name_token = self.expect(TokenKind::Identifier(String::new()))?;
             let span = name_token.span.clone();
@@ -232,34 +375,16 @@ impl<'src> Parser<'src> {
                     TokenKind::Identifier(s) if s == "tile" => {
                         self.advance()?;
                         self.expect(TokenKind::LeftParen)?;
-                        let x = if let TokenKind::IntLiteral(n) = self.advance()?.kind {
-                            n
-                        } else {
-                            return Err(FlareError::UnexpectedToken(
-                                "expected integer for tile x".to_string(),
-                            ));
-                        };
+                        let x = self.parse_schedule_int("tile x")?;
 
                         let y = if self.match_token(&TokenKind::Comma) {
-                            if let TokenKind::IntLiteral(n) = self.advance()?.kind {
-                                Some(n)
-                            } else {
-                                return Err(FlareError::UnexpectedToken(
-                                    "expected integer for tile y".to_string(),
-                                ));
-                            }
+                            Some(self.parse_schedule_int("tile y")?)
                         } else {
                             None
                         };
 
                         let z = if y.is_some() && self.match_token(&TokenKind::Comma) {
-                            if let TokenKind::IntLiteral(n) = self.advance()?.kind {
-                                Some(n)
-                            } else {
-                                return Err(FlareError::UnexpectedToken(
-                                    "expected integer for tile z".to_string(),
-                                ));
-                            }
+                            Some(self.parse_schedule_int("tile z")?)
                         } else {
                             None
                         };
@@ -271,13 +396,7 @@ impl<'src> Parser<'src> {
                     TokenKind::Identifier(s) if s == "vectorize" => {
                         self.advance()?;
                         self.expect(TokenKind::LeftParen)?;
-                        let n = if let TokenKind::IntLiteral(n) = self.advance()?.kind {
-                            n
-                        } else {
-                            return Err(FlareError::UnexpectedToken(
-                                "expected integer for vectorize".to_string(),
-                            ));
-                        };
+                        let n = self.parse_schedule_int("vectorize")?;
                         self.expect(TokenKind::RightParen)?;
                         self.match_token(&TokenKind::Semicolon);
                         directives.push(ScheduleDirective::Vectorize(n));
@@ -285,36 +404,26 @@ impl<'src> Parser<'src> {
                     TokenKind::Identifier(s) if s == "unroll" => {
                         self.advance()?;
                         self.expect(TokenKind::LeftParen)?;
-                        let n = if let TokenKind::IntLiteral(n) = self.advance()?.kind {
-                            n
-                        } else {
-                            return Err(FlareError::UnexpectedToken(
-                                "expected integer for unroll".to_string(),
-                            ));
-                        };
+                        let n = self.parse_schedule_int("unroll")?;
                         self.expect(TokenKind::RightParen)?;
                         self.match_token(&TokenKind::Semicolon);
                         directives.push(ScheduleDirective::Unroll(n));
                     }
+                    TokenKind::Identifier(s) if s == "unroll_jam" => {
+                        self.advance()?;
+                        self.expect(TokenKind::LeftParen)?;
+                        let n = self.parse_schedule_int("unroll_jam")?;
+                        self.expect(TokenKind::RightParen)?;
+                        self.match_token(&TokenKind::Semicolon);
+                        directives.push(ScheduleDirective::UnrollJam(n));
+                    }
                     TokenKind::Identifier(s) if s == "threads" => {
                         self.advance()?;
                         self.expect(TokenKind::LeftParen)?;
-                        let x = if let TokenKind::IntLiteral(n) = self.advance()?.kind {
-                            n
-                        } else {
-                            return Err(FlareError::UnexpectedToken(
-                                "expected integer for threads x".to_string(),
-                            ));
-                        };
+                        let x = self.parse_schedule_int("threads x")?;
 
                         let y = if self.match_token(&TokenKind::Comma) {
-                            if let TokenKind::IntLiteral(n) = self.advance()?.kind {
-                                Some(n)
-                            } else {
-                                return Err(FlareError::UnexpectedToken(
-                                    "expected integer for threads y".to_string(),
-                                ));
-                            }
+                            Some(self.parse_schedule_int("threads y")?)
                         } else {
                             None
                         };
@@ -345,9 +454,10 @@ impl<'src> Parser<'src> {
                                 MemoryLocation::Named(self.get_string_from_span(&location_span))
                             }
                             _ => {
-                                return Err(FlareError::UnexpectedToken(
-                                    "expected memory location".to_string(),
-                                ))
+                                return Err(FlareError::UnexpectedToken {
+                                    message: "expected memory location".to_string(),
+                                    span: location_span,
+                                })
                             }
                         };
 
@@ -368,13 +478,13 @@ impl<'src> Parser<'src> {
                     TokenKind::Pipeline => {
                         self.advance()?;
                         let depth = if self.match_token(&TokenKind::LeftParen) {
-                            let d = if let TokenKind::IntLiteral(n) = self.advance()?.kind {
-                                Some(n)
-                            } else {
+                            let depth = if self.check(&TokenKind::RightParen) {
                                 None
+                            } else {
+                                Some(self.parse_schedule_int("pipeline depth")?)
                             };
                             self.expect(TokenKind::RightParen)?;
-                            d
+                            depth
                         } else {
                             None
                         };
@@ -386,11 +496,22 @@ impl<'src> Parser<'src> {
                         self.match_token(&TokenKind::Semicolon);
                         directives.push(ScheduleDirective::Parallel);
                     }
+                    TokenKind::Hints => {
+                        self.advance()?;
+                        self.expect(TokenKind::LeftBrace)?;
+                        let mut hints = Vec::new();
+                        while self.check_attribute() {
+                            hints.push(self.parse_attribute()?);
+                            self.match_token(&TokenKind::Semicolon);
+                        }
+                        self.expect(TokenKind::RightBrace)?;
+                        directives.push(ScheduleDirective::Hints(hints));
+                    }
                     _ => {
-                        return Err(FlareError::UnexpectedToken(format!(
-                            "unknown schedule directive: {:?}",
-                            token.kind
-                        )))
+                        return Err(FlareError::UnexpectedToken {
+                            message: format!("unknown schedule directive: {:?}", token.kind),
+                            span: token.span.clone(),
+                        })
                     }
                 }
             }
@@ -400,11 +521,52 @@ impl<'src> Parser<'src> {
         let span = self.span_from(start);
         Ok(ScheduleBlock {
             target,
+            mode,
             directives,
             span,
         })
     }
 
+    /// A schedule factor: an integer literal, possibly negated, or a
+    /// program-level `const` declared earlier that folds to one. Factors must
+    /// be positive.
+    fn parse_schedule_int(&mut self, what: &str) -> Result<i64, FlareError> {
+        let start = self.peek().map_or(0, |token| token.span.start);
+        let negate = self.match_token(&TokenKind::Minus);
+        let kind = self.advance()?.kind.clone();
+        let value = match &kind {
+            TokenKind::IntLiteral(n) => *n,
+            TokenKind::Identifier(name) if !negate => match self.consts.get(name.as_str()) {
+                Some(value) => *value,
+                None => {
+                    return Err(FlareError::UnexpectedToken {
+                        message: format!(
+                            "'{}' in {} is not a constant integer declared before the schedule",
+                            name, what
+                        ),
+                        span: self.span_from(start),
+                    })
+                }
+            },
+            _ => {
+                return Err(FlareError::UnexpectedToken {
+                    message: format!("expected integer for {}", what),
+                    span: self.span_from(start),
+                })
+            }
+        };
+        let value = if negate { -value } else { value };
+
+        if value < 1 {
+            return Err(FlareError::NonPositiveScheduleValue {
+                what: what.to_string(),
+                value,
+                span: self.span_from(start),
+            });
+        }
+        Ok(value)
+    }
+
     pub(crate) fn parse_fusion(&mut self) -> Result<FusionBlock<'src>, FlareError> {
         let start = self.expect(TokenKind::Fuse)?.span.start;
 
@@ -414,7 +576,10 @@ impl<'src> Parser<'src> {
             let name_span = name_token.span.clone();
             targets.push(self.get_string_from_span(&name_span));
 
-            if !self.match_token(&TokenKind::Comma) {
+            // a trailing comma is followed by the strategy or the next item
+            if !self.match_token(&TokenKind::Comma)
+                || !matches!(self.peek_kind(), Some(TokenKind::Identifier(_)))
+            {
                 break;
             }
         }
@@ -455,7 +620,7 @@ impl<'src> Parser<'src> {
                     let barrier_span = barrier_token.span.clone();
                     barriers.push(self.get_string_from_span(&barrier_span));
 
-                    if !self.match_token(&TokenKind::Comma) {
+                    if !self.list_continues(&TokenKind::RightBracket) {
                         break;
                     }
                 }
@@ -479,47 +644,150 @@ impl<'src> Parser<'src> {
         let start = 0;
         let mut items = Vec::new();
 
-        while self.peek().is_some() {
-            let mut attributes = Vec::new();
-            while self.check(&TokenKind::At) {
-                attributes.push(self.parse_attribute()?);
-            }
+        while let Some(item) = self.parse_item()? {
+            items.extend(item);
+        }
 
-            if let Some(token) = self.peek() {
-                match &token.kind {
-                    TokenKind::Kernel => {
-                        let mut kernel = self.parse_kernel()?;
-                        kernel.attributes = attributes;
-                        items.push(Stmt::Kernel(kernel));
-                    }
-                    TokenKind::Fuse => {
-                        let fusion = self.parse_fusion()?;
-                        items.push(Stmt::Fusion(fusion));
-                    }
-                    TokenKind::Schedule => {
-                        let schedule = self.parse_schedule()?;
-                        items.push(Stmt::Schedule(schedule));
-                    }
-                    TokenKind::Fn => {
-                        items.push(self.parse_statement()?);
-                    }
-                    TokenKind::Type => {
-                        items.push(self.parse_statement()?);
-                    }
-                    TokenKind::Let => {
-                        items.push(self.parse_statement()?);
-                    }
-                    _ => {
-                        return Err(FlareError::UnexpectedToken(format!(
-                            "Expected top-level item, found {:?}",
-                            token.kind
-                        )))
+        let span = self.span_from(start);
+        Ok(Program { items, span })
+    }
+
+    /// Parses the next top-level item with its attributes, or returns `None`
+    /// at the end of input. An `impl` block yields one item per method.
+    pub(crate) fn parse_item(&mut self) -> Result<Option<Vec<Stmt<'src>>>, FlareError> {
+        if self.peek().is_none() {
+            return self.lex_error.clone().map_or(Ok(None), Err);
+        }
+
+        let mut attributes = Vec::new();
+        while self.check_attribute() {
+            attributes.push(self.parse_attribute()?);
+        }
+
+        let Some(token) = self.peek() else {
+            return Ok(Some(Vec::new()));
+        };
+        let item = match &token.kind {
+            TokenKind::Kernel => {
+                let mut kernel = self.parse_kernel()?;
+                kernel.attributes = attributes;
+                apply_layout_attributes(&mut kernel)?;
+                Stmt::Kernel(kernel)
+            }
+            TokenKind::Fuse => Stmt::Fusion(self.parse_fusion()?),
+            TokenKind::Schedule => Stmt::Schedule(self.parse_schedule()?),
+            TokenKind::Fn => {
+                let mut stmt = self.parse_statement()?;
+                if let Stmt::Function {
+                    attributes: fn_attributes,
+                    ..
+                } = &mut stmt
+                {
+                    *fn_attributes = attributes;
+                }
+                stmt
+            }
+            TokenKind::Impl => return Ok(Some(self.parse_impl()?)),
+            TokenKind::Type => self.parse_statement()?,
+            TokenKind::Let => {
+                let mut stmt = self.parse_statement()?;
+                if let Stmt::Let {
+                    attributes: let_attributes,
+                    ..
+                } = &mut stmt
+                {
+                    *let_attributes = attributes;
+                }
+                stmt
+            }
+            TokenKind::Const => {
+                let mut stmt = self.parse_statement()?;
+                if let Stmt::Const {
+                    attributes: const_attributes,
+                    ..
+                } = &mut stmt
+                {
+                    *const_attributes = attributes;
+                }
+                if let Stmt::Const { name, value, .. } = &stmt {
+                    if let Some(value) = fold_int(value, &self.consts) {
+                        self.consts.insert(name, value);
                     }
                 }
+                stmt
+            }
+            _ => {
+                return Err(FlareError::UnexpectedToken {
+                    message: format!("Expected top-level item, found {:?}", token.kind),
+                    span: token.span.clone(),
+                })
             }
+        };
+        Ok(Some(vec![item]))
+    }
+}
+
+/// Top-level items of a source file, parsed one at a time; iteration ends
+/// after the first error.
+pub struct Items<'src> {
+    parser: Option<Parser<'src>>,
+    pending: VecDeque<Stmt<'src>>,
+    error: Option<FlareError>,
+}
+
+impl<'src> Items<'src> {
+    pub fn new(source: &'src str) -> Self {
+        let (parser, error) = match Parser::new(source) {
+            Ok(parser) => (Some(parser), None),
+            Err(error) => (None, Some(error)),
+        };
+        Self {
+            parser,
+            pending: VecDeque::new(),
+            error,
         }
+    }
+}
 
-        let span = self.span_from(start);
-        Ok(Program { items, span })
+impl<'src> Iterator for Items<'src> {
+    type Item = Result<Stmt<'src>, FlareError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(error) = self.error.take() {
+            return Some(Err(error));
+        }
+        while self.pending.is_empty() {
+            let result = self.parser.as_mut()?.parse_item();
+            match result {
+                Ok(Some(items)) => self.pending.extend(items),
+                Ok(None) => self.parser = None,
+                Err(error) => {
+                    self.parser = None;
+                    return Some(Err(error));
+                }
+            }
+        }
+        self.pending.pop_front().map(Ok)
     }
 }
+
+/// Source spelling of punctuation tokens for error messages.
+fn describe_token(kind: &TokenKind) -> String {
+    let text = match kind {
+        TokenKind::LeftBrace => "{",
+        TokenKind::RightBrace => "}",
+        TokenKind::LeftParen => "(",
+        TokenKind::RightParen => ")",
+        TokenKind::LeftBracket => "[",
+        TokenKind::RightBracket => "]",
+        TokenKind::Less => "<",
+        TokenKind::Greater => ">",
+        TokenKind::Colon => ":",
+        TokenKind::Comma => ",",
+        TokenKind::Semicolon => ";",
+        TokenKind::Assign => "=",
+        TokenKind::Identifier(_) => return "identifier".to_string(),
+        other => return format!("{:?}", other),
+    };
+    format!("`{}`", text)
+}
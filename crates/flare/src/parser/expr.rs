@@ -2,6 +2,7 @@ use super::core::Parser;
 use crate::ast::*;
 use crate::lexer::token::TokenKind;
 use crate::FlareError;
+use std::ops::Range;
 
 impl<'src> Parser<'src> {
     pub(crate) fn parse_expression(&mut self) -> Result<Expr<'src>, FlareError> {
@@ -9,7 +10,7 @@ impl<'src> Parser<'src> {
     }
 
     fn parse_assignment(&mut self) -> Result<Expr<'src>, FlareError> {
-        let expr = self.parse_logical_or()?;
+        let expr = self.parse_ternary()?;
 
         if let Some(token) = self.peek() {
             let start = expr.span().start;
@@ -52,6 +53,27 @@ impl<'src> Parser<'src> {
         Ok(expr)
     }
 
+    /// `cond ? a : b`, sugar for `if cond { a } else { b }`. Right
+    /// associative, so `a ? b : c ? d : e` nests in the else branch.
+    fn parse_ternary(&mut self) -> Result<Expr<'src>, FlareError> {
+        let condition = self.parse_logical_or()?;
+        if !self.match_token(&TokenKind::Question) {
+            return Ok(condition);
+        }
+
+        let start = condition.span().start;
+        let then_branch = self.parse_ternary()?;
+        self.expect(TokenKind::Colon)?;
+        let else_branch = self.parse_ternary()?;
+        let span = self.span_from(start);
+        Ok(Expr::If {
+            condition: Box::new(condition),
+            then_branch: Box::new(then_branch),
+            else_branch: Some(Box::new(else_branch)),
+            span,
+        })
+    }
+
     fn parse_logical_or(&mut self) -> Result<Expr<'src>, FlareError> {
         let mut left = self.parse_logical_and()?;
 
@@ -114,6 +136,8 @@ impl<'src> Parser<'src> {
 
     fn parse_comparison(&mut self) -> Result<Expr<'src>, FlareError> {
         let mut left = self.parse_range()?;
+        // right operand of the comparison parsed so far, if any
+        let mut middle: Option<Range<usize>> = None;
 
         while let Some(token) = self.peek() {
             let op = match &token.kind {
@@ -123,10 +147,24 @@ impl<'src> Parser<'src> {
                 TokenKind::GreaterEqual => BinOp::GreaterEqual,
                 _ => break,
             };
-            self.advance()?;
+            let op_span = self.advance()?.span.clone();
             let start = left.span().start;
             let right = self.parse_range()?;
             let span = self.span_from(start);
+
+            // `a < b < c` would compare the bool `a < b` with `c`
+            if let Some(middle) = middle {
+                let suggestion = format!(
+                    "{} && {} {} {}",
+                    self.get_string_from_span(&left.span()),
+                    self.get_string_from_span(&middle),
+                    self.get_string_from_span(&op_span),
+                    self.get_string_from_span(&right.span())
+                );
+                return Err(FlareError::ChainedComparison { suggestion, span });
+            }
+            middle = Some(right.span());
+
             left = Expr::Binary {
                 left: Box::new(left),
                 op,
@@ -155,6 +193,7 @@ impl<'src> Parser<'src> {
             return Ok(Expr::Range {
                 start: Some(Box::new(start_expr)),
                 end,
+                step: None,
                 span,
             });
         }
@@ -187,7 +226,7 @@ impl<'src> Parser<'src> {
     }
 
     fn parse_factor(&mut self) -> Result<Expr<'src>, FlareError> {
-        let mut left = self.parse_unary()?;
+        let mut left = self.parse_cast()?;
 
         while let Some(token) = self.peek() {
             let op = match &token.kind {
@@ -198,7 +237,7 @@ impl<'src> Parser<'src> {
             };
             self.advance()?;
             let start = left.span().start;
-            let right = self.parse_unary()?;
+            let right = self.parse_cast()?;
             let span = self.span_from(start);
             left = Expr::Binary {
                 left: Box::new(left),
@@ -211,7 +250,24 @@ impl<'src> Parser<'src> {
         Ok(left)
     }
 
-    fn parse_unary(&mut self) -> Result<Expr<'src>, FlareError> {
+    fn parse_cast(&mut self) -> Result<Expr<'src>, FlareError> {
+        let mut expr = self.parse_unary()?;
+
+        while self.match_token(&TokenKind::As) {
+            let start = expr.span().start;
+            let target_type = self.parse_type()?;
+            let span = self.span_from(start);
+            expr = Expr::Cast {
+                expr: Box::new(expr),
+                target_type,
+                span,
+            };
+        }
+
+        Ok(expr)
+    }
+
+    pub(crate) fn parse_unary(&mut self) -> Result<Expr<'src>, FlareError> {
         if let Some(token) = self.peek() {
             let start = token.span.start;
             match &token.kind {
@@ -235,6 +291,28 @@ impl<'src> Parser<'src> {
                         span,
                     });
                 }
+                // in prefix position `*` can only be a dereference; binary
+                // multiplication is handled by `parse_factor`
+                TokenKind::Star => {
+                    self.advance()?;
+                    let expr = self.parse_unary()?;
+                    let span = self.span_from(start);
+                    return Ok(Expr::Unary {
+                        op: UnOp::Deref,
+                        expr: Box::new(expr),
+                        span,
+                    });
+                }
+                TokenKind::Ampersand => {
+                    self.advance()?;
+                    let expr = self.parse_unary()?;
+                    let span = self.span_from(start);
+                    return Ok(Expr::Unary {
+                        op: UnOp::AddrOf,
+                        expr: Box::new(expr),
+                        span,
+                    });
+                }
                 _ => {}
             }
         }
@@ -256,7 +334,7 @@ impl<'src> Parser<'src> {
                         if !self.check(&TokenKind::RightParen) {
                             loop {
                                 args.push(self.parse_expression()?);
-                                if !self.match_token(&TokenKind::Comma) {
+                                if !self.list_continues(&TokenKind::RightParen) {
                                     break;
                                 }
                             }
@@ -277,7 +355,7 @@ impl<'src> Parser<'src> {
                         if !self.check(&TokenKind::RightBracket) {
                             loop {
                                 indices.push(self.parse_expression()?);
-                                if !self.match_token(&TokenKind::Comma) {
+                                if !self.list_continues(&TokenKind::RightBracket) {
                                     break;
                                 }
                             }
@@ -319,12 +397,34 @@ impl<'src> Parser<'src> {
 
         match &token.kind {
             TokenKind::IntLiteral(n) => Ok(Expr::IntLiteral(*n, span)),
+            TokenKind::UIntLiteral(n) => Ok(Expr::UIntLiteral(*n, span)),
             TokenKind::FloatLiteral(f) => Ok(Expr::FloatLiteral(*f, span)),
             TokenKind::StringLiteral(s) => Ok(Expr::StringLiteral(s.clone(), span)),
             TokenKind::True => Ok(Expr::BoolLiteral(true, span)),
             TokenKind::False => Ok(Expr::BoolLiteral(false, span)),
             TokenKind::Identifier(_) => {
                 let name = self.get_string_from_span(&span);
+                // the argument of `sizeof` is a type, not an expression
+                if name == "sizeof" && self.match_token(&TokenKind::LeftParen) {
+                    let ty = self.parse_type()?;
+                    self.expect(TokenKind::RightParen)?;
+                    let span = self.span_from(span.start);
+                    return Ok(Expr::SizeOf { ty, span });
+                }
+                // `as_type<T>(x)` takes its target type as a generic argument
+                if name == "as_type" && self.match_token(&TokenKind::Less) {
+                    let target_type = self.parse_type()?;
+                    self.expect(TokenKind::Greater)?;
+                    self.expect(TokenKind::LeftParen)?;
+                    let expr = self.parse_expression()?;
+                    self.expect(TokenKind::RightParen)?;
+                    let span = self.span_from(span.start);
+                    return Ok(Expr::Bitcast {
+                        expr: Box::new(expr),
+                        target_type,
+                        span,
+                    });
+                }
                 Ok(Expr::Ident(name, span))
             }
             TokenKind::LeftParen => {
@@ -339,7 +439,7 @@ impl<'src> Parser<'src> {
                 if !self.check(&TokenKind::RightBracket) {
                     loop {
                         elements.push(self.parse_expression()?);
-                        if !self.match_token(&TokenKind::Comma) {
+                        if !self.list_continues(&TokenKind::RightBracket) {
                             break;
                         }
                     }
@@ -435,15 +535,18 @@ impl<'src> Parser<'src> {
                             }
                             TokenKind::IntLiteral(n) => Expr::IntLiteral(*n, tok_span),
                             _ => {
-                                return Err(FlareError::UnexpectedToken(format!(
-                                    "Expected dimension in tensor initialization, found {:?}",
-                                    tok.kind
-                                )))
+                                return Err(FlareError::UnexpectedToken {
+                                    message: format!(
+                                        "Expected dimension in tensor initialization, found {:?}",
+                                        tok.kind
+                                    ),
+                                    span: tok_span,
+                                })
                             }
                         };
                         shape.push(dim_expr);
 
-                        if !self.match_token(&TokenKind::Comma) {
+                        if !self.list_continues(&TokenKind::Greater) {
                             break;
                         }
                     }
@@ -453,10 +556,10 @@ impl<'src> Parser<'src> {
                 let span = self.span_from(start);
                 Ok(Expr::TensorInit { dtype, shape, span })
             }
-            _ => Err(FlareError::UnexpectedToken(format!(
-                "unexpected token in expression: {:?}",
-                token.kind
-            ))),
+            _ => Err(FlareError::UnexpectedToken {
+                message: format!("unexpected token in expression: {:?}", token.kind),
+                span,
+            }),
         }
     }
 
@@ -11,13 +11,25 @@ impl<'src> Parser<'src> {
         let name = self.get_string_from_span(&name_token_span);
 
         let mut generic_params = Vec::new();
+        let mut const_params = Vec::new();
         if self.match_token(&TokenKind::Less) {
             loop {
-                let generic_token = self.expect(TokenKind::Identifier(String::new()))?;
-                let generic_span = generic_token.span.clone();
-                generic_params.push(self.get_string_from_span(&generic_span));
+                if self.check(&TokenKind::Const) {
+                    let const_start = self.advance()?.span.start;
+                    let generic_token = self.expect(TokenKind::Identifier(String::new()))?;
+                    let generic_span = generic_token.span.clone();
+                    let name = self.get_string_from_span(&generic_span);
+                    self.expect(TokenKind::Colon)?;
+                    let ty = self.parse_type()?;
+                    let span = self.span_from(const_start);
+                    const_params.push(ConstParam { name, ty, span });
+                } else {
+                    let generic_token = self.expect(TokenKind::Identifier(String::new()))?;
+                    let generic_span = generic_token.span.clone();
+                    generic_params.push(self.get_string_from_span(&generic_span));
+                }
 
-                if !self.match_token(&TokenKind::Comma) {
+                if !self.list_continues(&TokenKind::Greater) {
                     break;
                 }
             }
@@ -30,6 +42,10 @@ impl<'src> Parser<'src> {
         if !self.check(&TokenKind::RightParen) {
             loop {
                 let param_start = self.peek().map(|t| t.span.start).unwrap_or(0);
+                let mut attributes = Vec::new();
+                while self.check_attribute() {
+                    attributes.push(self.parse_attribute()?);
+                }
                 let param_name_token = self.expect(TokenKind::Identifier(String::new()))?;
                 let param_name_token_span = param_name_token.span.clone();
                 let param_name = self.get_string_from_span(&param_name_token_span);
@@ -40,10 +56,11 @@ impl<'src> Parser<'src> {
                 params.push(Param {
                     name: param_name,
                     ty: param_type,
+                    attributes,
                     span: param_span,
                 });
 
-                if !self.match_token(&TokenKind::Comma) {
+                if !self.list_continues(&TokenKind::RightParen) {
                     break;
                 }
             }
@@ -57,6 +74,16 @@ impl<'src> Parser<'src> {
             None
         };
 
+        let mut constraints = Vec::new();
+        if self.match_token(&TokenKind::Where) {
+            loop {
+                constraints.push(self.parse_expression()?);
+                if !self.list_continues(&TokenKind::LeftBrace) {
+                    break;
+                }
+            }
+        }
+
         self.expect(TokenKind::LeftBrace)?;
         let mut grid = None;
         let mut block = None;
@@ -78,14 +105,16 @@ impl<'src> Parser<'src> {
             }
         }
 
-        self.expect(TokenKind::RightBrace)?;
+        self.expect_after(TokenKind::RightBrace, "kernel body")?;
 
         let span = self.span_from(start);
         Ok(KernelDef {
             name,
             generic_params,
+            const_params,
             params,
             return_type,
+            constraints,
             grid,
             block,
             shared_memory,
@@ -105,7 +134,7 @@ impl<'src> Parser<'src> {
         if !self.check(&TokenKind::RightBracket) {
             loop {
                 dimensions.push(self.parse_expression()?);
-                if !self.match_token(&TokenKind::Comma) {
+                if !self.list_continues(&TokenKind::RightBracket) {
                     break;
                 }
             }
@@ -124,7 +153,7 @@ impl<'src> Parser<'src> {
         if !self.check(&TokenKind::RightBracket) {
             loop {
                 dimensions.push(self.parse_expression()?);
-                if !self.match_token(&TokenKind::Comma) {
+                if !self.list_continues(&TokenKind::RightBracket) {
                     break;
                 }
             }
@@ -153,7 +182,7 @@ impl<'src> Parser<'src> {
             if !self.check(&TokenKind::RightBracket) {
                 loop {
                     shape.push(self.parse_expression()?);
-                    if !self.match_token(&TokenKind::Comma) {
+                    if !self.list_continues(&TokenKind::RightBracket) {
                         break;
                     }
                 }
@@ -161,10 +190,17 @@ impl<'src> Parser<'src> {
 
             self.expect(TokenKind::RightBracket)?;
 
+            // optional element type: `tile: [16, 16]: f32`
+            let ty = if self.match_token(&TokenKind::Colon) {
+                Some(self.parse_type()?)
+            } else {
+                None
+            };
+
             let span = self.span_from(decl_start);
             decls.push(SharedMemoryDecl {
                 name,
-                ty: None,
+                ty,
                 shape,
                 span,
             });
@@ -188,34 +224,43 @@ impl<'src> Parser<'src> {
         Ok(statements)
     }
 
+    pub(crate) fn check_attribute(&self) -> bool {
+        match self.peek_kind() {
+            Some(TokenKind::At) => true,
+            Some(kind) => annotation_name(kind).is_some(),
+            None => false,
+        }
+    }
+
     pub(crate) fn parse_attribute(&mut self) -> Result<Attribute<'src>, FlareError> {
-        let start = self.expect(TokenKind::At)?.span.start;
-        let name_token = self.advance()?;
-        let name_span = name_token.span.clone();
-        let name = match &name_token.kind {
-            TokenKind::Identifier(_) => self.get_string_from_span(&name_span),
-            TokenKind::FusionPoint => "fusion_point",
-            TokenKind::Fusable => "fusable",
-            TokenKind::FusionTransform => "fusion_transform",
-            TokenKind::FusedKernel => "fused_kernel",
-            TokenKind::Optimize => "optimize",
-            TokenKind::AutoTune => "auto_tune",
-            TokenKind::ScheduleAnnotation => "schedule",
-            TokenKind::MemoryAnnotation => "memory",
-            TokenKind::DependsOn => "depends_on",
-            TokenKind::Independent => "independent",
-            TokenKind::PreferParallel => "prefer_parallel",
-            TokenKind::MustWait => "must_wait",
-            TokenKind::DynamicDispatch => "dynamic_dispatch",
-            TokenKind::PipelineDepth => "pipeline_depth",
-            TokenKind::P2PTransferAnnotation => "p2p_transfer",
-            TokenKind::AllReduceAnnotation => "all_reduce",
-            _ => {
-                return Err(FlareError::UnexpectedToken(format!(
-                    "expected attribute name, found {:?}",
-                    name_token.kind
-                )))
+        let token = self.advance()?;
+        let start = token.span.start;
+        let name = match &token.kind {
+            TokenKind::At => {
+                let name_token = self.advance()?;
+                let name_span = name_token.span.clone();
+                match &name_token.kind {
+                    TokenKind::Identifier(_) => self.get_string_from_span(&name_span),
+                    _ => {
+                        return Err(FlareError::UnexpectedToken {
+                            message: format!(
+                                "expected attribute name, found {:?}",
+                                name_token.kind
+                            ),
+                            span: name_span,
+                        })
+                    }
+                }
             }
+            kind => match annotation_name(kind) {
+                Some(name) => name,
+                None => {
+                    return Err(FlareError::UnexpectedToken {
+                        message: format!("expected attribute, found {:?}", kind),
+                        span: token.span.clone(),
+                    })
+                }
+            },
         };
 
         let mut args = Vec::new();
@@ -223,24 +268,9 @@ impl<'src> Parser<'src> {
         if self.match_token(&TokenKind::LeftParen) {
             if !self.check(&TokenKind::RightParen) {
                 loop {
-                    let arg_token = self.advance()?;
-                    let arg_span = arg_token.span.clone();
-                    let arg = match &arg_token.kind {
-                        TokenKind::Identifier(_) => {
-                            AttributeArg::Ident(self.get_string_from_span(&arg_span))
-                        }
-                        TokenKind::IntLiteral(n) => AttributeArg::IntLiteral(*n),
-                        TokenKind::StringLiteral(s) => AttributeArg::StringLiteral(s.clone()),
-                        _ => {
-                            return Err(FlareError::UnexpectedToken(format!(
-                                "expected attribute argument, found {:?}",
-                                arg_token.kind
-                            )))
-                        }
-                    };
-                    args.push(arg);
-
-                    if !self.match_token(&TokenKind::Comma) {
+                    args.push(self.parse_attribute_arg()?);
+
+                    if !self.list_continues(&TokenKind::RightParen) {
                         break;
                     }
                 }
@@ -251,4 +281,121 @@ impl<'src> Parser<'src> {
         let span = self.span_from(start);
         Ok(Attribute { name, args, span })
     }
+
+    /// Parses a single attribute argument: a literal or identifier, a
+    /// `key = value` pair, or a bracketed `[a, b, c]` list.
+    fn parse_attribute_arg(&mut self) -> Result<AttributeArg<'src>, FlareError> {
+        if self.match_token(&TokenKind::LeftBracket) {
+            let mut items = Vec::new();
+            if !self.check(&TokenKind::RightBracket) {
+                loop {
+                    items.push(self.parse_attribute_arg()?);
+                    if !self.list_continues(&TokenKind::RightBracket) {
+                        break;
+                    }
+                }
+            }
+            self.expect(TokenKind::RightBracket)?;
+            return Ok(AttributeArg::List(items));
+        }
+
+        let arg_token = self.advance()?.clone();
+        let arg_span = arg_token.span.clone();
+        match &arg_token.kind {
+            TokenKind::IntLiteral(n) => Ok(AttributeArg::IntLiteral(*n)),
+            TokenKind::StringLiteral(s) => Ok(AttributeArg::StringLiteral(s.clone())),
+            _ => {
+                // keywords such as `device` are accepted as plain words here
+                let word = self.get_string_from_span(&arg_span);
+                if !word.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+                    return Err(FlareError::UnexpectedToken {
+                        message: format!("expected attribute argument, found {:?}", arg_token.kind),
+                        span: arg_span,
+                    });
+                }
+
+                if self.match_token(&TokenKind::Assign) {
+                    let value = self.parse_attribute_arg()?;
+                    Ok(AttributeArg::KeyValue {
+                        key: word,
+                        value: Box::new(value),
+                    })
+                } else {
+                    Ok(AttributeArg::Ident(word))
+                }
+            }
+        }
+    }
+}
+
+/// Stores the strides of each `@layout(A, [1, M])` on the type of param `A`.
+pub(crate) fn apply_layout_attributes(kernel: &mut KernelDef) -> Result<(), FlareError> {
+    for attr in kernel
+        .attributes
+        .iter()
+        .filter(|attr| attr.name == "layout")
+    {
+        let invalid = |message: String| FlareError::UnexpectedToken {
+            message,
+            span: attr.span.clone(),
+        };
+
+        let (name, items) = match attr.args.as_slice() {
+            [AttributeArg::Ident(name), AttributeArg::List(items)] => (*name, items),
+            _ => return Err(invalid("expected `@layout(param, [strides])`".to_string())),
+        };
+
+        let mut strides = Vec::new();
+        for item in items {
+            match item {
+                AttributeArg::IntLiteral(n) => strides.push(n.to_string()),
+                AttributeArg::Ident(dim) => strides.push(dim.to_string()),
+                other => return Err(invalid(format!("invalid stride {:?}", other))),
+            }
+        }
+
+        let param = kernel
+            .params
+            .iter_mut()
+            .find(|param| param.name == name)
+            .ok_or_else(|| invalid(format!("@layout names unknown parameter '{}'", name)))?;
+        match &mut param.ty {
+            Type::Tensor {
+                shape,
+                strides: param_strides,
+                ..
+            } if shape.len() == strides.len() => *param_strides = Some(strides),
+            _ => {
+                return Err(invalid(format!(
+                    "@layout of '{}' needs one stride per dimension of a tensor parameter",
+                    name
+                )))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Attribute name for the lexer's dedicated `@name` annotation tokens.
+pub(crate) fn annotation_name(kind: &TokenKind) -> Option<&'static str> {
+    match kind {
+        TokenKind::FusionPoint => Some("fusion_point"),
+        TokenKind::Fusable => Some("fusable"),
+        TokenKind::FusionTransform => Some("fusion_transform"),
+        TokenKind::FusedKernel => Some("fused_kernel"),
+        TokenKind::Optimize => Some("optimize"),
+        TokenKind::AutoTune => Some("auto_tune"),
+        TokenKind::ScheduleAnnotation => Some("schedule"),
+        TokenKind::MemoryAnnotation => Some("memory"),
+        TokenKind::DependsOn => Some("depends_on"),
+        TokenKind::Independent => Some("independent"),
+        TokenKind::PreferParallel => Some("prefer_parallel"),
+        TokenKind::MustWait => Some("must_wait"),
+        TokenKind::DynamicDispatch => Some("dynamic_dispatch"),
+        TokenKind::PipelineDepth => Some("pipeline_depth"),
+        TokenKind::P2PTransferAnnotation => Some("p2p_transfer"),
+        TokenKind::AllReduceAnnotation => Some("all_reduce"),
+        TokenKind::StageAnnotation => Some("stage"),
+        _ => None,
+    }
 }
@@ -14,11 +14,15 @@ impl<'src> Parser<'src> {
                 TokenKind::While => self.parse_while_statement(),
                 TokenKind::For => self.parse_for_statement(),
                 TokenKind::Return => self.parse_return_statement(),
+                TokenKind::Break => self.parse_break_statement(),
+                TokenKind::Continue => self.parse_continue_statement(),
+                TokenKind::Label(_) => self.parse_labeled_loop(),
                 TokenKind::LeftBrace => self.parse_block_statement(),
                 TokenKind::SyncThreads => self.parse_sync_threads(),
                 TokenKind::LoadShared => self.parse_load_shared(),
                 TokenKind::Type => self.parse_type_def(),
                 TokenKind::Fn => self.parse_function(),
+                _ if self.check_attribute() => self.parse_annotated_statement(),
                 _ => {
                     let expr = self.parse_expression()?;
                     if self.match_token(&TokenKind::Semicolon) {}
@@ -26,7 +30,7 @@ impl<'src> Parser<'src> {
                 }
             }
         } else {
-            Err(FlareError::UnexpectedEof)
+            Err(self.eof_error("statement".to_string()))
         }
     }
 
@@ -51,10 +55,73 @@ impl<'src> Parser<'src> {
             name,
             ty,
             value,
+            attributes: Vec::new(),
             span,
         })
     }
 
+    /// Parses attributes ahead of a statement: `@stage(n)` before any
+    /// statement, and others such as `@constant` before a `let` binding.
+    fn parse_annotated_statement(&mut self) -> Result<Stmt<'src>, FlareError> {
+        let start = self.next_span().start;
+        let mut attributes = Vec::new();
+        let mut stage = None;
+        while self.check_attribute() {
+            let attribute = self.parse_attribute()?;
+            if attribute.name != "stage" {
+                attributes.push(attribute);
+                continue;
+            }
+            if stage.is_some() {
+                return Err(FlareError::UnexpectedToken {
+                    message: "a statement can belong to only one stage".to_string(),
+                    span: attribute.span,
+                });
+            }
+            stage = match attribute.args.as_slice() {
+                [AttributeArg::IntLiteral(n)] if *n >= 0 => Some(*n),
+                _ => {
+                    return Err(FlareError::UnexpectedToken {
+                        message: "expected `@stage(n)` with a non-negative stage number"
+                            .to_string(),
+                        span: attribute.span,
+                    })
+                }
+            };
+        }
+
+        let stmt = if self.check(&TokenKind::Let) {
+            let mut stmt = self.parse_let_statement()?;
+            if let Stmt::Let {
+                attributes: let_attributes,
+                ..
+            } = &mut stmt
+            {
+                *let_attributes = attributes;
+            }
+            stmt
+        } else if attributes.is_empty() {
+            self.parse_statement()?
+        } else {
+            return Err(FlareError::UnexpectedToken {
+                message: format!(
+                    "statement attributes must precede a `let`, found {:?}",
+                    self.peek_kind()
+                ),
+                span: self.next_span(),
+            });
+        };
+
+        Ok(match stage {
+            Some(stage) => Stmt::Staged {
+                stage,
+                stmt: Box::new(stmt),
+                span: self.span_from(start),
+            },
+            None => stmt,
+        })
+    }
+
     fn parse_var_statement(&mut self) -> Result<Stmt<'src>, FlareError> {
         let start = self.expect(TokenKind::Var)?.span.start;
         let name_token = self.expect(TokenKind::Identifier(String::new()))?;
@@ -78,6 +145,7 @@ impl<'src> Parser<'src> {
         let span = self.span_from(start);
         Ok(Stmt::Var {
             name,
+            name_span: name_token_span,
             ty,
             value,
             span,
@@ -105,6 +173,7 @@ impl<'src> Parser<'src> {
             name,
             ty,
             value,
+            attributes: Vec::new(),
             span,
         })
     }
@@ -135,6 +204,7 @@ impl<'src> Parser<'src> {
 
         let span = self.span_from(start);
         Ok(Stmt::While {
+            label: None,
             condition,
             body,
             span,
@@ -147,11 +217,30 @@ impl<'src> Parser<'src> {
         let var_token_span = var_token.span.clone();
         let var = self.get_string_from_span(&var_token_span);
         self.expect(TokenKind::In)?;
-        let iterator = self.parse_expression()?;
+        let mut iterator = self.parse_expression()?;
+
+        if matches!(self.peek_kind(), Some(TokenKind::Identifier(s)) if s == "step") {
+            self.advance()?;
+            let step_expr = self.parse_unary()?;
+            match &mut iterator {
+                Expr::Range { step, span, .. } => {
+                    *step = Some(Box::new(step_expr));
+                    *span = self.span_from(span.start);
+                }
+                _ => {
+                    return Err(FlareError::UnexpectedToken {
+                        message: "`step` requires a range iterator".to_string(),
+                        span: iterator.span(),
+                    })
+                }
+            }
+        }
+
         let body = Box::new(self.parse_statement()?);
 
         let span = self.span_from(start);
         Ok(Stmt::For {
+            label: None,
             var,
             iterator,
             body,
@@ -161,7 +250,11 @@ impl<'src> Parser<'src> {
 
     fn parse_return_statement(&mut self) -> Result<Stmt<'src>, FlareError> {
         let start = self.expect(TokenKind::Return)?.span.start;
-        let value = if self.check(&TokenKind::Semicolon) {
+        // a bare `return` ends at a `;`, the brace closing its block, or EOF
+        let value = if self.check(&TokenKind::Semicolon)
+            || self.check(&TokenKind::RightBrace)
+            || self.peek().is_none()
+        {
             None
         } else {
             Some(self.parse_expression()?)
@@ -172,6 +265,69 @@ impl<'src> Parser<'src> {
         Ok(Stmt::Return { value, span })
     }
 
+    fn parse_labeled_loop(&mut self) -> Result<Stmt<'src>, FlareError> {
+        let label_token = self.expect(TokenKind::Label(String::new()))?;
+        let start = label_token.span.start;
+        let label_span = start + 1..label_token.span.end;
+        let label = self.get_string_from_span(&label_span);
+        self.expect(TokenKind::Colon)?;
+
+        let mut stmt = match self.peek_kind() {
+            Some(TokenKind::For) => self.parse_for_statement()?,
+            Some(TokenKind::While) => self.parse_while_statement()?,
+            _ => {
+                return Err(FlareError::UnexpectedToken {
+                    message: format!("expected `for` or `while` after label '{}", label),
+                    span: self.next_span(),
+                })
+            }
+        };
+
+        if let Stmt::For {
+            label: loop_label,
+            span,
+            ..
+        }
+        | Stmt::While {
+            label: loop_label,
+            span,
+            ..
+        } = &mut stmt
+        {
+            *loop_label = Some(label);
+            span.start = start;
+        }
+
+        Ok(stmt)
+    }
+
+    fn parse_break_statement(&mut self) -> Result<Stmt<'src>, FlareError> {
+        let start = self.expect(TokenKind::Break)?.span.start;
+        let label = self.parse_jump_label()?;
+        self.match_token(&TokenKind::Semicolon);
+
+        let span = self.span_from(start);
+        Ok(Stmt::Break { label, span })
+    }
+
+    fn parse_continue_statement(&mut self) -> Result<Stmt<'src>, FlareError> {
+        let start = self.expect(TokenKind::Continue)?.span.start;
+        let label = self.parse_jump_label()?;
+        self.match_token(&TokenKind::Semicolon);
+
+        let span = self.span_from(start);
+        Ok(Stmt::Continue { label, span })
+    }
+
+    fn parse_jump_label(&mut self) -> Result<Option<&'src str>, FlareError> {
+        if !self.check(&TokenKind::Label(String::new())) {
+            return Ok(None);
+        }
+        let label_token = self.advance()?;
+        let label_span = label_token.span.start + 1..label_token.span.end;
+        Ok(Some(self.get_string_from_span(&label_span)))
+    }
+
     fn parse_block_statement(&mut self) -> Result<Stmt<'src>, FlareError> {
         let start = self.expect(TokenKind::LeftBrace)?.span.start;
         let mut statements = Vec::new();
@@ -201,13 +357,28 @@ impl<'src> Parser<'src> {
         let dest_token = self.expect(TokenKind::Identifier(String::new()))?;
         let dest_token_span = dest_token.span.clone();
         let dest = self.get_string_from_span(&dest_token_span);
+        let mut indices = Vec::new();
+        if self.match_token(&TokenKind::LeftBracket) {
+            loop {
+                indices.push(self.parse_expression()?);
+                if !self.list_continues(&TokenKind::RightBracket) {
+                    break;
+                }
+            }
+            self.expect(TokenKind::RightBracket)?;
+        }
         self.expect(TokenKind::Comma)?;
         let src = self.parse_expression()?;
         self.expect(TokenKind::RightParen)?;
         self.match_token(&TokenKind::Semicolon);
 
         let span = self.span_from(start);
-        Ok(Stmt::LoadShared { dest, src, span })
+        Ok(Stmt::LoadShared {
+            dest,
+            indices,
+            src,
+            span,
+        })
     }
 
     fn parse_type_def(&mut self) -> Result<Stmt<'src>, FlareError> {
@@ -245,10 +416,11 @@ impl<'src> Parser<'src> {
                 params.push(Param {
                     name: param_name,
                     ty: param_type,
+                    attributes: Vec::new(),
                     span: param_span,
                 });
 
-                if !self.match_token(&TokenKind::Comma) {
+                if !self.list_continues(&TokenKind::RightParen) {
                     break;
                 }
             }
@@ -270,7 +442,54 @@ impl<'src> Parser<'src> {
             params,
             return_type,
             body,
+            attributes: Vec::new(),
+            receiver: None,
             span,
         })
     }
+
+    /// Parses `impl Type { fn ... }` into its methods, each taking a `Type`
+    /// receiver as its first parameter.
+    pub(crate) fn parse_impl(&mut self) -> Result<Vec<Stmt<'src>>, FlareError> {
+        self.expect(TokenKind::Impl)?;
+        let target_token = self.expect(TokenKind::Identifier(String::new()))?;
+        let target_span = target_token.span.clone();
+        let target = self.get_string_from_span(&target_span);
+        self.expect(TokenKind::LeftBrace)?;
+
+        let mut methods = Vec::new();
+        while !self.check(&TokenKind::RightBrace) && self.peek().is_some() {
+            let mut attributes = Vec::new();
+            while self.check_attribute() {
+                attributes.push(self.parse_attribute()?);
+            }
+
+            let mut method = self.parse_function()?;
+            if let Stmt::Function {
+                name,
+                params,
+                attributes: method_attributes,
+                receiver,
+                ..
+            } = &mut method
+            {
+                if !matches!(params.first(), Some(Param { ty: Type::Named(ty, _), .. }) if *ty == target)
+                {
+                    return Err(FlareError::UnexpectedToken {
+                        message: format!(
+                            "method '{}' of `impl {}` must take a {} as its first parameter",
+                            name, target, target
+                        ),
+                        span: method.span(),
+                    });
+                }
+                *method_attributes = attributes;
+                *receiver = Some(target);
+            }
+            methods.push(method);
+        }
+
+        self.expect_after(TokenKind::RightBrace, "impl block")?;
+        Ok(methods)
+    }
 }
@@ -0,0 +1,59 @@
+/// Maps byte offsets in a source string to 1-based line and column numbers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineMap {
+    line_starts: Vec<usize>,
+}
+
+impl LineMap {
+    /// Indexes the line starts of `source`, where `\n`, `\r\n`, and a bare
+    /// `\r` each end one line.
+    pub fn new(source: &str) -> Self {
+        let bytes = source.as_bytes();
+        let mut line_starts = vec![0];
+        for (offset, &byte) in bytes.iter().enumerate() {
+            let crlf = byte == b'\r' && bytes.get(offset + 1) == Some(&b'\n');
+            if byte == b'\n' || (byte == b'\r' && !crlf) {
+                line_starts.push(offset + 1);
+            }
+        }
+        Self { line_starts }
+    }
+
+    pub fn line(&self, offset: usize) -> usize {
+        self.line_starts.partition_point(|&start| start <= offset)
+    }
+
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let line = self.line(offset);
+        (line, offset - self.line_starts[line - 1] + 1)
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crlf_line_col() {
+        let source = "kernel k() {\r\n    let x = 1\r\n}\r\n";
+        let map = LineMap::new(source);
+
+        assert_eq!(map.line_count(), 4);
+        assert_eq!(map.line_col(source.find("let").unwrap()), (2, 5));
+        assert_eq!(map.line_col(source.find('}').unwrap()), (3, 1));
+        // the `\r` of a CRLF still belongs to the line it ends
+        assert_eq!(map.line_col(source.find('\r').unwrap()), (1, 13));
+    }
+
+    #[test]
+    fn test_bare_cr_line_breaks() {
+        let source = "let a = 1\rlet b = 2\n";
+        let map = LineMap::new(source);
+
+        assert_eq!(map.line_col(source.find("let b").unwrap()), (2, 1));
+    }
+}
@@ -0,0 +1,3 @@
+pub mod line_map;
+
+pub use line_map::*;
@@ -0,0 +1,398 @@
+use crate::ast::visit::{walk_expr, walk_stmt, Visitor};
+use crate::ast::{BinOp, Expr, Param, Stmt, Type, UnOp};
+use crate::FlareError;
+use std::collections::HashMap;
+
+/// Type and shape errors in a top-level kernel or function: values that
+/// would implicitly narrow a float to an integer, and whole-tensor
+/// assignments combining tensors of another shape. Only types the source
+/// spells out are followed; values whose type is up to the backend, such
+/// as calls and members, are not checked.
+pub fn type_errors(item: &Stmt) -> Vec<FlareError> {
+    let mut checker = TypeChecker {
+        scopes: vec![HashMap::new()],
+        errors: Vec::new(),
+    };
+    match item {
+        Stmt::Kernel(kernel) => {
+            for param in &kernel.const_params {
+                checker.declare(param.name, ValueTy::from_ast(&param.ty));
+            }
+            checker.declare_params(&kernel.params);
+            let writes_output = kernel.params.iter().all(|param| param.name != OUTPUT);
+            if let Some(ty) = kernel.return_type.as_ref().filter(|_| writes_output) {
+                checker.declare(OUTPUT, ValueTy::from_ast(ty));
+            }
+            for stmt in kernel.compute.iter().flatten().chain(&kernel.body) {
+                checker.visit_stmt(stmt);
+            }
+        }
+        Stmt::Function { params, body, .. } => {
+            checker.declare_params(params);
+            checker.visit_expr(body);
+        }
+        _ => {}
+    }
+    checker.errors
+}
+
+/// Name under which kernel bodies write their return tensor.
+const OUTPUT: &str = "output";
+
+/// Scalar types in promotion order: arithmetic on two scalars gives the
+/// later of the two.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Scalar {
+    Bool,
+    I32,
+    U32,
+    I64,
+    U64,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn name(&self) -> &'static str {
+        match self {
+            Scalar::Bool => "bool",
+            Scalar::I32 => "i32",
+            Scalar::U32 => "u32",
+            Scalar::I64 => "i64",
+            Scalar::U64 => "u64",
+            Scalar::F32 => "f32",
+            Scalar::F64 => "f64",
+        }
+    }
+
+    fn is_float(&self) -> bool {
+        matches!(self, Scalar::F32 | Scalar::F64)
+    }
+
+    fn is_integer(&self) -> bool {
+        matches!(self, Scalar::I32 | Scalar::U32 | Scalar::I64 | Scalar::U64)
+    }
+}
+
+/// What the checks know of a value's type. Element types the checks do
+/// not follow, such as vectors and named types, are `None`.
+#[derive(Debug, Clone, PartialEq)]
+enum ValueTy<'src> {
+    Scalar(Scalar),
+    Tensor {
+        elem: Option<Scalar>,
+        shape: Vec<&'src str>,
+    },
+    /// Arrays and pointers, which index like a tensor but have no shape.
+    Buffer(Option<Scalar>),
+}
+
+impl<'src> ValueTy<'src> {
+    fn from_ast(ty: &Type<'src>) -> Option<Self> {
+        let scalar = |ty: &Type<'src>| match Self::from_ast(ty)? {
+            ValueTy::Scalar(scalar) => Some(scalar),
+            _ => None,
+        };
+        match ty {
+            Type::I32(_) => Some(ValueTy::Scalar(Scalar::I32)),
+            Type::I64(_) => Some(ValueTy::Scalar(Scalar::I64)),
+            Type::U32(_) => Some(ValueTy::Scalar(Scalar::U32)),
+            Type::U64(_) => Some(ValueTy::Scalar(Scalar::U64)),
+            Type::F32(_) => Some(ValueTy::Scalar(Scalar::F32)),
+            Type::F64(_) => Some(ValueTy::Scalar(Scalar::F64)),
+            Type::Bool(_) => Some(ValueTy::Scalar(Scalar::Bool)),
+            Type::Tensor { dtype, shape, .. } => Some(ValueTy::Tensor {
+                elem: scalar(dtype),
+                shape: shape.clone(),
+            }),
+            Type::Array { dtype, .. } | Type::Ptr(dtype) => Some(ValueTy::Buffer(scalar(dtype))),
+            Type::Named(..) | Type::Vector { .. } | Type::Matrix { .. } => None,
+        }
+    }
+
+    /// Type of arithmetic between `self` and `other`. A scalar broadcasts
+    /// against a tensor; two tensors must have the same shape.
+    fn promote(&self, other: &Self) -> Option<Self> {
+        match (self, other) {
+            (ValueTy::Scalar(a), ValueTy::Scalar(b)) => Some(ValueTy::Scalar(*a.max(b))),
+            (ValueTy::Tensor { elem, shape }, ValueTy::Scalar(scalar))
+            | (ValueTy::Scalar(scalar), ValueTy::Tensor { elem, shape }) => Some(ValueTy::Tensor {
+                elem: elem.map(|elem| elem.max(*scalar)),
+                shape: shape.clone(),
+            }),
+            (
+                ValueTy::Tensor { elem: a, shape },
+                ValueTy::Tensor {
+                    elem: b,
+                    shape: other_shape,
+                },
+            ) if shape == other_shape => Some(ValueTy::Tensor {
+                elem: a.zip(*b).map(|(a, b)| a.max(b)),
+                shape: shape.clone(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+struct TypeChecker<'src> {
+    /// Type of each binding in scope, `None` where it is not known.
+    scopes: Vec<HashMap<&'src str, Option<ValueTy<'src>>>>,
+    errors: Vec<FlareError>,
+}
+
+impl<'src> TypeChecker<'src> {
+    fn declare(&mut self, name: &'src str, ty: Option<ValueTy<'src>>) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name, ty);
+        }
+    }
+
+    fn declare_params(&mut self, params: &[Param<'src>]) {
+        for param in params {
+            self.declare(param.name, ValueTy::from_ast(&param.ty));
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<ValueTy<'src>> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .cloned()
+            .flatten()
+    }
+
+    /// A `let` or `var` takes its declared type, or the type of its value.
+    fn bind(&mut self, name: &'src str, ty: Option<&Type<'src>>, value: Option<&Expr<'src>>) {
+        let ty = match ty {
+            Some(ty) => {
+                let ty = ValueTy::from_ast(ty);
+                if let (Some(ty), Some(value)) = (&ty, value) {
+                    self.check_narrowing(ty, value);
+                }
+                ty
+            }
+            None => value.and_then(|value| self.infer(value)),
+        };
+        self.declare(name, ty);
+    }
+
+    fn infer(&self, expr: &Expr<'src>) -> Option<ValueTy<'src>> {
+        match expr {
+            Expr::IntLiteral(..) => Some(ValueTy::Scalar(Scalar::I32)),
+            Expr::UIntLiteral(..) => Some(ValueTy::Scalar(Scalar::U32)),
+            Expr::FloatLiteral(..) => Some(ValueTy::Scalar(Scalar::F32)),
+            Expr::BoolLiteral(..) => Some(ValueTy::Scalar(Scalar::Bool)),
+            Expr::Ident(name, _) => self.lookup(name),
+            Expr::Binary {
+                left, op, right, ..
+            } => match op {
+                BinOp::Equal
+                | BinOp::NotEqual
+                | BinOp::Less
+                | BinOp::Greater
+                | BinOp::LessEqual
+                | BinOp::GreaterEqual
+                | BinOp::And
+                | BinOp::Or => Some(ValueTy::Scalar(Scalar::Bool)),
+                _ => self.infer(left)?.promote(&self.infer(right)?),
+            },
+            Expr::Unary { op, expr, .. } => match op {
+                UnOp::Neg => self.infer(expr),
+                UnOp::Not => Some(ValueTy::Scalar(Scalar::Bool)),
+                UnOp::AddrOf | UnOp::Deref => None,
+            },
+            Expr::Index { object, .. } => match self.infer(object)? {
+                ValueTy::Tensor { elem, .. } | ValueTy::Buffer(elem) => elem.map(ValueTy::Scalar),
+                ValueTy::Scalar(_) => None,
+            },
+            Expr::Assign { value, .. } => self.infer(value),
+            Expr::Cast { target_type, .. } | Expr::Bitcast { target_type, .. } => {
+                ValueTy::from_ast(target_type)
+            }
+            Expr::ThreadIdx { dim: Some(_), .. }
+            | Expr::BlockIdx { dim: Some(_), .. }
+            | Expr::BlockDim { dim: Some(_), .. } => Some(ValueTy::Scalar(Scalar::U32)),
+            _ => None,
+        }
+    }
+
+    /// Rejects storing a float `value` into an integer slot without `as`.
+    fn check_narrowing(&mut self, target: &ValueTy, value: &Expr<'src>) {
+        let (ValueTy::Scalar(to), Some(ValueTy::Scalar(from))) = (target, self.infer(value)) else {
+            return;
+        };
+        if from.is_float() && to.is_integer() {
+            self.errors.push(FlareError::ImplicitNarrowing {
+                from: from.name().to_string(),
+                to: to.name().to_string(),
+                span: value.span(),
+            });
+        }
+    }
+
+    /// Rejects whole tensors in the arithmetic assigned to a tensor of
+    /// `shape` whose own shape differs.
+    fn check_shapes(&mut self, shape: &[&str], value: &Expr<'src>) {
+        match value {
+            Expr::Binary { left, right, .. } => {
+                self.check_shapes(shape, left);
+                self.check_shapes(shape, right);
+            }
+            Expr::Unary { expr, .. } => self.check_shapes(shape, expr),
+            Expr::Ident(name, span) => {
+                if let Some(ValueTy::Tensor { shape: found, .. }) = self.lookup(name) {
+                    if found != shape {
+                        self.errors.push(FlareError::ShapeMismatch {
+                            name: name.to_string(),
+                            found: found.iter().map(|dim| dim.to_string()).collect(),
+                            expected: shape.iter().map(|dim| dim.to_string()).collect(),
+                            span: span.clone(),
+                        });
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'src> Visitor<'src> for TypeChecker<'src> {
+    fn visit_stmt(&mut self, stmt: &Stmt<'src>) {
+        match stmt {
+            Stmt::Let {
+                name, ty, value, ..
+            } => {
+                self.visit_expr(value);
+                self.bind(name, ty.as_ref(), Some(value));
+            }
+            Stmt::Var {
+                name, ty, value, ..
+            } => {
+                if let Some(value) = value {
+                    self.visit_expr(value);
+                }
+                self.bind(name, ty.as_ref(), value.as_ref());
+            }
+            Stmt::Block { statements, .. } => {
+                self.scopes.push(HashMap::new());
+                for stmt in statements {
+                    self.visit_stmt(stmt);
+                }
+                self.scopes.pop();
+            }
+            Stmt::For {
+                var,
+                iterator,
+                body,
+                ..
+            } => {
+                self.visit_expr(iterator);
+                self.scopes.push(HashMap::new());
+                // the counter's type is up to the backend
+                self.declare(var, None);
+                self.visit_stmt(body);
+                self.scopes.pop();
+            }
+            _ => walk_stmt(self, stmt),
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &Expr<'src>) {
+        match expr {
+            Expr::Assign { target, value, .. } => match self.infer(target) {
+                Some(ValueTy::Tensor { shape, .. }) => self.check_shapes(&shape, value),
+                Some(target_ty) => self.check_narrowing(&target_ty, value),
+                None => {}
+            },
+            Expr::CompoundAssign { target, value, .. } => {
+                if let Some(target_ty) = self.infer(target) {
+                    self.check_narrowing(&target_ty, value);
+                }
+            }
+            Expr::Block { statements, .. } => {
+                self.scopes.push(HashMap::new());
+                for stmt in statements {
+                    self.visit_stmt(stmt);
+                }
+                self.scopes.pop();
+                return;
+            }
+            _ => {}
+        }
+        walk_expr(self, expr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Flare;
+
+    use super::*;
+
+    fn errors_in(source: &str) -> Vec<FlareError> {
+        let program = Flare::compile_from_string(source).unwrap();
+        program.items.iter().flat_map(type_errors).collect()
+    }
+
+    #[test]
+    fn test_implicit_narrowing() {
+        let source = r#"
+            kernel k(A: Tensor<f32, [N]>, I: Tensor<i32, [N]>, x: f32) {
+                let i = thread_idx.x
+                let n: i32 = x * 2
+                var m: u32 = 0
+                m += A[i]
+                I[i] = A[i]
+                let ok: i32 = x as i32
+                let wide: f32 = m
+            }
+        "#;
+        let errors = errors_in(source);
+
+        let spans: Vec<&str> = errors.iter().map(|error| &source[error.span()]).collect();
+        assert_eq!(spans, ["x * 2", "A[i]", "A[i]"]);
+        assert!(errors[0]
+            .to_string()
+            .contains("cannot implicitly convert 'f32' to 'i32'"));
+        assert!(errors[1].to_string().contains("'f32' to 'u32'"));
+    }
+
+    #[test]
+    fn test_elementwise_shape_mismatch() {
+        let source = r#"
+            kernel k(A: Tensor<f32, [M, N]>, B: Tensor<f32, [N]>, C: Tensor<f32, [N]>, s: f32) {
+                B = C * s + A
+                C = -B
+            }
+        "#;
+        let errors = errors_in(source);
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(&source[errors[0].span()], "A");
+        assert!(errors[0]
+            .to_string()
+            .contains("of shape [M, N] cannot be combined elementwise into shape [N]"));
+    }
+
+    #[test]
+    fn test_bindings_follow_scopes() {
+        let source = r#"
+            kernel k(x: f32) {
+                if x > 0.0 {
+                    let x = 1
+                    let n: i32 = x
+                }
+                for x in 0..4 {
+                    let n: i32 = x
+                }
+                let n: i32 = x
+            }
+        "#;
+        let errors = errors_in(source);
+
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(&errors[0], FlareError::ImplicitNarrowing { from, .. } if from == "f32"));
+    }
+}
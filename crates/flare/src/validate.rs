@@ -0,0 +1,566 @@
+use crate::ast::visit::{walk_expr, walk_stmt, Visitor};
+use crate::ast::{Attribute, Expr, KernelDef, Program, ReduceOp, ScheduleDirective, Stmt, Type};
+use crate::{Diagnostic, FlareError};
+use std::ops::Range;
+
+/// Attribute names the compiler understands: the lexer's dedicated `@name`
+/// annotation tokens plus attributes consumed by the backends.
+pub const KNOWN_ATTRIBUTES: &[&str] = &[
+    "fusion_point",
+    "fusable",
+    "fusion_transform",
+    "fused_kernel",
+    "optimize",
+    "auto_tune",
+    "schedule",
+    "memory",
+    "depends_on",
+    "independent",
+    "prefer_parallel",
+    "must_wait",
+    "dynamic_dispatch",
+    "pipeline_depth",
+    "p2p_transfer",
+    "all_reduce",
+    "specialize",
+    "function_constant",
+    "pack_uniforms",
+    "elementwise",
+    "layout",
+    "auto_barrier",
+    "pure",
+    "constant",
+];
+
+/// Rejects kernel, const, `let` and schedule hint attributes outside
+/// `KNOWN_ATTRIBUTES`, which would otherwise be accepted and silently ignored.
+pub fn validate_attributes(program: &Program) -> Result<(), FlareError> {
+    program.items.iter().try_for_each(item_attributes)
+}
+
+/// Prefix of the names backends generate, such as loop flags and mangled
+/// reserved words, which user bindings may not start with.
+pub const RESERVED_PREFIX: &str = "flare_";
+
+/// Rejects `var` declarations that have neither a type nor an initializer,
+/// since no backend can pick a type for them, and bindings or parameters
+/// whose names start with `RESERVED_PREFIX`.
+pub fn validate_bindings(program: &Program) -> Result<(), FlareError> {
+    program.items.iter().try_for_each(item_bindings)
+}
+
+struct LetAttributeChecker {
+    error: Option<FlareError>,
+}
+
+impl<'src> Visitor<'src> for LetAttributeChecker {
+    fn visit_stmt(&mut self, stmt: &Stmt<'src>) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Stmt::Let { attributes, .. } = stmt {
+            if let Err(error) = attributes.iter().try_for_each(validate_attribute) {
+                self.error = Some(error);
+                return;
+            }
+        }
+        walk_stmt(self, stmt);
+    }
+}
+
+struct BindingChecker {
+    error: Option<FlareError>,
+}
+
+impl<'src> Visitor<'src> for BindingChecker {
+    fn visit_stmt(&mut self, stmt: &Stmt<'src>) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Stmt::Var {
+            name,
+            name_span,
+            ty: None,
+            value: None,
+            ..
+        } = stmt
+        {
+            self.error = Some(FlareError::UntypedVar {
+                name: name.to_string(),
+                span: name_span.clone(),
+            });
+            return;
+        }
+        if let Some((name, span)) = declared_names(stmt)
+            .into_iter()
+            .find(|(name, _)| name.starts_with(RESERVED_PREFIX))
+        {
+            self.error = Some(FlareError::ReservedName {
+                name: name.to_string(),
+                span: span.clone(),
+            });
+            return;
+        }
+        walk_stmt(self, stmt);
+    }
+}
+
+/// Names `stmt` itself binds, with where each is written.
+fn declared_names<'a>(stmt: &'a Stmt) -> Vec<(&'a str, &'a Range<usize>)> {
+    match stmt {
+        Stmt::Let { name, span, .. } | Stmt::Const { name, span, .. } => vec![(*name, span)],
+        Stmt::Var {
+            name, name_span, ..
+        } => vec![(*name, name_span)],
+        Stmt::For { var, span, .. } => vec![(*var, span)],
+        Stmt::Function { params, .. } => params.iter().map(|p| (p.name, &p.span)).collect(),
+        Stmt::Kernel(kernel) => kernel
+            .params
+            .iter()
+            .map(|param| (param.name, &param.span))
+            .chain(
+                kernel
+                    .shared_memory
+                    .iter()
+                    .flatten()
+                    .map(|decl| (decl.name, &decl.span)),
+            )
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Rejects range expressions outside a `for` iterator or a slice index,
+/// where no backend can give them a value.
+pub fn validate_ranges(program: &Program) -> Result<(), FlareError> {
+    program.items.iter().try_for_each(item_ranges)
+}
+
+struct RangeChecker {
+    error: Option<FlareError>,
+}
+
+impl<'src> Visitor<'src> for RangeChecker {
+    fn visit_stmt(&mut self, stmt: &Stmt<'src>) {
+        if let Stmt::For { iterator, body, .. } = stmt {
+            // the iterator itself may be a range, or `(range).rev()`; its
+            // bounds may not
+            match iterator {
+                Expr::Range { .. } => walk_expr(self, iterator),
+                Expr::Call { func, args, .. } if args.is_empty() => match func.as_ref() {
+                    Expr::Member {
+                        object,
+                        field: "rev",
+                        ..
+                    } if matches!(object.as_ref(), Expr::Range { .. }) => walk_expr(self, object),
+                    _ => self.visit_expr(iterator),
+                },
+                _ => self.visit_expr(iterator),
+            }
+            self.visit_stmt(body);
+            return;
+        }
+        walk_stmt(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr<'src>) {
+        if self.error.is_some() {
+            return;
+        }
+        match expr {
+            Expr::Range { span, .. } => {
+                self.error = Some(FlareError::MisplacedRange { span: span.clone() });
+            }
+            Expr::Index {
+                object, indices, ..
+            } => {
+                self.visit_expr(object);
+                for index in indices {
+                    match index {
+                        Expr::Range { .. } => walk_expr(self, index),
+                        _ => self.visit_expr(index),
+                    }
+                }
+            }
+            _ => walk_expr(self, expr),
+        }
+    }
+}
+
+/// Rejects `load_shared` into a name the enclosing kernel does not declare
+/// in its `shared_memory` block.
+pub fn validate_shared_loads(program: &Program) -> Result<(), FlareError> {
+    program.items.iter().try_for_each(item_shared_loads)
+}
+
+/// Rejects writes to a kernel's implicit `output` buffer whose index count
+/// differs from the rank of the declared return tensor.
+pub fn validate_output_writes(program: &Program) -> Result<(), FlareError> {
+    program.items.iter().try_for_each(item_output_writes)
+}
+
+type ItemCheck = fn(&Stmt) -> Result<(), FlareError>;
+
+/// The checks above, each run on one top-level item, in the order their
+/// errors are reported.
+const ITEM_CHECKS: &[ItemCheck] = &[
+    item_attributes,
+    item_bindings,
+    item_ranges,
+    item_shared_loads,
+    item_output_writes,
+];
+
+/// Runs every check above on a single top-level item, for callers that see
+/// items one at a time instead of a whole `Program`.
+pub fn validate_item(item: &Stmt) -> Result<(), FlareError> {
+    ITEM_CHECKS.iter().try_for_each(|check| check(item))
+}
+
+/// Runs every check `validate_item` runs, collecting each failure instead
+/// of stopping at the first.
+pub fn item_errors(item: &Stmt) -> Vec<FlareError> {
+    ITEM_CHECKS
+        .iter()
+        .filter_map(|check| check(item).err())
+        .collect()
+}
+
+/// Runs every check on `program` and returns the first failure: each item
+/// check over all items in turn, then `program_errors`.
+pub fn validate_program(program: &Program) -> Result<(), FlareError> {
+    for check in ITEM_CHECKS {
+        program.items.iter().try_for_each(check)?;
+    }
+    program_errors(program)
+        .into_iter()
+        .next()
+        .map_or(Ok(()), Err)
+}
+
+/// Failures of the checks relating items to each other, which can only run
+/// once every item is parsed: `target_errors`, then `reduction_errors`.
+pub fn program_errors(program: &Program) -> Vec<FlareError> {
+    let mut errors = target_errors(program);
+    errors.extend(reduction_errors(program));
+    errors
+}
+
+/// Rejects `schedule` and `fuse` blocks naming a kernel the program does
+/// not define, one error per unknown name.
+pub fn target_errors(program: &Program) -> Vec<FlareError> {
+    program
+        .targets()
+        .filter(|target| program.kernel_by_name(target.name).is_none())
+        .map(|target| FlareError::UnknownTarget {
+            block: target.block.to_string(),
+            name: target.name.to_string(),
+            span: target.span.clone(),
+        })
+        .collect()
+}
+
+/// Rejects `@all_reduce(buffer, op)` attributes, on a kernel or in the
+/// `hints` of its schedule, whose buffer is not a parameter of the kernel
+/// or whose `op` names a function that is not `fn(a: T, b: T) -> T` over
+/// the buffer's element type.
+pub fn validate_reductions(program: &Program) -> Result<(), FlareError> {
+    reduction_errors(program)
+        .into_iter()
+        .next()
+        .map_or(Ok(()), Err)
+}
+
+/// Every failure `validate_reductions` would report, in source order.
+pub fn reduction_errors(program: &Program) -> Vec<FlareError> {
+    let on_kernels = program
+        .kernels()
+        .flat_map(|kernel| kernel.attributes.iter().map(move |attr| (kernel, attr)));
+    let in_hints = program.schedules().flat_map(|schedule| {
+        let kernel = schedule
+            .target
+            .and_then(|target| program.kernel_by_name(target));
+        schedule
+            .directives
+            .iter()
+            .filter_map(|directive| match directive {
+                ScheduleDirective::Hints(hints) => Some(hints),
+                _ => None,
+            })
+            .flatten()
+            .filter_map(move |attr| Some((kernel?, attr)))
+    });
+
+    on_kernels
+        .chain(in_hints)
+        .filter(|(_, attr)| attr.name == "all_reduce")
+        .filter_map(|(kernel, attr)| check_reduction(program, kernel, attr).err())
+        .collect()
+}
+
+fn check_reduction(
+    program: &Program,
+    kernel: &KernelDef,
+    attr: &Attribute,
+) -> Result<(), FlareError> {
+    let invalid = |reason: String| FlareError::InvalidReduction {
+        reason,
+        span: attr.span.clone(),
+    };
+
+    let Some(reduce) = attr.as_all_reduce() else {
+        return Err(invalid(
+            "expected `(buffer)` or `(buffer, op)` arguments".to_string(),
+        ));
+    };
+    let Some(buffer) = kernel
+        .params
+        .iter()
+        .find(|param| param.name == reduce.buffer)
+    else {
+        return Err(invalid(format!(
+            "'{}' is not a parameter of kernel '{}'",
+            reduce.buffer, kernel.name
+        )));
+    };
+    let ReduceOp::Custom(name) = reduce.op else {
+        return Ok(());
+    };
+
+    let function = program.items.iter().find_map(|item| match item {
+        Stmt::Function {
+            name: fn_name,
+            params,
+            return_type,
+            receiver: None,
+            ..
+        } if *fn_name == name => Some((params, return_type)),
+        _ => None,
+    });
+    let Some((params, return_type)) = function else {
+        return Err(invalid(format!("unknown reduction function '{}'", name)));
+    };
+    let [a, b] = params.as_slice() else {
+        return Err(invalid(format!(
+            "reduction function '{}' must take two parameters, found {}",
+            name,
+            params.len()
+        )));
+    };
+    let binary = a.ty.same_as(&b.ty) && return_type.as_ref().is_some_and(|ty| ty.same_as(&a.ty));
+    if !binary {
+        return Err(invalid(format!(
+            "reduction function '{}' must have the signature `fn(a: T, b: T) -> T`",
+            name
+        )));
+    }
+    let element = match &buffer.ty {
+        Type::Tensor { dtype, .. } | Type::Array { dtype, .. } | Type::Ptr(dtype) => dtype.as_ref(),
+        scalar => scalar,
+    };
+    if !element.same_as(&a.ty) {
+        return Err(invalid(format!(
+            "reduction function '{}' does not combine the elements of '{}'",
+            name, reduce.buffer
+        )));
+    }
+    Ok(())
+}
+
+/// Warns about `schedule` and `fuse` blocks targeting a kernel with no
+/// statements, whose directives then have nothing to act on. The warning
+/// points at the block, with the kernel as its related span.
+pub fn empty_targets(program: &Program) -> Vec<Diagnostic> {
+    program
+        .targets()
+        .filter_map(|target| {
+            let kernel = program.kernel_by_name(target.name)?;
+            let empty = kernel
+                .compute
+                .iter()
+                .flatten()
+                .chain(&kernel.body)
+                .next()
+                .is_none();
+            empty.then(|| {
+                Diagnostic::warning(
+                    format!(
+                        "{} block targets kernel '{}', which has no statements for it to act on",
+                        target.block, target.name
+                    ),
+                    target.span.clone(),
+                )
+                .with_related(kernel.span.clone())
+            })
+        })
+        .collect()
+}
+
+fn item_attributes(item: &Stmt) -> Result<(), FlareError> {
+    let mut checker = LetAttributeChecker { error: None };
+    checker.visit_stmt(item);
+    if let Some(error) = checker.error {
+        return Err(error);
+    }
+
+    match item {
+        Stmt::Kernel(kernel) => kernel
+            .attributes
+            .iter()
+            .chain(kernel.params.iter().flat_map(|param| &param.attributes))
+            .try_for_each(validate_attribute),
+        Stmt::Const { attributes, .. } | Stmt::Function { attributes, .. } => {
+            attributes.iter().try_for_each(validate_attribute)
+        }
+        Stmt::Schedule(schedule) => {
+            for directive in &schedule.directives {
+                if let ScheduleDirective::Hints(hints) = directive {
+                    hints.iter().try_for_each(validate_attribute)?;
+                }
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+fn item_bindings(item: &Stmt) -> Result<(), FlareError> {
+    let mut checker = BindingChecker { error: None };
+    checker.visit_stmt(item);
+    checker.error.map_or(Ok(()), Err)
+}
+
+fn item_ranges(item: &Stmt) -> Result<(), FlareError> {
+    let mut checker = RangeChecker { error: None };
+    checker.visit_stmt(item);
+    checker.error.map_or(Ok(()), Err)
+}
+
+fn item_shared_loads(item: &Stmt) -> Result<(), FlareError> {
+    let shared = match item {
+        Stmt::Kernel(kernel) => kernel
+            .shared_memory
+            .iter()
+            .flatten()
+            .map(|decl| decl.name)
+            .collect(),
+        _ => Vec::new(),
+    };
+    let mut checker = SharedLoadChecker {
+        shared,
+        error: None,
+    };
+    checker.visit_stmt(item);
+    checker.error.map_or(Ok(()), Err)
+}
+
+struct SharedLoadChecker<'src> {
+    shared: Vec<&'src str>,
+    error: Option<FlareError>,
+}
+
+impl<'src> Visitor<'src> for SharedLoadChecker<'src> {
+    fn visit_stmt(&mut self, stmt: &Stmt<'src>) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Stmt::LoadShared { dest, span, .. } = stmt {
+            if !self.shared.contains(dest) {
+                self.error = Some(FlareError::UndeclaredSharedBuffer {
+                    name: dest.to_string(),
+                    span: span.clone(),
+                });
+                return;
+            }
+        }
+        walk_stmt(self, stmt);
+    }
+}
+
+fn item_output_writes(item: &Stmt) -> Result<(), FlareError> {
+    let Stmt::Kernel(kernel) = item else {
+        return Ok(());
+    };
+    let Some(Type::Tensor { shape, .. }) = &kernel.return_type else {
+        return Ok(());
+    };
+    if kernel.params.iter().any(|param| param.name == OUTPUT) {
+        return Ok(());
+    }
+
+    let mut checker = OutputWriteChecker {
+        rank: shape.len(),
+        error: None,
+    };
+    checker.visit_stmt(item);
+    checker.error.map_or(Ok(()), Err)
+}
+
+/// Name under which kernel bodies write their return tensor.
+const OUTPUT: &str = "output";
+
+struct OutputWriteChecker {
+    rank: usize,
+    error: Option<FlareError>,
+}
+
+impl<'src> Visitor<'src> for OutputWriteChecker {
+    fn visit_expr(&mut self, expr: &Expr<'src>) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Expr::Assign { target, span, .. } | Expr::CompoundAssign { target, span, .. } = expr
+        {
+            if let Expr::Index {
+                object, indices, ..
+            } = target.as_ref()
+            {
+                if matches!(object.as_ref(), Expr::Ident(OUTPUT, _)) && indices.len() != self.rank {
+                    self.error = Some(FlareError::OutputRankMismatch {
+                        expected: self.rank,
+                        found: indices.len(),
+                        span: span.clone(),
+                    });
+                    return;
+                }
+            }
+        }
+        walk_expr(self, expr);
+    }
+}
+
+fn validate_attribute(attribute: &Attribute) -> Result<(), FlareError> {
+    if KNOWN_ATTRIBUTES.contains(&attribute.name) {
+        return Ok(());
+    }
+
+    let suggestion = KNOWN_ATTRIBUTES
+        .iter()
+        .map(|known| (edit_distance(attribute.name, known), *known))
+        .filter(|(distance, known)| *distance <= known.len().max(3) / 3)
+        .min()
+        .map(|(_, known)| known.to_string());
+
+    Err(FlareError::UnknownAttribute {
+        name: attribute.name.to_string(),
+        suggestion,
+        span: attribute.span.clone(),
+    })
+}
+
+/// Levenshtein distance between two identifiers.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut curr = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + usize::from(ca != *cb);
+            curr[j + 1] = substitution.min(prev[j + 1] + 1).min(curr[j] + 1);
+        }
+        prev = curr;
+    }
+
+    prev[b.len()]
+}